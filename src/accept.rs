@@ -0,0 +1,184 @@
+use crate::proxy::IpRange;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Decides whether a new connection is served, right after it's accepted and before
+/// it takes a worker or any of it is read. Rejected connections are closed.
+///
+/// Connections that stay silent are closed by `header_read_timeout`, which is counted
+/// from the moment they are accepted. See [`ServerConfig`](crate::config::ServerConfig).
+pub trait AcceptFilter: fmt::Debug + Send + Sync {
+    /// Returns false to close the connection without serving it
+    fn accept(&self, peer: SocketAddr) -> bool;
+
+    /// Called when a connection the filter accepted is closed
+    fn release(&self, _peer: SocketAddr) {}
+}
+
+/// Rejects the connections from the ip ranges
+#[derive(Debug, Clone, Default)]
+pub struct Blocklist {
+    ranges: Vec<IpRange>,
+}
+
+impl Blocklist {
+    pub fn new() -> Blocklist {
+        Blocklist::default()
+    }
+
+    pub fn block(mut self, range: IpRange) -> Self {
+        self.ranges.push(range);
+        self
+    }
+}
+
+impl AcceptFilter for Blocklist {
+    fn accept(&self, peer: SocketAddr) -> bool {
+        !self.ranges.iter().any(|range| range.contains(peer.ip()))
+    }
+}
+
+/// Limits the number of connections open at the same time
+#[derive(Debug)]
+pub struct MaxConnections {
+    max: usize,
+    open: AtomicUsize,
+}
+
+impl MaxConnections {
+    pub fn new(max: usize) -> MaxConnections {
+        MaxConnections {
+            max,
+            open: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl AcceptFilter for MaxConnections {
+    fn accept(&self, _peer: SocketAddr) -> bool {
+        self.open
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| {
+                (open < self.max).then_some(open + 1)
+            })
+            .is_ok()
+    }
+
+    fn release(&self, _peer: SocketAddr) {
+        self.open.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Limits the number of connections each client ip can have open at the same time
+#[derive(Debug)]
+pub struct MaxConnectionsPerIp {
+    max: usize,
+    open: Mutex<HashMap<IpAddr, usize>>,
+}
+
+impl MaxConnectionsPerIp {
+    pub fn new(max: usize) -> MaxConnectionsPerIp {
+        MaxConnectionsPerIp {
+            max,
+            open: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl AcceptFilter for MaxConnectionsPerIp {
+    fn accept(&self, peer: SocketAddr) -> bool {
+        let mut open = self.open.lock().unwrap_or_else(|e| e.into_inner());
+        let count = open.entry(peer.ip().to_canonical()).or_insert(0);
+        if *count >= self.max {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+
+    fn release(&self, peer: SocketAddr) {
+        let mut open = self.open.lock().unwrap_or_else(|e| e.into_inner());
+        let ip = peer.ip().to_canonical();
+        if let Some(count) = open.get_mut(&ip) {
+            *count -= 1;
+            if *count == 0 {
+                open.remove(&ip);
+            }
+        }
+    }
+}
+
+/// A connection accepted by every filter, which releases it from them when dropped
+pub(crate) struct Admission {
+    filters: Vec<Arc<dyn AcceptFilter>>,
+    peer: SocketAddr,
+}
+
+impl Drop for Admission {
+    fn drop(&mut self) {
+        for filter in &self.filters {
+            filter.release(self.peer);
+        }
+    }
+}
+
+/// Runs the filters in order, returning `None` as soon as one rejects the connection
+pub(crate) fn admit(filters: &[Arc<dyn AcceptFilter>], peer: SocketAddr) -> Option<Admission> {
+    let mut admission = Admission {
+        filters: Vec::with_capacity(filters.len()),
+        peer,
+    };
+    for filter in filters {
+        if !filter.accept(peer) {
+            // Dropping the admission releases the filters that already accepted it
+            return None;
+        }
+        admission.filters.push(Arc::clone(filter));
+    }
+    Some(admission)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(addr: &str) -> SocketAddr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn test_blocklist() {
+        let blocklist = Blocklist::new().block("10.0.0.0/8".parse().unwrap());
+        assert!(!blocklist.accept(peer("10.1.2.3:80")));
+        assert!(blocklist.accept(peer("192.168.0.1:80")));
+    }
+
+    #[test]
+    fn test_max_connections_per_ip() {
+        let filter = MaxConnectionsPerIp::new(2);
+        assert!(filter.accept(peer("10.0.0.1:1")));
+        assert!(filter.accept(peer("10.0.0.1:2")));
+        assert!(!filter.accept(peer("10.0.0.1:3")));
+        assert!(filter.accept(peer("10.0.0.2:1")));
+        filter.release(peer("10.0.0.1:1"));
+        assert!(filter.accept(peer("10.0.0.1:3")));
+    }
+
+    #[test]
+    fn test_admit_releases_on_drop_and_rejection() {
+        let max = Arc::new(MaxConnections::new(1));
+        let filters: Vec<Arc<dyn AcceptFilter>> = vec![
+            max.clone(),
+            Arc::new(Blocklist::new().block("10.0.0.0/8".parse().unwrap())),
+        ];
+
+        // The blocklist rejects it after the limit counted it, which must be undone
+        assert!(admit(&filters, peer("10.0.0.1:1")).is_none());
+        let admission = admit(&filters, peer("192.168.0.1:1")).unwrap();
+        assert!(admit(&filters, peer("192.168.0.2:1")).is_none());
+        drop(admission);
+        assert!(admit(&filters, peer("192.168.0.2:1")).is_some());
+    }
+}
@@ -0,0 +1,84 @@
+//! Socket activation: listeners bound by the service manager, like systemd, and passed
+//! to the process already open. Connections that arrive while the server restarts
+//! wait in the socket instead of being refused.
+
+use std::env;
+use std::io;
+use std::net::TcpListener;
+use std::os::fd::FromRawFd;
+
+/// First descriptor passed by the service manager, after stdin, stdout and stderr
+const FIRST_FD: i32 = 3;
+const F_SETFD: i32 = 2;
+const FD_CLOEXEC: i32 = 1;
+
+extern "C" {
+    fn fcntl(fd: i32, cmd: i32, ...) -> i32;
+}
+
+/// Returns the descriptors passed to the process with the pid, from the values of
+/// `LISTEN_PID` and `LISTEN_FDS`. They're meant for another process if the pid differs
+fn passed_fds(pid: u32, listen_pid: Option<&str>, listen_fds: Option<&str>) -> Vec<i32> {
+    if listen_pid.and_then(|p| p.trim().parse::<u32>().ok()) != Some(pid) {
+        return Vec::new();
+    }
+    let count = listen_fds.and_then(|n| n.trim().parse::<i32>().ok());
+    (FIRST_FD..FIRST_FD + count.unwrap_or(0).max(0)).collect()
+}
+
+/// Returns the listeners passed by the service manager, empty if the process wasn't
+/// started through socket activation. The environment variables that pass them are
+/// removed, so they're only taken once and child processes don't take them too
+/// # Example
+/// ```no_run
+/// use HTTP_Server::activation;
+/// use HTTP_Server::router::Router;
+/// use HTTP_Server::server::Server;
+///
+/// let server = Server::builder(Router::new())
+///     .listeners(activation::listeners().unwrap())
+///     .build();
+/// // Binds 127.0.0.1:8080 if there are no listeners, like when started by hand
+/// server.run().unwrap();
+/// ```
+pub fn listeners() -> io::Result<Vec<TcpListener>> {
+    let listen_pid = env::var("LISTEN_PID").ok();
+    let listen_fds = env::var("LISTEN_FDS").ok();
+    let fds = passed_fds(
+        std::process::id(),
+        listen_pid.as_deref(),
+        listen_fds.as_deref(),
+    );
+    for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        env::remove_var(name);
+    }
+    fds.into_iter()
+        .map(|fd| {
+            // SAFETY: the service manager passed the descriptor for this process to
+            // own, and the environment no longer lists it so it isn't taken twice
+            let listener = unsafe {
+                if fcntl(fd, F_SETFD, FD_CLOEXEC) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                TcpListener::from_raw_fd(fd)
+            };
+            // Fails if the descriptor isn't a socket
+            listener.local_addr()?;
+            Ok(listener)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_passed_fds() {
+        assert_eq!(passed_fds(42, Some("42"), Some("2")), [3, 4]);
+        assert_eq!(passed_fds(42, Some("42"), None), Vec::<i32>::new());
+        assert_eq!(passed_fds(42, Some("41"), Some("2")), Vec::<i32>::new());
+        assert_eq!(passed_fds(42, None, Some("2")), Vec::<i32>::new());
+        assert_eq!(passed_fds(42, Some("42"), Some("-1")), Vec::<i32>::new());
+    }
+}
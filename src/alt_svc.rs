@@ -0,0 +1,172 @@
+//! Alternative services ([RFC 7838](https://www.rfc-editor.org/rfc/rfc7838)) advertised
+//! in the `Alt-Svc` header of every response, so clients can switch to an endpoint in
+//! front of the server that speaks another protocol, like HTTP/3.
+//!
+//! Origins served over `http` can also be listed in the `/.well-known/http-opportunistic`
+//! resource ([RFC 8164](https://www.rfc-editor.org/rfc/rfc8164)), letting clients use an
+//! encrypted alternative for them.
+
+use crate::context::Context;
+use crate::http_method::HttpMethod;
+use crate::http_status::HttpStatus;
+use serde_json::json;
+use std::time::Duration;
+
+/// Path of the resource listing the origins that can be accessed opportunistically
+pub const WELL_KNOWN_PATH: &str = "/.well-known/http-opportunistic";
+
+/// The alternative services of the server, see
+/// [`ServerConfig::alt_svc`](crate::config::ServerConfig::alt_svc).
+/// Without services the header is `clear`, telling clients to forget the ones
+/// advertised before.
+/// # Example
+/// ```
+/// use HTTP_Server::alt_svc::AltSvc;
+/// use HTTP_Server::config::ServerConfig;
+/// use std::time::Duration;
+///
+/// let alt_svc = AltSvc::new()
+///     .service("h3", ":443")
+///     .service("h2", "alt.example.com:443")
+///     .max_age(Duration::from_secs(86400));
+/// assert_eq!(
+///     alt_svc.header_value(),
+///     r#"h3=":443"; ma=86400, h2="alt.example.com:443"; ma=86400"#
+/// );
+///
+/// let config = ServerConfig {
+///     alt_svc: Some(alt_svc),
+///     ..ServerConfig::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AltSvc {
+    /// ALPN protocol ids and the authorities serving them
+    services: Vec<(String, String)>,
+    max_age: Option<Duration>,
+    persist: bool,
+    origins: Vec<String>,
+}
+
+impl AltSvc {
+    pub fn new() -> Self {
+        AltSvc::default()
+    }
+
+    /// Add an alternative service, the ALPN id of its protocol like `h3` and its authority,
+    /// `host:port` or `:port` for the same host
+    pub fn service(mut self, protocol: &str, authority: &str) -> Self {
+        self.services
+            .push((protocol.to_string(), authority.to_string()));
+        self
+    }
+
+    /// Set for how long clients may keep using the services, 24 hours if it isn't set
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Set whether clients keep the services when their network changes
+    pub fn persist(mut self, persist: bool) -> Self {
+        self.persist = persist;
+        self
+    }
+
+    /// Add an `http` origin, like `http://example.com`, to the ones listed in
+    /// [`WELL_KNOWN_PATH`]. The resource is only served if there are origins
+    pub fn opportunistic_origin(mut self, origin: &str) -> Self {
+        self.origins.push(origin.to_string());
+        self
+    }
+
+    /// Returns the value of the `Alt-Svc` header
+    pub fn header_value(&self) -> String {
+        if self.services.is_empty() {
+            return "clear".to_string();
+        }
+        let mut params = String::new();
+        if let Some(max_age) = self.max_age {
+            params += &format!("; ma={}", max_age.as_secs());
+        }
+        if self.persist {
+            params += "; persist=1";
+        }
+        self.services
+            .iter()
+            .map(|(protocol, authority)| {
+                let authority = authority.replace('\\', "\\\\").replace('"', "\\\"");
+                format!("{}=\"{authority}\"{params}", encode_protocol(protocol))
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Answers the request if it's a `GET` of [`WELL_KNOWN_PATH`] and there are
+    /// origins to list, returning whether it did
+    pub(crate) fn serve_well_known(&self, ctx: &mut Context) -> bool {
+        if self.origins.is_empty()
+            || ctx.request.method.routed_as() != HttpMethod::Get
+            || ctx.request.path != WELL_KNOWN_PATH
+        {
+            return false;
+        }
+        ctx.json(HttpStatus::Ok, json!({ "origins": self.origins }));
+        true
+    }
+}
+
+/// Percent-encodes the characters of a protocol id that aren't allowed in a token
+fn encode_protocol(protocol: &str) -> String {
+    protocol
+        .bytes()
+        .map(|b| match b {
+            b'%' => "%25".to_string(),
+            b if b.is_ascii_alphanumeric() || b"!#$&'*+-.^_`|~".contains(&b) => {
+                (b as char).to_string()
+            }
+            b => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::mock_stream::MockWriter;
+    use serde_json::Value;
+
+    #[test]
+    fn test_header_value() {
+        assert_eq!(AltSvc::new().header_value(), "clear");
+        let alt_svc = AltSvc::new()
+            .service("h3", ":443")
+            .service("w=x:y", "a\"b")
+            .persist(true);
+        assert_eq!(
+            alt_svc.header_value(),
+            r#"h3=":443"; persist=1, w%3Dx%3Ay="a\"b"; persist=1"#
+        );
+    }
+
+    #[test]
+    fn test_serve_well_known() {
+        let serve = |alt_svc: &AltSvc, path: &str| {
+            let writer = MockWriter::default();
+            let mut ctx = Context::new(writer.clone());
+            ctx.request.method = HttpMethod::Get;
+            ctx.request.path = path.to_string();
+            (alt_svc.serve_well_known(&mut ctx), writer.contents())
+        };
+        let alt_svc = AltSvc::new().service("h3", ":443");
+        assert!(!serve(&alt_svc, WELL_KNOWN_PATH).0);
+
+        let alt_svc = alt_svc.opportunistic_origin("http://example.com");
+        assert!(!serve(&alt_svc, "/").0);
+        let (served, response) = serve(&alt_svc, WELL_KNOWN_PATH);
+        assert!(served);
+        let body = response.split("\r\n\r\n").nth(1).unwrap();
+        let body: Value = serde_json::from_str(body).unwrap();
+        assert_eq!(body, json!({"origins": ["http://example.com"]}));
+    }
+}
@@ -1,5 +1,9 @@
+use crate::http_response::HttpResponse;
 use crate::http_status::HttpStatus;
+use crate::problem::Problem;
+use serde_json::error::Category;
 use serde_json::{json, Value};
+use std::sync::Arc;
 use std::{fmt, io};
 
 #[derive(Debug)]
@@ -10,17 +14,127 @@ pub enum ApiErr {
     StreamError(io::Error),
     Conflict(String),
     InvalidRequest,
+    HeadersTooLarge,
+    DuplicateHeader(String),
+    InvalidCookie(String),
+    InvalidJson(serde_json::Error),
+    PayloadTooLarge,
+    ExpectationFailed,
+    VersionNotSupported,
+    InvalidCatalog(String),
+    UnsupportedEncoding(String),
+    InvalidRule(String),
+    HostNotAllowed(String),
+    InvalidGeoIpDatabase(String),
+    AmbiguousRequest(String),
+    InvalidQuery(String),
+    InvalidHeader(String),
+    InvalidPatch(String),
+    PatchFailed(String),
+    InvalidMsgpack(String),
+    ContentTypeMismatch(String),
+}
+
+/// Turns the errors the server answers by itself, like a malformed request or a
+/// handler that panicked, into the response of the application, see
+/// [`ServerConfig::error_renderer`](crate::config::ServerConfig::error_renderer).
+/// # Example
+/// ```
+/// use HTTP_Server::api_err::ErrorRenderer;
+/// use HTTP_Server::config::ServerConfig;
+/// use HTTP_Server::http_response::HttpResponse;
+/// use serde_json::json;
+///
+/// let config = ServerConfig {
+///     error_renderer: Some(ErrorRenderer::new(|err| {
+///         HttpResponse::new(err.http_status())
+///             .json(&json!({"error": {"code": err.http_status().to_string(), "message": err.to_string()}}))
+///     })),
+///     ..ServerConfig::default()
+/// };
+/// ```
+#[derive(Clone)]
+pub struct ErrorRenderer(Arc<dyn Fn(&ApiErr) -> HttpResponse + Send + Sync>);
+
+impl ErrorRenderer {
+    pub fn new<F>(render: F) -> ErrorRenderer
+    where
+        F: Fn(&ApiErr) -> HttpResponse + Send + Sync + 'static,
+    {
+        ErrorRenderer(Arc::new(render))
+    }
+
+    pub fn render(&self, err: &ApiErr) -> HttpResponse {
+        (self.0)(err)
+    }
+}
+
+impl fmt::Debug for ErrorRenderer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ErrorRenderer")
+    }
+}
+
+/// Read timeouts surface as `WouldBlock` on some platforms and `TimedOut` on others
+fn is_timeout(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
 }
 
 impl ApiErr {
+    /// Returns whether the error means the client is gone, so there's no one to answer
+    pub fn is_disconnect(&self) -> bool {
+        match self {
+            ApiErr::StreamError(err) => matches!(
+                err.kind(),
+                io::ErrorKind::UnexpectedEof
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::BrokenPipe
+            ),
+            _ => false,
+        }
+    }
+
+    /// Returns whether the request that failed looks like an attack rather than a
+    /// broken client, like the ambiguous lengths of request smuggling
+    pub fn is_malicious(&self) -> bool {
+        matches!(self, ApiErr::AmbiguousRequest(_))
+    }
+
     pub fn http_status(&self) -> HttpStatus {
         match self {
+            ApiErr::StreamError(err) if is_timeout(err) => HttpStatus::RequestTimeout,
             ApiErr::StreamError(_) => HttpStatus::InternalServerError,
             ApiErr::InternalError(_) => HttpStatus::InternalServerError,
             ApiErr::MediaTypeNotSupported => HttpStatus::BadRequest,
             ApiErr::InvalidMethod => HttpStatus::BadRequest,
             ApiErr::Conflict(_) => HttpStatus::Conflict,
             ApiErr::InvalidRequest => HttpStatus::BadRequest,
+            ApiErr::HeadersTooLarge => HttpStatus::RequestHeaderFieldsTooLarge,
+            ApiErr::DuplicateHeader(_) => HttpStatus::BadRequest,
+            ApiErr::InvalidCookie(_) => HttpStatus::InternalServerError,
+            ApiErr::PayloadTooLarge => HttpStatus::PayloadTooLarge,
+            ApiErr::ExpectationFailed => HttpStatus::ExpectationFailed,
+            ApiErr::VersionNotSupported => HttpStatus::HttpVersionNotSupported,
+            ApiErr::InvalidCatalog(_) => HttpStatus::InternalServerError,
+            ApiErr::UnsupportedEncoding(_) => HttpStatus::UnsupportedMediaType,
+            ApiErr::InvalidRule(_) => HttpStatus::InternalServerError,
+            ApiErr::HostNotAllowed(_) => HttpStatus::MisdirectedRequest,
+            ApiErr::InvalidGeoIpDatabase(_) => HttpStatus::InternalServerError,
+            ApiErr::AmbiguousRequest(_) => HttpStatus::BadRequest,
+            ApiErr::InvalidQuery(_) => HttpStatus::BadRequest,
+            ApiErr::InvalidHeader(_) => HttpStatus::BadRequest,
+            ApiErr::InvalidPatch(_) => HttpStatus::BadRequest,
+            ApiErr::PatchFailed(_) => HttpStatus::UnprocessableEntity,
+            ApiErr::InvalidMsgpack(_) => HttpStatus::BadRequest,
+            ApiErr::ContentTypeMismatch(_) => HttpStatus::UnsupportedMediaType,
+            ApiErr::InvalidJson(err) => match err.classify() {
+                Category::Data => HttpStatus::UnprocessableEntity,
+                _ => HttpStatus::BadRequest,
+            },
         }
     }
 
@@ -30,17 +144,46 @@ impl ApiErr {
             "message": message,
         })
     }
+
+    /// Returns the error as a Problem Details object, with its message as the detail
+    pub fn to_problem(&self) -> Problem {
+        Problem::new(self.http_status()).detail(&self.to_string())
+    }
 }
 
 impl fmt::Display for ApiErr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let error = match self {
+            ApiErr::StreamError(err) if is_timeout(err) => "Request timed out.".into(),
             ApiErr::StreamError(err) => err.to_string(),
             ApiErr::InternalError(err) => err.clone(),
             ApiErr::MediaTypeNotSupported => "Media type not supported.".into(),
             ApiErr::InvalidMethod => "Invalid method.".into(),
             ApiErr::Conflict(err) => format!("{err} already exists!"),
             ApiErr::InvalidRequest => "Invalid request.".into(),
+            ApiErr::HeadersTooLarge => "Request header fields too large.".into(),
+            ApiErr::DuplicateHeader(header) => format!("Duplicate {header} header."),
+            ApiErr::InvalidCookie(reason) => format!("Invalid cookie: {reason}."),
+            ApiErr::InvalidJson(err) => format!("Invalid json: {err}."),
+            ApiErr::PayloadTooLarge => "Payload too large.".into(),
+            ApiErr::ExpectationFailed => "Expectation failed.".into(),
+            ApiErr::VersionNotSupported => "HTTP version not supported.".into(),
+            ApiErr::InvalidCatalog(reason) => format!("Invalid message catalog: {reason}."),
+            ApiErr::UnsupportedEncoding(encoding) => {
+                format!("Content encoding {encoding} not supported.")
+            }
+            ApiErr::InvalidRule(reason) => format!("Invalid rule: {reason}."),
+            ApiErr::HostNotAllowed(host) => format!("Host {host} not allowed."),
+            ApiErr::InvalidGeoIpDatabase(reason) => format!("Invalid GeoIP database: {reason}."),
+            ApiErr::AmbiguousRequest(reason) => format!("Ambiguous request: {reason}."),
+            ApiErr::InvalidQuery(reason) => format!("Invalid query: {reason}."),
+            ApiErr::InvalidHeader(line) => format!("Invalid header line `{line}`."),
+            ApiErr::InvalidPatch(reason) => format!("Invalid patch: {reason}."),
+            ApiErr::PatchFailed(reason) => format!("Patch failed: {reason}."),
+            ApiErr::InvalidMsgpack(reason) => format!("Invalid msgpack: {reason}."),
+            ApiErr::ContentTypeMismatch(media_type) => {
+                format!("Body doesn't match its content type {media_type}.")
+            }
         };
         write!(f, "{error}")
     }
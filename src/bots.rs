@@ -0,0 +1,271 @@
+use crate::context::Context;
+use crate::http_status::HttpStatus;
+use std::fmt;
+
+/// Kind of client that sent a request, according to its `User-Agent`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AgentClass {
+    Browser,
+    /// Search engine and other indexing crawlers
+    Crawler,
+    /// Uptime checkers and health probes
+    Monitor,
+    /// Other automated clients, like scripts and command line tools
+    Bot,
+    /// Requests without a `User-Agent`
+    Unknown,
+}
+
+impl fmt::Display for AgentClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let class = match self {
+            AgentClass::Browser => "browser",
+            AgentClass::Crawler => "crawler",
+            AgentClass::Monitor => "monitor",
+            AgentClass::Bot => "bot",
+            AgentClass::Unknown => "unknown",
+        };
+        write!(f, "{class}")
+    }
+}
+
+const CRAWLERS: &[&str] = &[
+    "googlebot",
+    "bingbot",
+    "slurp",
+    "duckduckbot",
+    "baiduspider",
+    "yandexbot",
+    "applebot",
+    "facebookexternalhit",
+    "twitterbot",
+    "linkedinbot",
+    "ahrefsbot",
+    "semrushbot",
+    "gptbot",
+];
+
+const MONITORS: &[&str] = &[
+    "uptimerobot",
+    "pingdom",
+    "statuscake",
+    "datadog",
+    "newrelicpinger",
+    "kube-probe",
+    "elb-healthchecker",
+    "googlehc",
+];
+
+const BOTS: &[&str] = &[
+    "bot",
+    "crawler",
+    "spider",
+    "curl",
+    "wget",
+    "python-requests",
+    "python-urllib",
+    "go-http-client",
+    "java/",
+    "okhttp",
+    "libwww-perl",
+    "httpie",
+];
+
+/// Classifies requests by their `User-Agent` with case insensitive substring lists.
+/// The lists are checked in order: monitors, crawlers and then bots,
+/// any other agent is a browser.
+/// # Example
+/// ```
+/// use HTTP_Server::bots::{AgentClass, BotClassifier};
+///
+/// let classifier = BotClassifier::default().crawler("examplebot");
+/// let googlebot = "Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)";
+/// assert_eq!(classifier.classify(Some(googlebot)), AgentClass::Crawler);
+/// assert_eq!(classifier.classify(Some("ExampleBot/1.0")), AgentClass::Crawler);
+/// assert_eq!(classifier.classify(Some("curl/8.4.0")), AgentClass::Bot);
+/// assert_eq!(classifier.classify(None), AgentClass::Unknown);
+/// ```
+#[derive(Debug, Clone)]
+pub struct BotClassifier {
+    monitors: Vec<String>,
+    crawlers: Vec<String>,
+    bots: Vec<String>,
+    /// Value of the `X-Robots-Tag` header sent to crawlers
+    robots_tag: Option<String>,
+    /// Classes answered with `403 Forbidden`
+    denied: Vec<AgentClass>,
+}
+
+impl Default for BotClassifier {
+    fn default() -> Self {
+        let list = |agents: &[&str]| agents.iter().map(|a| a.to_string()).collect();
+        BotClassifier {
+            monitors: list(MONITORS),
+            crawlers: list(CRAWLERS),
+            bots: list(BOTS),
+            ..BotClassifier::empty()
+        }
+    }
+}
+
+impl BotClassifier {
+    /// Create a classifier without any known agent, everything is a browser
+    pub fn empty() -> BotClassifier {
+        BotClassifier {
+            monitors: Vec::new(),
+            crawlers: Vec::new(),
+            bots: Vec::new(),
+            robots_tag: None,
+            denied: Vec::new(),
+        }
+    }
+
+    pub fn monitor(mut self, agent: &str) -> Self {
+        self.monitors.push(agent.to_lowercase());
+        self
+    }
+
+    pub fn crawler(mut self, agent: &str) -> Self {
+        self.crawlers.push(agent.to_lowercase());
+        self
+    }
+
+    pub fn bot(mut self, agent: &str) -> Self {
+        self.bots.push(agent.to_lowercase());
+        self
+    }
+
+    /// Send crawlers an `X-Robots-Tag` header with the directives, like `noindex, nofollow`
+    pub fn robots_tag(mut self, directives: &str) -> Self {
+        self.robots_tag = Some(directives.to_string());
+        self
+    }
+
+    /// Answer the requests of the class with `403 Forbidden`
+    pub fn deny(mut self, class: AgentClass) -> Self {
+        self.denied.push(class);
+        self
+    }
+
+    pub fn classify(&self, user_agent: Option<&str>) -> AgentClass {
+        let user_agent = match user_agent {
+            Some(user_agent) if !user_agent.trim().is_empty() => user_agent.to_lowercase(),
+            _ => return AgentClass::Unknown,
+        };
+        let matches = |agents: &[String]| agents.iter().any(|a| user_agent.contains(a.as_str()));
+
+        if matches(&self.monitors) {
+            AgentClass::Monitor
+        } else if matches(&self.crawlers) {
+            AgentClass::Crawler
+        } else if matches(&self.bots) {
+            AgentClass::Bot
+        } else {
+            AgentClass::Browser
+        }
+    }
+
+    /// Returns a middleware that sets the class of every request, available with
+    /// [`Context::agent_class`], and applies the robots tag and denied classes
+    /// # Example
+    /// ```
+    /// use HTTP_Server::bots::{AgentClass, BotClassifier};
+    /// use HTTP_Server::router::Router;
+    ///
+    /// let mut router = Router::new();
+    /// router.use_middleware(
+    ///     BotClassifier::default()
+    ///         .robots_tag("noindex")
+    ///         .deny(AgentClass::Bot)
+    ///         .middleware(),
+    /// );
+    /// ```
+    pub fn middleware(self) -> impl Fn(&mut Context) -> bool + Send + Sync + 'static {
+        move |ctx: &mut Context| {
+            let class = self.classify(ctx.request.headers.get("User-Agent").map(|h| h.as_str()));
+            ctx.agent_class = Some(class);
+
+            if self.denied.contains(&class) {
+                ctx.string(HttpStatus::Forbidden, "Forbidden");
+                return false;
+            }
+            if let (AgentClass::Crawler, Some(robots_tag)) = (class, &self.robots_tag) {
+                ctx.add_response_header("X-Robots-Tag", robots_tag);
+            }
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::Headers;
+    use crate::http_method::HttpMethod;
+    use crate::http_request::HttpRequest;
+    use crate::utils::mock_stream::MockWriter;
+
+    fn context(user_agent: &str) -> (Context, MockWriter) {
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        let mut headers = Headers::new();
+        headers.insert("User-Agent", user_agent);
+        ctx.request = HttpRequest::new(HttpMethod::Get, "/".into(), headers, "".into());
+        (ctx, writer)
+    }
+
+    #[test]
+    fn test_classify() {
+        let classifier = BotClassifier::default();
+        let browser = "Mozilla/5.0 (X11; Linux x86_64; rv:120.0) Gecko/20100101 Firefox/120.0";
+        assert_eq!(classifier.classify(Some(browser)), AgentClass::Browser);
+        assert_eq!(
+            classifier.classify(Some("Mozilla/5.0 (compatible; bingbot/2.0)")),
+            AgentClass::Crawler
+        );
+        // Monitors are checked before the generic "bot"
+        assert_eq!(
+            classifier.classify(Some("Mozilla/5.0+(compatible; UptimeRobot/2.0)")),
+            AgentClass::Monitor
+        );
+        assert_eq!(
+            classifier.classify(Some("kube-probe/1.28")),
+            AgentClass::Monitor
+        );
+        assert_eq!(
+            classifier.classify(Some("python-requests/2.31")),
+            AgentClass::Bot
+        );
+        assert_eq!(classifier.classify(Some(" ")), AgentClass::Unknown);
+    }
+
+    #[test]
+    fn test_empty_classifier() {
+        let classifier = BotClassifier::empty().monitor("Checker");
+        assert_eq!(classifier.classify(Some("curl/8.0")), AgentClass::Browser);
+        assert_eq!(
+            classifier.classify(Some("my-checker/1")),
+            AgentClass::Monitor
+        );
+    }
+
+    #[test]
+    fn test_middleware() {
+        let middleware = BotClassifier::default()
+            .robots_tag("noindex, nofollow")
+            .deny(AgentClass::Bot)
+            .middleware();
+
+        let (mut ctx, writer) = context("Googlebot/2.1");
+        assert!(middleware(&mut ctx));
+        assert_eq!(ctx.agent_class(), Some(AgentClass::Crawler));
+        ctx.string(HttpStatus::Ok, "");
+        assert!(writer
+            .contents()
+            .contains("X-Robots-Tag: noindex, nofollow\r\n"));
+
+        let (mut ctx, writer) = context("Wget/1.21");
+        assert!(!middleware(&mut ctx));
+        assert!(writer.contents().starts_with("HTTP/1.1 403 Forbidden"));
+    }
+}
@@ -0,0 +1,68 @@
+//! End-to-end latency budgets. A client, or the service before this one, sends how long
+//! it's willing to wait in the `X-Request-Timeout` header, in seconds like `2.5`.
+//! Handlers read what's left of it with
+//! [`Context::remaining_budget`](crate::context::Context::remaining_budget) and pass it
+//! along, decremented, on the requests they make to other services with
+//! [`Context::budget_header`](crate::context::Context::budget_header), so the whole chain
+//! gives up at the same time.
+
+use std::time::Duration;
+
+/// Header carrying the latency budget of the request
+pub const HEADER: &str = "X-Request-Timeout";
+
+/// Parses a budget in seconds, with up to millisecond precision. Negative, non-finite
+/// or malformed values return `None`
+/// # Example
+/// ```
+/// use HTTP_Server::budget;
+/// use std::time::Duration;
+///
+/// assert_eq!(budget::parse("2.5"), Some(Duration::from_millis(2500)));
+/// assert_eq!(budget::parse(" 30 "), Some(Duration::from_secs(30)));
+/// assert_eq!(budget::parse("-1"), None);
+/// assert_eq!(budget::parse("1s"), None);
+/// ```
+pub fn parse(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit() || b == b'.') {
+        return None;
+    }
+    let seconds: f64 = value.parse().ok()?;
+    let millis = (seconds * 1000.0).round();
+    if !millis.is_finite() || millis > u64::MAX as f64 {
+        return None;
+    }
+    Some(Duration::from_millis(millis as u64))
+}
+
+/// Formats a budget in seconds with millisecond precision, the way [`parse`] reads it
+/// # Example
+/// ```
+/// use HTTP_Server::budget;
+/// use std::time::Duration;
+///
+/// assert_eq!(budget::format(Duration::from_millis(2500)), "2.500");
+/// assert_eq!(budget::format(Duration::ZERO), "0.000");
+/// ```
+pub fn format(budget: Duration) -> String {
+    format!("{}.{:03}", budget.as_secs(), budget.subsec_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("0"), Some(Duration::ZERO));
+        assert_eq!(parse("0.0004"), Some(Duration::ZERO));
+        assert_eq!(parse(".25"), Some(Duration::from_millis(250)));
+        assert_eq!(parse("1.2.3"), None);
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("inf"), None);
+        assert_eq!(parse("1e3"), None);
+        let budget = Duration::from_millis(61_007);
+        assert_eq!(parse(&format(budget)), Some(budget));
+    }
+}
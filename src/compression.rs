@@ -0,0 +1,189 @@
+use crate::negotiation::negotiate_encoding;
+use crate::utils::deflate;
+
+/// Content codings the server can compress responses with, in order of preference
+const CODINGS: [&str; 2] = ["gzip", "deflate"];
+
+/// Compression of the response bodies for the clients that accept it, negotiated with
+/// their `Accept-Encoding` header. See [`ServerConfig::compression`](crate::config::ServerConfig::compression).
+///
+/// Only the bodies of at least `min_size` bytes whose `Content-Type` is in the allowlist
+/// are compressed, as small or already compressed bodies like images don't get smaller.
+/// Responses that could be compressed get `Vary: Accept-Encoding`, so caches keep a copy
+/// for each coding. Files and streamed responses are sent as they are.
+///
+/// The level, from 1, the fastest, to 9, the smallest, adapts to the response and the
+/// server: big bodies are compressed faster, from 256 KiB at level 4 and from 4 MiB at
+/// level 1, and while the server has more open connections than 3/4 of its workers
+/// every body is compressed at level 1, so compressing doesn't starve the requests.
+/// # Example
+/// ```
+/// use HTTP_Server::compression::Compression;
+///
+/// let compression = Compression::default()
+///     .min_size(256)
+///     .content_type("application/wasm")
+///     .level(9)
+///     .size_level(1024 * 1024, 5)
+///     .busy_level(0.5, 2);
+/// assert!(compression.compresses("text/html; charset=utf-8"));
+/// assert!(compression.compresses("application/wasm"));
+/// assert!(!compression.compresses("image/png"));
+/// assert_eq!(compression.level_for(2048, 0.1), 9);
+/// assert_eq!(compression.level_for(2 * 1024 * 1024, 0.1), 5);
+/// assert_eq!(compression.level_for(2048, 0.8), 2);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Compression {
+    min_size: usize,
+    content_types: Vec<String>,
+    level: u8,
+    /// Levels of the bodies of at least the size, sorted by size
+    size_levels: Vec<(usize, u8)>,
+    /// Highest level while the server is busier than the load
+    busy_level: Option<(f64, u8)>,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression {
+            min_size: 1024,
+            content_types: Vec::new(),
+            level: deflate::DEFAULT_LEVEL,
+            size_levels: Vec::new(),
+            busy_level: Some((0.75, 1)),
+        }
+        .size_level(256 * 1024, 4)
+        .size_level(4 * 1024 * 1024, 1)
+        .content_type("text/*")
+        .content_type("application/json")
+        .content_type("application/javascript")
+        .content_type("application/xml")
+        .content_type("image/svg+xml")
+    }
+}
+
+impl Compression {
+    /// Set the smallest body that is compressed, in bytes
+    pub fn min_size(mut self, min_size: usize) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Allow compressing a media type, or all of a type with one like `text/*`
+    pub fn content_type(mut self, media_type: &str) -> Self {
+        self.content_types.push(media_type.to_ascii_lowercase());
+        self
+    }
+
+    /// Set the level the bodies are compressed with, from 1 to 9. 6 by default
+    pub fn level(mut self, level: u8) -> Self {
+        self.level = level.clamp(1, 9);
+        self
+    }
+
+    /// Compress the bodies of at least `min_size` bytes with the level instead,
+    /// replacing the level set for the same size
+    pub fn size_level(mut self, min_size: usize, level: u8) -> Self {
+        self.size_levels.retain(|(size, _)| *size != min_size);
+        self.size_levels.push((min_size, level.clamp(1, 9)));
+        self.size_levels.sort_by_key(|(size, _)| *size);
+        self
+    }
+
+    /// Compress with at most the level while the server is busier than `load`, the
+    /// open connections for each of its workers, queued ones included
+    pub fn busy_level(mut self, load: f64, level: u8) -> Self {
+        self.busy_level = Some((load, level.clamp(1, 9)));
+        self
+    }
+
+    /// Keep the levels however busy the server is
+    pub fn ignore_load(mut self) -> Self {
+        self.busy_level = None;
+        self
+    }
+
+    /// Returns the level a body of the size is compressed with under the load
+    pub fn level_for(&self, size: usize, load: f64) -> u8 {
+        let level = self
+            .size_levels
+            .iter()
+            .rev()
+            .find(|(min_size, _)| size >= *min_size)
+            .map_or(self.level, |(_, level)| *level);
+        match self.busy_level {
+            Some((busy, busy_level)) if load > busy => level.min(busy_level),
+            _ => level,
+        }
+    }
+
+    /// Returns whether bodies of the content type are compressed
+    pub fn compresses(&self, content_type: &str) -> bool {
+        let content_type = content_type.to_ascii_lowercase();
+        let media_type = content_type.split(';').next().unwrap_or_default().trim();
+        self.content_types
+            .iter()
+            .any(|allowed| match allowed.strip_suffix("/*") {
+                Some(kind) => media_type.split_once('/').is_some_and(|(k, _)| k == kind),
+                None => allowed == media_type,
+            })
+    }
+
+    /// Compresses the body if it's big enough and the client accepts one of the codings,
+    /// returning the coding used with the compressed body
+    pub(crate) fn encode(
+        &self,
+        accept_encoding: Option<&str>,
+        body: &[u8],
+        load: f64,
+    ) -> Option<(&'static str, Vec<u8>)> {
+        if body.len() < self.min_size {
+            return None;
+        }
+        let level = self.level_for(body.len(), load);
+        match negotiate_encoding(accept_encoding, &CODINGS)? {
+            "gzip" => Some(("gzip", deflate::gzip(body, level))),
+            _ => Some(("deflate", deflate::zlib_compress(body, level))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::inflate::{gunzip, zlib_decompress};
+
+    #[test]
+    fn test_encode() {
+        let compression = Compression::default().min_size(10);
+        let body = b"hello hello hello hello";
+        let (coding, gzipped) = compression.encode(Some("gzip"), body, 0.0).unwrap();
+        assert_eq!(coding, "gzip");
+        assert_eq!(gunzip(&gzipped, 100).unwrap(), body);
+
+        let (coding, deflated) = compression.encode(Some("deflate"), body, 0.0).unwrap();
+        assert_eq!(coding, "deflate");
+        assert_eq!(zlib_decompress(&deflated, 100).unwrap(), body);
+
+        assert!(compression.encode(Some("br"), body, 0.0).is_none());
+        assert!(compression.encode(None, body, 0.0).is_none());
+        assert!(compression.encode(Some("gzip"), b"short", 0.0).is_none());
+    }
+
+    #[test]
+    fn test_level_for() {
+        let compression = Compression::default();
+        assert_eq!(compression.level_for(2048, 0.0), 6);
+        assert_eq!(compression.level_for(256 * 1024, 0.0), 4);
+        assert_eq!(compression.level_for(10 * 1024 * 1024, 0.0), 1);
+        assert_eq!(compression.level_for(2048, 0.9), 1);
+
+        let compression = compression
+            .level(12)
+            .size_level(256 * 1024, 7)
+            .ignore_load();
+        assert_eq!(compression.level_for(2048, 5.0), 9);
+        assert_eq!(compression.level_for(300 * 1024, 5.0), 7);
+    }
+}
@@ -0,0 +1,227 @@
+use crate::accept::AcceptFilter;
+use crate::alt_svc::AltSvc;
+use crate::api_err::ErrorRenderer;
+use crate::compression::Compression;
+use crate::cookie::{CookieKeys, CookiePolicy};
+use crate::diagnostics::ReportFormat;
+use crate::etag::ETagMode;
+use crate::geoip::GeoIpResolver;
+use crate::localization::Catalogs;
+use crate::proxy::IpRange;
+use crate::rules::Rules;
+use crate::scrub::Scrubber;
+use crate::subsystems::Subsystems;
+use crate::tarpit::Tarpit;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How request header lines that can't be parsed are handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HeaderParsing {
+    /// Answer the request with `400 Bad Request`
+    #[default]
+    Strict,
+    /// Skip the line, logging it, and keep the rest of the request
+    Lenient,
+}
+
+/// How much the answer to a request the server can't parse tells about what's wrong
+/// with it. Listeners can override it with [`ListenerOptions`], like detailed errors
+/// on an internal admin port and generic ones on the public port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ParseErrors {
+    /// The error with what's wrong, like the invalid header line
+    #[default]
+    Detailed,
+    /// Only the status, like `400 Bad Request`, revealing nothing about how the
+    /// request was parsed
+    Generic,
+}
+
+/// Settings of a single listener that override the ones of the [`ServerConfig`] for the
+/// connections it accepts, see [`Server::serve_with`](crate::server::Server::serve_with)
+/// # Example
+/// ```
+/// use HTTP_Server::config::{ListenerOptions, ParseErrors};
+///
+/// let public = ListenerOptions {
+///     parse_errors: Some(ParseErrors::Generic),
+/// };
+/// let admin = ListenerOptions::default();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ListenerOptions {
+    /// Replaces [`ServerConfig::parse_errors`], `None` keeps it
+    pub parse_errors: Option<ParseErrors>,
+}
+
+impl ListenerOptions {
+    /// Returns the config of the listener, sharing the server one if nothing changes
+    pub(crate) fn apply(&self, config: &Arc<ServerConfig>) -> Arc<ServerConfig> {
+        match self.parse_errors {
+            Some(parse_errors) if parse_errors != config.parse_errors => Arc::new(ServerConfig {
+                parse_errors,
+                ..ServerConfig::clone(config)
+            }),
+            _ => Arc::clone(config),
+        }
+    }
+}
+
+/// How requests are written to the access log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AccessLogFormat {
+    /// The client ip, request line, headers and body on a single line
+    #[default]
+    Line,
+    /// A `curl` command sending the request again, to reproduce it.
+    /// See [`Scrubber::curl`](crate::scrub::Scrubber::curl).
+    Curl,
+}
+
+/// Tunables used by the [`Server`](crate::server::Server) while handling requests.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Size of the chunks read from the connection while parsing a request.
+    pub read_buffer_size: usize,
+    /// Initial capacity of the buffer the request head is read into.
+    /// The buffer grows as needed up to `max_header_size`.
+    pub header_buffer_size: usize,
+    /// Maximum size in bytes of the request line and headers, including the
+    /// terminating empty line. Bigger requests are answered with
+    /// `431 Request Header Fields Too Large`.
+    pub max_header_size: usize,
+    /// Maximum size in bytes of a request body. Bigger requests are answered with
+    /// `413 Payload Too Large` without reading their body, or the rest of it once their
+    /// chunks add up to more.
+    pub max_body_size: usize,
+    /// Time allowed to receive the request line and headers, counted from the first byte
+    /// of a kept alive connection or from the moment a new connection is accepted.
+    pub header_read_timeout: Option<Duration>,
+    /// Time allowed to receive the request body once the headers were read.
+    pub body_read_timeout: Option<Duration>,
+    /// Maximum time a single write of the response may block.
+    pub write_timeout: Option<Duration>,
+    /// Whether connections are kept open to serve more than one request.
+    pub keep_alive: bool,
+    /// How long an open connection may stay idle waiting for its next request.
+    pub keep_alive_timeout: Duration,
+    /// Maximum number of requests served over a single connection before closing it.
+    pub max_requests_per_connection: usize,
+    /// How long a server shutting down waits for the requests being served before it
+    /// returns, see [`Server::start`](crate::server::Server::start). 30 seconds by default.
+    pub shutdown_timeout: Duration,
+    /// Keys used to sign and encrypt cookies, see [`CookieKeys`].
+    pub cookie_keys: CookieKeys,
+    /// Attributes given to every cookie the server sets, unless the cookie sets them itself.
+    pub cookie_policy: CookiePolicy,
+    /// Localized messages looked up by [`Context::t`](crate::context::Context::t).
+    pub catalogs: Catalogs,
+    /// Proxies whose `Forwarded` and `X-Forwarded-For` headers are trusted to resolve
+    /// [`Context::client_ip`](crate::context::Context::client_ip). Empty by default.
+    pub trusted_proxies: Vec<IpRange>,
+    /// Rules that block, log or tag requests before they reach the router,
+    /// they can be reloaded while the server runs. See [`RuleSet`](crate::rules::RuleSet).
+    pub rules: Rules,
+    /// Hosts the server answers for. Requests without a `Host` header get a
+    /// `400 Bad Request` and requests for other hosts a `421 Misdirected Request`.
+    /// A leading dot also allows the subdomains, `.example.com` allows `example.com`
+    /// and `api.example.com`. Empty allows every host.
+    pub allowed_hosts: Vec<String>,
+    /// Resolver of the location of the clients, looked up by
+    /// [`Context::geo`](crate::context::Context::geo). `None` by default.
+    pub geoip: Option<Arc<dyn GeoIpResolver>>,
+    /// Where abusive clients are sent to be answered extremely slowly. `None` by default.
+    pub tarpit: Option<Tarpit>,
+    /// Whether every request is sent to the server logger, scrubbed by `scrubber`.
+    pub access_log: bool,
+    /// Whether responses have an `X-Queue-Time` header with the milliseconds the
+    /// request waited for a worker, see [`Context::queue_time`](crate::context::Context::queue_time).
+    /// It's `0.000` for the requests kept alive after the first one of a connection.
+    /// Off by default.
+    pub queue_time_header: bool,
+    /// How the requests are written to the access log.
+    pub access_log_format: AccessLogFormat,
+    /// Redacts sensitive data from the requests written to the logs.
+    pub scrubber: Scrubber,
+    /// Filters deciding which connections are served, checked in order as soon as
+    /// they are accepted. Empty by default.
+    pub accept_filters: Vec<Arc<dyn AcceptFilter>>,
+    /// What happens to header lines without a colon or with an invalid name.
+    /// Folded lines are always rejected.
+    pub header_parsing: HeaderParsing,
+    /// How much the answers to requests that can't be parsed tell. `Detailed` by default.
+    pub parse_errors: ParseErrors,
+    /// Whether requests that look like an attack, like an ambiguous length meant to
+    /// smuggle a request past a proxy, get their connection closed without an answer.
+    /// Off by default.
+    pub drop_malicious_requests: bool,
+    /// Compression of the response bodies for the clients that accept it.
+    /// `None` by default.
+    pub compression: Option<Compression>,
+    /// Entity tags computed for the `200 OK` responses to `GET` requests that don't set
+    /// one. Requests whose `If-None-Match` matches the tag get a `304 Not Modified`.
+    /// `Off` by default.
+    pub etag: ETagMode,
+    /// Whether `ctx.json` indents its output, handy while debugging.
+    /// Compact by default.
+    pub pretty_json: bool,
+    /// Whether errors like a malformed request are answered as `application/problem+json`
+    /// instead of a json object with their message. Off by default.
+    pub problem_details: bool,
+    /// Builds the response of the errors the server answers by itself, like a malformed
+    /// request or a handler that panicked, instead of `problem_details` or the json
+    /// with their message. `None` by default.
+    pub error_renderer: Option<ErrorRenderer>,
+    /// Alternative services advertised in the `Alt-Svc` header of every response,
+    /// like an HTTP/3 endpoint in front of the server. `None` by default.
+    pub alt_svc: Option<AltSvc>,
+    /// Optional subsystems and whether the server starts without the ones that fail.
+    /// The disabled ones are logged when the server starts.
+    pub subsystems: Subsystems,
+    /// Prints what the server runs with when it starts, see
+    /// [`StartupReport`](crate::diagnostics::StartupReport). `None` by default.
+    pub startup_report: Option<ReportFormat>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            read_buffer_size: 8 * 1024,
+            header_buffer_size: 1024,
+            max_header_size: 32 * 1024,
+            max_body_size: 10 * 1024 * 1024,
+            header_read_timeout: Some(Duration::from_secs(10)),
+            body_read_timeout: Some(Duration::from_secs(30)),
+            write_timeout: Some(Duration::from_secs(30)),
+            keep_alive: true,
+            keep_alive_timeout: Duration::from_secs(5),
+            max_requests_per_connection: 100,
+            shutdown_timeout: Duration::from_secs(30),
+            cookie_keys: CookieKeys::default(),
+            cookie_policy: CookiePolicy::default(),
+            catalogs: Catalogs::default(),
+            trusted_proxies: Vec::new(),
+            rules: Rules::default(),
+            allowed_hosts: Vec::new(),
+            geoip: None,
+            tarpit: None,
+            access_log: false,
+            queue_time_header: false,
+            access_log_format: AccessLogFormat::default(),
+            scrubber: Scrubber::default(),
+            accept_filters: Vec::new(),
+            header_parsing: HeaderParsing::default(),
+            parse_errors: ParseErrors::default(),
+            drop_malicious_requests: false,
+            compression: None,
+            etag: ETagMode::default(),
+            pretty_json: false,
+            problem_details: false,
+            error_renderer: None,
+            alt_svc: None,
+            subsystems: Subsystems::default(),
+            startup_report: None,
+        }
+    }
+}
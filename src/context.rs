@@ -1,22 +1,163 @@
+use crate::api_err::ApiErr;
+use crate::bots::AgentClass;
+use crate::budget;
+use crate::config::ServerConfig;
+use crate::cookie::{Cookie, CookieJar};
+use crate::csv::CsvWriter;
+use crate::etag::{self, ETagMode};
+use crate::geoip::GeoInfo;
+use crate::headers;
+use crate::http_method::HttpMethod;
 use crate::http_request::HttpRequest;
+use crate::http_response::HttpResponse;
 use crate::http_status::HttpStatus;
+use crate::http_version::HttpVersion;
+use crate::links;
+use crate::mime;
+#[cfg(feature = "msgpack")]
+use crate::msgpack;
+use crate::negotiation::negotiate_media_type;
+use crate::patch::Patch;
+use crate::problem::{self, Problem};
+use crate::proxy;
+use crate::query;
+use crate::range::{self, ByteRange};
+use crate::router::Route;
+use crate::subsystems::Degraded;
+use crate::utils::http_date;
+use serde::de::DeserializeOwned;
 use serde_json::{json, Value};
-use std::any::TypeId;
+use std::any::{Any, TypeId};
+use std::cell::{OnceCell, RefCell};
 use std::collections::HashMap;
 use std::fmt::Display;
-use std::io;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::rc::Rc;
+use std::str::FromStr;
 use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::{Duration, Instant, UNIX_EPOCH};
 
 const HTTP_VERSION: &str = "HTTP/1.1";
 
 type Writer = dyn io::Write;
 
+pub(crate) fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
 pub struct Context {
     pub request: HttpRequest,
     pub logger: Option<Sender<String>>,
     writer: Box<Writer>,
     response_headers: HashMap<String, String>,
     pub(crate) path_params: HashMap<String, String>,
+    pub(crate) config: Arc<ServerConfig>,
+    cookies: OnceCell<CookieJar>,
+    pub(crate) remote_addr: Option<SocketAddr>,
+    pub(crate) tags: Vec<String>,
+    pub(crate) agent_class: Option<AgentClass>,
+    geo: OnceCell<Option<GeoInfo>>,
+    /// Response returned by the handler, sent once the response middlewares ran
+    pub(crate) response: Option<HttpResponse>,
+    /// Set when the connection can't serve another request after this response
+    pub(crate) close_connection: bool,
+    /// Error that stopped the response from being written
+    write_error: Option<io::Error>,
+    /// Set once the head of the response is written, later responses are ignored
+    committed: bool,
+    /// Set when the response was sent without its body, so streamed writes are dropped
+    body_skipped: bool,
+    /// Route that matched the request
+    pub(crate) route: Option<Route>,
+    /// Hypermedia links added to the json responses, see [`links`]
+    links: Vec<(String, String)>,
+    /// Open connections for each worker when the request was read, see
+    /// [`Compression::busy_level`](crate::compression::Compression::busy_level)
+    pub(crate) load: f64,
+    /// When the server started reading the request, the latency budget counts from it
+    pub(crate) received: Instant,
+    /// How long the request waited in the queue for a worker
+    pub(crate) queue_time: Duration,
+    /// Callbacks run once the response is sent, see [`Context::defer`]
+    deferred: Vec<Box<dyn FnOnce()>>,
+    /// Values middlewares pass to handlers, one of each type, see [`Context::set`]
+    extensions: HashMap<TypeId, Box<dyn Any>>,
+}
+
+impl Drop for Context {
+    /// Runs the deferred callbacks of a response that didn't run them yet
+    fn drop(&mut self) {
+        self.run_deferred();
+    }
+}
+
+/// Writes everything written to it as chunks of a `Transfer-Encoding: chunked` body.
+/// See [`Context::stream_with`].
+struct ChunkedWriter<'a> {
+    inner: &'a mut Writer,
+}
+
+impl ChunkedWriter<'_> {
+    /// Writes the last chunk and the trailer fields, ending the body
+    fn finish(self, trailers: &[(String, String)]) -> io::Result<()> {
+        let mut end = String::from("0\r\n");
+        for (name, value) in trailers {
+            end += &format!("{name}: {value}\r\n");
+        }
+        end += "\r\n";
+        self.inner.write_all(end.as_bytes())?;
+        self.inner.flush()
+    }
+}
+
+impl Write for ChunkedWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // An empty chunk would end the body
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut chunk = format!("{:X}\r\n", buf.len()).into_bytes();
+        chunk.extend_from_slice(buf);
+        chunk.extend_from_slice(b"\r\n");
+        self.inner.write_all(&chunk)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Keeps the response a context writes in memory, readable through any of its clones
+/// once the context is done. Sub-requests are answered into it, see
+/// [`Router::dispatch`](crate::router::Router::dispatch).
+#[derive(Clone, Default)]
+pub(crate) struct ResponseBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl ResponseBuffer {
+    /// Takes the bytes written so far, leaving the buffer empty
+    pub(crate) fn take(&self) -> Vec<u8> {
+        self.0.take()
+    }
+}
+
+impl Write for ResponseBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
 impl Context {
@@ -27,11 +168,36 @@ impl Context {
             writer: Box::new(writer),
             path_params: HashMap::new(),
             response_headers: HashMap::new(),
+            config: Arc::new(ServerConfig::default()),
+            cookies: OnceCell::new(),
+            remote_addr: None,
+            tags: Vec::new(),
+            agent_class: None,
+            geo: OnceCell::new(),
+            response: None,
+            close_connection: false,
+            write_error: None,
+            committed: false,
+            body_skipped: false,
+            route: None,
+            links: Vec::new(),
+            load: 0.0,
+            received: Instant::now(),
+            queue_time: Duration::ZERO,
+            deferred: Vec::new(),
+            extensions: HashMap::new(),
         }
     }
 
+    /// Sets a header of the response. A name or value with a CR or LF is dropped, as
+    /// it would let the client inject headers or a whole response
     pub fn add_response_header<K: Display, V: Display>(&mut self, k: K, v: V) {
-        self.response_headers.insert(k.to_string(), v.to_string());
+        let (name, value) = (k.to_string(), v.to_string());
+        if name.contains(['\r', '\n']) || value.contains(['\r', '\n']) {
+            return;
+        }
+        self.response_headers
+            .insert(headers::canonical_name(&name), value);
     }
 
     /// Send a json response to the client
@@ -42,51 +208,970 @@ impl Context {
     ///    "status": "200 OK",
     ///    "body": "Hello World"
     /// }
+    /// ```
+    /// The json is indented if [`ServerConfig::pretty_json`] is set.
     pub fn json<T: Display + 'static>(&mut self, status: HttpStatus, body: T) {
-        let mut r;
-        if TypeId::of::<T>() == TypeId::of::<Value>() {
-            r = body.to_string();
-        } else {
-            r = json!({"status": status.to_string(), "body": body.to_string()}).to_string()
+        let r = match (&body as &dyn Any).downcast_ref::<Value>() {
+            Some(value) => self.json_body(status, value, self.config.pretty_json),
+            None => {
+                let value = json!({"status": status.to_string(), "body": body.to_string()});
+                self.json_body(status, &value, self.config.pretty_json)
+            }
+        };
+
+        self.add_response_header("Content-Type", "application/json");
+        let result = self.send_response(status, r.as_bytes());
+        self.record_write(result)
+    }
+
+    /// Send the value as indented json, whatever the config says, for debugging endpoints
+    /// # Example
+    /// ```
+    /// use HTTP_Server::context::Context;
+    /// use HTTP_Server::http_status::HttpStatus;
+    /// use serde_json::json;
+    ///
+    /// fn debug_state(ctx: &mut Context) {
+    ///     ctx.json_pretty(HttpStatus::Ok, &json!({"workers": 4, "queued": 0}));
+    /// }
+    /// ```
+    pub fn json_pretty(&mut self, status: HttpStatus, value: &Value) {
+        let body = self.json_body(status, value, true);
+        self.add_response_header("Content-Type", "application/json");
+        let result = self.send_response(status, body.as_bytes());
+        self.record_write(result)
+    }
+
+    /// Serializes a json response, with the links of the request if it's a success
+    fn json_body(&self, status: HttpStatus, value: &Value, pretty: bool) -> String {
+        let linked = match status.is_success() {
+            true => links::inject(value, &self.links),
+            false => None,
+        };
+        let value = linked.as_ref().unwrap_or(value);
+        // The alternate format of a Value is indented
+        match pretty {
+            true => format!("{value:#}"),
+            false => value.to_string(),
         }
+    }
 
+    /// Send the value as json with an `ETag` of its serialization.
+    /// A `GET` whose `If-None-Match` has the same tag is answered with
+    /// `304 Not Modified` and no body, so polling clients only download changes.
+    /// # Example
+    /// ```
+    /// use HTTP_Server::context::Context;
+    /// use HTTP_Server::http_status::HttpStatus;
+    /// use serde_json::json;
+    ///
+    /// fn status(ctx: &mut Context) {
+    ///     ctx.json_cached(HttpStatus::Ok, &json!({"state": "running"}));
+    /// }
+    /// ```
+    pub fn json_cached(&mut self, status: HttpStatus, value: &Value) {
+        let body = self.json_body(status, value, self.config.pretty_json);
+        // The tag is of the bytes sent, so it changes with their formatting
+        self.add_response_header("ETag", etag::strong(body.as_bytes()));
         self.add_response_header("Content-Type", "application/json");
-        self.add_response_header("Content-Length", r.len());
-        self.send_response(status, &r)
+        let result = self.send_response(status, body.as_bytes());
+        self.record_write(result)
+    }
+
+    /// Send a response without a body, like a `202 Accepted` or a `204 No Content`
+    /// # Example
+    /// ```
+    /// use HTTP_Server::context::Context;
+    /// use HTTP_Server::http_status::HttpStatus;
+    ///
+    /// fn delete_user(ctx: &mut Context) {
+    ///     ctx.status(HttpStatus::NoContent);
+    /// }
+    /// ```
+    pub fn status(&mut self, status: HttpStatus) {
+        self.response_headers.remove("Content-Type");
+        let result = self.send_response(status, &[]);
+        self.record_write(result)
+    }
+
+    /// Send a `201 Created` json response, see [`Context::json`], with the url of the
+    /// new resource in the `Location` header, left out if it has a CR or LF
+    /// # Example
+    /// ```
+    /// use HTTP_Server::context::Context;
+    /// use serde_json::json;
+    ///
+    /// fn create_user(ctx: &mut Context) {
+    ///     ctx.created("/users/42", json!({"id": 42, "name": "alice"}));
+    /// }
+    /// ```
+    pub fn created<T: Display + 'static>(&mut self, location: &str, body: T) {
+        self.add_response_header("Location", location);
+        self.json(HttpStatus::Created, body)
     }
 
     /// Send a string response to the client
     pub fn string(&mut self, status: HttpStatus, body: &str) {
         self.add_response_header("Content-Type", "text/plain");
-        self.add_response_header("Content-Length", body.len());
-        self.send_response(status, body)
+        let result = self.send_response(status, body.as_bytes());
+        self.record_write(result)
+    }
+
+    /// Send a binary response to the client, like an image or a protobuf message.
+    /// A `GET` with a `Range` header gets only the bytes it asks for, see [`Context::file`]
+    pub fn bytes(&mut self, status: HttpStatus, content_type: &str, body: &[u8]) {
+        self.add_response_header("Content-Type", content_type);
+        self.add_response_header("Accept-Ranges", "bytes");
+        // The tag is of the whole body, not of the part that is sent
+        self.set_etag(status, body);
+        let result = match self.byte_range(status, body.len() as u64) {
+            ByteRange::Full => self.send_response(status, body),
+            ByteRange::Partial(first, last) => {
+                let part = &body[first as usize..=last as usize];
+                self.send_response(HttpStatus::PartialContent, part)
+            }
+            ByteRange::Unsatisfiable => self.send_response(HttpStatus::RangeNotSatisfiable, &[]),
+        };
+        self.record_write(result)
+    }
+
+    /// Send an html response to the client
+    pub fn html(&mut self, status: HttpStatus, body: &str) {
+        self.add_response_header("Content-Type", "text/html; charset=utf-8");
+        let result = self.send_response(status, body.as_bytes());
+        self.record_write(result)
+    }
+
+    /// Send the value as MessagePack, for bandwidth sensitive clients
+    #[cfg(feature = "msgpack")]
+    pub fn msgpack<T: serde::Serialize>(&mut self, status: HttpStatus, value: &T) {
+        match msgpack::to_vec(value) {
+            Ok(body) => self.bytes(status, msgpack::MEDIA_TYPE, &body),
+            Err(err) => self.send_error(&err),
+        }
+    }
+
+    /// Send an `application/problem+json` error with the title and the detail of this
+    /// occurrence. See [`Problem`] for the other members
+    /// # Example
+    /// ```
+    /// use HTTP_Server::context::Context;
+    /// use HTTP_Server::http_status::HttpStatus;
+    ///
+    /// fn transfer(ctx: &mut Context) {
+    ///     ctx.problem(HttpStatus::Conflict, "Insufficient funds", "The balance is 30, but the transfer is of 50.");
+    /// }
+    /// ```
+    pub fn problem(&mut self, status: HttpStatus, title: &str, detail: &str) {
+        self.send_problem(&Problem::new(status).title(title).detail(detail))
+    }
+
+    /// Send the problem as an `application/problem+json` body
+    pub fn send_problem(&mut self, problem: &Problem) {
+        let body = problem.to_value().to_string();
+        self.add_response_header("Content-Type", problem::MEDIA_TYPE);
+        let result = self.send_response(problem.status(), body.as_bytes());
+        self.record_write(result)
+    }
+
+    /// Send the error with the [`ServerConfig::error_renderer`] if there's one.
+    /// Otherwise it's sent with its status, as Problem Details if
+    /// [`ServerConfig::problem_details`] is set or as json with its message
+    pub fn send_error(&mut self, err: &ApiErr) {
+        if let Some(renderer) = self.config.error_renderer.clone() {
+            return self.send(renderer.render(err));
+        }
+        match self.config.problem_details {
+            true => self.send_problem(&err.to_problem()),
+            false => self.json(err.http_status(), err.to_value()),
+        }
+    }
+
+    /// Send the status of the error without its message, for the errors whose details
+    /// shouldn't be revealed to the client
+    pub(crate) fn send_generic_error(&mut self, err: &ApiErr) {
+        let status = err.http_status();
+        match self.config.problem_details {
+            true => self.send_problem(&Problem::new(status)),
+            false => {
+                let status_line = status.to_string();
+                let reason = status_line.split_once(' ').map_or("", |(_, reason)| reason);
+                self.json(status, json!({ "message": format!("{reason}.") }))
+            }
+        }
+    }
+
+    /// Send the value as json, plain text or html, whichever the client `Accept` header prefers.
+    /// With the `msgpack` feature, MessagePack is offered too, after the others.
+    /// Responds with `406 Not Acceptable` if the client accepts none of them
+    pub fn respond(&mut self, status: HttpStatus, value: &Value) {
+        let accept = self.header("Accept");
+        let offered = [
+            "application/json",
+            "text/plain",
+            "text/html",
+            #[cfg(feature = "msgpack")]
+            msgpack::MEDIA_TYPE,
+        ];
+        match negotiate_media_type(accept.as_deref(), &offered) {
+            #[cfg(feature = "msgpack")]
+            Some(msgpack::MEDIA_TYPE) => self.msgpack(status, value),
+            Some("text/plain") => match value {
+                Value::String(text) => self.string(status, text),
+                _ => self.string(status, &value.to_string()),
+            },
+            Some("text/html") => {
+                let body = match value {
+                    Value::String(text) => format!("<p>{}</p>", escape_html(text)),
+                    _ => format!(
+                        "<pre>{}</pre>",
+                        escape_html(&serde_json::to_string_pretty(value).unwrap_or_default())
+                    ),
+                };
+                self.html(
+                    status,
+                    &format!("<!DOCTYPE html>\n<html><body>{body}</body></html>"),
+                )
+            }
+            Some(_) => self.json(status, value.clone()),
+            None => self.string(HttpStatus::NotAcceptable, "Not Acceptable"),
+        }
+    }
+
+    /// Send a response built with [`HttpResponse`]
+    pub fn send(&mut self, response: HttpResponse) {
+        for (key, value) in response.headers() {
+            self.add_response_header(key, value);
+        }
+        let result = self.send_response(response.status(), response.body());
+        self.record_write(result)
+    }
+
+    /// Send a file from disk, with the `Content-Type` inferred from its extension.
+    /// The file is copied to the client as it's read, so it's never fully in memory.
+    /// Responds with `404 Not Found` if the file doesn't exist or is a directory
+    /// and with `403 Forbidden` if it can't be read.
+    ///
+    /// A `GET` with a `Range` header gets a `206 Partial Content` with the bytes it asks
+    /// for, or a `416 Range Not Satisfiable` if they start past the end of the file.
+    /// With an `If-Range` that no longer matches the file, the whole file is sent.
+    pub fn file<P: AsRef<Path>>(&mut self, status: HttpStatus, path: P) {
+        let path = path.as_ref();
+        let opened = File::open(path).and_then(|file| Ok((file.metadata()?, file)));
+        let (metadata, mut file) = match opened {
+            Ok((metadata, _)) if metadata.is_dir() => {
+                return self.string(HttpStatus::NotFound, "Not Found")
+            }
+            Ok(opened) => opened,
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                return self.string(HttpStatus::Forbidden, "Forbidden")
+            }
+            Err(_) => return self.string(HttpStatus::NotFound, "Not Found"),
+        };
+
+        self.add_response_header("Content-Type", mime::from_path(path));
+        if let Ok(modified) = metadata.modified() {
+            self.add_response_header("Last-Modified", http_date::format(modified));
+            if self.config.etag != ETagMode::Off {
+                // Files can be too big to hash, so their tag is made of their size and time
+                let seconds = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+                let tag = format!("W/\"{:x}-{:x}\"", metadata.len(), seconds.as_secs());
+                self.add_response_header("ETag", tag);
+            }
+        }
+        if self.is_not_modified(status) {
+            let result = self.send_response(HttpStatus::NotModified, &[]);
+            return self.record_write(result);
+        }
+        self.add_response_header("Accept-Ranges", "bytes");
+        let (status, first, len) = match self.byte_range(status, metadata.len()) {
+            ByteRange::Full => (status, 0, metadata.len()),
+            ByteRange::Partial(first, last) => {
+                (HttpStatus::PartialContent, first, last - first + 1)
+            }
+            ByteRange::Unsatisfiable => {
+                let result = self.send_response(HttpStatus::RangeNotSatisfiable, &[]);
+                return self.record_write(result);
+            }
+        };
+        if !self.commit(status) {
+            return;
+        }
+        self.add_response_header("Content-Length", len);
+        let head = self.response_head(status);
+        let sends_body = self.sends_body(status);
+        let result = file
+            .seek(SeekFrom::Start(first))
+            .and_then(|_| self.writer.write_all(&head))
+            .and_then(|_| match sends_body {
+                true => io::copy(&mut file.take(len), &mut self.writer).map(|_| ()),
+                false => Ok(()),
+            })
+            .and_then(|_| self.writer.flush());
+        self.record_write(result)
+    }
+
+    /// Send the body as it's read, without knowing its size, like a big export or
+    /// the output of a process. See [`Context::stream_with`].
+    pub fn stream<R: Read>(&mut self, status: HttpStatus, content_type: &str, mut body: R) {
+        self.stream_with(status, content_type, |writer| {
+            io::copy(&mut body, writer).map(|_| ())
+        })
+    }
+
+    /// Send a body written by the callback as it's generated, instead of buffering it.
+    /// Every write is sent as a chunk of a `Transfer-Encoding: chunked` response.
+    /// HTTP/1.0 clients don't understand chunks, so their body is sent as is and ended
+    /// by closing the connection.
+    /// If the callback fails the connection is closed without ending the body,
+    /// so the client knows the response is incomplete.
+    /// # Example
+    /// ```
+    /// use HTTP_Server::context::Context;
+    /// use HTTP_Server::http_status::HttpStatus;
+    /// use std::io::Write;
+    ///
+    /// fn export(ctx: &mut Context) {
+    ///     ctx.stream_with(HttpStatus::Ok, "text/csv", |writer| {
+    ///         for id in 0..1000 {
+    ///             writeln!(writer, "{id},user{id}")?;
+    ///         }
+    ///         Ok(())
+    ///     });
+    /// }
+    /// ```
+    pub fn stream_with<F>(&mut self, status: HttpStatus, content_type: &str, write_body: F)
+    where
+        F: FnOnce(&mut dyn Write) -> io::Result<()>,
+    {
+        self.stream_with_trailers(status, content_type, &[], |writer| {
+            write_body(writer).map(|_| Vec::new())
+        })
+    }
+
+    /// Stream a body like [`Context::stream_with`], followed by trailer fields whose
+    /// values are only known once it's written, like a checksum of the body.
+    /// The names are announced in the `Trailer` header and the callback returns their
+    /// values, fields it didn't announce aren't sent. HTTP/1.0 bodies aren't chunked,
+    /// so they get no trailers.
+    /// # Example
+    /// ```
+    /// use HTTP_Server::context::Context;
+    /// use HTTP_Server::http_status::HttpStatus;
+    /// use std::io::Write;
+    /// use std::time::Instant;
+    ///
+    /// fn report(ctx: &mut Context) {
+    ///     let start = Instant::now();
+    ///     ctx.stream_with_trailers(HttpStatus::Ok, "text/plain", &["Server-Timing"], |writer| {
+    ///         writeln!(writer, "a long report")?;
+    ///         let elapsed = start.elapsed().as_secs_f64() * 1000.0;
+    ///         Ok(vec![("Server-Timing".to_string(), format!("total;dur={elapsed:.1}"))])
+    ///     });
+    /// }
+    /// ```
+    pub fn stream_with_trailers<F>(
+        &mut self,
+        status: HttpStatus,
+        content_type: &str,
+        trailers: &[&str],
+        write_body: F,
+    ) where
+        F: FnOnce(&mut dyn Write) -> io::Result<Vec<(String, String)>>,
+    {
+        if !self.commit(status) {
+            return;
+        }
+        if !self.sends_body(status) {
+            let result = self
+                .stream_head(status, content_type)
+                .and_then(|_| self.writer.flush());
+            return self.record_write(result);
+        }
+        if !trailers.is_empty() && self.request.version != HttpVersion::Http10 {
+            self.add_response_header("Trailer", trailers.join(", "));
+        }
+        let result = self
+            .stream_head(status, content_type)
+            .and_then(|chunked| match chunked {
+                true => {
+                    let mut writer = ChunkedWriter {
+                        inner: &mut self.writer,
+                    };
+                    let mut values = write_body(&mut writer)?;
+                    values.retain(|(name, value)| {
+                        let announced = trailers.iter().any(|t| t.eq_ignore_ascii_case(name));
+                        announced && !value.contains(['\r', '\n'])
+                    });
+                    writer.finish(&values)
+                }
+                false => {
+                    write_body(&mut self.writer)?;
+                    self.writer.flush()
+                }
+            });
+        self.record_write(result)
+    }
+
+    /// Send the body as `text/csv` written record by record, like a big export.
+    /// The returned writer streams the rows as [`Context::stream_with`] does, and
+    /// [`CsvWriter::finish`] must be called to end the body
+    /// # Example
+    /// ```
+    /// use HTTP_Server::context::Context;
+    /// use HTTP_Server::http_status::HttpStatus;
+    ///
+    /// fn export(ctx: &mut Context) {
+    ///     let mut csv = ctx.csv(HttpStatus::Ok);
+    ///     let _ = csv.row(["id", "name"]);
+    ///     for id in 0..1000 {
+    ///         if csv.row([id.to_string(), format!("user, {id}")]).is_err() {
+    ///             return;
+    ///         }
+    ///     }
+    ///     let _ = csv.finish();
+    /// }
+    /// ```
+    pub fn csv(&mut self, status: HttpStatus) -> CsvWriter<'_> {
+        if !self.commit(status) {
+            return CsvWriter::new(self, false, true);
+        }
+        let result = self.stream_head(status, "text/csv; charset=utf-8");
+        let chunked = *result.as_ref().unwrap_or(&false);
+        let failed = self.stream_result(result.map(|_| ())).is_err();
+        CsvWriter::new(self, chunked, failed)
+    }
+
+    /// Sets the headers of a streamed response and writes its head, returning whether
+    /// its body is sent in chunks. Without a body, the later writes are dropped
+    fn stream_head(&mut self, status: HttpStatus, content_type: &str) -> io::Result<bool> {
+        self.add_response_header("Content-Type", content_type);
+        self.response_headers.remove("Content-Length");
+        let chunked = self.request.version != HttpVersion::Http10;
+        if !self.sends_body(status) {
+            self.body_skipped = true;
+        } else if chunked {
+            self.add_response_header("Transfer-Encoding", "chunked");
+        } else {
+            self.add_response_header("Connection", "close");
+            self.close_connection = true;
+        }
+
+        let head = self.response_head(status);
+        self.writer.write_all(&head)?;
+        Ok(chunked)
     }
 
-    fn send_response(&mut self, status: HttpStatus, body: &str) {
+    /// Writes part of the body of a streamed response, as a chunk if it's chunked.
+    /// An empty `data` on a chunked body ends it
+    pub(crate) fn write_stream(&mut self, chunked: bool, data: &[u8]) -> io::Result<()> {
+        if self.body_skipped {
+            return Ok(());
+        }
+        let result = match (chunked, data.is_empty()) {
+            (true, true) => ChunkedWriter {
+                inner: &mut self.writer,
+            }
+            .finish(&[]),
+            (true, false) => ChunkedWriter {
+                inner: &mut self.writer,
+            }
+            .write_all(data)
+            .and_then(|_| self.writer.flush()),
+            (false, _) => self
+                .writer
+                .write_all(data)
+                .and_then(|_| self.writer.flush()),
+        };
+        self.stream_result(result)
+    }
+
+    /// Keeps a copy of the error of a streamed write, returning it to the caller too
+    fn stream_result(&mut self, result: io::Result<()>) -> io::Result<()> {
+        if let Err(e) = &result {
+            self.record_write(Err(io::Error::new(e.kind(), e.to_string())));
+        }
+        result
+    }
+
+    /// Marks the response as sent, returning false if it already was, in which case
+    /// the new one must be dropped so two responses aren't written to the connection
+    fn commit(&mut self, status: HttpStatus) -> bool {
+        if self.committed {
+            if let Some(logger) = &self.logger {
+                _ = logger.send(format!("Response already sent, ignoring a {status}"));
+            }
+            return false;
+        }
+        self.committed = true;
+        true
+    }
+
+    /// Returns whether the response was already sent, so middlewares that run after
+    /// the handler know they can no longer change it
+    pub fn is_committed(&self) -> bool {
+        self.committed
+    }
+
+    /// Sends a `103 Early Hints` interim response with the headers, usually `Link`s to
+    /// preload, so the client can start fetching them while the handler still works on
+    /// the final response. Headers with line breaks are left out. Nothing is sent to
+    /// HTTP/1.0 clients, which don't expect interim responses, or once the response
+    /// was sent
+    /// # Example
+    /// ```
+    /// use HTTP_Server::context::Context;
+    /// use HTTP_Server::http_status::HttpStatus;
+    ///
+    /// fn page(ctx: &mut Context) {
+    ///     ctx.early_hints(&[
+    ///         ("Link", "</style.css>; rel=preload; as=style"),
+    ///         ("Link", "</app.js>; rel=preload; as=script"),
+    ///     ]);
+    ///     // Render the page...
+    ///     ctx.html(HttpStatus::Ok, "<html>...</html>");
+    /// }
+    /// ```
+    pub fn early_hints(&mut self, headers: &[(&str, &str)]) {
+        if self.committed || self.request.version == HttpVersion::Http10 {
+            return;
+        }
+        let mut response = format!("{HTTP_VERSION} 103 Early Hints\r\n");
+        for (name, value) in headers {
+            if !name.contains(['\r', '\n']) && !value.contains(['\r', '\n']) {
+                response += &format!("{}: {value}\r\n", headers::canonical_name(name));
+            }
+        }
+        response += "\r\n";
+        let result = self
+            .writer
+            .write_all(response.as_bytes())
+            .and_then(|_| self.writer.flush());
+        self.record_write(result);
+    }
+
+    /// Marks a streamed response as cut short, so the connection is closed without
+    /// ending its body and the client knows it's incomplete
+    pub(crate) fn abort_stream(&mut self) {
+        self.close_connection = true;
+    }
+
+    /// Returns the status line and headers of the response, ending with the blank line.
+    /// The headers are always in the same order: `Date` and `Server`, the `Content-*`
+    /// ones and then the rest, each group sorted by name
+    fn response_head(&self, status: HttpStatus) -> Vec<u8> {
         let mut response = format!("{HTTP_VERSION} {status}\r\n");
-        response += &self
-            .response_headers
+        let mut headers: Vec<(&String, &String)> = self.response_headers.iter().collect();
+        headers.sort_by_key(|(name, _)| {
+            let group = match name.as_str() {
+                "Date" => 0,
+                "Server" => 1,
+                name if name.starts_with("Content-") => 2,
+                _ => 3,
+            };
+            (group, name.as_str())
+        });
+        response += &headers
             .iter()
             .map(|(key, value)| format!("{}: {}\r\n", key, value))
             .collect::<String>();
+        if let Some(jar) = self.cookies.get() {
+            for cookie in jar.outgoing() {
+                response += &format!("Set-Cookie: {}\r\n", cookie);
+            }
+        }
 
         response += "\r\n";
+        response.into_bytes()
+    }
 
-        if let Some(size) = self.response_headers.get("Content-Length") {
-            if size != "0" {
-                response += body;
+    /// Returns whether the body of a response with the status is sent. Responses to a
+    /// `HEAD` and the statuses that can't have one are sent without it, whatever the
+    /// handler set
+    fn sends_body(&self, status: HttpStatus) -> bool {
+        status.allows_body() && self.request.method != HttpMethod::Head
+    }
+
+    /// Writes the response with the body, compressed if the config allows it,
+    /// and its `Content-Length`. The body of a `HEAD` is left out, but not its length
+    fn send_response(&mut self, status: HttpStatus, body: &[u8]) -> io::Result<()> {
+        if !self.commit(status) {
+            return Ok(());
+        }
+        self.set_etag(status, body);
+        let (status, body) = match self.is_not_modified(status) {
+            true => (HttpStatus::NotModified, &[][..]),
+            false => (status, body),
+        };
+        let encoded = self.compress(status, body);
+        let body = encoded.as_deref().unwrap_or(body);
+        // A 304 describes the body the client already has, so it has no length of its
+        // own, and a 204 can't have a body at all
+        if status.allows_body() {
+            self.add_response_header("Content-Length", body.len());
+        } else {
+            self.response_headers.remove("Content-Length");
+        }
+        let mut response = self.response_head(status);
+        if self.sends_body(status) {
+            response.extend_from_slice(body);
+        }
+
+        self.writer.write_all(&response)?;
+        self.writer.flush()
+    }
+
+    /// Returns the body compressed with the coding the client prefers, setting the
+    /// headers that describe it, or `None` if it's sent as is
+    fn compress(&mut self, status: HttpStatus, body: &[u8]) -> Option<Vec<u8>> {
+        let config = Arc::clone(&self.config);
+        let compression = config.compression.as_ref()?;
+        let content_type = self.response_headers.get("Content-Type")?;
+        // Ranges are of the body as it is, so parts aren't compressed
+        if matches!(
+            status,
+            HttpStatus::NoContent | HttpStatus::PartialContent | HttpStatus::RangeNotSatisfiable
+        ) || self.response_headers.contains_key("Content-Encoding")
+            || !compression.compresses(content_type)
+        {
+            return None;
+        }
+
+        let vary = match self.response_headers.get("Vary") {
+            Some(vary) if vary.to_ascii_lowercase().contains("accept-encoding") => vary.clone(),
+            Some(vary) => format!("{vary}, Accept-Encoding"),
+            None => "Accept-Encoding".to_string(),
+        };
+        self.add_response_header("Vary", vary);
+        let accept_encoding = self.header("Accept-Encoding");
+        let (coding, encoded) = compression.encode(accept_encoding.as_deref(), body, self.load)?;
+        self.add_response_header("Content-Encoding", coding);
+        // The compressed bytes differ from the ones the strong tag was computed for
+        if let Some(tag) = self.response_headers.get_mut("ETag") {
+            if !tag.starts_with("W/") {
+                tag.insert_str(0, "W/");
+            }
+        }
+        Some(encoded)
+    }
+
+    /// Sets the `ETag` of a response that doesn't have one, if the config asks for it
+    fn set_etag(&mut self, status: HttpStatus, body: &[u8]) {
+        if status != HttpStatus::Ok
+            || self.request.method.routed_as() != HttpMethod::Get
+            || self.response_headers.contains_key("ETag")
+        {
+            return;
+        }
+        match self.config.etag {
+            ETagMode::Off => {}
+            ETagMode::Strong => self.add_response_header("ETag", etag::strong(body)),
+            ETagMode::Weak => self.add_response_header("ETag", etag::weak(body)),
+        }
+    }
+
+    /// Returns whether the client of a `GET` already has the response, according to
+    /// the `If-None-Match` or, without it, the `If-Modified-Since` of the request
+    fn is_not_modified(&self, status: HttpStatus) -> bool {
+        if status != HttpStatus::Ok || self.request.method.routed_as() != HttpMethod::Get {
+            return false;
+        }
+        if let Some(if_none_match) = self.header("If-None-Match") {
+            return self
+                .response_headers
+                .get("ETag")
+                .is_some_and(|tag| etag::none_match_matches(&if_none_match, tag));
+        }
+        let since = self.header("If-Modified-Since");
+        let modified = self.response_headers.get("Last-Modified");
+        match (since.as_deref().and_then(http_date::parse), modified) {
+            (Some(since), Some(modified)) => {
+                http_date::parse(modified).is_some_and(|modified| modified <= since)
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the part of a body of `len` bytes the `Range` of the request asks for,
+    /// setting its `Content-Range`. Only a `200 OK` to a `GET` can be partial
+    fn byte_range(&mut self, status: HttpStatus, len: u64) -> ByteRange {
+        if status != HttpStatus::Ok
+            || self.request.method.routed_as() != HttpMethod::Get
+            || self.is_not_modified(status)
+            || !self.if_range_matches()
+        {
+            return ByteRange::Full;
+        }
+        let Some(header) = self.header("Range") else {
+            return ByteRange::Full;
+        };
+        let byte_range = range::parse(&header, len);
+        match byte_range {
+            ByteRange::Full => {}
+            ByteRange::Partial(first, last) => {
+                self.add_response_header("Content-Range", format!("bytes {first}-{last}/{len}"))
+            }
+            ByteRange::Unsatisfiable => {
+                self.add_response_header("Content-Range", format!("bytes */{len}"))
             }
         }
+        byte_range
+    }
 
-        if let Err(e) = self.writer.write(response.as_bytes()) {
-            println!("Error writing response: {}", e);
+    /// Returns whether the `If-Range` of the request, if any, is still the current
+    /// strong `ETag` or `Last-Modified` of the response
+    fn if_range_matches(&self) -> bool {
+        let Some(if_range) = self.header("If-Range") else {
+            return true;
+        };
+        let if_range = if_range.trim();
+        if if_range.starts_with('"') {
+            return self
+                .response_headers
+                .get("ETag")
+                .is_some_and(|tag| tag == if_range);
         }
+        let modified = self.response_headers.get("Last-Modified");
+        match (
+            http_date::parse(if_range),
+            modified.and_then(|m| http_date::parse(m)),
+        ) {
+            (Some(since), Some(modified)) => since == modified,
+            _ => false,
+        }
+    }
+
+    /// Keeps the error of a failed write, so the connection isn't reused
+    fn record_write(&mut self, result: io::Result<()>) {
+        if let Err(e) = result {
+            self.close_connection = true;
+            self.write_error = Some(e);
+        }
+    }
+
+    /// Run the callback after the response is sent to the client, for work like audit
+    /// logs or metrics that shouldn't delay it. Callbacks run in the order they were
+    /// added, before the next request of the connection is read.
+    /// # Example
+    /// ```
+    /// use HTTP_Server::context::Context;
+    /// use HTTP_Server::http_status::HttpStatus;
+    ///
+    /// fn delete_user(ctx: &mut Context) {
+    ///     let id = ctx.param("id").unwrap_or_default();
+    ///     ctx.string(HttpStatus::Ok, "Deleted");
+    ///     ctx.defer(move || println!("audit: deleted user {id}"));
+    /// }
+    /// ```
+    pub fn defer<F: FnOnce() + 'static>(&mut self, callback: F) {
+        self.deferred.push(Box::new(callback));
+    }
+
+    /// Runs the deferred callbacks, once the response is sent
+    pub(crate) fn run_deferred(&mut self) {
+        for callback in std::mem::take(&mut self.deferred) {
+            callback();
+        }
+    }
+
+    /// Returns the error that stopped the response from being fully written to the
+    /// client, like a closed connection. The connection is closed after the request.
+    pub fn write_error(&self) -> Option<&io::Error> {
+        self.write_error.as_ref()
+    }
+
+    /// Add a hypermedia link to the json responses, replacing the one with the same relation.
+    /// See [`links`] for the ones routes get from their metadata
+    pub fn link(&mut self, rel: &str, href: &str) {
+        self.remove_link(rel);
+        self.links.push((rel.to_string(), href.to_string()));
+    }
+
+    /// Remove a hypermedia link, like `next` on the last page, returning its url
+    pub fn remove_link(&mut self, rel: &str) -> Option<String> {
+        let index = self.links.iter().position(|(r, _)| r == rel)?;
+        Some(self.links.remove(index).1)
+    }
+
+    /// Returns the hypermedia links added to the json responses, as relation and url
+    pub fn links(&self) -> &[(String, String)] {
+        &self.links
+    }
+
+    /// Returns the route that matched the request, with its name and metadata.
+    /// `None` in the router middlewares, which run before routing
+    pub fn route(&self) -> Option<&Route> {
+        self.route.as_ref()
     }
 
     pub fn param(&self, key: &str) -> Option<String> {
         self.path_params.get(key).cloned()
     }
 
+    /// Returns the address of the peer that sent the request,
+    /// `None` when the context wasn't created from a tcp connection
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr
+    }
+
+    /// Returns the ip of the client, taken from the `Forwarded` or `X-Forwarded-For`
+    /// headers when the request comes from one of the trusted proxies of the config,
+    /// or the peer ip otherwise
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        let peer = self.remote_addr?.ip();
+        let trusted_proxies = &self.config.trusted_proxies;
+        Some(proxy::client_ip(
+            peer,
+            &self.request.headers,
+            trusted_proxies,
+        ))
+    }
+
+    /// Returns the tags the server rules added to the request
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    /// Store a value for the rest of the request, so middlewares can pass data like the
+    /// authenticated user to the handlers. There's one value of each type, setting
+    /// another returns the previous one.
+    /// # Example
+    /// ```
+    /// use HTTP_Server::context::Context;
+    /// use HTTP_Server::http_status::HttpStatus;
+    /// use HTTP_Server::router::Router;
+    ///
+    /// struct User(String);
+    ///
+    /// fn authenticate(ctx: &mut Context) -> bool {
+    ///     match ctx.request.headers.get("X-User").cloned() {
+    ///         Some(name) => {
+    ///             ctx.set(User(name));
+    ///             true
+    ///         }
+    ///         None => {
+    ///             ctx.string(HttpStatus::Forbidden, "Forbidden");
+    ///             false
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// fn profile(ctx: &mut Context) {
+    ///     let name = ctx.get::<User>().map(|user| user.0.clone()).unwrap_or_default();
+    ///     ctx.string(HttpStatus::Ok, &name);
+    /// }
+    ///
+    /// let mut router = Router::new();
+    /// router.use_middleware(authenticate).get("/profile", profile);
+    /// ```
+    pub fn set<T: 'static>(&mut self, value: T) -> Option<T> {
+        let previous = self.extensions.insert(TypeId::of::<T>(), Box::new(value))?;
+        previous.downcast().ok().map(|previous| *previous)
+    }
+
+    /// Returns the value of the type stored with [`Context::set`]
+    pub fn get<T: 'static>(&self) -> Option<&T> {
+        self.extensions.get(&TypeId::of::<T>())?.downcast_ref()
+    }
+
+    pub fn get_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        self.extensions.get_mut(&TypeId::of::<T>())?.downcast_mut()
+    }
+
+    /// Removes the value of the type, returning it
+    pub fn remove<T: 'static>(&mut self) -> Option<T> {
+        let value = self.extensions.remove(&TypeId::of::<T>())?;
+        value.downcast().ok().map(|value| *value)
+    }
+
+    /// Returns when the client stops waiting for the response, from the latency budget
+    /// of the [`X-Request-Timeout`](budget) header. `None` if the request has no budget
+    pub fn deadline(&self) -> Option<Instant> {
+        let budget = budget::parse(self.request.headers.get(budget::HEADER)?)?;
+        self.received.checked_add(budget)
+    }
+
+    /// Returns what's left of the latency budget of the request, zero once it's spent
+    pub fn remaining_budget(&self) -> Option<Duration> {
+        let deadline = self.deadline()?;
+        Some(deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Returns the header to add to the requests made to other services while handling
+    /// this one, carrying the remaining budget so they give up when the client does
+    /// # Example
+    /// ```
+    /// use HTTP_Server::context::Context;
+    /// use HTTP_Server::http_status::HttpStatus;
+    /// use std::time::Duration;
+    ///
+    /// fn search(ctx: &mut Context) {
+    ///     if ctx.remaining_budget() == Some(Duration::ZERO) {
+    ///         return ctx.string(HttpStatus::RequestTimeout, "Out of time");
+    ///     }
+    ///     let mut upstream = String::from("GET /index?q=rust HTTP/1.1\r\n");
+    ///     if let Some((name, value)) = ctx.budget_header() {
+    ///         upstream += &format!("{name}: {value}\r\n");
+    ///     }
+    ///     // ...
+    /// }
+    /// ```
+    pub fn budget_header(&self) -> Option<(&'static str, String)> {
+        let remaining = self.remaining_budget()?;
+        Some((budget::HEADER, budget::format(remaining)))
+    }
+
+    /// Returns the optional subsystems disabled because they failed to start,
+    /// see [`Subsystems`](crate::subsystems::Subsystems)
+    pub fn degraded(&self) -> Vec<Degraded> {
+        self.config.subsystems.degraded()
+    }
+
+    /// Returns how long the request waited in the queue for a worker before it was read.
+    /// Only the first request of a connection waits, later ones are read by the worker
+    /// that already has it and get zero. Compared with the time the handler takes, it
+    /// tells whether the server needs more workers or faster handlers. The histogram of
+    /// all of them is at [`Server::queue_times`](crate::server::Server::queue_times)
+    /// # Example
+    /// ```
+    /// use HTTP_Server::context::Context;
+    ///
+    /// fn record_queue_time(ctx: &mut Context) -> bool {
+    ///     let queue_time = ctx.queue_time();
+    ///     if let Some(logger) = ctx.logger.clone() {
+    ///         ctx.defer(move || {
+    ///             _ = logger.send(format!("queue_time_ms={}", queue_time.as_millis()));
+    ///         });
+    ///     }
+    ///     true
+    /// }
+    /// ```
+    pub fn queue_time(&self) -> Duration {
+        self.queue_time
+    }
+
+    /// Returns the kind of client that sent the request, set by the
+    /// [`BotClassifier`](crate::bots::BotClassifier) middleware
+    pub fn agent_class(&self) -> Option<AgentClass> {
+        self.agent_class
+    }
+
+    /// Returns the location of the client ip, resolved on the first call with the
+    /// [`GeoIpResolver`](crate::geoip::GeoIpResolver) of the config.
+    /// `None` if there's no resolver or it doesn't know the ip
+    pub fn geo(&self) -> Option<&GeoInfo> {
+        self.geo
+            .get_or_init(|| {
+                let resolver = self.config.geoip.as_ref()?;
+                resolver.lookup(self.client_ip()?)
+            })
+            .as_ref()
+    }
+
     pub fn header(&self, key: &str) -> Option<String> {
         self.request.headers.get(key).cloned()
     }
@@ -94,4 +1179,1099 @@ impl Context {
     pub fn body(&self) -> String {
         self.request.body.clone()
     }
+
+    /// Deserialize the json body of the request.
+    /// Fails if the request Content-Type isn't json, if the body isn't valid json (400)
+    /// or if it doesn't match the type (422)
+    pub fn bind_json<T: DeserializeOwned>(&self) -> Result<T, ApiErr> {
+        let media_type = self.media_type();
+        if media_type != "application/json" && !media_type.ends_with("+json") {
+            return Err(ApiErr::MediaTypeNotSupported);
+        }
+
+        serde_json::from_str(&self.request.body).map_err(ApiErr::InvalidJson)
+    }
+
+    /// Deserialize the MessagePack body of the request.
+    /// Fails if the request Content-Type isn't MessagePack or if the body isn't valid
+    /// MessagePack for the type (400)
+    #[cfg(feature = "msgpack")]
+    pub fn bind_msgpack<T: DeserializeOwned>(&self) -> Result<T, ApiErr> {
+        let media_type = self.media_type();
+        let accepted = [
+            msgpack::MEDIA_TYPE,
+            "application/x-msgpack",
+            "application/vnd.msgpack",
+        ];
+        if !accepted.contains(&media_type.as_str()) {
+            return Err(ApiErr::MediaTypeNotSupported);
+        }
+        msgpack::from_slice(self.request.body_bytes())
+    }
+
+    /// Parses the body of a `PATCH` request, a JSON Patch or a JSON Merge Patch
+    /// according to its Content-Type. See [`Patch::parse`] for the errors
+    pub fn patch(&self) -> Result<Patch, ApiErr> {
+        if self.request.method != HttpMethod::Patch {
+            return Err(ApiErr::InvalidMethod);
+        }
+        Patch::parse(&self.media_type(), &self.request.body)
+    }
+
+    /// Applies the patch of the request to the document, returning the patched one
+    /// # Example
+    /// ```
+    /// use HTTP_Server::context::Context;
+    /// use HTTP_Server::headers::Headers;
+    /// use HTTP_Server::http_method::HttpMethod;
+    /// use HTTP_Server::http_request::HttpRequest;
+    /// use serde_json::json;
+    ///
+    /// let mut headers = Headers::new();
+    /// headers.insert("Content-Type", "application/merge-patch+json");
+    /// let mut ctx = Context::new(Vec::new());
+    /// ctx.request = HttpRequest::new(HttpMethod::Patch, "/users/1".into(), headers, r#"{"age":31}"#.into());
+    /// let user = ctx.apply_patch(json!({"name": "alice", "age": 30})).unwrap();
+    /// assert_eq!(user, json!({"name": "alice", "age": 31}));
+    /// ```
+    pub fn apply_patch(&self, document: Value) -> Result<Value, ApiErr> {
+        self.patch()?.apply(document)
+    }
+
+    /// Returns the lowercase media type of the request Content-Type, without parameters
+    fn media_type(&self) -> String {
+        let content_type = self.header("Content-Type").unwrap_or_default();
+        content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase()
+    }
+
+    /// Returns the first value of the query parameter, `None` if it's missing or malformed
+    pub fn query(&self, name: &str) -> Option<String> {
+        let pairs = query::parse(self.request.query()?).ok()?;
+        pairs
+            .into_iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Parses the first value of the query parameter.
+    /// Fails with `ApiErr::InvalidQuery` (400) if it can't be parsed into the type
+    /// # Example
+    /// ```
+    /// use HTTP_Server::context::Context;
+    /// use HTTP_Server::headers::Headers;
+    /// use HTTP_Server::http_method::HttpMethod;
+    /// use HTTP_Server::http_request::HttpRequest;
+    ///
+    /// let mut ctx = Context::new(Vec::new());
+    /// ctx.request = HttpRequest::new(HttpMethod::Get, "/posts?page=2".into(), Headers::new(), "".into());
+    /// assert_eq!(ctx.query_as::<u32>("page").unwrap(), Some(2));
+    /// assert_eq!(ctx.query_as::<u32>("per_page").unwrap(), None);
+    /// ```
+    pub fn query_as<T: FromStr>(&self, name: &str) -> Result<Option<T>, ApiErr> {
+        let Some(query) = self.request.query() else {
+            return Ok(None);
+        };
+        let pairs = query::parse(query)?;
+        match pairs.into_iter().find(|(key, _)| key == name) {
+            Some((_, value)) => match value.parse() {
+                Ok(value) => Ok(Some(value)),
+                Err(_) => Err(ApiErr::InvalidQuery(format!(
+                    "invalid value `{value}` for {name}"
+                ))),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Deserialize the query of the request, like a pagination or filter struct.
+    /// Fails with `ApiErr::InvalidQuery` (400) if it doesn't match the type,
+    /// see [`query::deserialize`]
+    pub fn bind_query<T: DeserializeOwned>(&self) -> Result<T, ApiErr> {
+        query::deserialize(self.request.query().unwrap_or_default())
+    }
+
+    /// Returns the cookies sent with the request and the ones added to the response
+    pub fn cookies(&self) -> &CookieJar {
+        self.cookies.get_or_init(|| {
+            CookieJar::new(
+                self.request.headers.get("Cookie").map(|h| h.as_str()),
+                self.config.cookie_keys.clone(),
+                self.config.cookie_policy,
+            )
+        })
+    }
+
+    pub fn cookies_mut(&mut self) -> &mut CookieJar {
+        self.cookies();
+        self.cookies.get_mut().expect("cookie jar is initialized")
+    }
+
+    pub fn cookie(&self, name: &str) -> Option<String> {
+        self.cookies().get(name).map(|v| v.to_string())
+    }
+
+    /// Returns the value of a cookie set with `set_signed_cookie`,
+    /// or `None` if it's missing or its signature doesn't match
+    pub fn signed_cookie(&self, name: &str) -> Option<String> {
+        self.cookies().get_signed(name)
+    }
+
+    /// Returns the value of a cookie set with `set_private_cookie`,
+    /// or `None` if it's missing or was tampered with
+    pub fn private_cookie(&self, name: &str) -> Option<String> {
+        self.cookies().get_private(name)
+    }
+
+    /// Add a cookie to the response, with the server cookie policy applied to it
+    pub fn set_cookie(&mut self, cookie: Cookie) -> Result<(), ApiErr> {
+        self.cookies_mut().add(cookie)
+    }
+
+    /// Add a cookie to the response signed with the server cookie keys, so the client
+    /// can read it but not modify it.
+    /// Fails if the server has no cookie keys
+    pub fn set_signed_cookie(&mut self, cookie: Cookie) -> Result<(), ApiErr> {
+        self.cookies_mut().add_signed(cookie)
+    }
+
+    /// Add a cookie to the response encrypted with the server cookie keys, so the client
+    /// can neither read nor modify it.
+    /// Fails if the server has no cookie keys
+    pub fn set_private_cookie(&mut self, cookie: Cookie) -> Result<(), ApiErr> {
+        self.cookies_mut().add_private(cookie)
+    }
+
+    /// Returns the locale of the server catalogs the client prefers according to its
+    /// `Accept-Language` header, or the default locale if it accepts none of them
+    pub fn locale(&self) -> Option<String> {
+        let accept_language = self.request.headers.get("Accept-Language");
+        let catalogs = &self.config.catalogs;
+        catalogs
+            .negotiate(accept_language.map(|h| h.as_str()))
+            .map(|locale| locale.to_string())
+    }
+
+    /// Set the `Content-Language` header of the response
+    pub fn set_content_language(&mut self, locale: &str) {
+        self.add_response_header("Content-Language", locale);
+    }
+
+    /// Returns the message in the negotiated locale, or the key itself if it's missing
+    pub fn t(&self, key: &str) -> String {
+        self.t_with(key, &[])
+    }
+
+    /// Returns the message in the negotiated locale with its `{ $name }` placeables
+    /// replaced by the args, or the key itself if it's missing
+    /// # Example
+    /// ```
+    /// use HTTP_Server::context::Context;
+    /// use HTTP_Server::http_status::HttpStatus;
+    ///
+    /// fn handler(ctx: &mut Context) {
+    ///     let greeting = ctx.t_with("welcome", &[("name", "Ana")]);
+    ///     if let Some(locale) = ctx.locale() {
+    ///         ctx.set_content_language(&locale);
+    ///     }
+    ///     ctx.string(HttpStatus::Ok, &greeting);
+    /// }
+    /// ```
+    pub fn t_with(&self, key: &str, args: &[(&str, &str)]) -> String {
+        self.locale()
+            .and_then(|locale| self.config.catalogs.message(&locale, key, args))
+            .unwrap_or_else(|| key.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::Compression;
+    use crate::cookie::{CookieKey, CookieKeys};
+    use crate::geoip::RangeResolver;
+    use crate::headers::Headers;
+    use crate::http_method::HttpMethod;
+    use crate::localization::Catalogs;
+    use crate::utils::inflate::gunzip;
+    use crate::utils::mock_stream::{MockTcpStream, MockWriter};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn context_with_cookies(header: &str) -> Context {
+        let mut ctx = Context::new(MockTcpStream {
+            read_data: vec![],
+            position: 0,
+            write_data: vec![],
+        });
+        let mut headers = Headers::new();
+        headers.insert("Cookie", header);
+        ctx.request = HttpRequest::new(HttpMethod::Get, "/".into(), headers, "".into());
+        ctx.config = Arc::new(ServerConfig {
+            cookie_keys: CookieKeys::new(CookieKey::from_secret(b"secret")),
+            ..ServerConfig::default()
+        });
+        ctx
+    }
+
+    fn context_with_body(content_type: &str, body: &str) -> Context {
+        let mut ctx = Context::new(Vec::new());
+        let mut headers = Headers::new();
+        headers.insert("Content-Type", content_type);
+        ctx.request = HttpRequest::new(HttpMethod::Post, "/".into(), headers, body.into());
+        ctx
+    }
+
+    #[test]
+    fn test_bind_json() {
+        let ctx = context_with_body("application/json; charset=utf-8", r#"{"a": 1, "b": 2}"#);
+        let body: HashMap<String, i32> = ctx.bind_json().unwrap();
+        assert_eq!(body.get("a"), Some(&1));
+        assert_eq!(body.get("b"), Some(&2));
+
+        let ctx = context_with_body("application/problem+json", r#"{"a": 1}"#);
+        assert!(ctx.bind_json::<HashMap<String, i32>>().is_ok());
+    }
+
+    #[test]
+    fn test_bind_json_wrong_content_type() {
+        let ctx = context_with_body("text/plain", r#"{"a": 1}"#);
+        let err = ctx.bind_json::<HashMap<String, i32>>().unwrap_err();
+        assert!(matches!(err, ApiErr::MediaTypeNotSupported));
+    }
+
+    #[test]
+    fn test_bind_json_invalid_body() {
+        let ctx = context_with_body("application/json", r#"{"a": 1"#);
+        let err = ctx.bind_json::<HashMap<String, i32>>().unwrap_err();
+        assert_eq!(err.http_status(), HttpStatus::BadRequest);
+
+        let ctx = context_with_body("application/json", r#"{"a": "one"}"#);
+        let err = ctx.bind_json::<HashMap<String, i32>>().unwrap_err();
+        assert_eq!(err.http_status(), HttpStatus::UnprocessableEntity);
+    }
+
+    fn respond_with_accept(accept: Option<&str>, value: Value) -> String {
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        let mut headers = Headers::new();
+        if let Some(accept) = accept {
+            headers.insert("Accept", accept);
+        }
+        ctx.request = HttpRequest::new(HttpMethod::Get, "/".into(), headers, "".into());
+        ctx.respond(HttpStatus::Ok, &value);
+        writer.contents()
+    }
+
+    #[test]
+    fn test_respond_negotiates_content_type() {
+        let response = respond_with_accept(None, json!({"a": 1}));
+        assert!(response.contains("Content-Type: application/json"));
+        assert!(response.ends_with(r#"{"a":1}"#));
+
+        let response = respond_with_accept(Some("text/plain"), json!("hello"));
+        assert!(response.contains("Content-Type: text/plain"));
+        assert!(response.ends_with("\r\n\r\nhello"));
+
+        let response = respond_with_accept(Some("text/html,*/*;q=0.8"), json!("<b>"));
+        assert!(response.contains("Content-Type: text/html"));
+        assert!(response.contains("<p>&lt;b&gt;</p>"));
+    }
+
+    #[test]
+    fn test_bytes() {
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        let png = [0x89, b'P', b'N', b'G', 0x00, 0xff];
+        ctx.bytes(HttpStatus::Ok, "image/png", &png);
+        let response = writer.bytes();
+        assert!(response.ends_with(&png));
+        assert!(writer.contents().contains("Content-Length: 6\r\n"));
+    }
+
+    #[test]
+    fn test_file() {
+        let dir = std::env::temp_dir().join(format!("ctx-file-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let icon = dir.join("favicon.ico");
+        std::fs::write(&icon, [0, 0, 1, 0]).unwrap();
+
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.file(HttpStatus::Ok, &icon);
+        let response = writer.contents();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Type: image/x-icon\r\n"));
+        assert!(response.contains("Content-Length: 4\r\n"));
+        assert!(writer.bytes().ends_with(&[0, 0, 1, 0]));
+
+        for missing in [dir.join("missing.ico"), dir.clone()] {
+            let writer = MockWriter::default();
+            Context::new(writer.clone()).file(HttpStatus::Ok, missing);
+            assert!(writer.contents().starts_with("HTTP/1.1 404 Not Found"));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_stream() {
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.stream(HttpStatus::Ok, "text/plain", "hello".as_bytes());
+        let response = writer.contents();
+        assert!(response.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(!response.contains("Content-Length"));
+        assert!(response.ends_with("\r\n\r\n5\r\nhello\r\n0\r\n\r\n"));
+        assert!(!ctx.close_connection);
+    }
+
+    #[test]
+    fn test_stream_with() {
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.stream_with(HttpStatus::Ok, "text/csv", |w| {
+            w.write_all(b"a,b\n")?;
+            w.write_all(b"")?;
+            w.write_all(b"1,2\n")
+        });
+        assert!(writer
+            .contents()
+            .ends_with("4\r\na,b\n\r\n4\r\n1,2\n\r\n0\r\n\r\n"));
+
+        // A failed body is left unterminated
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.stream_with(HttpStatus::Ok, "text/csv", |w| {
+            w.write_all(b"a,b\n")?;
+            Err(io::ErrorKind::Other.into())
+        });
+        assert!(writer.contents().ends_with("4\r\na,b\n\r\n"));
+        assert!(ctx.close_connection);
+    }
+
+    #[test]
+    fn test_stream_http10() {
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.request.version = HttpVersion::Http10;
+        ctx.stream(HttpStatus::Ok, "text/plain", "hello".as_bytes());
+        let response = writer.contents();
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(!response.contains("Transfer-Encoding"));
+        assert!(response.ends_with("\r\n\r\nhello"));
+        assert!(ctx.close_connection);
+    }
+
+    struct BrokenWriter;
+
+    impl Write for BrokenWriter {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_error() {
+        let mut ctx = Context::new(MockWriter::default());
+        ctx.string(HttpStatus::Ok, "ok");
+        assert!(ctx.write_error().is_none());
+        assert!(!ctx.close_connection);
+
+        let mut ctx = Context::new(BrokenWriter);
+        ctx.string(HttpStatus::Ok, "ok");
+        let err = ctx.write_error().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        assert!(ctx.close_connection);
+    }
+
+    fn compressed_response(accept_encoding: Option<&str>, content_type: &str) -> MockWriter {
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.config = Arc::new(ServerConfig {
+            compression: Some(Compression::default().min_size(16)),
+            ..ServerConfig::default()
+        });
+        if let Some(accept_encoding) = accept_encoding {
+            ctx.request
+                .headers
+                .insert("Accept-Encoding", accept_encoding);
+        }
+        ctx.add_response_header("Vary", "Origin");
+        ctx.bytes(HttpStatus::Ok, content_type, &[b'a'; 100]);
+        writer
+    }
+
+    #[test]
+    fn test_compression() {
+        let writer = compressed_response(Some("gzip, deflate"), "text/plain");
+        let response = writer.contents();
+        assert!(response.contains("Content-Encoding: gzip\r\n"));
+        assert!(response.contains("Vary: Origin, Accept-Encoding\r\n"));
+        let bytes = writer.bytes();
+        let body_start = response.find("\r\n\r\n").unwrap() + 4;
+        let body = &bytes[body_start..];
+        assert!(response.contains(&format!("Content-Length: {}\r\n", body.len())));
+        assert_eq!(gunzip(body, 1000).unwrap(), [b'a'; 100]);
+
+        // Not accepted by the client, but the response still varies
+        let response = compressed_response(None, "text/plain").contents();
+        assert!(!response.contains("Content-Encoding"));
+        assert!(response.contains("Vary: Origin, Accept-Encoding\r\n"));
+        assert!(response.contains("Content-Length: 100\r\n"));
+
+        let response = compressed_response(Some("gzip"), "image/png").contents();
+        assert!(!response.contains("Content-Encoding"));
+        assert!(response.contains("Vary: Origin\r\n"));
+    }
+
+    #[test]
+    fn test_json_cached() {
+        let value = json!({"state": "running"});
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.json_cached(HttpStatus::Ok, &value);
+        let response = writer.contents();
+        let tag = etag::strong(value.to_string().as_bytes());
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains(&format!("ETag: {tag}\r\n")));
+        assert!(response.ends_with(r#"{"state":"running"}"#));
+
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.request
+            .headers
+            .insert("If-None-Match", &format!("\"old\", W/{tag}"));
+        ctx.json_cached(HttpStatus::Ok, &value);
+        let response = writer.contents();
+        assert!(response.starts_with("HTTP/1.1 304 Not Modified"));
+        assert!(response.contains(&format!("ETag: {tag}\r\n")));
+        assert!(!response.contains("Content-Length"));
+        assert!(response.ends_with("\r\n\r\n"));
+
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.request.headers.insert("If-None-Match", "\"old\"");
+        ctx.json_cached(HttpStatus::Ok, &value);
+        assert!(writer.contents().starts_with("HTTP/1.1 200 OK"));
+
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.config = Arc::new(ServerConfig {
+            pretty_json: true,
+            ..ServerConfig::default()
+        });
+        ctx.json_cached(HttpStatus::Ok, &value);
+        let response = writer.contents();
+        let body = format!("{value:#}");
+        assert!(response.ends_with(&body));
+        let pretty_tag = etag::strong(body.as_bytes());
+        assert_ne!(pretty_tag, tag);
+        assert!(response.contains(&format!("ETag: {pretty_tag}\r\n")));
+    }
+
+    fn get_with(header: Option<(&str, &str)>, etag: ETagMode) -> (Context, MockWriter) {
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.config = Arc::new(ServerConfig {
+            etag,
+            ..ServerConfig::default()
+        });
+        if let Some((key, value)) = header {
+            ctx.request.headers.insert(key, value);
+        }
+        (ctx, writer)
+    }
+
+    #[test]
+    fn test_etag() {
+        let tag = etag::strong(b"hello");
+        let (mut ctx, writer) = get_with(None, ETagMode::Strong);
+        ctx.string(HttpStatus::Ok, "hello");
+        assert!(writer.contents().contains(&format!("ETag: {tag}\r\n")));
+
+        let (mut ctx, writer) = get_with(Some(("If-None-Match", &tag)), ETagMode::Weak);
+        ctx.string(HttpStatus::Ok, "hello");
+        assert!(writer.contents().starts_with("HTTP/1.1 304 Not Modified"));
+        assert!(writer.contents().contains(&format!("ETag: W/{tag}\r\n")));
+
+        // Set by the handler
+        let (mut ctx, writer) = get_with(Some(("If-None-Match", "\"v2\"")), ETagMode::Off);
+        ctx.add_response_header("ETag", "\"v2\"");
+        ctx.string(HttpStatus::Ok, "hello");
+        assert!(writer.contents().starts_with("HTTP/1.1 304 Not Modified"));
+
+        let (mut ctx, writer) = get_with(None, ETagMode::Off);
+        ctx.string(HttpStatus::Ok, "hello");
+        assert!(!writer.contents().contains("ETag"));
+    }
+
+    #[test]
+    fn test_json_pretty() {
+        let value = json!({"a": [1, 2]});
+        let pretty = "{\n  \"a\": [\n    1,\n    2\n  ]\n}";
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.json_pretty(HttpStatus::Ok, &value);
+        assert!(writer.contents().ends_with(&format!("\r\n\r\n{pretty}")));
+
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.config = Arc::new(ServerConfig {
+            pretty_json: true,
+            ..ServerConfig::default()
+        });
+        let config = Arc::clone(&ctx.config);
+        ctx.json(HttpStatus::Ok, value.clone());
+        assert!(writer.contents().ends_with(&format!("\r\n\r\n{pretty}")));
+
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.config = config;
+        ctx.json(HttpStatus::Ok, "hi");
+        assert!(writer.contents().ends_with("\n  \"status\": \"200 OK\"\n}"));
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn test_msgpack() {
+        let mut headers = Headers::new();
+        headers.insert("Content-Type", "application/msgpack");
+        headers.insert("Accept", "application/msgpack");
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.request = HttpRequest::new(HttpMethod::Post, "/".into(), headers, "".into());
+        ctx.request.body_bytes = b"\x81\xa2id\x07".to_vec();
+        let body: Value = ctx.bind_msgpack().unwrap();
+        assert_eq!(body, json!({"id": 7}));
+
+        ctx.respond(HttpStatus::Ok, &body);
+        let response = writer.bytes();
+        assert!(writer
+            .contents()
+            .contains("Content-Type: application/msgpack\r\n"));
+        assert!(response.ends_with(b"\r\n\r\n\x81\xa2id\x07"));
+
+        ctx.request
+            .headers
+            .insert("Content-Type", "application/json");
+        assert!(matches!(
+            ctx.bind_msgpack::<Value>(),
+            Err(ApiErr::MediaTypeNotSupported)
+        ));
+    }
+
+    #[test]
+    fn test_send_error() {
+        let err = ApiErr::InvalidQuery("bad page".into());
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.send_error(&err);
+        assert!(writer.contents().starts_with("HTTP/1.1 400 Bad Request"));
+        assert!(writer
+            .contents()
+            .ends_with(r#"{"message":"Invalid query: bad page."}"#));
+
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.config = Arc::new(ServerConfig {
+            problem_details: true,
+            ..ServerConfig::default()
+        });
+        ctx.send_error(&err);
+        let response = writer.contents();
+        assert!(response.contains("Content-Type: application/problem+json\r\n"));
+        let body: Value = serde_json::from_str(response.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "type": "about:blank",
+                "title": "Bad Request",
+                "status": 400,
+                "detail": "Invalid query: bad page.",
+            })
+        );
+
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.problem(HttpStatus::Conflict, "Taken", "The name is taken.");
+        assert!(writer.contents().starts_with("HTTP/1.1 409 Conflict"));
+        assert!(writer.contents().contains(r#""title":"Taken""#));
+    }
+
+    #[test]
+    fn test_response_head_order() {
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.add_response_header("x-trace", "1");
+        ctx.add_response_header("server", "test");
+        ctx.add_response_header("cache-control", "no-store");
+        ctx.add_response_header("Content-Language", "en");
+        ctx.add_response_header("CONTENT-language", "es");
+        ctx.string(HttpStatus::Ok, "hi");
+        assert_eq!(
+            writer.contents(),
+            "HTTP/1.1 200 OK\r\nServer: test\r\nContent-Language: es\r\nContent-Length: 2\r\n\
+             Content-Type: text/plain\r\nCache-Control: no-store\r\nX-Trace: 1\r\n\r\nhi"
+        );
+    }
+
+    #[test]
+    fn test_early_hints() {
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.early_hints(&[("link", "</a.css>; rel=preload"), ("X-Bad", "a\r\nb")]);
+        ctx.string(HttpStatus::Ok, "hi");
+        ctx.early_hints(&[("Link", "</b.css>; rel=preload")]);
+        let response = writer.contents();
+        assert!(response.starts_with(
+            "HTTP/1.1 103 Early Hints\r\nLink: </a.css>; rel=preload\r\n\r\nHTTP/1.1 200 OK\r\n"
+        ));
+        assert!(!response.contains("X-Bad") && !response.contains("b.css"));
+
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.request.version = HttpVersion::Http10;
+        ctx.early_hints(&[("Link", "</a.css>; rel=preload")]);
+        assert_eq!(writer.contents(), "");
+    }
+
+    #[test]
+    fn test_budget() {
+        let mut ctx = Context::new(MockWriter::default());
+        assert_eq!(ctx.remaining_budget(), None);
+        assert_eq!(ctx.budget_header(), None);
+
+        ctx.request.headers.insert("X-Request-Timeout", "10");
+        let remaining = ctx.remaining_budget().unwrap();
+        assert!(remaining > Duration::from_secs(9) && remaining <= Duration::from_secs(10));
+        let (name, value) = ctx.budget_header().unwrap();
+        assert_eq!(name, "X-Request-Timeout");
+        assert!(budget::parse(&value).unwrap() <= remaining);
+
+        ctx.request.headers.insert("X-Request-Timeout", "0.5");
+        ctx.received = Instant::now() - Duration::from_secs(1);
+        assert_eq!(ctx.remaining_budget(), Some(Duration::ZERO));
+        assert_eq!(ctx.budget_header().unwrap().1, "0.000");
+
+        ctx.request.headers.insert("X-Request-Timeout", "soon");
+        assert_eq!(ctx.deadline(), None);
+    }
+
+    #[test]
+    fn test_stream_trailers() {
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.stream_with_trailers(
+            HttpStatus::Ok,
+            "text/plain",
+            &["Digest", "Server-Timing"],
+            |w| {
+                w.write_all(b"hello")?;
+                Ok(vec![
+                    ("digest".to_string(), "crc32=3610a686".to_string()),
+                    ("X-Other".to_string(), "1".to_string()),
+                    ("Server-Timing".to_string(), "a\r\nInjected: 1".to_string()),
+                ])
+            },
+        );
+        let response = writer.contents();
+        assert!(response.contains("Trailer: Digest, Server-Timing\r\n"));
+        assert!(response.ends_with("\r\n\r\n5\r\nhello\r\n0\r\ndigest: crc32=3610a686\r\n\r\n"));
+
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.request.version = HttpVersion::Http10;
+        ctx.stream_with_trailers(HttpStatus::Ok, "text/plain", &["Digest"], |w| {
+            w.write_all(b"hello")?;
+            Ok(vec![("Digest".to_string(), "x".to_string())])
+        });
+        let response = writer.contents();
+        assert!(!response.contains("Trailer"));
+        assert!(response.ends_with("\r\n\r\nhello"));
+    }
+
+    #[test]
+    fn test_status_and_created() {
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.status(HttpStatus::Accepted);
+        assert!(writer
+            .contents()
+            .starts_with("HTTP/1.1 202 Accepted\r\nContent-Length: 0\r\n\r\n"));
+
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.status(HttpStatus::NoContent);
+        assert_eq!(writer.contents(), "HTTP/1.1 204 No Content\r\n\r\n");
+
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.created("/users/1", json!({"id": 1}));
+        let response = writer.contents();
+        assert!(response.starts_with("HTTP/1.1 201 Created\r\n"));
+        assert!(response.contains("Location: /users/1\r\n"));
+        assert!(response.ends_with("\r\n\r\n{\"id\":1}"));
+
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.created("/users/1\r\nSet-Cookie: session=evil", json!({"id": 1}));
+        let response = writer.contents();
+        assert!(response.starts_with("HTTP/1.1 201 Created\r\n"));
+        assert!(!response.contains("Location"));
+        assert!(!response.contains("Set-Cookie"));
+    }
+
+    #[test]
+    fn test_add_response_header_drops_line_breaks() {
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.add_response_header("X-Ok", "value");
+        ctx.add_response_header("X-Bad", "a\nb");
+        ctx.add_response_header("X-Bad\r\nX-Injected", "c");
+        ctx.string(HttpStatus::Ok, "hello");
+        let response = writer.contents();
+        assert!(response.contains("X-Ok: value\r\n"));
+        assert!(!response.contains("X-Bad"));
+        assert!(!response.contains("X-Injected"));
+    }
+
+    #[test]
+    fn test_bodyless_responses() {
+        let head = |send: &dyn Fn(&mut Context)| {
+            let writer = MockWriter::default();
+            let mut ctx = Context::new(writer.clone());
+            ctx.request.method = HttpMethod::Head;
+            send(&mut ctx);
+            writer.contents()
+        };
+        let response = head(&|ctx| ctx.string(HttpStatus::Ok, "hello"));
+        assert!(response.contains("Content-Length: 5\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+
+        let response = head(&|ctx| {
+            ctx.stream(HttpStatus::Ok, "text/plain", "hello".as_bytes());
+        });
+        assert!(!response.contains("Transfer-Encoding"));
+        assert!(response.ends_with("Content-Type: text/plain\r\n\r\n"));
+
+        let response = head(&|ctx| {
+            let mut csv = ctx.csv(HttpStatus::Ok);
+            csv.row(["id"]).unwrap();
+            csv.finish().unwrap();
+        });
+        assert!(response.ends_with("charset=utf-8\r\n\r\n"));
+
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.add_response_header("Content-Length", 5);
+        ctx.bytes(HttpStatus::NoContent, "text/plain", b"hello");
+        let response = writer.contents();
+        assert!(!response.contains("Content-Length") && !response.contains("hello"));
+
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.stream(HttpStatus::NoContent, "text/plain", "hello".as_bytes());
+        assert_eq!(
+            writer.contents(),
+            "HTTP/1.1 204 No Content\r\nContent-Type: text/plain\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_double_response() {
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        assert!(!ctx.is_committed());
+        ctx.string(HttpStatus::Ok, "first");
+        assert!(ctx.is_committed());
+        ctx.json(HttpStatus::InternalServerError, json!({"second": true}));
+        ctx.stream(HttpStatus::Ok, "text/plain", &b"third"[..]);
+        assert!(ctx.csv(HttpStatus::Ok).row(["fourth"]).is_err());
+        assert!(ctx.write_error().is_none());
+        assert!(!ctx.close_connection);
+
+        let response = writer.contents();
+        assert_eq!(response.matches("HTTP/1.1").count(), 1);
+        assert!(response.ends_with("\r\n\r\nfirst"));
+    }
+
+    #[test]
+    fn test_extensions() {
+        #[derive(Debug, PartialEq)]
+        struct Tenant(u32);
+
+        let mut ctx = Context::new(MockWriter::default());
+        assert_eq!(ctx.get::<Tenant>(), None);
+        assert_eq!(ctx.set(Tenant(1)), None);
+        ctx.set("user");
+        assert_eq!(ctx.set(Tenant(2)), Some(Tenant(1)));
+        ctx.get_mut::<Tenant>().unwrap().0 += 1;
+        assert_eq!(ctx.get::<Tenant>(), Some(&Tenant(3)));
+        assert_eq!(ctx.get::<&str>(), Some(&"user"));
+        assert_eq!(ctx.remove::<Tenant>(), Some(Tenant(3)));
+        assert_eq!(ctx.get::<Tenant>(), None);
+    }
+
+    #[test]
+    fn test_defer() {
+        let writer = MockWriter::default();
+        let order = Rc::new(RefCell::new(Vec::new()));
+        let mut ctx = Context::new(writer.clone());
+        for i in 0..2 {
+            let order = Rc::clone(&order);
+            let written = writer.clone();
+            ctx.defer(move || order.borrow_mut().push((i, written.contents().len())));
+        }
+        ctx.string(HttpStatus::Ok, "done");
+        assert!(order.borrow().is_empty());
+        ctx.run_deferred();
+        let sent = writer.contents().len();
+        assert_eq!(*order.borrow(), [(0, sent), (1, sent)]);
+
+        // Left over callbacks run when the context is dropped
+        let mut ctx = Context::new(MockWriter::default());
+        let ran = Rc::clone(&order);
+        ctx.defer(move || ran.borrow_mut().clear());
+        drop(ctx);
+        assert!(order.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_bytes_range() {
+        let (mut ctx, writer) = get_with(Some(("Range", "bytes=2-4")), ETagMode::Off);
+        ctx.bytes(HttpStatus::Ok, "video/mp4", b"0123456789");
+        let response = writer.contents();
+        assert!(response.starts_with("HTTP/1.1 206 Partial Content"));
+        assert!(response.contains("Content-Range: bytes 2-4/10\r\n"));
+        assert!(response.contains("Content-Length: 3\r\n"));
+        assert!(response.ends_with("\r\n\r\n234"));
+
+        let (mut ctx, writer) = get_with(Some(("Range", "bytes=10-")), ETagMode::Off);
+        ctx.bytes(HttpStatus::Ok, "video/mp4", b"0123456789");
+        let response = writer.contents();
+        assert!(response.starts_with("HTTP/1.1 416 Range Not Satisfiable"));
+        assert!(response.contains("Content-Range: bytes */10\r\n"));
+
+        // The tag is of the whole body and an outdated one gets all of it
+        let (mut ctx, writer) = get_with(Some(("Range", "bytes=-2")), ETagMode::Strong);
+        ctx.request.headers.insert("If-Range", "\"old\"");
+        ctx.bytes(HttpStatus::Ok, "video/mp4", b"0123456789");
+        let response = writer.contents();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains(&format!("ETag: {}\r\n", etag::strong(b"0123456789"))));
+        assert!(response.ends_with("0123456789"));
+    }
+
+    #[test]
+    fn test_file_range() {
+        let path = std::env::temp_dir().join(format!("ctx-range-{}.txt", std::process::id()));
+        std::fs::write(&path, "0123456789").unwrap();
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+
+        let (mut ctx, writer) = get_with(Some(("Range", "bytes=-3")), ETagMode::Off);
+        ctx.request
+            .headers
+            .insert("If-Range", &http_date::format(modified));
+        ctx.file(HttpStatus::Ok, &path);
+        let response = writer.contents();
+        assert!(response.starts_with("HTTP/1.1 206 Partial Content"));
+        assert!(response.contains("Accept-Ranges: bytes\r\n"));
+        assert!(response.contains("Content-Range: bytes 7-9/10\r\n"));
+        assert!(response.ends_with("\r\n\r\n789"));
+
+        let (mut ctx, writer) = get_with(Some(("Range", "bytes=20-30")), ETagMode::Off);
+        ctx.file(HttpStatus::Ok, &path);
+        let response = writer.contents();
+        assert!(response.starts_with("HTTP/1.1 416 Range Not Satisfiable"));
+        assert!(response.contains("Content-Length: 0\r\n"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_file_if_modified_since() {
+        let path = std::env::temp_dir().join(format!("ctx-modified-{}.txt", std::process::id()));
+        std::fs::write(&path, "content").unwrap();
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+        let last_modified = http_date::format(modified);
+
+        let (mut ctx, writer) =
+            get_with(Some(("If-Modified-Since", &last_modified)), ETagMode::Off);
+        ctx.file(HttpStatus::Ok, &path);
+        let response = writer.contents();
+        assert!(response.starts_with("HTTP/1.1 304 Not Modified"));
+        assert!(response.contains(&format!("Last-Modified: {last_modified}\r\n")));
+        assert!(response.ends_with("\r\n\r\n"));
+
+        let old = "Thu, 01 Jan 1970 00:00:00 GMT";
+        let (mut ctx, writer) = get_with(Some(("If-Modified-Since", old)), ETagMode::Strong);
+        ctx.file(HttpStatus::Ok, &path);
+        let response = writer.contents();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("ETag: W/\"7-"));
+        assert!(response.ends_with("content"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_respond_not_acceptable() {
+        let response = respond_with_accept(Some("image/png"), json!({"a": 1}));
+        assert!(response.starts_with("HTTP/1.1 406 Not Acceptable"));
+    }
+
+    #[test]
+    fn test_cookie() {
+        let ctx = context_with_cookies("theme=dark; lang=en");
+        assert_eq!(ctx.cookie("theme"), Some("dark".to_string()));
+        assert_eq!(ctx.cookie("missing"), None);
+    }
+
+    #[test]
+    fn test_signed_cookie() {
+        let keys = CookieKeys::new(CookieKey::from_secret(b"secret"));
+        let signed = keys.sign("user", "42").unwrap();
+
+        let ctx = context_with_cookies(&format!("user={signed}"));
+        assert_eq!(ctx.signed_cookie("user"), Some("42".to_string()));
+
+        let ctx = context_with_cookies("user=42");
+        assert_eq!(ctx.signed_cookie("user"), None);
+    }
+
+    #[test]
+    fn test_private_cookie() {
+        let keys = CookieKeys::new(CookieKey::from_secret(b"secret"));
+        let encrypted = keys.encrypt("user", "42").unwrap();
+
+        let ctx = context_with_cookies(&format!("user={encrypted}"));
+        assert_eq!(ctx.private_cookie("user"), Some("42".to_string()));
+        assert_eq!(ctx.signed_cookie("user"), None);
+    }
+
+    #[test]
+    fn test_set_cookie_writes_set_cookie_headers() {
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.set_cookie(Cookie::new("a", 1)).unwrap();
+        ctx.set_cookie(Cookie::new("b", 2).http_only(false))
+            .unwrap();
+        ctx.string(HttpStatus::Ok, "ok");
+
+        let response = writer.contents();
+        assert!(response.contains("Set-Cookie: a=1; HttpOnly; SameSite=Lax\r\n"));
+        assert!(response.contains("Set-Cookie: b=2; SameSite=Lax\r\n"));
+    }
+
+    #[test]
+    fn test_set_signed_cookie_without_keys() {
+        let mut ctx = Context::new(Vec::new());
+        assert!(ctx.set_signed_cookie(Cookie::new("user", 42)).is_err());
+        assert!(ctx.cookies().outgoing().is_empty());
+    }
+
+    fn context_with_language(accept_language: &str) -> Context {
+        let mut ctx = Context::new(Vec::new());
+        let mut headers = Headers::new();
+        headers.insert("Accept-Language", accept_language);
+        ctx.request = HttpRequest::new(HttpMethod::Get, "/".into(), headers, "".into());
+        let mut catalogs = Catalogs::new("en");
+        catalogs
+            .add("en", "welcome = Welcome, { $name }!\nbye = Bye")
+            .unwrap()
+            .add("es", "welcome = ¡Bienvenida, { $name }!")
+            .unwrap();
+        ctx.config = Arc::new(ServerConfig {
+            catalogs,
+            ..ServerConfig::default()
+        });
+        ctx
+    }
+
+    #[test]
+    fn test_localized_messages() {
+        let ctx = context_with_language("es-AR, es;q=0.9, en;q=0.5");
+        assert_eq!(ctx.locale().as_deref(), Some("es"));
+        assert_eq!(
+            ctx.t_with("welcome", &[("name", "Ana")]),
+            "¡Bienvenida, Ana!"
+        );
+        assert_eq!(ctx.t("bye"), "Bye");
+        assert_eq!(ctx.t("missing"), "missing");
+
+        let ctx = context_with_language("fr");
+        assert_eq!(ctx.locale().as_deref(), Some("en"));
+        assert_eq!(ctx.t_with("welcome", &[("name", "Ana")]), "Welcome, Ana!");
+    }
+
+    #[test]
+    fn test_set_content_language() {
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.set_content_language("es");
+        ctx.string(HttpStatus::Ok, "Hola");
+        assert!(writer.contents().contains("Content-Language: es\r\n"));
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let mut ctx = Context::new(Vec::new());
+        assert_eq!(ctx.client_ip(), None);
+
+        let mut headers = Headers::new();
+        headers.insert("X-Forwarded-For", "203.0.113.7");
+        ctx.request = HttpRequest::new(HttpMethod::Get, "/".into(), headers, "".into());
+        ctx.remote_addr = Some("10.0.0.1:4000".parse().unwrap());
+        assert_eq!(ctx.client_ip(), Some("10.0.0.1".parse().unwrap()));
+
+        ctx.config = Arc::new(ServerConfig {
+            trusted_proxies: vec!["10.0.0.0/8".parse().unwrap()],
+            ..ServerConfig::default()
+        });
+        assert_eq!(ctx.client_ip(), Some("203.0.113.7".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_geo() {
+        let mut ctx = Context::new(Vec::new());
+        ctx.remote_addr = Some("200.1.2.3:4000".parse().unwrap());
+        assert_eq!(ctx.geo(), None);
+
+        let info = GeoInfo {
+            country: Some("AR".into()),
+            asn: Some(7303),
+            as_organization: None,
+        };
+        let resolver = RangeResolver::new().range("200.0.0.0/8".parse().unwrap(), info.clone());
+        let mut ctx = Context::new(Vec::new());
+        ctx.remote_addr = Some("200.1.2.3:4000".parse().unwrap());
+        ctx.config = Arc::new(ServerConfig {
+            geoip: Some(Arc::new(resolver)),
+            ..ServerConfig::default()
+        });
+        assert_eq!(ctx.geo(), Some(&info));
+    }
+
+    #[test]
+    fn test_query() {
+        let mut ctx = Context::new(Vec::new());
+        assert_eq!(ctx.query("q"), None);
+        assert_eq!(ctx.bind_query::<HashMap<String, u32>>().unwrap().len(), 0);
+
+        let path = "/search?q=rust+http&page=3&page=4&limit=ten";
+        ctx.request = HttpRequest::new(HttpMethod::Get, path.into(), Headers::new(), "".into());
+        assert_eq!(ctx.query("q").as_deref(), Some("rust http"));
+        assert_eq!(ctx.query_as::<u32>("page").unwrap(), Some(3));
+        let err = ctx.query_as::<u32>("limit").unwrap_err();
+        assert_eq!(err.http_status(), HttpStatus::BadRequest);
+        assert!(ctx.bind_query::<HashMap<String, u32>>().is_err());
+        let query: HashMap<String, String> = ctx.bind_query().unwrap();
+        assert_eq!(query["limit"], "ten");
+    }
 }
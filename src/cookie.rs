@@ -0,0 +1,638 @@
+use crate::api_err::ApiErr;
+use crate::headers::is_token;
+use crate::utils::base64;
+use crate::utils::crypto::{chacha20, constant_time_eq, hmac_sha256, random_bytes};
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Display;
+
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl Display for SameSite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        };
+        write!(f, "{}", value)
+    }
+}
+
+/// Returns whether the value is made of cookie octets, optionally in double quotes (RFC 6265)
+fn is_cookie_value(value: &str) -> bool {
+    let value = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value);
+    value
+        .bytes()
+        .all(|b| matches!(b, 0x21 | 0x23..=0x2b | 0x2d..=0x3a | 0x3c..=0x5b | 0x5d..=0x7e))
+}
+
+/// Returns whether the value can be the one of a `Path` or `Domain` attribute
+fn is_attribute_value(value: &str) -> bool {
+    !value.chars().any(|c| c.is_ascii_control() || c == ';')
+}
+
+/// A cookie to be sent to the client in a `Set-Cookie` header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    pub path: Option<String>,
+    pub domain: Option<String>,
+    pub max_age: Option<i64>,
+    /// Left as `None` to use the value of the server [`CookiePolicy`].
+    pub secure: Option<bool>,
+    /// Left as `None` to use the value of the server [`CookiePolicy`].
+    pub http_only: Option<bool>,
+    pub same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    pub fn new<N: Display, V: Display>(name: N, value: V) -> Cookie {
+        Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            path: None,
+            domain: None,
+            max_age: None,
+            secure: None,
+            http_only: None,
+            same_site: None,
+        }
+    }
+
+    pub fn path<P: Display>(mut self, path: P) -> Cookie {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    pub fn domain<D: Display>(mut self, domain: D) -> Cookie {
+        self.domain = Some(domain.to_string());
+        self
+    }
+
+    /// Number of seconds until the cookie expires, zero or less expires it immediately.
+    pub fn max_age(mut self, seconds: i64) -> Cookie {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> Cookie {
+        self.secure = Some(secure);
+        self
+    }
+
+    pub fn http_only(mut self, http_only: bool) -> Cookie {
+        self.http_only = Some(http_only);
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Cookie {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    /// Checks the requirements browsers enforce on the cookie, rejecting it when they would ignore it:
+    /// `__Secure-` cookies must be `Secure`, `__Host-` cookies must also have `Path=/` and no
+    /// `Domain`, and `SameSite=None` cookies must be `Secure`.
+    /// The name must be a token, the value cookie octets, optionally in double quotes, and the
+    /// path and domain can't have control characters or `;`, so none of them can add
+    /// attributes or headers to the `Set-Cookie` header.
+    pub fn validate(&self) -> Result<(), ApiErr> {
+        if !is_token(&self.name) {
+            return Err(ApiErr::InvalidCookie(format!(
+                "{:?} isn't a valid cookie name",
+                self.name
+            )));
+        }
+        if !is_cookie_value(&self.value) {
+            return Err(ApiErr::InvalidCookie(format!(
+                "{} has a value with characters not allowed in cookies",
+                self.name
+            )));
+        }
+        for (attribute, value) in [("Path", &self.path), ("Domain", &self.domain)] {
+            if value.as_deref().is_some_and(|v| !is_attribute_value(v)) {
+                return Err(ApiErr::InvalidCookie(format!(
+                    "{} has a {attribute} with control characters or ';'",
+                    self.name
+                )));
+            }
+        }
+        let secure = self.secure == Some(true);
+        let name = self.name.to_ascii_lowercase();
+        if name.starts_with("__secure-") && !secure {
+            return Err(ApiErr::InvalidCookie(format!(
+                "{} must be Secure",
+                self.name
+            )));
+        }
+        if name.starts_with("__host-")
+            && (!secure || self.path.as_deref() != Some("/") || self.domain.is_some())
+        {
+            return Err(ApiErr::InvalidCookie(format!(
+                "{} must be Secure, have Path=/ and no Domain",
+                self.name
+            )));
+        }
+        if self.same_site == Some(SameSite::None) && !secure {
+            return Err(ApiErr::InvalidCookie(format!(
+                "{} has SameSite=None so it must be Secure",
+                self.name
+            )));
+        }
+        Ok(())
+    }
+
+    /// Parses the value of a `Cookie` request header into its name and value pairs.
+    /// # Example
+    /// ```
+    /// use HTTP_Server::cookie::Cookie;
+    ///
+    /// let cookies = Cookie::parse_header("session=abc; theme=dark");
+    /// assert_eq!(cookies, vec![("session".into(), "abc".into()), ("theme".into(), "dark".into())]);
+    /// ```
+    pub fn parse_header(header: &str) -> Vec<(String, String)> {
+        header
+            .split(';')
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(name, value)| {
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .unwrap_or(value);
+                (name.trim().to_string(), value.to_string())
+            })
+            .filter(|(name, _)| !name.is_empty())
+            .collect()
+    }
+}
+
+impl Display for Cookie {
+    /// Formats the cookie as the value of a `Set-Cookie` header.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age)?;
+        }
+        if self.secure == Some(true) {
+            write!(f, "; Secure")?;
+        }
+        if self.http_only == Some(true) {
+            write!(f, "; HttpOnly")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site)?;
+        }
+        Ok(())
+    }
+}
+
+/// A secret used to sign and encrypt cookies.
+/// Separate keys for each purpose are derived from the secret, which should be
+/// at least 32 random bytes.
+#[derive(Clone)]
+pub struct CookieKey {
+    signing: [u8; 32],
+    encryption: [u8; 32],
+    authentication: [u8; 32],
+}
+
+impl CookieKey {
+    pub fn from_secret(secret: &[u8]) -> CookieKey {
+        CookieKey {
+            signing: hmac_sha256(secret, b"cookie-signing"),
+            encryption: hmac_sha256(secret, b"cookie-encryption"),
+            authentication: hmac_sha256(secret, b"cookie-authentication"),
+        }
+    }
+
+    fn sign(&self, name: &str, value: &str) -> [u8; 32] {
+        hmac_sha256(&self.signing, format!("{name}={value}").as_bytes())
+    }
+
+    fn tag(&self, name: &str, sealed: &[u8]) -> [u8; 32] {
+        let mut data = format!("{name}=").into_bytes();
+        data.extend_from_slice(sealed);
+        hmac_sha256(&self.authentication, &data)
+    }
+}
+
+impl fmt::Debug for CookieKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CookieKey(..)")
+    }
+}
+
+/// The keys used for signed and encrypted cookies.
+///
+/// New cookies always use the current key, while cookies signed or encrypted with
+/// any of the previous keys are still accepted, so keys can be rotated without
+/// invalidating every cookie at once.
+#[derive(Debug, Clone, Default)]
+pub struct CookieKeys {
+    keys: Vec<CookieKey>,
+}
+
+impl CookieKeys {
+    pub fn new(current: CookieKey) -> CookieKeys {
+        CookieKeys {
+            keys: vec![current],
+        }
+    }
+
+    /// Adds a retired key that is only used to verify and decrypt cookies.
+    pub fn with_previous(mut self, key: CookieKey) -> CookieKeys {
+        self.keys.push(key);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Signs the value, returning `None` when there are no keys.
+    /// The signature covers the cookie name, so a value can't be moved to another cookie.
+    pub fn sign(&self, name: &str, value: &str) -> Option<String> {
+        let key = self.keys.first()?;
+        let signature = base64::encode_url(&key.sign(name, value));
+        Some(format!("{signature}.{value}"))
+    }
+
+    /// Returns the original value if it was signed with any of the keys.
+    pub fn verify(&self, name: &str, signed: &str) -> Option<String> {
+        let (signature, value) = signed.split_once('.')?;
+        let signature = base64::decode_url(signature)?;
+        self.keys
+            .iter()
+            .any(|key| constant_time_eq(&key.sign(name, value), &signature))
+            .then(|| value.to_string())
+    }
+
+    /// Encrypts and authenticates the value, returning `None` when there are no keys.
+    pub fn encrypt(&self, name: &str, value: &str) -> Option<String> {
+        let key = self.keys.first()?;
+        let mut nonce = [0u8; NONCE_LEN];
+        random_bytes(&mut nonce);
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend(chacha20(&key.encryption, &nonce, 1, value.as_bytes()));
+        let tag = key.tag(name, &sealed);
+        sealed.extend_from_slice(&tag);
+        Some(base64::encode_url(&sealed))
+    }
+
+    /// Returns the original value if it was encrypted with any of the keys and wasn't tampered with.
+    pub fn decrypt(&self, name: &str, encrypted: &str) -> Option<String> {
+        let sealed = base64::decode_url(encrypted)?;
+        if sealed.len() < NONCE_LEN + TAG_LEN {
+            return None;
+        }
+        let (sealed, tag) = sealed.split_at(sealed.len() - TAG_LEN);
+        let key = self
+            .keys
+            .iter()
+            .find(|key| constant_time_eq(&key.tag(name, sealed), tag))?;
+
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let nonce: [u8; NONCE_LEN] = nonce.try_into().ok()?;
+        String::from_utf8(chacha20(&key.encryption, &nonce, 1, ciphertext)).ok()
+    }
+}
+
+/// Attributes applied to every cookie sent by the server that doesn't set them itself.
+/// By default cookies are `HttpOnly` and `SameSite=Lax`; `Secure` is off since the
+/// server itself only speaks plain HTTP, but should be turned on behind HTTPS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CookiePolicy {
+    pub same_site: Option<SameSite>,
+    pub secure: bool,
+    pub http_only: bool,
+}
+
+impl CookiePolicy {
+    /// Fills in the attributes the cookie left unset.
+    pub fn apply(&self, cookie: &mut Cookie) {
+        cookie.same_site = cookie.same_site.or(self.same_site);
+        cookie.secure.get_or_insert(self.secure);
+        cookie.http_only.get_or_insert(self.http_only);
+    }
+}
+
+impl Default for CookiePolicy {
+    fn default() -> Self {
+        CookiePolicy {
+            same_site: Some(SameSite::Lax),
+            secure: false,
+            http_only: true,
+        }
+    }
+}
+
+/// The cookies received with a request and the ones to send back with the response.
+#[derive(Debug, Clone, Default)]
+pub struct CookieJar {
+    incoming: HashMap<String, String>,
+    outgoing: Vec<Cookie>,
+    keys: CookieKeys,
+    policy: CookiePolicy,
+}
+
+impl CookieJar {
+    /// Creates a jar from the value of the `Cookie` request header.
+    pub fn new(header: Option<&str>, keys: CookieKeys, policy: CookiePolicy) -> CookieJar {
+        let incoming = header.map(Cookie::parse_header).unwrap_or_default();
+        CookieJar {
+            incoming: incoming.into_iter().collect(),
+            outgoing: Vec::new(),
+            keys,
+            policy,
+        }
+    }
+
+    /// Returns the raw value of a cookie sent by the client.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.incoming.get(name).map(|v| v.as_str())
+    }
+
+    /// Returns the value of a signed cookie if its signature is valid.
+    pub fn get_signed(&self, name: &str) -> Option<String> {
+        self.keys.verify(name, self.get(name)?)
+    }
+
+    /// Returns the decrypted value of an encrypted cookie if it wasn't tampered with.
+    pub fn get_private(&self, name: &str) -> Option<String> {
+        self.keys.decrypt(name, self.get(name)?)
+    }
+
+    /// Adds a cookie to the response, replacing any other with the same name.
+    /// The jar policy fills in the attributes the cookie left unset, and the cookie is
+    /// rejected if it doesn't pass [`Cookie::validate`].
+    pub fn add(&mut self, mut cookie: Cookie) -> Result<(), ApiErr> {
+        self.policy.apply(&mut cookie);
+        cookie.validate()?;
+        self.outgoing.retain(|c| c.name != cookie.name);
+        self.outgoing.push(cookie);
+        Ok(())
+    }
+
+    /// Signs the cookie value and adds it to the response.
+    /// Fails without adding it when there are no keys configured.
+    pub fn add_signed(&mut self, mut cookie: Cookie) -> Result<(), ApiErr> {
+        cookie.value = self
+            .keys
+            .sign(&cookie.name, &cookie.value)
+            .ok_or_else(|| ApiErr::InternalError("No cookie keys configured.".into()))?;
+        self.add(cookie)
+    }
+
+    /// Encrypts the cookie value and adds it to the response.
+    /// Fails without adding it when there are no keys configured.
+    pub fn add_private(&mut self, mut cookie: Cookie) -> Result<(), ApiErr> {
+        cookie.value = self
+            .keys
+            .encrypt(&cookie.name, &cookie.value)
+            .ok_or_else(|| ApiErr::InternalError("No cookie keys configured.".into()))?;
+        self.add(cookie)
+    }
+
+    /// Asks the client to delete the cookie.
+    pub fn remove(&mut self, name: &str) -> Result<(), ApiErr> {
+        let mut cookie = Cookie::new(name, "").path("/").max_age(0);
+        let lowercase = name.to_ascii_lowercase();
+        if lowercase.starts_with("__secure-") || lowercase.starts_with("__host-") {
+            cookie = cookie.secure(true);
+        }
+        self.add(cookie)
+    }
+
+    /// Returns the cookies to send with the response.
+    pub fn outgoing(&self) -> &[Cookie] {
+        &self.outgoing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> CookieKeys {
+        CookieKeys::new(CookieKey::from_secret(b"a secret that is long enough!!!!"))
+    }
+
+    #[test]
+    fn test_cookie_display() {
+        let cookie = Cookie::new("id", 7)
+            .path("/")
+            .max_age(60)
+            .secure(true)
+            .http_only(true)
+            .same_site(SameSite::Lax);
+        assert_eq!(
+            cookie.to_string(),
+            "id=7; Path=/; Max-Age=60; Secure; HttpOnly; SameSite=Lax"
+        );
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let cookies = Cookie::parse_header(" a=1;b=\"two\" ; invalid; c=");
+        assert_eq!(
+            cookies,
+            vec![
+                ("a".to_string(), "1".to_string()),
+                ("b".to_string(), "two".to_string()),
+                ("c".to_string(), "".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_signed_cookie_roundtrip() {
+        let keys = keys();
+        let signed = keys.sign("user", "42").unwrap();
+        assert_eq!(keys.verify("user", &signed), Some("42".to_string()));
+        assert_eq!(keys.verify("other", &signed), None);
+        assert_eq!(keys.verify("user", &signed.replace(".42", ".43")), None);
+        assert_eq!(keys.verify("user", "42"), None);
+    }
+
+    #[test]
+    fn test_private_cookie_roundtrip() {
+        let keys = keys();
+        let encrypted = keys.encrypt("user", "user-42-admin").unwrap();
+        assert!(!encrypted.contains("user-42-admin"));
+        assert_eq!(
+            keys.decrypt("user", &encrypted),
+            Some("user-42-admin".to_string())
+        );
+        assert_eq!(keys.decrypt("other", &encrypted), None);
+
+        let mut tampered = base64::decode_url(&encrypted).unwrap();
+        tampered[NONCE_LEN] ^= 1;
+        assert_eq!(keys.decrypt("user", &base64::encode_url(&tampered)), None);
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let old = CookieKeys::new(CookieKey::from_secret(b"old secret"));
+        let signed = old.sign("user", "42").unwrap();
+        let encrypted = old.encrypt("user", "42").unwrap();
+
+        let rotated = CookieKeys::new(CookieKey::from_secret(b"new secret"))
+            .with_previous(CookieKey::from_secret(b"old secret"));
+        assert_eq!(rotated.verify("user", &signed), Some("42".to_string()));
+        assert_eq!(rotated.decrypt("user", &encrypted), Some("42".to_string()));
+
+        let new_only = CookieKeys::new(CookieKey::from_secret(b"new secret"));
+        assert_eq!(new_only.verify("user", &signed), None);
+        assert_eq!(new_only.decrypt("user", &encrypted), None);
+    }
+
+    #[test]
+    fn test_jar_without_keys() {
+        let mut jar = CookieJar::new(
+            Some("user=42"),
+            CookieKeys::default(),
+            CookiePolicy::default(),
+        );
+        assert_eq!(jar.get("user"), Some("42"));
+        assert_eq!(jar.get_signed("user"), None);
+        assert!(jar.add_signed(Cookie::new("user", 42)).is_err());
+        assert!(jar.outgoing().is_empty());
+    }
+
+    #[test]
+    fn test_jar_add_replaces_cookie() {
+        let mut jar = CookieJar::new(None, keys(), CookiePolicy::default());
+        jar.add(Cookie::new("theme", "light")).unwrap();
+        jar.add(Cookie::new("theme", "dark")).unwrap();
+        assert_eq!(jar.outgoing().len(), 1);
+        assert_eq!(jar.outgoing()[0].value, "dark");
+    }
+
+    #[test]
+    fn test_jar_applies_policy() {
+        let policy = CookiePolicy {
+            same_site: Some(SameSite::Strict),
+            secure: true,
+            http_only: true,
+        };
+        let mut jar = CookieJar::new(None, keys(), policy);
+        jar.add(Cookie::new("a", 1)).unwrap();
+        jar.add(
+            Cookie::new("b", 2)
+                .http_only(false)
+                .same_site(SameSite::Lax),
+        )
+        .unwrap();
+        assert_eq!(
+            jar.outgoing()[0].to_string(),
+            "a=1; Secure; HttpOnly; SameSite=Strict"
+        );
+        assert_eq!(jar.outgoing()[1].to_string(), "b=2; Secure; SameSite=Lax");
+    }
+
+    #[test]
+    fn test_validate_prefixes() {
+        assert!(Cookie::new("__Secure-id", 1).validate().is_err());
+        assert!(Cookie::new("__Secure-id", 1)
+            .secure(true)
+            .validate()
+            .is_ok());
+        assert!(Cookie::new("__Host-id", 1).secure(true).validate().is_err());
+        assert!(Cookie::new("__Host-id", 1)
+            .secure(true)
+            .path("/")
+            .domain("example.com")
+            .validate()
+            .is_err());
+        assert!(Cookie::new("__host-id", 1).path("/").validate().is_err());
+        assert!(Cookie::new("__Host-id", 1)
+            .secure(true)
+            .path("/")
+            .validate()
+            .is_ok());
+        assert!(Cookie::new("id", 1)
+            .same_site(SameSite::None)
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn test_validate_rejects_injections() {
+        assert!(Cookie::new("lang", "en-US").validate().is_ok());
+        assert!(Cookie::new("lang", "\"en\"").validate().is_ok());
+        assert!(Cookie::new("lang", "").validate().is_ok());
+
+        for name in [
+            "", "la ng", "lang;", "la=ng", "la\r\nng", "lang\"", "l\u{e9}",
+        ] {
+            assert!(Cookie::new(name, "en").validate().is_err(), "{name:?}");
+        }
+        for value in [
+            "en\r\nSet-Cookie: admin=1",
+            "en; Domain=evil.com",
+            "en,fr",
+            "e n",
+            "e\\n",
+            "\"en",
+            "e\"n",
+            "en\u{7f}",
+            "\u{e9}",
+        ] {
+            assert!(Cookie::new("lang", value).validate().is_err(), "{value:?}");
+        }
+        assert!(Cookie::new("lang", "en").path("/a b").validate().is_ok());
+        assert!(Cookie::new("lang", "en")
+            .path("/; Domain=evil.com")
+            .validate()
+            .is_err());
+        assert!(Cookie::new("lang", "en")
+            .path("/\r\nX-Injected: 1")
+            .validate()
+            .is_err());
+        assert!(Cookie::new("lang", "en")
+            .domain("example.com; Secure")
+            .validate()
+            .is_err());
+        assert!(Cookie::new("lang", "en")
+            .domain("example.com\n")
+            .validate()
+            .is_err());
+
+        let mut jar = CookieJar::new(None, keys(), CookiePolicy::default());
+        assert!(jar.add(Cookie::new("lang", "en\r\nX-Injected: 1")).is_err());
+        assert!(jar.outgoing().is_empty());
+    }
+
+    #[test]
+    fn test_jar_rejects_invalid_prefixed_cookie() {
+        let mut jar = CookieJar::new(None, keys(), CookiePolicy::default());
+        assert!(jar.add(Cookie::new("__Host-id", 1).path("/")).is_err());
+        assert!(jar.outgoing().is_empty());
+        jar.remove("__Host-id").unwrap();
+        assert_eq!(
+            jar.outgoing()[0].to_string(),
+            "__Host-id=; Path=/; Max-Age=0; Secure; HttpOnly; SameSite=Lax"
+        );
+    }
+}
@@ -0,0 +1,149 @@
+//! Streaming of `text/csv` responses ([RFC 4180](https://www.rfc-editor.org/rfc/rfc4180)),
+//! see [`Context::csv`](crate::context::Context::csv).
+
+use crate::context::Context;
+use std::borrow::Cow;
+use std::io;
+
+/// Rows are sent once this many bytes are buffered
+const BUFFER_SIZE: usize = 8 * 1024;
+
+/// Quotes the field if it has a comma, a quote or a line break, doubling its quotes
+/// # Example
+/// ```
+/// use HTTP_Server::csv;
+///
+/// assert_eq!(csv::escape("plain"), "plain");
+/// assert_eq!(csv::escape("a, b"), r#""a, b""#);
+/// assert_eq!(csv::escape(r#"say "hi""#), r#""say ""hi""""#);
+/// ```
+pub fn escape(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\r', '\n']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+/// Writes the records of a `text/csv` response as they are generated, returned by
+/// [`Context::csv`]. Rows are buffered and sent in chunks of about 8 KiB.
+///
+/// The body only ends with [`CsvWriter::finish`]. If the writer is dropped before,
+/// like when the handler gives up on an error, the connection is closed so the
+/// client knows the export is incomplete.
+pub struct CsvWriter<'a> {
+    ctx: &'a mut Context,
+    chunked: bool,
+    buffer: Vec<u8>,
+    /// Set once a write failed, nothing else is sent
+    failed: bool,
+    finished: bool,
+}
+
+impl<'a> CsvWriter<'a> {
+    pub(crate) fn new(ctx: &'a mut Context, chunked: bool, failed: bool) -> CsvWriter<'a> {
+        CsvWriter {
+            ctx,
+            chunked,
+            buffer: Vec::with_capacity(BUFFER_SIZE),
+            failed,
+            finished: false,
+        }
+    }
+
+    /// Writes a record, escaping its fields.
+    /// Fails if the response couldn't be sent, in which case the export should stop
+    pub fn row<I, S>(&mut self, fields: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        if self.failed {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "the response couldn't be sent",
+            ));
+        }
+        for (i, field) in fields.into_iter().enumerate() {
+            if i > 0 {
+                self.buffer.push(b',');
+            }
+            self.buffer
+                .extend_from_slice(escape(field.as_ref()).as_bytes());
+        }
+        self.buffer.extend_from_slice(b"\r\n");
+        match self.buffer.len() >= BUFFER_SIZE {
+            true => self.send(),
+            false => Ok(()),
+        }
+    }
+
+    /// Sends the buffered rows and ends the body
+    pub fn finish(mut self) -> io::Result<()> {
+        self.finished = true;
+        self.send()?;
+        match self.chunked {
+            true => self.ctx.write_stream(true, &[]),
+            false => Ok(()),
+        }
+    }
+
+    fn send(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let result = self.ctx.write_stream(self.chunked, &self.buffer);
+        self.buffer.clear();
+        self.failed = result.is_err();
+        result
+    }
+}
+
+impl Drop for CsvWriter<'_> {
+    fn drop(&mut self) {
+        // Failed writes already close the connection
+        if !self.finished && !self.failed {
+            self.ctx.abort_stream();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_status::HttpStatus;
+    use crate::utils::mock_stream::MockWriter;
+
+    #[test]
+    fn test_csv() {
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        let mut csv = ctx.csv(HttpStatus::Ok);
+        csv.row(["id", "name"]).unwrap();
+        csv.row(["1".to_string(), "Doe, \"Jo\"\nJr".to_string()])
+            .unwrap();
+        csv.finish().unwrap();
+        let response = writer.contents();
+        assert!(response.contains("Content-Type: text/csv; charset=utf-8\r\n"));
+        assert!(response.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(response
+            .ends_with("\r\n\r\n1D\r\nid,name\r\n1,\"Doe, \"\"Jo\"\"\nJr\"\r\n\r\n0\r\n\r\n"));
+        assert!(!ctx.close_connection);
+    }
+
+    #[test]
+    fn test_csv_streams_big_exports() {
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        let mut csv = ctx.csv(HttpStatus::Ok);
+        for id in 0..2000 {
+            csv.row([id.to_string(), format!("user{id}")]).unwrap();
+        }
+        // Rows are sent before the export ends
+        let sent = writer.contents().len();
+        assert!(sent > 2 * BUFFER_SIZE);
+        drop(csv);
+        assert!(!writer.contents().ends_with("0\r\n\r\n"));
+        assert!(ctx.close_connection);
+    }
+}
@@ -0,0 +1,254 @@
+//! Startup diagnostics. With [`ServerConfig::startup_report`](crate::config::ServerConfig::startup_report)
+//! the server prints what it's about to run with when it starts: the addresses it
+//! listens on, the routes and workers, the features it was built with, the subsystems
+//! that failed to start and the limits and timeouts it enforces. A misconfiguration
+//! shows up at boot instead of with the first request it breaks.
+
+use crate::config::ServerConfig;
+use crate::subsystems::Degraded;
+use serde_json::{json, Map, Value};
+use std::fmt::{self, Display};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// How the startup report is printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReportFormat {
+    /// A line per entry, for people reading the output
+    #[default]
+    Text,
+    /// A single json object, for log collectors
+    Json,
+}
+
+/// Cargo features the crate was built with
+pub fn features() -> Vec<&'static str> {
+    [
+        ("decompression", cfg!(feature = "decompression")),
+        ("mmdb", cfg!(feature = "mmdb")),
+        ("msgpack", cfg!(feature = "msgpack")),
+        ("markdown", cfg!(feature = "markdown")),
+        ("signals", cfg!(feature = "signals")),
+    ]
+    .into_iter()
+    .filter_map(|(feature, enabled)| enabled.then_some(feature))
+    .collect()
+}
+
+/// What a server runs with, see [`Server::startup_report`](crate::server::Server::startup_report)
+/// # Example
+/// ```
+/// use HTTP_Server::diagnostics::ReportFormat;
+/// use HTTP_Server::router::Router;
+/// use HTTP_Server::server::Server;
+///
+/// let server = Server::builder(Router::new()).threads(2).build();
+/// let report = server.startup_report(&["127.0.0.1:8080".parse().unwrap()]);
+/// assert_eq!(report.workers, 2);
+///
+/// let text = report.render(ReportFormat::Text);
+/// assert!(text.contains("listeners: 127.0.0.1:8080"));
+/// let json = report.render(ReportFormat::Json);
+/// assert!(json.contains(r#""workers":2"#));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct StartupReport {
+    pub listeners: Vec<SocketAddr>,
+    pub routes: usize,
+    pub workers: usize,
+    pub features: Vec<&'static str>,
+    pub degraded: Vec<Degraded>,
+    /// Resolved limits, timeouts and settings of the config, in seconds for the durations
+    /// and `null` for the ones turned off
+    pub settings: Vec<(&'static str, Value)>,
+}
+
+impl StartupReport {
+    pub fn new(
+        listeners: &[SocketAddr],
+        routes: usize,
+        workers: usize,
+        config: &ServerConfig,
+    ) -> StartupReport {
+        StartupReport {
+            listeners: listeners.to_vec(),
+            routes,
+            workers,
+            features: features(),
+            degraded: config.subsystems.degraded(),
+            settings: settings(config),
+        }
+    }
+
+    /// Renders the report in the format, json on a single line
+    pub fn render(&self, format: ReportFormat) -> String {
+        match format {
+            ReportFormat::Text => self.to_string(),
+            ReportFormat::Json => self.to_json().to_string(),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let degraded: Map<String, Value> = self
+            .degraded
+            .iter()
+            .map(|d| (d.name.clone(), Value::from(d.error.clone())))
+            .collect();
+        let settings: Map<String, Value> = self
+            .settings
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect();
+        json!({
+            "listeners": self.listeners.iter().map(|a| a.to_string()).collect::<Vec<_>>(),
+            "routes": self.routes,
+            "workers": self.workers,
+            "features": self.features,
+            "degraded": degraded,
+            "settings": settings,
+        })
+    }
+}
+
+impl Display for StartupReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let list = |items: Vec<String>| match items.is_empty() {
+            true => "none".to_string(),
+            false => items.join(", "),
+        };
+        writeln!(f, "Startup report")?;
+        let listeners = self.listeners.iter().map(|a| a.to_string()).collect();
+        writeln!(f, "  listeners: {}", list(listeners))?;
+        writeln!(f, "  routes: {}", self.routes)?;
+        writeln!(f, "  workers: {}", self.workers)?;
+        let features = self.features.iter().map(|f| f.to_string()).collect();
+        writeln!(f, "  features: {}", list(features))?;
+        let degraded = self
+            .degraded
+            .iter()
+            .map(|d| format!("{} ({})", d.name, d.error))
+            .collect();
+        write!(f, "  degraded: {}", list(degraded))?;
+        for (name, value) in &self.settings {
+            match value {
+                Value::Null => write!(f, "\n  {name}: off")?,
+                Value::String(value) => write!(f, "\n  {name}: {value}")?,
+                Value::Array(values) => {
+                    let values = values
+                        .iter()
+                        .map(|v| v.as_str().map_or_else(|| v.to_string(), String::from))
+                        .collect();
+                    write!(f, "\n  {name}: {}", list(values))?
+                }
+                value => write!(f, "\n  {name}: {value}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn seconds(duration: Duration) -> Value {
+    Value::from(duration.as_secs_f64())
+}
+
+fn settings(config: &ServerConfig) -> Vec<(&'static str, Value)> {
+    vec![
+        ("max_header_size", config.max_header_size.into()),
+        ("max_body_size", config.max_body_size.into()),
+        (
+            "header_read_timeout",
+            config.header_read_timeout.map_or(Value::Null, seconds),
+        ),
+        (
+            "body_read_timeout",
+            config.body_read_timeout.map_or(Value::Null, seconds),
+        ),
+        (
+            "write_timeout",
+            config.write_timeout.map_or(Value::Null, seconds),
+        ),
+        (
+            "keep_alive_timeout",
+            match config.keep_alive {
+                true => seconds(config.keep_alive_timeout),
+                false => Value::Null,
+            },
+        ),
+        (
+            "max_requests_per_connection",
+            config.max_requests_per_connection.into(),
+        ),
+        ("shutdown_timeout", seconds(config.shutdown_timeout)),
+        ("allowed_hosts", config.allowed_hosts.clone().into()),
+        ("trusted_proxies", config.trusted_proxies.len().into()),
+        ("accept_filters", config.accept_filters.len().into()),
+        ("geoip", config.geoip.is_some().into()),
+        ("tarpit", config.tarpit.is_some().into()),
+        ("compression", config.compression.is_some().into()),
+        ("etag", format!("{:?}", config.etag).into()),
+        (
+            "header_parsing",
+            format!("{:?}", config.header_parsing).into(),
+        ),
+        ("parse_errors", format!("{:?}", config.parse_errors).into()),
+        (
+            "drop_malicious_requests",
+            config.drop_malicious_requests.into(),
+        ),
+        ("problem_details", config.problem_details.into()),
+        ("access_log", config.access_log.into()),
+        ("queue_time_header", config.queue_time_header.into()),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subsystems::{FailurePolicy, Subsystems};
+
+    #[test]
+    fn test_startup_report() {
+        let subsystems = Subsystems::new(FailurePolicy::Degrade);
+        _ = subsystems.start("geoip", || Err::<(), _>("missing database"));
+        let config = ServerConfig {
+            keep_alive: false,
+            write_timeout: None,
+            header_read_timeout: Some(Duration::from_millis(2500)),
+            allowed_hosts: vec!["example.com".into(), ".api.example.com".into()],
+            subsystems,
+            ..ServerConfig::default()
+        };
+        let addr: SocketAddr = "127.0.0.1:80".parse().unwrap();
+        let report = StartupReport::new(&[addr], 3, 15, &config);
+
+        let text = report.render(ReportFormat::Text);
+        assert!(text.starts_with("Startup report\n  listeners: 127.0.0.1:80\n"));
+        assert!(text.contains("\n  routes: 3\n  workers: 15\n"));
+        assert!(text.contains("\n  degraded: geoip (missing database)\n"));
+        assert!(text.contains("\n  header_read_timeout: 2.5\n"));
+        assert!(text.contains("\n  write_timeout: off\n"));
+        assert!(text.contains("\n  keep_alive_timeout: off\n"));
+        assert!(text.contains("\n  allowed_hosts: example.com, .api.example.com\n"));
+        assert!(text.contains("\n  etag: Off\n"));
+        assert!(!text.ends_with('\n'));
+
+        let json: Value = serde_json::from_str(&report.render(ReportFormat::Json)).unwrap();
+        assert_eq!(json["listeners"], json!(["127.0.0.1:80"]));
+        assert_eq!(json["workers"], 15);
+        assert_eq!(json["features"], json!(features()));
+        assert_eq!(json["degraded"]["geoip"], "missing database");
+        assert_eq!(json["settings"]["header_read_timeout"], 2.5);
+        assert_eq!(json["settings"]["write_timeout"], Value::Null);
+        assert_eq!(json["settings"]["max_body_size"], 10 * 1024 * 1024);
+        assert_eq!(json["settings"]["shutdown_timeout"], 30.0);
+    }
+
+    #[test]
+    fn test_empty_lists() {
+        let report = StartupReport::new(&[], 0, 1, &ServerConfig::default());
+        let text = report.to_string();
+        assert!(text.contains("\n  listeners: none\n"));
+        assert!(text.contains("\n  degraded: none\n"));
+        assert!(text.contains("\n  allowed_hosts: none\n"));
+    }
+}
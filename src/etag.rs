@@ -0,0 +1,53 @@
+use crate::utils::base64;
+use crate::utils::crypto::sha256;
+
+/// Which entity tags the server computes for the responses that don't set an `ETag`.
+/// See [`ServerConfig::etag`](crate::config::ServerConfig::etag).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ETagMode {
+    /// Only the responses of the handlers that set one have a tag
+    #[default]
+    Off,
+    /// A digest of the body, for bodies that are the same byte for byte
+    Strong,
+    /// A digest of the body marked as weak, for bodies that only mean the same
+    Weak,
+}
+
+/// Returns a strong entity tag for the body, a digest of its bytes in quotes
+/// # Example
+/// ```
+/// use HTTP_Server::etag;
+///
+/// let tag = etag::strong(br#"{"id":1}"#);
+/// assert!(tag.starts_with('"') && tag.ends_with('"'));
+/// assert_eq!(tag, etag::strong(br#"{"id":1}"#));
+/// assert_ne!(tag, etag::strong(br#"{"id":2}"#));
+/// ```
+pub fn strong(body: &[u8]) -> String {
+    format!("\"{}\"", base64::encode_url(&sha256(body)[..16]))
+}
+
+/// Returns a weak entity tag for the body, its strong tag with the `W/` prefix
+pub fn weak(body: &[u8]) -> String {
+    format!("W/{}", strong(body))
+}
+
+/// Returns whether an `If-None-Match` header matches the entity tag, in which case
+/// a `GET` can be answered with `304 Not Modified`.
+/// Tags are compared weakly, ignoring the `W/` prefix, and `*` matches any tag.
+/// # Example
+/// ```
+/// use HTTP_Server::etag;
+///
+/// assert!(etag::none_match_matches(r#""a", W/"b""#, r#""b""#));
+/// assert!(etag::none_match_matches("*", r#""b""#));
+/// assert!(!etag::none_match_matches(r#""a""#, r#""b""#));
+/// ```
+pub fn none_match_matches(if_none_match: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    let etag = opaque(etag);
+    if_none_match
+        .split(',')
+        .any(|tag| tag.trim() == "*" || opaque(tag) == etag)
+}
@@ -0,0 +1,56 @@
+use crate::proxy::IpRange;
+use std::fmt;
+use std::net::IpAddr;
+
+/// Location and network of an ip address
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GeoInfo {
+    /// ISO 3166-1 alpha-2 country code, like `AR`
+    pub country: Option<String>,
+    /// Autonomous system number
+    pub asn: Option<u32>,
+    /// Organization the autonomous system belongs to
+    pub as_organization: Option<String>,
+}
+
+/// Resolves the location of the clients, see [`Context::geo`](crate::context::Context::geo).
+/// It's called at most once per request, the first time a handler asks for it.
+pub trait GeoIpResolver: fmt::Debug + Send + Sync {
+    fn lookup(&self, ip: IpAddr) -> Option<GeoInfo>;
+}
+
+/// Resolver backed by a list of ip ranges, the first range containing the ip wins.
+/// Useful for tests and small private networks.
+/// # Example
+/// ```
+/// use HTTP_Server::geoip::{GeoInfo, GeoIpResolver, RangeResolver};
+///
+/// let info = GeoInfo { country: Some("AR".into()), ..GeoInfo::default() };
+/// let resolver = RangeResolver::new().range("200.0.0.0/8".parse().unwrap(), info.clone());
+/// assert_eq!(resolver.lookup("200.1.2.3".parse().unwrap()), Some(info));
+/// assert_eq!(resolver.lookup("10.1.2.3".parse().unwrap()), None);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RangeResolver {
+    ranges: Vec<(IpRange, GeoInfo)>,
+}
+
+impl RangeResolver {
+    pub fn new() -> RangeResolver {
+        RangeResolver::default()
+    }
+
+    pub fn range(mut self, range: IpRange, info: GeoInfo) -> Self {
+        self.ranges.push((range, info));
+        self
+    }
+}
+
+impl GeoIpResolver for RangeResolver {
+    fn lookup(&self, ip: IpAddr) -> Option<GeoInfo> {
+        self.ranges
+            .iter()
+            .find(|(range, _)| range.contains(ip))
+            .map(|(_, info)| info.clone())
+    }
+}
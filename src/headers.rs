@@ -0,0 +1,211 @@
+use crate::api_err::ApiErr;
+use std::collections::HashMap;
+
+/// Headers that can't be sent more than once in a request.
+/// Repeating any of them makes the request invalid.
+const SINGLETON_HEADERS: [&str; 11] = [
+    "authorization",
+    "content-length",
+    "content-type",
+    "from",
+    "host",
+    "if-modified-since",
+    "if-unmodified-since",
+    "max-forwards",
+    "proxy-authorization",
+    "referer",
+    "user-agent",
+];
+
+/// Words of header names whose usual casing isn't just the first letter in uppercase
+const WORD_CASING: [&str; 7] = ["DNT", "ETag", "MD5", "TE", "WWW", "WebSocket", "XSS"];
+
+/// Returns the header name in its usual casing, every word capitalized like
+/// `Content-Length`, with exceptions like `ETag` and `WWW-Authenticate`
+/// # Example
+/// ```
+/// use HTTP_Server::headers::canonical_name;
+///
+/// assert_eq!(canonical_name("content-length"), "Content-Length");
+/// assert_eq!(canonical_name("X-REQUEST-ID"), "X-Request-Id");
+/// assert_eq!(canonical_name("etag"), "ETag");
+/// assert_eq!(canonical_name("sec-websocket-accept"), "Sec-WebSocket-Accept");
+/// ```
+pub fn canonical_name(name: &str) -> String {
+    name.split('-')
+        .map(|word| {
+            if let Some(casing) = WORD_CASING.iter().find(|w| w.eq_ignore_ascii_case(word)) {
+                return casing.to_string();
+            }
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => {
+                    first.to_ascii_uppercase().to_string() + &chars.as_str().to_ascii_lowercase()
+                }
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Returns whether the text is a token, as header names and cookie names must be
+pub(crate) fn is_token(text: &str) -> bool {
+    !text.is_empty()
+        && text
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// Request headers with case-insensitive lookups.
+///
+/// Repeated list-valued headers are combined into a single comma-separated value
+/// (`Cookie` uses `; ` as that's its own separator), while every line as received
+/// stays available through [`Headers::get_all`] and [`Headers::iter`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Headers {
+    values: HashMap<String, String>,
+    raw: Vec<(String, String)>,
+}
+
+impl Headers {
+    pub fn new() -> Headers {
+        Headers::default()
+    }
+
+    /// Adds a received header, combining it with any previous value of the same header.
+    /// Fails with `ApiErr::DuplicateHeader` when a singleton header like `Host` is repeated.
+    pub fn append(&mut self, name: &str, value: &str) -> Result<(), ApiErr> {
+        let key = name.to_ascii_lowercase();
+        match self.values.get_mut(&key) {
+            Some(_) if SINGLETON_HEADERS.contains(&key.as_str()) => {
+                return Err(ApiErr::DuplicateHeader(name.to_string()));
+            }
+            Some(combined) => {
+                let separator = if key == "cookie" { "; " } else { ", " };
+                combined.push_str(separator);
+                combined.push_str(value);
+            }
+            None => {
+                self.values.insert(key, value.to_string());
+            }
+        }
+        self.raw.push((name.to_string(), value.to_string()));
+        Ok(())
+    }
+
+    /// Sets a header, replacing every previous value it had.
+    pub fn insert(&mut self, name: &str, value: &str) {
+        self.remove(name);
+        self.values
+            .insert(name.to_ascii_lowercase(), value.to_string());
+        self.raw.push((name.to_string(), value.to_string()));
+    }
+
+    /// Removes a header returning its combined value.
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        self.raw.retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+        self.values.remove(&name.to_ascii_lowercase())
+    }
+
+    /// Returns the value of the header, combined if it was sent more than once.
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.values.get(&name.to_ascii_lowercase())
+    }
+
+    /// Returns every value received for the header in order, without combining them.
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.raw
+            .iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.values.contains_key(&name.to_ascii_lowercase())
+    }
+
+    /// Returns the number of distinct headers.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Iterates over the headers as they were received, including repeated ones.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.raw
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+}
+
+impl From<HashMap<String, String>> for Headers {
+    fn from(map: HashMap<String, String>) -> Self {
+        let mut headers = Headers::new();
+        for (key, value) in &map {
+            headers.insert(key, value);
+        }
+        headers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_is_case_insensitive() {
+        let mut headers = Headers::new();
+        headers.append("Content-Type", "text/plain").unwrap();
+        assert_eq!(headers.get("content-type"), Some(&"text/plain".to_string()));
+        assert_eq!(headers.get("CONTENT-TYPE"), Some(&"text/plain".to_string()));
+        assert!(headers.contains("Content-type"));
+    }
+
+    #[test]
+    fn test_list_headers_are_combined() {
+        let mut headers = Headers::new();
+        headers.append("Accept", "text/html").unwrap();
+        headers.append("accept", "application/json").unwrap();
+        assert_eq!(
+            headers.get("Accept"),
+            Some(&"text/html, application/json".to_string())
+        );
+        assert_eq!(
+            headers.get_all("Accept"),
+            vec!["text/html", "application/json"]
+        );
+        assert_eq!(headers.len(), 1);
+    }
+
+    #[test]
+    fn test_cookie_headers_are_combined_with_semicolons() {
+        let mut headers = Headers::new();
+        headers.append("Cookie", "a=1").unwrap();
+        headers.append("Cookie", "b=2").unwrap();
+        assert_eq!(headers.get("Cookie"), Some(&"a=1; b=2".to_string()));
+    }
+
+    #[test]
+    fn test_singleton_headers_cannot_repeat() {
+        let mut headers = Headers::new();
+        headers.append("Content-Length", "5").unwrap();
+        let err = headers.append("content-length", "5").unwrap_err();
+        assert!(matches!(err, ApiErr::DuplicateHeader(_)));
+        assert_eq!(headers.get_all("Content-Length"), vec!["5"]);
+    }
+
+    #[test]
+    fn test_insert_replaces_previous_values() {
+        let mut headers = Headers::new();
+        headers.append("Accept", "text/html").unwrap();
+        headers.append("Accept", "text/plain").unwrap();
+        headers.insert("accept", "*/*");
+        assert_eq!(headers.get("Accept"), Some(&"*/*".to_string()));
+        assert_eq!(headers.get_all("Accept"), vec!["*/*"]);
+    }
+}
@@ -5,6 +5,8 @@ use crate::api_err::ApiErr;
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum HttpMethod {
     Get,
+    /// Answered like a `GET` but without the body
+    Head,
     Post,
     Put,
     Delete,
@@ -15,6 +17,7 @@ impl HttpMethod {
     pub fn from_string(verb: &str) -> Result<HttpMethod, ApiErr> {
         match verb {
             "GET" => Ok(HttpMethod::Get),
+            "HEAD" => Ok(HttpMethod::Head),
             "POST" => Ok(HttpMethod::Post),
             "PUT" => Ok(HttpMethod::Put),
             "DELETE" => Ok(HttpMethod::Delete),
@@ -22,12 +25,22 @@ impl HttpMethod {
             _ => Err(ApiErr::InvalidMethod),
         }
     }
+
+    /// Returns the method of the routes that answer it. `HEAD` requests are answered
+    /// by the `GET` routes, the body their handlers send is left out
+    pub fn routed_as(self) -> HttpMethod {
+        match self {
+            HttpMethod::Head => HttpMethod::Get,
+            method => method,
+        }
+    }
 }
 
 impl Display for HttpMethod {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let verb = match self {
             HttpMethod::Get => "GET",
+            HttpMethod::Head => "HEAD",
             HttpMethod::Post => "POST",
             HttpMethod::Put => "PUT",
             HttpMethod::Delete => "DELETE",
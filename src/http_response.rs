@@ -0,0 +1,228 @@
+use crate::http_status::HttpStatus;
+use serde_json::Value;
+use std::fmt::Display;
+
+/// A response built by a handler instead of written right away with `ctx.string` and
+/// friends, so response middlewares can inspect and modify it before it's sent.
+/// See [`Router::get_response`](crate::router::Router::get_response).
+/// # Example
+/// ```
+/// use HTTP_Server::http_response::HttpResponse;
+/// use HTTP_Server::http_status::HttpStatus;
+/// use serde_json::json;
+///
+/// let response = HttpResponse::created()
+///     .header("Location", "/users/1")
+///     .json(&json!({"id": 1}));
+/// assert_eq!(response.status(), HttpStatus::Created);
+/// assert_eq!(response.get_header("content-type"), Some("application/json"));
+/// assert_eq!(response.body(), br#"{"id":1}"#);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpResponse {
+    status: HttpStatus,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn new(status: HttpStatus) -> HttpResponse {
+        HttpResponse {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn ok() -> HttpResponse {
+        HttpResponse::new(HttpStatus::Ok)
+    }
+
+    pub fn created() -> HttpResponse {
+        HttpResponse::new(HttpStatus::Created)
+    }
+
+    pub fn no_content() -> HttpResponse {
+        HttpResponse::new(HttpStatus::NoContent)
+    }
+
+    pub fn bad_request() -> HttpResponse {
+        HttpResponse::new(HttpStatus::BadRequest)
+    }
+
+    pub fn not_found() -> HttpResponse {
+        HttpResponse::new(HttpStatus::NotFound)
+    }
+
+    /// Set a header, replacing any previous value
+    pub fn header<K: Display, V: Display>(mut self, key: K, value: V) -> Self {
+        self.set_header(key, value);
+        self
+    }
+
+    /// Set a plain text body
+    pub fn text(self, body: &str) -> Self {
+        self.with_body("text/plain", body)
+    }
+
+    /// Set an html body
+    pub fn html(self, body: &str) -> Self {
+        self.with_body("text/html; charset=utf-8", body)
+    }
+
+    /// Set a json body
+    pub fn json(self, body: &Value) -> Self {
+        self.with_body("application/json", &body.to_string())
+    }
+
+    /// Set a binary body, like an image or a protobuf message
+    pub fn bytes(mut self, content_type: &str, body: &[u8]) -> Self {
+        self.set_header("Content-Type", content_type);
+        self.body = body.to_vec();
+        self
+    }
+
+    fn with_body(self, content_type: &str, body: &str) -> Self {
+        self.bytes(content_type, body.as_bytes())
+    }
+
+    pub fn status(&self) -> HttpStatus {
+        self.status
+    }
+
+    pub fn set_status(&mut self, status: HttpStatus) {
+        self.status = status;
+    }
+
+    /// Returns the value of the header, the name is case insensitive
+    pub fn get_header(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn set_header<K: Display, V: Display>(&mut self, key: K, value: V) {
+        let key = key.to_string();
+        self.remove_header(&key);
+        self.headers.push((key, value.to_string()));
+    }
+
+    pub fn remove_header(&mut self, key: &str) -> Option<String> {
+        let index = self
+            .headers
+            .iter()
+            .position(|(k, _)| k.eq_ignore_ascii_case(key))?;
+        Some(self.headers.remove(index).1)
+    }
+
+    /// Returns the headers in the order they were set
+    pub fn headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    pub fn set_body<B: Into<Vec<u8>>>(&mut self, body: B) {
+        self.body = body.into();
+    }
+
+    /// Parses a response written by a [`Context`](crate::context::Context), decoding its
+    /// body if it was streamed in chunks. Headers that appear more than once, like
+    /// `Set-Cookie`, are all kept. Interim `1xx` responses written before it, like
+    /// early hints, are skipped. Returns `None` if it isn't a complete response
+    pub(crate) fn parse(mut raw: &[u8]) -> Option<HttpResponse> {
+        let (end, head, code) = loop {
+            let end = raw.windows(4).position(|w| w == b"\r\n\r\n")?;
+            let head = std::str::from_utf8(&raw[..end]).ok()?;
+            let code: u16 = head.split(' ').nth(1)?.parse().ok()?;
+            if !(100..200).contains(&code) {
+                break (end, head, code);
+            }
+            raw = &raw[end + 4..];
+        };
+        let lines = head.split("\r\n").skip(1);
+        let mut response = HttpResponse::new(HttpStatus::from_code(code)?);
+        for line in lines {
+            let (key, value) = line.split_once(':')?;
+            response
+                .headers
+                .push((key.to_string(), value.trim().to_string()));
+        }
+
+        let body = &raw[end + 4..];
+        match response.remove_header("Transfer-Encoding") {
+            Some(_) => response.body = decode_chunks(body)?,
+            None => response.body = body.to_vec(),
+        }
+        Some(response)
+    }
+}
+
+/// Returns the data of a `Transfer-Encoding: chunked` body
+fn decode_chunks(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    loop {
+        let line_end = body.windows(2).position(|w| w == b"\r\n")?;
+        let size = std::str::from_utf8(&body[..line_end]).ok()?;
+        let size = usize::from_str_radix(size, 16).ok()?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Some(data);
+        }
+        data.extend_from_slice(body.get(..size)?);
+        body = body.get(size + 2..)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_headers() {
+        let mut response = HttpResponse::ok()
+            .header("X-Id", 1)
+            .header("Content-Type", "text/csv")
+            .text("a");
+        assert_eq!(response.get_header("x-id"), Some("1"));
+        assert_eq!(response.get_header("Content-Type"), Some("text/plain"));
+
+        response.set_header("x-id", 2);
+        assert_eq!(response.headers().count(), 2);
+        assert_eq!(response.remove_header("X-ID"), Some("2".to_string()));
+        assert_eq!(response.get_header("X-Id"), None);
+    }
+
+    #[test]
+    fn test_parse() {
+        let raw = b"HTTP/1.1 201 Created\r\nContent-Length: 2\r\nSet-Cookie: a=1\r\nSet-Cookie: b=2\r\n\r\nok";
+        let response = HttpResponse::parse(raw).unwrap();
+        assert_eq!(response.status(), HttpStatus::Created);
+        assert_eq!(
+            response
+                .headers()
+                .filter(|(k, _)| *k == "Set-Cookie")
+                .count(),
+            2
+        );
+        assert_eq!(response.body(), b"ok");
+
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\nA\r\n0123456789\r\n0\r\n\r\n";
+        let response = HttpResponse::parse(raw).unwrap();
+        assert_eq!(response.get_header("Transfer-Encoding"), None);
+        assert_eq!(response.body(), b"abc0123456789");
+
+        let raw = b"HTTP/1.1 103 Early Hints\r\nLink: </app.css>\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok";
+        let response = HttpResponse::parse(raw).unwrap();
+        assert_eq!(response.status(), HttpStatus::Ok);
+        assert_eq!(response.get_header("Link"), None);
+        assert_eq!(response.body(), b"ok");
+        assert!(HttpResponse::parse(b"HTTP/1.1 103 Early Hints\r\n\r\n").is_none());
+
+        assert!(HttpResponse::parse(b"HTTP/1.1 200 OK\r\n").is_none());
+        assert!(HttpResponse::parse(b"HTTP/1.1 299 Odd\r\n\r\n").is_none());
+    }
+}
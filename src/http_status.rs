@@ -1,15 +1,75 @@
 use std::fmt::Display;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum HttpStatus {
     Ok,
     Created,
+    Accepted,
     NoContent,
+    PartialContent,
+    NotModified,
     BadRequest,
+    Forbidden,
     NotFound,
+    NotAcceptable,
+    RequestTimeout,
     Conflict,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    RangeNotSatisfiable,
+    ExpectationFailed,
+    MisdirectedRequest,
     UnprocessableEntity,
+    RequestHeaderFieldsTooLarge,
     InternalServerError,
+    HttpVersionNotSupported,
+}
+
+/// Every status, in the order they are declared
+const ALL: [HttpStatus; 21] = [
+    HttpStatus::Ok,
+    HttpStatus::Created,
+    HttpStatus::Accepted,
+    HttpStatus::NoContent,
+    HttpStatus::PartialContent,
+    HttpStatus::NotModified,
+    HttpStatus::BadRequest,
+    HttpStatus::Forbidden,
+    HttpStatus::NotFound,
+    HttpStatus::NotAcceptable,
+    HttpStatus::RequestTimeout,
+    HttpStatus::Conflict,
+    HttpStatus::PayloadTooLarge,
+    HttpStatus::UnsupportedMediaType,
+    HttpStatus::RangeNotSatisfiable,
+    HttpStatus::ExpectationFailed,
+    HttpStatus::MisdirectedRequest,
+    HttpStatus::UnprocessableEntity,
+    HttpStatus::RequestHeaderFieldsTooLarge,
+    HttpStatus::InternalServerError,
+    HttpStatus::HttpVersionNotSupported,
+];
+
+impl HttpStatus {
+    /// Returns the status with the code, like `404`, if it's one of the known ones
+    pub fn from_code(code: u16) -> Option<HttpStatus> {
+        let code = code.to_string();
+        ALL.into_iter()
+            .find(|status| status.to_string().split(' ').next() == Some(code.as_str()))
+    }
+
+    /// Returns whether the status is a `2xx`
+    pub fn is_success(&self) -> bool {
+        self.to_string().starts_with('2')
+    }
+
+    /// Returns whether a response with the status can have a body. Informational
+    /// statuses, `204 No Content` and `304 Not Modified` never do
+    pub fn allows_body(&self) -> bool {
+        !self.to_string().starts_with('1')
+            && *self != HttpStatus::NoContent
+            && *self != HttpStatus::NotModified
+    }
 }
 
 impl Display for HttpStatus {
@@ -17,12 +77,25 @@ impl Display for HttpStatus {
         let code = match self {
             HttpStatus::Ok => "200 OK",
             HttpStatus::Created => "201 Created",
+            HttpStatus::Accepted => "202 Accepted",
             HttpStatus::NoContent => "204 No Content",
+            HttpStatus::PartialContent => "206 Partial Content",
+            HttpStatus::NotModified => "304 Not Modified",
             HttpStatus::BadRequest => "400 Bad Request",
+            HttpStatus::Forbidden => "403 Forbidden",
             HttpStatus::NotFound => "404 Not Found",
+            HttpStatus::NotAcceptable => "406 Not Acceptable",
+            HttpStatus::RequestTimeout => "408 Request Timeout",
             HttpStatus::Conflict => "409 Conflict",
+            HttpStatus::PayloadTooLarge => "413 Payload Too Large",
+            HttpStatus::UnsupportedMediaType => "415 Unsupported Media Type",
+            HttpStatus::RangeNotSatisfiable => "416 Range Not Satisfiable",
+            HttpStatus::ExpectationFailed => "417 Expectation Failed",
+            HttpStatus::MisdirectedRequest => "421 Misdirected Request",
             HttpStatus::UnprocessableEntity => "422 Unprocessable Entity",
+            HttpStatus::RequestHeaderFieldsTooLarge => "431 Request Header Fields Too Large",
             HttpStatus::InternalServerError => "500 Internal Server Error",
+            HttpStatus::HttpVersionNotSupported => "505 HTTP Version Not Supported",
         };
 
         write!(f, "{}", code)
@@ -0,0 +1,93 @@
+use std::fmt::Display;
+
+use crate::api_err::ApiErr;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum HttpVersion {
+    Http10,
+    Http11,
+}
+
+impl HttpVersion {
+    /// Parses the version of a request line.
+    /// Fails with `ApiErr::InvalidRequest` if it isn't an HTTP version at all and with
+    /// `ApiErr::VersionNotSupported` for well formed versions other than 1.0 and 1.1.
+    pub fn from_string(version: &str) -> Result<HttpVersion, ApiErr> {
+        match version {
+            "HTTP/1.0" => Ok(HttpVersion::Http10),
+            "HTTP/1.1" => Ok(HttpVersion::Http11),
+            _ => {
+                let number = version
+                    .strip_prefix("HTTP/")
+                    .ok_or(ApiErr::InvalidRequest)?;
+                let valid = match number.split_once('.') {
+                    Some((major, minor)) => is_digit(major) && is_digit(minor),
+                    None => is_digit(number),
+                };
+                if valid {
+                    Err(ApiErr::VersionNotSupported)
+                } else {
+                    Err(ApiErr::InvalidRequest)
+                }
+            }
+        }
+    }
+}
+
+fn is_digit(s: &str) -> bool {
+    s.len() == 1 && s.bytes().all(|b| b.is_ascii_digit())
+}
+
+impl Display for HttpVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let version = match self {
+            HttpVersion::Http10 => "HTTP/1.0",
+            HttpVersion::Http11 => "HTTP/1.1",
+        };
+        write!(f, "{}", version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_string() {
+        assert_eq!(
+            HttpVersion::from_string("HTTP/1.0").unwrap(),
+            HttpVersion::Http10
+        );
+        assert_eq!(
+            HttpVersion::from_string("HTTP/1.1").unwrap(),
+            HttpVersion::Http11
+        );
+    }
+
+    #[test]
+    fn test_from_string_unsupported() {
+        for version in ["HTTP/2.0", "HTTP/2", "HTTP/0.9", "HTTP/3"] {
+            assert!(matches!(
+                HttpVersion::from_string(version),
+                Err(ApiErr::VersionNotSupported)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_from_string_invalid() {
+        for version in [
+            "",
+            "http/1.1",
+            "HTTP/1.1.1",
+            "HTTP/11.1",
+            "HTTP/a.b",
+            "FOO/1.1",
+        ] {
+            assert!(matches!(
+                HttpVersion::from_string(version),
+                Err(ApiErr::InvalidRequest)
+            ));
+        }
+    }
+}
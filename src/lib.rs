@@ -1,10 +1,51 @@
+#![allow(non_snake_case)]
 
-pub mod http_status;
-pub mod router;
-pub mod server;
-pub mod context;
+pub mod accept;
+#[cfg(unix)]
+pub mod activation;
+pub mod alt_svc;
 pub mod api_err;
+pub mod bots;
+pub mod budget;
+pub mod compression;
+pub mod config;
+pub mod context;
+pub mod cookie;
+pub mod csv;
+pub mod diagnostics;
+pub mod etag;
+pub mod geoip;
+pub mod headers;
 pub mod http_method;
 pub mod http_request;
+pub mod http_response;
+pub mod http_status;
+pub mod http_version;
+pub mod links;
+pub mod localization;
+#[cfg(feature = "markdown")]
+pub mod markdown;
+pub mod mime;
+#[cfg(feature = "mmdb")]
+pub mod mmdb;
+#[cfg(feature = "msgpack")]
+pub mod msgpack;
+pub mod negotiation;
+pub mod patch;
+#[cfg(target_os = "linux")]
+pub mod prefork;
+pub mod problem;
+pub mod proxy;
+pub mod query;
+pub mod range;
+pub mod router;
+pub mod rules;
+pub mod scrub;
+pub mod server;
+#[cfg(all(unix, any(feature = "signals", target_os = "linux")))]
+mod signals;
+pub mod sniff;
+pub mod static_files;
+pub mod subsystems;
+pub mod tarpit;
 pub mod utils;
-
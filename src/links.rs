@@ -0,0 +1,126 @@
+//! Hypermedia links added to json responses as `_links` (HATEOAS), so clients can
+//! follow them instead of building urls.
+//!
+//! A route asks for links with its `links` metadata, a comma separated list of:
+//! - `self`: the url of the request
+//! - `pagination`: `first`, `prev` and `next` pages, changing the `page` query param
+//! - `rel=name`: the url of the route named `name`, with the path params of the request
+//!
+//! Handlers can change them with [`Context::link`](crate::context::Context::link) and
+//! [`Context::remove_link`](crate::context::Context::remove_link), like removing `next`
+//! on the last page.
+//! # Example
+//! ```
+//! use HTTP_Server::context::Context;
+//! use HTTP_Server::http_status::HttpStatus;
+//! use HTTP_Server::router::Router;
+//! use serde_json::json;
+//!
+//! fn posts(ctx: &mut Context) {
+//!     ctx.json(HttpStatus::Ok, json!({"posts": []}));
+//! }
+//!
+//! fn user(ctx: &mut Context) {
+//!     ctx.json(HttpStatus::Ok, json!({"name": ctx.param("name")}));
+//! }
+//!
+//! let mut router = Router::new();
+//! router
+//!     .get("/users/{name}", user)
+//!     .name("user")
+//!     .get("/users/{name}/posts", posts)
+//!     .metadata("links", "self, pagination, author=user");
+//! ```
+
+use crate::query;
+use crate::utils::percent;
+use serde_json::{json, Map, Value};
+
+/// Key of the route metadata listing its links
+pub const METADATA_KEY: &str = "links";
+
+/// Returns the url of the request with the `page` query param set to the page
+fn with_page(path: &str, query: Option<&str>, page: u64) -> String {
+    let mut pairs: Vec<String> = query
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| {
+            let key = pair.split_once('=').map_or(*pair, |(key, _)| key);
+            !pair.is_empty() && percent::decode(key).as_deref() != Some("page")
+        })
+        .map(|pair| pair.to_string())
+        .collect();
+    pairs.push(format!("page={page}"));
+    format!("{path}?{}", pairs.join("&"))
+}
+
+/// Returns the `first`, `prev` and `next` links of a paginated request, whose
+/// current page is in the `page` query param, 1 if it's missing or malformed
+pub(crate) fn pagination(path: &str, query: Option<&str>) -> Vec<(String, String)> {
+    let page = query::parse(query.unwrap_or_default())
+        .ok()
+        .and_then(|pairs| pairs.into_iter().find(|(key, _)| key == "page"))
+        .and_then(|(_, page)| page.parse::<u64>().ok())
+        .filter(|page| *page > 0)
+        .unwrap_or(1);
+
+    let mut links = vec![("first".to_string(), with_page(path, query, 1))];
+    if page > 1 {
+        links.push(("prev".to_string(), with_page(path, query, page - 1)));
+    }
+    links.push(("next".to_string(), with_page(path, query, page + 1)));
+    links
+}
+
+/// Returns the json body with the links in its `_links`, as `{"rel": {"href": url}}`.
+/// Bodies that aren't objects or already have `_links` are left as they are
+pub(crate) fn inject(body: &Value, links: &[(String, String)]) -> Option<Value> {
+    let Value::Object(object) = body else {
+        return None;
+    };
+    if links.is_empty() || object.contains_key("_links") {
+        return None;
+    }
+    let links: Map<String, Value> = links
+        .iter()
+        .map(|(rel, href)| (rel.clone(), json!({ "href": href })))
+        .collect();
+    let mut object = object.clone();
+    object.insert("_links".to_string(), Value::Object(links));
+    Some(Value::Object(object))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pagination() {
+        let links = pagination("/posts", Some("tag=a%20b&page=3"));
+        assert_eq!(
+            links,
+            [
+                ("first".to_string(), "/posts?tag=a%20b&page=1".to_string()),
+                ("prev".to_string(), "/posts?tag=a%20b&page=2".to_string()),
+                ("next".to_string(), "/posts?tag=a%20b&page=4".to_string()),
+            ]
+        );
+        let links = pagination("/posts", None);
+        assert_eq!(links.len(), 2);
+        assert_eq!(links[1], ("next".to_string(), "/posts?page=2".to_string()));
+        assert_eq!(pagination("/posts", Some("page=x")).len(), 2);
+    }
+
+    #[test]
+    fn test_inject() {
+        let links = [("self".to_string(), "/users/1".to_string())];
+        let body = inject(&json!({"id": 1}), &links).unwrap();
+        assert_eq!(
+            body,
+            json!({"id": 1, "_links": {"self": {"href": "/users/1"}}})
+        );
+        assert!(inject(&json!([1]), &links).is_none());
+        assert!(inject(&json!({"_links": {}}), &links).is_none());
+        assert!(inject(&json!({"id": 1}), &[]).is_none());
+    }
+}
@@ -0,0 +1,243 @@
+use crate::api_err::ApiErr;
+use crate::negotiation::negotiate_language;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Extension of the catalog files loaded by [`Catalogs::load_dir`]
+const CATALOG_EXTENSION: &str = "ftl";
+
+/// Messages of a single locale, written in a small subset of the Fluent syntax:
+/// ```text
+/// # Comments start with a hash
+/// welcome = Welcome, { $name }!
+/// long-message = Values can continue
+///     in indented lines.
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Catalog {
+    messages: HashMap<String, String>,
+}
+
+impl Catalog {
+    /// Parses the messages of a catalog file
+    /// # Example
+    /// ```
+    /// use HTTP_Server::localization::Catalog;
+    ///
+    /// let catalog = Catalog::parse("hello = Hello, { $name }!").unwrap();
+    /// assert_eq!(catalog.format("hello", &[("name", "Ana")]), Some("Hello, Ana!".to_string()));
+    /// assert!(Catalog::parse("not a message").is_err());
+    /// ```
+    pub fn parse(source: &str) -> Result<Catalog, ApiErr> {
+        let mut messages: HashMap<String, String> = HashMap::new();
+        let mut last_key: Option<String> = None;
+        for (number, line) in source.lines().enumerate() {
+            let invalid =
+                |reason: &str| ApiErr::InvalidCatalog(format!("line {}: {reason}", number + 1));
+            if line.trim().is_empty() || line.trim_start().starts_with('#') {
+                continue;
+            }
+
+            if line.starts_with([' ', '\t']) {
+                let key = last_key
+                    .as_ref()
+                    .ok_or_else(|| invalid("continuation without a message"))?;
+                let value = messages.entry(key.clone()).or_default();
+                if !value.is_empty() {
+                    value.push('\n');
+                }
+                value.push_str(line.trim());
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| invalid("expected key = value"))?;
+            let key = key.trim();
+            let valid_key = key.starts_with(|c: char| c.is_ascii_alphabetic())
+                && key
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !valid_key {
+                return Err(invalid("invalid message key"));
+            }
+            if messages
+                .insert(key.to_string(), value.trim().to_string())
+                .is_some()
+            {
+                return Err(invalid("duplicated message key"));
+            }
+            last_key = Some(key.to_string());
+        }
+        Ok(Catalog { messages })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.messages.get(key).map(|m| m.as_str())
+    }
+
+    /// Returns the message replacing its `{ $name }` placeables with the args.
+    /// Placeables without an arg are left as is.
+    pub fn format(&self, key: &str, args: &[(&str, &str)]) -> Option<String> {
+        let mut message = self.get(key)?.to_string();
+        for (name, value) in args {
+            message = message
+                .replace(&format!("{{ ${name} }}"), value)
+                .replace(&format!("{{${name}}}"), value);
+        }
+        Some(message)
+    }
+}
+
+/// Message catalogs of every locale the server is translated to.
+/// Lookups fall back to the default locale when a message is missing.
+#[derive(Debug, Clone, Default)]
+pub struct Catalogs {
+    default_locale: Option<String>,
+    catalogs: HashMap<String, Catalog>,
+    /// Locales in the order they were added, used to break negotiation ties
+    locales: Vec<String>,
+}
+
+impl Catalogs {
+    pub fn new(default_locale: &str) -> Catalogs {
+        Catalogs {
+            default_locale: Some(default_locale.to_string()),
+            ..Catalogs::default()
+        }
+    }
+
+    /// Adds the catalog of a locale from its source, usually bundled with `include_str!`
+    /// # Example
+    /// ```
+    /// use HTTP_Server::localization::Catalogs;
+    ///
+    /// let mut catalogs = Catalogs::new("en");
+    /// catalogs
+    ///     .add("en", "greeting = Hello")
+    ///     .unwrap()
+    ///     .add("es", "greeting = Hola")
+    ///     .unwrap();
+    /// assert_eq!(catalogs.negotiate(Some("es-AR, en;q=0.5")), Some("es"));
+    /// assert_eq!(catalogs.message("es", "greeting", &[]), Some("Hola".to_string()));
+    /// ```
+    pub fn add(&mut self, locale: &str, source: &str) -> Result<&mut Self, ApiErr> {
+        let catalog = Catalog::parse(source)?;
+        if self.catalogs.insert(locale.to_string(), catalog).is_none() {
+            self.locales.push(locale.to_string());
+        }
+        Ok(self)
+    }
+
+    /// Adds every `<locale>.ftl` file of the directory
+    pub fn load_dir<P: AsRef<Path>>(&mut self, dir: P) -> Result<&mut Self, ApiErr> {
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(dir).map_err(ApiErr::StreamError)? {
+            let path = entry.map_err(ApiErr::StreamError)?.path();
+            if path.extension().is_some_and(|ext| ext == CATALOG_EXTENSION) {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        for path in paths {
+            let Some(locale) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            let source = fs::read_to_string(&path).map_err(ApiErr::StreamError)?;
+            self.add(locale, &source)?;
+        }
+        Ok(self)
+    }
+
+    pub fn default_locale(&self) -> Option<&str> {
+        self.default_locale.as_deref()
+    }
+
+    pub fn locales(&self) -> Vec<&str> {
+        self.locales.iter().map(|l| l.as_str()).collect()
+    }
+
+    /// Picks the locale to answer with from the `Accept-Language` header,
+    /// falling back to the default locale
+    pub fn negotiate(&self, accept_language: Option<&str>) -> Option<&str> {
+        negotiate_language(accept_language, &self.locales()).or(self.default_locale())
+    }
+
+    /// Returns the formatted message of the locale, or of the default locale if the
+    /// locale doesn't have it
+    pub fn message(&self, locale: &str, key: &str, args: &[(&str, &str)]) -> Option<String> {
+        let localized = self.catalogs.get(locale).and_then(|c| c.format(key, args));
+        localized.or_else(|| {
+            let default = self.catalogs.get(self.default_locale()?)?;
+            default.format(key, args)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catalog_parse() {
+        let source = "# greetings\nhello = Hello\n\nlong = First line\n    second line\n";
+        let catalog = Catalog::parse(source).unwrap();
+        assert_eq!(catalog.get("hello"), Some("Hello"));
+        assert_eq!(catalog.get("long"), Some("First line\nsecond line"));
+        assert_eq!(catalog.get("missing"), None);
+    }
+
+    #[test]
+    fn test_catalog_parse_errors() {
+        for source in ["  orphan", "no equals", "1key = x", "a = 1\na = 2"] {
+            assert!(Catalog::parse(source).is_err(), "{source}");
+        }
+    }
+
+    #[test]
+    fn test_catalog_format() {
+        let catalog = Catalog::parse("hi = Hi { $name }, {$name}! { $other }").unwrap();
+        assert_eq!(
+            catalog.format("hi", &[("name", "Ana")]),
+            Some("Hi Ana, Ana! { $other }".to_string())
+        );
+    }
+
+    #[test]
+    fn test_catalogs_fallback_to_default_locale() {
+        let mut catalogs = Catalogs::new("en");
+        catalogs
+            .add("en", "hello = Hello\nbye = Bye")
+            .unwrap()
+            .add("es", "hello = Hola")
+            .unwrap();
+        assert_eq!(catalogs.message("es", "bye", &[]), Some("Bye".to_string()));
+        assert_eq!(
+            catalogs.message("fr", "hello", &[]),
+            Some("Hello".to_string())
+        );
+        assert_eq!(catalogs.message("es", "missing", &[]), None);
+        assert_eq!(catalogs.negotiate(Some("fr")), Some("en"));
+        assert_eq!(catalogs.negotiate(None), Some("en"));
+    }
+
+    #[test]
+    fn test_catalogs_load_dir() {
+        let dir = std::env::temp_dir().join(format!("catalogs-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("en.ftl"), "hello = Hello").unwrap();
+        fs::write(dir.join("pt-BR.ftl"), "hello = Olá").unwrap();
+        fs::write(dir.join("notes.txt"), "ignored").unwrap();
+
+        let mut catalogs = Catalogs::new("en");
+        let result = catalogs.load_dir(&dir).map(|c| c.locales().len());
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(result.unwrap(), 2);
+        assert_eq!(
+            catalogs.message("pt-BR", "hello", &[]),
+            Some("Olá".to_string())
+        );
+    }
+}
@@ -0,0 +1,446 @@
+//! Markdown pages rendered to html, to serve a directory of docs as a small wiki.
+//! See [`StaticDir::markdown`](crate::static_files::StaticDir::markdown).
+//!
+//! The common subset of Markdown is supported: headings, paragraphs, emphasis, code
+//! spans and fenced blocks, links, images, lists, block quotes and rules. Raw html is
+//! escaped, so pages can't inject scripts.
+
+use crate::context::{escape_html, Context};
+use crate::http_status::HttpStatus;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+const DEFAULT_TEMPLATE: &str = "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{title}</title></head><body>\n{content}</body></html>";
+
+/// A rendered page with the modification time and size of its file
+type Page = (SystemTime, u64, Arc<String>);
+
+/// Renders Markdown files into an html template, keeping the pages until their file
+/// changes. Clones share the cached pages.
+/// # Example
+/// ```
+/// use HTTP_Server::markdown::Markdown;
+/// use HTTP_Server::router::Router;
+/// use HTTP_Server::static_files::StaticDir;
+///
+/// let mut router = Router::new();
+/// // GET /docs/guide serves ./docs/guide.md and GET /docs/ serves ./docs/index.md
+/// router.mount_static(
+///     "/docs",
+///     StaticDir::new("./docs").markdown(
+///         Markdown::new().template("<html><title>{title} - Wiki</title><main>{content}</main></html>"),
+///     ),
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct Markdown {
+    template: String,
+    /// Rendered pages by their file
+    cache: Arc<Mutex<HashMap<PathBuf, Page>>>,
+}
+
+impl Default for Markdown {
+    fn default() -> Self {
+        Markdown {
+            template: DEFAULT_TEMPLATE.to_string(),
+            cache: Arc::default(),
+        }
+    }
+}
+
+impl Markdown {
+    pub fn new() -> Markdown {
+        Markdown::default()
+    }
+
+    /// Set the html the pages are rendered into, where `{content}` is replaced by the
+    /// rendered page and `{title}` by its first heading, or the file name without one
+    pub fn template(mut self, template: &str) -> Self {
+        self.template = template.to_string();
+        self
+    }
+
+    /// Returns the page of the file, rendering it if it changed since the last time
+    pub fn render_file(&self, path: &Path) -> io::Result<Arc<String>> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata.modified()?;
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((time, len, page)) = cache.get(path) {
+            if *time == modified && *len == metadata.len() {
+                return Ok(Arc::clone(page));
+            }
+        }
+        let source = fs::read_to_string(path)?;
+        let title = title(&source).unwrap_or_else(|| {
+            let stem = path.file_stem().unwrap_or_default();
+            stem.to_string_lossy().to_string()
+        });
+        let content = to_html(&source);
+        let page: String = self
+            .template
+            .split("{content}")
+            .map(|part| part.replace("{title}", &escape_html(&title)))
+            .collect::<Vec<_>>()
+            .join(&content);
+        let page = Arc::new(page);
+        cache.insert(
+            path.to_path_buf(),
+            (modified, metadata.len(), Arc::clone(&page)),
+        );
+        Ok(page)
+    }
+
+    /// Answers the request with the page of the file
+    pub(crate) fn serve(&self, ctx: &mut Context, path: &Path) {
+        match self.render_file(path) {
+            Ok(page) => ctx.html(HttpStatus::Ok, &page),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                ctx.string(HttpStatus::NotFound, "Not Found")
+            }
+            Err(_) => ctx.string(HttpStatus::Forbidden, "Forbidden"),
+        }
+    }
+}
+
+/// Returns the text of the first heading
+fn title(source: &str) -> Option<String> {
+    source.lines().find_map(|line| {
+        let (level, text) = heading(line)?;
+        (level == 1).then(|| text.to_string())
+    })
+}
+
+/// Returns the level and text of an ATX heading like `## Install`
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let line = line.trim_start();
+    let level = line.bytes().take_while(|b| *b == b'#').count();
+    let text = &line[level..];
+    let valid = (1..=6).contains(&level) && (text.is_empty() || text.starts_with(' '));
+    valid.then(|| (level, text.trim().trim_end_matches('#').trim_end()))
+}
+
+/// Returns whether the line is a thematic break like `---` or `* * *`
+fn is_rule(line: &str) -> bool {
+    let chars: Vec<char> = line.chars().filter(|c| !c.is_whitespace()).collect();
+    chars.len() >= 3 && ['-', '*', '_'].iter().any(|m| chars.iter().all(|c| c == m))
+}
+
+/// Returns whether the line is an ordered list item and the text after its marker
+fn list_item(line: &str) -> Option<(bool, &str)> {
+    let trimmed = line.trim_start();
+    if let Some(text) = ["- ", "* ", "+ "]
+        .iter()
+        .find_map(|marker| trimmed.strip_prefix(marker))
+    {
+        return Some((false, text));
+    }
+    let digits = trimmed.bytes().take_while(|b| b.is_ascii_digit()).count();
+    let text = trimmed[digits..].strip_prefix(". ")?;
+    (digits > 0 && digits <= 9).then_some((true, text))
+}
+
+fn indentation(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+/// Returns the id of a heading, its lowercase words joined with dashes
+fn slug(text: &str) -> String {
+    let words: String = text
+        .chars()
+        .filter(|c| c.is_alphanumeric() || c.is_whitespace() || *c == '-')
+        .collect();
+    words
+        .to_lowercase()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Renders Markdown to html
+/// # Example
+/// ```
+/// use HTTP_Server::markdown;
+///
+/// assert_eq!(
+///     markdown::to_html("# Title\n\nSome *text* with `code`."),
+///     "<h1 id=\"title\">Title</h1>\n<p>Some <em>text</em> with <code>code</code>.</p>\n"
+/// );
+/// ```
+pub fn to_html(source: &str) -> String {
+    let lines: Vec<&str> = source.lines().collect();
+    let mut html = String::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            i += 1;
+        } else if let Some(fence) = ["```", "~~~"].into_iter().find(|f| trimmed.starts_with(f)) {
+            let language = trimmed[3..].trim();
+            let end = lines[i + 1..]
+                .iter()
+                .position(|l| l.trim().starts_with(fence))
+                .map_or(lines.len(), |end| i + 1 + end);
+            let code: String = lines[i + 1..end]
+                .iter()
+                .map(|l| escape_html(l) + "\n")
+                .collect();
+            match language.is_empty() {
+                true => html += "<pre><code>",
+                false => {
+                    html += &format!("<pre><code class=\"language-{}\">", escape_html(language))
+                }
+            }
+            html += &format!("{code}</code></pre>\n");
+            i = end + 1;
+        } else if let Some((level, text)) = heading(line) {
+            html += &format!(
+                "<h{level} id=\"{}\">{}</h{level}>\n",
+                slug(text),
+                inline(text)
+            );
+            i += 1;
+        } else if is_rule(trimmed) {
+            html += "<hr>\n";
+            i += 1;
+        } else if trimmed.starts_with('>') {
+            let mut quote = Vec::new();
+            while let Some(text) = lines.get(i).and_then(|l| l.trim_start().strip_prefix('>')) {
+                quote.push(text.strip_prefix(' ').unwrap_or(text));
+                i += 1;
+            }
+            html += &format!(
+                "<blockquote>\n{}</blockquote>\n",
+                to_html(&quote.join("\n"))
+            );
+        } else if let Some((ordered, _)) = list_item(line) {
+            i = list(&lines, i, ordered, &mut html);
+        } else {
+            let start = i;
+            while i < lines.len()
+                && !lines[i].trim().is_empty()
+                && (i == start || !starts_block(lines[i]))
+            {
+                i += 1;
+            }
+            let text: Vec<&str> = lines[start..i].iter().map(|l| l.trim()).collect();
+            html += &format!("<p>{}</p>\n", inline(&text.join("\n")));
+        }
+    }
+    html
+}
+
+/// Returns whether the line starts a block other than a paragraph, ending the paragraph
+fn starts_block(line: &str) -> bool {
+    let trimmed = line.trim();
+    heading(line).is_some()
+        || is_rule(trimmed)
+        || trimmed.starts_with('>')
+        || trimmed.starts_with("```")
+        || trimmed.starts_with("~~~")
+        || list_item(line).is_some()
+}
+
+/// Renders the list starting at the line, returning the line after it. The lines
+/// indented under an item, like a nested list, are rendered as its content
+fn list(lines: &[&str], mut i: usize, ordered: bool, html: &mut String) -> usize {
+    let tag = if ordered { "ol" } else { "ul" };
+    let indent = indentation(lines[i]);
+    *html += &format!("<{tag}>\n");
+    while let Some((item_ordered, text)) = lines.get(i).and_then(|l| list_item(l)) {
+        if item_ordered != ordered || indentation(lines[i]) != indent {
+            break;
+        }
+        let mut content = vec![text.to_string()];
+        i += 1;
+        while let Some(line) = lines.get(i) {
+            let continues = !line.trim().is_empty() && !starts_block(line);
+            if indentation(line) <= indent && !continues {
+                break;
+            }
+            content.push(line.trim_start().to_string());
+            i += 1;
+        }
+        let item = to_html(&content.join("\n"));
+        // The first paragraph is the text of the item, as in a tight list
+        let item = match item
+            .strip_prefix("<p>")
+            .and_then(|p| p.split_once("</p>\n"))
+        {
+            Some((text, rest)) => format!("{text}{rest}"),
+            None => item,
+        };
+        *html += &format!("<li>{item}</li>\n");
+        // A blank line between items keeps the list going
+        if lines.get(i).is_some_and(|l| l.trim().is_empty())
+            && lines.get(i + 1).and_then(|l| list_item(l)).is_some()
+        {
+            i += 1;
+        }
+    }
+    *html += &format!("</{tag}>\n");
+    i
+}
+
+/// Returns the url, or `#` if its scheme could run code like `javascript:`
+fn safe_url(url: &str) -> String {
+    let scheme = url
+        .split_once(':')
+        .map(|(scheme, _)| scheme.to_ascii_lowercase())
+        .filter(|scheme| !scheme.contains(['/', '?', '#']));
+    match scheme.as_deref() {
+        None | Some("http" | "https" | "mailto") => escape_html(url),
+        Some(_) => "#".to_string(),
+    }
+}
+
+/// Returns the text and url of a link starting at the `[`, and the length of the link
+fn link(text: &str) -> Option<(&str, &str, usize)> {
+    let mut depth = 0;
+    let close = text.char_indices().find_map(|(i, c)| {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            _ => {}
+        }
+        (depth == 0).then_some(i)
+    })?;
+    let rest = text[close + 1..].strip_prefix('(')?;
+    let end = rest.find(')')?;
+    let url = rest[..end].split_whitespace().next().unwrap_or_default();
+    Some((&text[1..close], url, close + end + 3))
+}
+
+/// Renders the inline elements of a block, escaping everything else
+fn inline(text: &str) -> String {
+    let mut html = String::new();
+    let mut i = 0;
+    let mut previous = ' ';
+    while let Some(c) = text[i..].chars().next() {
+        let rest = &text[i..];
+        let (rendered, len) = match c {
+            '\\' => match rest[1..].chars().next() {
+                Some(next) if next.is_ascii_punctuation() => (escape_html(&next.to_string()), 2),
+                _ => ("\\".to_string(), 1),
+            },
+            '`' => {
+                let ticks = rest.bytes().take_while(|b| *b == b'`').count();
+                match rest[ticks..].find(&rest[..ticks]) {
+                    Some(end) => {
+                        let code = rest[ticks..ticks + end].trim();
+                        (
+                            format!("<code>{}</code>", escape_html(code)),
+                            2 * ticks + end,
+                        )
+                    }
+                    None => (rest[..ticks].to_string(), ticks),
+                }
+            }
+            '!' if rest.starts_with("![") => match link(&rest[1..]) {
+                Some((alt, url, len)) => (
+                    format!(
+                        "<img src=\"{}\" alt=\"{}\">",
+                        safe_url(url),
+                        escape_html(alt)
+                    ),
+                    len + 1,
+                ),
+                None => ("!".to_string(), 1),
+            },
+            '[' => match link(rest) {
+                Some((label, url, len)) => (
+                    format!("<a href=\"{}\">{}</a>", safe_url(url), inline(label)),
+                    len,
+                ),
+                None => ("[".to_string(), 1),
+            },
+            '*' | '_' if c == '*' || !previous.is_alphanumeric() => {
+                let size = if rest[1..].starts_with(c) { 2 } else { 1 };
+                let delimiter = &rest[..size];
+                let inner = &rest[size..];
+                let end = inner
+                    .match_indices(delimiter)
+                    .map(|(end, _)| end)
+                    .find(|end| *end > 0 && !inner[..*end].ends_with(char::is_whitespace));
+                match end.filter(|_| !inner.starts_with(char::is_whitespace)) {
+                    Some(end) => {
+                        let tag = if size == 2 { "strong" } else { "em" };
+                        (
+                            format!("<{tag}>{}</{tag}>", inline(&inner[..end])),
+                            end + 2 * size,
+                        )
+                    }
+                    None => (delimiter.to_string(), size),
+                }
+            }
+            c => (escape_html(&c.to_string()), c.len_utf8()),
+        };
+        html += &rendered;
+        i += len;
+        previous = text[..i].chars().next_back().unwrap_or(' ');
+    }
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocks() {
+        let source = "# Guide #\n\nIntro line\nsecond line\n\n## Install it\n\n```sh\ncargo add <crate>\n```\n\n> quoted\n> *text*\n\n---\n\n- one\n- two\n  - nested\n\n1. first\n2. second\n";
+        assert_eq!(
+            to_html(source),
+            "<h1 id=\"guide\">Guide</h1>\n\
+             <p>Intro line\nsecond line</p>\n\
+             <h2 id=\"install-it\">Install it</h2>\n\
+             <pre><code class=\"language-sh\">cargo add &lt;crate&gt;\n</code></pre>\n\
+             <blockquote>\n<p>quoted\n<em>text</em></p>\n</blockquote>\n\
+             <hr>\n\
+             <ul>\n<li>one</li>\n<li>two<ul>\n<li>nested</li>\n</ul>\n</li>\n</ul>\n\
+             <ol>\n<li>first</li>\n<li>second</li>\n</ol>\n"
+        );
+    }
+
+    #[test]
+    fn test_inline() {
+        assert_eq!(
+            inline("**bold** and _em_ in snake_case_name, 2 * 3"),
+            "<strong>bold</strong> and <em>em</em> in snake_case_name, 2 * 3"
+        );
+        assert_eq!(
+            inline("see [the *docs*](https://example.com/a?b=1&c=2) ![logo](/logo.png)"),
+            "see <a href=\"https://example.com/a?b=1&amp;c=2\">the <em>docs</em></a> <img src=\"/logo.png\" alt=\"logo\">"
+        );
+        assert_eq!(
+            inline("``a ` b`` \\*not em\\*"),
+            "<code>a ` b</code> *not em*"
+        );
+        assert_eq!(
+            inline("<script>alert(1)</script> [x](javascript:alert(1))"),
+            "&lt;script&gt;alert(1)&lt;/script&gt; <a href=\"#\">x</a>)"
+        );
+    }
+
+    #[test]
+    fn test_render_file() {
+        let dir = std::env::temp_dir().join(format!("markdown-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notes.md");
+        fs::write(&path, "Some {content}").unwrap();
+
+        let markdown = Markdown::new().template("<title>{title}</title>{content}");
+        let page = markdown.render_file(&path).unwrap();
+        assert_eq!(*page, "<title>notes</title><p>Some {content}</p>\n");
+        assert!(Arc::ptr_eq(&page, &markdown.render_file(&path).unwrap()));
+
+        fs::write(&path, "# A & B\nchanged").unwrap();
+        let page = markdown.render_file(&path).unwrap();
+        assert!(page.starts_with("<title>A &amp; B</title><h1"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,71 @@
+use std::path::Path;
+
+/// Media type used for files with an unknown extension
+pub const DEFAULT: &str = "application/octet-stream";
+
+const TYPES: &[(&str, &str)] = &[
+    ("html", "text/html; charset=utf-8"),
+    ("htm", "text/html; charset=utf-8"),
+    ("css", "text/css; charset=utf-8"),
+    ("js", "text/javascript; charset=utf-8"),
+    ("mjs", "text/javascript; charset=utf-8"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("xml", "application/xml"),
+    ("txt", "text/plain; charset=utf-8"),
+    ("md", "text/markdown; charset=utf-8"),
+    ("csv", "text/csv; charset=utf-8"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("svg", "image/svg+xml"),
+    ("ico", "image/x-icon"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("wasm", "application/wasm"),
+    ("mp3", "audio/mpeg"),
+    ("ogg", "audio/ogg"),
+    ("wav", "audio/wav"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+];
+
+/// Returns the media type of a file from its extension, case insensitive.
+/// Unknown extensions are served as [`DEFAULT`].
+/// # Example
+/// ```
+/// use HTTP_Server::mime;
+///
+/// assert_eq!(mime::from_path("static/favicon.ICO"), "image/x-icon");
+/// assert_eq!(mime::from_path("Makefile"), mime::DEFAULT);
+/// ```
+pub fn from_path<P: AsRef<Path>>(path: P) -> &'static str {
+    let Some(extension) = path.as_ref().extension().and_then(|e| e.to_str()) else {
+        return DEFAULT;
+    };
+    TYPES
+        .iter()
+        .find(|(ext, _)| ext.eq_ignore_ascii_case(extension))
+        .map_or(DEFAULT, |(_, media_type)| media_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_path() {
+        assert_eq!(from_path("index.html"), "text/html; charset=utf-8");
+        assert_eq!(from_path("/a/b.min.JS"), "text/javascript; charset=utf-8");
+        assert_eq!(from_path(".png"), DEFAULT);
+        assert_eq!(from_path("archive.tar.unknown"), DEFAULT);
+    }
+}
@@ -0,0 +1,385 @@
+//! Reader of [MaxMind DB](https://maxmind.github.io/MaxMind-DB/) files, like the
+//! GeoLite2 Country and ASN databases.
+
+use crate::api_err::ApiErr;
+use crate::geoip::{GeoInfo, GeoIpResolver};
+use serde_json::{Map, Number, Value};
+use std::fmt;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+const METADATA_MARKER: &[u8] = b"\xab\xcd\xefMaxMind.com";
+/// Bytes of zeros between the search tree and the data section
+const DATA_SECTION_SEPARATOR: usize = 16;
+/// Maximum nesting of maps and arrays, so a corrupt file can't overflow the stack
+const MAX_DEPTH: usize = 32;
+
+fn invalid(reason: &str) -> ApiErr {
+    ApiErr::InvalidGeoIpDatabase(reason.to_string())
+}
+
+/// Decoder of the values of the data section and the metadata
+struct Decoder<'a> {
+    data: &'a [u8],
+}
+
+impl Decoder<'_> {
+    fn bytes(&self, offset: usize, len: usize) -> Result<&[u8], ApiErr> {
+        self.data
+            .get(offset..offset + len)
+            .ok_or_else(|| invalid("value out of bounds"))
+    }
+
+    fn uint(&self, offset: usize, len: usize) -> Result<u128, ApiErr> {
+        if len > 16 {
+            return Err(invalid("integer too large"));
+        }
+        let bytes = self.bytes(offset, len)?;
+        Ok(bytes.iter().fold(0, |n, b| (n << 8) | *b as u128))
+    }
+
+    /// Decodes the value at the offset, returning it with the offset of the next value
+    fn decode(&self, offset: usize, depth: usize) -> Result<(Value, usize), ApiErr> {
+        if depth > MAX_DEPTH {
+            return Err(invalid("values nested too deep"));
+        }
+        let control = *self.bytes(offset, 1)?.first().unwrap();
+        let mut offset = offset + 1;
+        let mut kind = control >> 5;
+        if kind == 0 {
+            kind = 7 + self.bytes(offset, 1)?[0];
+            offset += 1;
+        }
+
+        if kind == 1 {
+            let size = ((control >> 3) & 0x3) as usize;
+            let low = (control & 0x7) as u128;
+            let pointer = match size {
+                0 => (low << 8) | self.uint(offset, 1)?,
+                1 => ((low << 16) | self.uint(offset, 2)?) + 2048,
+                2 => ((low << 24) | self.uint(offset, 3)?) + 526336,
+                _ => self.uint(offset, 4)?,
+            };
+            // Pointers are followed once, the value they point to is never a pointer
+            let (value, _) = self.decode(pointer as usize, depth + 1)?;
+            return Ok((value, offset + size + 1));
+        }
+
+        let mut size = (control & 0x1f) as usize;
+        match size {
+            29 => {
+                size = 29 + self.uint(offset, 1)? as usize;
+                offset += 1;
+            }
+            30 => {
+                size = 285 + self.uint(offset, 2)? as usize;
+                offset += 2;
+            }
+            31 => {
+                size = 65821 + self.uint(offset, 3)? as usize;
+                offset += 3;
+            }
+            _ => {}
+        }
+
+        let value = match kind {
+            2 => {
+                let text = std::str::from_utf8(self.bytes(offset, size)?)
+                    .map_err(|_| invalid("invalid utf-8 string"))?;
+                Value::String(text.to_string())
+            }
+            3 => {
+                let bytes = self.bytes(offset, 8)?.try_into().unwrap();
+                size = 8;
+                Number::from_f64(f64::from_be_bytes(bytes)).map_or(Value::Null, Value::Number)
+            }
+            4 => Value::Array(
+                self.bytes(offset, size)?
+                    .iter()
+                    .map(|b| Value::from(*b))
+                    .collect(),
+            ),
+            5 | 6 | 9 => Value::from(self.uint(offset, size)? as u64),
+            // 128 bit integers don't fit in json numbers
+            10 => Value::String(self.uint(offset, size)?.to_string()),
+            8 => {
+                let n = self.uint(offset, size)? as u32;
+                // Shorter encodings are zero padded, not sign extended
+                Value::from(n as i32)
+            }
+            7 => {
+                let mut map = Map::new();
+                for _ in 0..size {
+                    let (key, next) = self.decode(offset, depth + 1)?;
+                    let (value, next) = self.decode(next, depth + 1)?;
+                    let Value::String(key) = key else {
+                        return Err(invalid("map key isn't a string"));
+                    };
+                    map.insert(key, value);
+                    offset = next;
+                }
+                return Ok((Value::Object(map), offset));
+            }
+            11 => {
+                let mut array = Vec::with_capacity(size.min(1024));
+                for _ in 0..size {
+                    let (value, next) = self.decode(offset, depth + 1)?;
+                    array.push(value);
+                    offset = next;
+                }
+                return Ok((Value::Array(array), offset));
+            }
+            14 => return Ok((Value::Bool(size != 0), offset)),
+            15 => {
+                let bytes = self.bytes(offset, 4)?.try_into().unwrap();
+                size = 4;
+                Number::from_f64(f32::from_be_bytes(bytes) as f64)
+                    .map_or(Value::Null, Value::Number)
+            }
+            _ => return Err(invalid("unknown value type")),
+        };
+        Ok((value, offset + size))
+    }
+}
+
+/// GeoIp resolver reading a MaxMind DB file loaded in memory.
+/// The country is read from `country.iso_code` and the network from
+/// `autonomous_system_number` and `autonomous_system_organization`,
+/// so it works with both the GeoLite2 Country and ASN databases.
+pub struct MmdbResolver {
+    data: Vec<u8>,
+    node_count: usize,
+    record_size: usize,
+    ip_version: u64,
+    database_type: String,
+}
+
+impl fmt::Debug for MmdbResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MmdbResolver")
+            .field("database_type", &self.database_type)
+            .field("node_count", &self.node_count)
+            .finish()
+    }
+}
+
+impl MmdbResolver {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<MmdbResolver, ApiErr> {
+        MmdbResolver::from_bytes(fs::read(path).map_err(ApiErr::StreamError)?)
+    }
+
+    pub fn from_bytes(data: Vec<u8>) -> Result<MmdbResolver, ApiErr> {
+        let marker = data
+            .windows(METADATA_MARKER.len())
+            .rposition(|w| w == METADATA_MARKER)
+            .ok_or_else(|| invalid("metadata not found"))?;
+        let metadata_start = marker + METADATA_MARKER.len();
+        let decoder = Decoder {
+            data: &data[metadata_start..],
+        };
+        let (metadata, _) = decoder.decode(0, 0)?;
+        let field = |name: &str| {
+            metadata
+                .get(name)
+                .and_then(|v| v.as_u64())
+                .ok_or_else(|| invalid(&format!("metadata without {name}")))
+        };
+
+        let node_count = field("node_count")? as usize;
+        let record_size = field("record_size")? as usize;
+        let ip_version = field("ip_version")?;
+        if ![24, 28, 32].contains(&record_size) || ![4, 6].contains(&ip_version) {
+            return Err(invalid("unsupported record size or ip version"));
+        }
+        if node_count * record_size / 4 + DATA_SECTION_SEPARATOR > marker {
+            return Err(invalid("search tree out of bounds"));
+        }
+        let database_type = metadata
+            .get("database_type")
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .to_string();
+
+        Ok(MmdbResolver {
+            data,
+            node_count,
+            record_size,
+            ip_version,
+            database_type,
+        })
+    }
+
+    pub fn database_type(&self) -> &str {
+        &self.database_type
+    }
+
+    fn tree_size(&self) -> usize {
+        self.node_count * self.record_size / 4
+    }
+
+    /// Returns the left or right record of the node
+    fn record(&self, node: usize, right: bool) -> usize {
+        let node_size = self.record_size / 4;
+        let b = &self.data[node * node_size..(node + 1) * node_size];
+        let be = |bytes: &[u8]| bytes.iter().fold(0, |n, b| (n << 8) | *b as usize);
+        match (self.record_size, right) {
+            (24, false) => be(&b[..3]),
+            (24, true) => be(&b[3..]),
+            (28, false) => ((b[3] as usize & 0xf0) << 20) | be(&b[..3]),
+            (28, true) => ((b[3] as usize & 0x0f) << 24) | be(&b[4..]),
+            (_, false) => be(&b[..4]),
+            (_, true) => be(&b[4..]),
+        }
+    }
+
+    /// Returns the data of the network containing the ip
+    pub fn lookup_value(&self, ip: IpAddr) -> Option<Value> {
+        let bits: Vec<bool> = match (ip.to_canonical(), self.ip_version) {
+            (IpAddr::V4(ip), 4) => (0..32)
+                .map(|i| u32::from(ip) >> (31 - i) & 1 == 1)
+                .collect(),
+            // IPv4 addresses live in the ::/96 subtree of IPv6 databases
+            (IpAddr::V4(ip), _) => (0..128)
+                .map(|i| i >= 96 && u32::from(ip) >> (127 - i) & 1 == 1)
+                .collect(),
+            (IpAddr::V6(ip), 6) => (0..128)
+                .map(|i| u128::from(ip) >> (127 - i) & 1 == 1)
+                .collect(),
+            (IpAddr::V6(_), _) => return None,
+        };
+
+        let mut node = 0;
+        for bit in bits {
+            if node >= self.node_count {
+                break;
+            }
+            node = self.record(node, bit);
+        }
+        // A record equal to the node count means there's no data for the network
+        if node <= self.node_count {
+            return None;
+        }
+
+        let offset = node - self.node_count - DATA_SECTION_SEPARATOR;
+        let decoder = Decoder {
+            data: self.data.get(self.tree_size() + DATA_SECTION_SEPARATOR..)?,
+        };
+        decoder.decode(offset, 0).ok().map(|(value, _)| value)
+    }
+}
+
+impl GeoIpResolver for MmdbResolver {
+    fn lookup(&self, ip: IpAddr) -> Option<GeoInfo> {
+        let value = self.lookup_value(ip)?;
+        Some(GeoInfo {
+            country: value
+                .pointer("/country/iso_code")
+                .and_then(|v| v.as_str())
+                .map(|c| c.to_string()),
+            asn: value
+                .get("autonomous_system_number")
+                .and_then(|v| v.as_u64())
+                .map(|n| n as u32),
+            as_organization: value
+                .get("autonomous_system_organization")
+                .and_then(|v| v.as_str())
+                .map(|o| o.to_string()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes a string or a map of string keys and small values
+    fn encode(value: &Value) -> Vec<u8> {
+        match value {
+            Value::String(s) => [vec![0x40 | s.len() as u8], s.as_bytes().to_vec()].concat(),
+            Value::Number(n) => vec![0xc1, n.as_u64().unwrap() as u8],
+            Value::Object(map) => {
+                let mut bytes = vec![0xe0 | map.len() as u8];
+                for (key, value) in map {
+                    bytes.extend(encode(&Value::String(key.clone())));
+                    bytes.extend(encode(value));
+                }
+                bytes
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    /// Database with a single node of 24 bit records: the networks starting with
+    /// a 0 bit (0.0.0.0/1) have the data and the rest have nothing
+    fn database() -> Vec<u8> {
+        let node_count = 1;
+        let data = encode(&serde_json::json!({
+            "country": { "iso_code": "AR" },
+            "autonomous_system_number": 123,
+        }));
+        let left: u32 = node_count + 16;
+        let right: u32 = node_count;
+        let metadata = encode(&serde_json::json!({
+            "node_count": node_count,
+            "record_size": 24,
+            "ip_version": 4,
+            "database_type": "Test",
+        }));
+        [
+            left.to_be_bytes()[1..].to_vec(),
+            right.to_be_bytes()[1..].to_vec(),
+            vec![0; DATA_SECTION_SEPARATOR],
+            data,
+            METADATA_MARKER.to_vec(),
+            metadata,
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn test_mmdb_lookup() {
+        let resolver = MmdbResolver::from_bytes(database()).unwrap();
+        assert_eq!(resolver.database_type(), "Test");
+        let info = resolver.lookup("1.2.3.4".parse().unwrap()).unwrap();
+        assert_eq!(info.country.as_deref(), Some("AR"));
+        assert_eq!(info.asn, Some(123));
+        assert_eq!(info.as_organization, None);
+        assert_eq!(resolver.lookup("200.2.3.4".parse().unwrap()), None);
+        assert_eq!(resolver.lookup("::1".parse().unwrap()), None);
+    }
+
+    #[test]
+    fn test_decode_types() {
+        let data = [
+            0x44, b't', b'e', b's', b't', // string
+            0xa2, 0x01, 0x00, // uint16 256
+            0x01, 0x01, 0xff, // int32 255, shorter values are zero padded
+            0x00, 0x07, // false
+            0x01, 0x04, 0x44, b't', b'e', b's', b't', // array of one string
+            0x20, 0x00, // pointer to offset 0
+        ];
+        let decoder = Decoder { data: &data };
+        assert_eq!(decoder.decode(0, 0).unwrap(), (Value::from("test"), 5));
+        assert_eq!(decoder.decode(5, 0).unwrap(), (Value::from(256), 8));
+        assert_eq!(decoder.decode(8, 0).unwrap(), (Value::from(255), 11));
+        assert_eq!(decoder.decode(11, 0).unwrap(), (Value::Bool(false), 13));
+        assert_eq!(
+            decoder.decode(13, 0).unwrap(),
+            (Value::Array(vec![Value::from("test")]), 20)
+        );
+        assert_eq!(decoder.decode(20, 0).unwrap(), (Value::from("test"), 22));
+    }
+
+    #[test]
+    fn test_invalid_database() {
+        assert!(MmdbResolver::from_bytes(b"not a database".to_vec()).is_err());
+        // Only the metadata, without the search tree it describes
+        let database = database();
+        let marker = database
+            .windows(METADATA_MARKER.len())
+            .rposition(|w| w == METADATA_MARKER)
+            .unwrap();
+        assert!(MmdbResolver::from_bytes(database[marker..].to_vec()).is_err());
+    }
+}
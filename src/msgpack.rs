@@ -0,0 +1,1077 @@
+//! [MessagePack](https://github.com/msgpack/msgpack/blob/master/spec.md) bodies, a
+//! compact binary json for bandwidth sensitive services.
+//! Values are encoded straight from [`Serialize`] and decoded into [`Deserialize`]
+//! types, keeping the width of floats and the binary and extension types: bytes
+//! serialized with `serialize_bytes`, like [`Binary`], are `bin` and [`Ext`] values
+//! are `ext`. Structs are maps keyed by their field names and enum variants are
+//! their name or a map of their name to their content, like in json.
+
+use crate::api_err::ApiErr;
+use serde::de::value::{BorrowedStrDeserializer, SeqDeserializer};
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor};
+use serde::ser::{self, Serialize};
+use serde::{Deserialize, Deserializer as _};
+use std::fmt::{self, Display};
+
+pub const MEDIA_TYPE: &str = "application/msgpack";
+/// Maximum nesting of maps and arrays, so a malicious body can't overflow the stack
+const MAX_DEPTH: usize = 128;
+/// Name of the newtype struct [`Ext`] serializes as, so the codec can tell it apart
+const EXT_TOKEN: &str = "$msgpack::Ext";
+
+/// Serializes the value into MessagePack
+/// # Example
+/// ```
+/// use HTTP_Server::msgpack;
+/// use serde_json::json;
+///
+/// let bytes = msgpack::to_vec(&json!({"id": 1, "tags": ["a"]})).unwrap();
+/// assert_eq!(bytes, b"\x82\xa2id\x01\xa4tags\x91\xa1a");
+/// assert_eq!(msgpack::from_slice::<serde_json::Value>(&bytes).unwrap(), json!({"id": 1, "tags": ["a"]}));
+/// ```
+pub fn to_vec<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, ApiErr> {
+    let mut serializer = Serializer {
+        output: Vec::new(),
+        ext: false,
+    };
+    value
+        .serialize(&mut serializer)
+        .map_err(|e| ApiErr::InternalError(e.0))?;
+    Ok(serializer.output)
+}
+
+/// Deserializes MessagePack into the type.
+/// Fails with `ApiErr::InvalidMsgpack` if it's malformed or doesn't match the type
+pub fn from_slice<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, ApiErr> {
+    let mut deserializer = Deserializer {
+        data: bytes,
+        pos: 0,
+        depth: 0,
+    };
+    let value = T::deserialize(&mut deserializer).map_err(|e| ApiErr::InvalidMsgpack(e.0))?;
+    if deserializer.pos != bytes.len() {
+        return Err(ApiErr::InvalidMsgpack(
+            "trailing bytes after the value".into(),
+        ));
+    }
+    Ok(value)
+}
+
+/// Bytes encoded as a MessagePack `bin` instead of an array of numbers
+/// # Example
+/// ```
+/// use HTTP_Server::msgpack::{self, Binary};
+///
+/// let bytes = msgpack::to_vec(&Binary(vec![1, 2])).unwrap();
+/// assert_eq!(bytes, b"\xc4\x02\x01\x02");
+/// assert_eq!(msgpack::from_slice::<Binary>(&bytes).unwrap(), Binary(vec![1, 2]));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Binary(pub Vec<u8>);
+
+impl Serialize for Binary {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Binary {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_byte_buf(BytesVisitor).map(Binary)
+    }
+}
+
+/// A MessagePack extension value, application defined data tagged with its type
+/// # Example
+/// ```
+/// use HTTP_Server::msgpack::{self, Ext};
+///
+/// let timestamp = Ext { kind: -1, data: 1_700_000_000u32.to_be_bytes().to_vec() };
+/// let bytes = msgpack::to_vec(&timestamp).unwrap();
+/// assert_eq!(bytes[..2], [0xd6, 0xff]);
+/// assert_eq!(msgpack::from_slice::<Ext>(&bytes).unwrap(), timestamp);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ext {
+    /// Type of the extension, negative ones are reserved by the spec
+    pub kind: i8,
+    pub data: Vec<u8>,
+}
+
+impl Serialize for Ext {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // The codec reads the type from the first byte
+        let mut bytes = vec![self.kind as u8];
+        bytes.extend_from_slice(&self.data);
+        serializer.serialize_newtype_struct(EXT_TOKEN, &Binary(bytes))
+    }
+}
+
+impl<'de> Deserialize<'de> for Ext {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let Binary(bytes) = deserializer.deserialize_newtype_struct(EXT_TOKEN, ExtVisitor)?;
+        let (kind, data) = bytes
+            .split_first()
+            .ok_or_else(|| de::Error::custom("extension without a type"))?;
+        Ok(Ext {
+            kind: *kind as i8,
+            data: data.to_vec(),
+        })
+    }
+}
+
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("binary data")
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Vec<u8>, E> {
+        Ok(bytes.to_vec())
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, bytes: Vec<u8>) -> Result<Vec<u8>, E> {
+        Ok(bytes)
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+        let mut bytes = Vec::new();
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(bytes)
+    }
+}
+
+struct ExtVisitor;
+
+impl<'de> Visitor<'de> for ExtVisitor {
+    type Value = Binary;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an extension value")
+    }
+
+    fn visit_newtype_struct<D: de::Deserializer<'de>>(self, d: D) -> Result<Binary, D::Error> {
+        Binary::deserialize(d)
+    }
+}
+
+/// Error of the codec, turned into an [`ApiErr`] by [`to_vec`] and [`from_slice`]
+#[derive(Debug)]
+struct Error(String);
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+fn invalid<T>(reason: &str) -> Result<T, Error> {
+    Err(Error(reason.to_string()))
+}
+
+struct Serializer {
+    output: Vec<u8>,
+    /// Set while serializing an [`Ext`], whose bytes are written as an extension
+    ext: bool,
+}
+
+/// Returns the marker of a string, array or map with its length, in the smallest format
+fn len_marker(len: usize, fix: (u8, usize), markers: [u8; 3]) -> Result<Vec<u8>, Error> {
+    Ok(match len {
+        _ if len < fix.1 => vec![fix.0 | len as u8],
+        // Only strings and binaries have an 8 bit length
+        0..=0xff if markers[0] != 0 => vec![markers[0], len as u8],
+        _ => match (u16::try_from(len), u32::try_from(len)) {
+            (Ok(len), _) => [&[markers[1]][..], &len.to_be_bytes()].concat(),
+            (_, Ok(len)) => [&[markers[2]][..], &len.to_be_bytes()].concat(),
+            _ => return invalid("length over 4 GiB"),
+        },
+    })
+}
+
+const STR: ((u8, usize), [u8; 3]) = ((0xa0, 32), [0xd9, 0xda, 0xdb]);
+const BIN: ((u8, usize), [u8; 3]) = ((0, 0), [0xc4, 0xc5, 0xc6]);
+const ARRAY: ((u8, usize), [u8; 3]) = ((0x90, 16), [0, 0xdc, 0xdd]);
+const MAP: ((u8, usize), [u8; 3]) = ((0x80, 16), [0, 0xde, 0xdf]);
+
+impl Serializer {
+    fn header(&mut self, len: usize, (fix, markers): ((u8, usize), [u8; 3])) -> Result<(), Error> {
+        let marker = len_marker(len, fix, markers)?;
+        self.output.extend(marker);
+        Ok(())
+    }
+
+    fn uint(&mut self, n: u64) {
+        match n {
+            0..=0x7f => self.output.push(n as u8),
+            0x80..=0xff => self.output.extend([0xcc, n as u8]),
+            0x100..=0xffff => {
+                self.output.push(0xcd);
+                self.output.extend((n as u16).to_be_bytes());
+            }
+            0x1_0000..=0xffff_ffff => {
+                self.output.push(0xce);
+                self.output.extend((n as u32).to_be_bytes());
+            }
+            _ => {
+                self.output.push(0xcf);
+                self.output.extend(n.to_be_bytes());
+            }
+        }
+    }
+
+    fn int(&mut self, n: i64) {
+        match n {
+            0.. => self.uint(n as u64),
+            -32..=-1 => self.output.push(n as u8),
+            -0x80..=-33 => self.output.extend([0xd0, n as u8]),
+            -0x8000..=-0x81 => {
+                self.output.push(0xd1);
+                self.output.extend((n as i16).to_be_bytes());
+            }
+            -0x8000_0000..=-0x8001 => {
+                self.output.push(0xd2);
+                self.output.extend((n as i32).to_be_bytes());
+            }
+            _ => {
+                self.output.push(0xd3);
+                self.output.extend(n.to_be_bytes());
+            }
+        }
+    }
+
+    fn ext(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let Some((kind, data)) = bytes.split_first() else {
+            return invalid("extension without a type");
+        };
+        match data.len() {
+            1 => self.output.push(0xd4),
+            2 => self.output.push(0xd5),
+            4 => self.output.push(0xd6),
+            8 => self.output.push(0xd7),
+            16 => self.output.push(0xd8),
+            len => self.header(len, ((0, 0), [0xc7, 0xc8, 0xc9]))?,
+        }
+        self.output.push(*kind);
+        self.output.extend_from_slice(data);
+        Ok(())
+    }
+
+    /// Starts an array or a map. Without a length its header is written once it ends
+    fn compound(
+        &mut self,
+        len: Option<usize>,
+        format: ((u8, usize), [u8; 3]),
+    ) -> Result<Compound<'_>, Error> {
+        let start = match len {
+            Some(len) => {
+                self.header(len, format)?;
+                None
+            }
+            None => Some(self.output.len()),
+        };
+        Ok(Compound {
+            ser: self,
+            start,
+            format,
+            count: 0,
+        })
+    }
+}
+
+/// Serializes the items of an array or the entries of a map
+struct Compound<'a> {
+    ser: &'a mut Serializer,
+    /// Where the header goes, for the ones whose length wasn't known upfront
+    start: Option<usize>,
+    format: ((u8, usize), [u8; 3]),
+    count: usize,
+}
+
+impl Compound<'_> {
+    fn item<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.count += 1;
+        value.serialize(&mut *self.ser)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        if let Some(start) = self.start {
+            let (fix, markers) = self.format;
+            let header = len_marker(self.count, fix, markers)?;
+            self.ser.output.splice(start..start, header);
+        }
+        Ok(())
+    }
+}
+
+impl<'a> ser::Serializer for &'a mut Serializer {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Compound<'a>;
+    type SerializeTuple = Compound<'a>;
+    type SerializeTupleStruct = Compound<'a>;
+    type SerializeTupleVariant = Compound<'a>;
+    type SerializeMap = Compound<'a>;
+    type SerializeStruct = Compound<'a>;
+    type SerializeStructVariant = Compound<'a>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), Error> {
+        self.output.push(if v { 0xc3 } else { 0xc2 });
+        Ok(())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), Error> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), Error> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), Error> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), Error> {
+        self.int(v);
+        Ok(())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), Error> {
+        self.serialize_u64(v as u64)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), Error> {
+        self.serialize_u64(v as u64)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), Error> {
+        self.serialize_u64(v as u64)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), Error> {
+        self.uint(v);
+        Ok(())
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<(), Error> {
+        self.output.push(0xca);
+        self.output.extend(v.to_be_bytes());
+        Ok(())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<(), Error> {
+        self.output.push(0xcb);
+        self.output.extend(v.to_be_bytes());
+        Ok(())
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), Error> {
+        self.serialize_str(v.encode_utf8(&mut [0; 4]))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), Error> {
+        self.header(v.len(), STR)?;
+        self.output.extend_from_slice(v.as_bytes());
+        Ok(())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), Error> {
+        if std::mem::take(&mut self.ext) {
+            return self.ext(v);
+        }
+        self.header(v.len(), BIN)?;
+        self.output.extend_from_slice(v);
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<(), Error> {
+        self.serialize_unit()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), Error> {
+        self.output.push(0xc0);
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), Error> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<(), Error> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.ext = name == EXT_TOKEN;
+        value.serialize(&mut *self)?;
+        // Left set if the value wasn't bytes
+        match std::mem::take(&mut self.ext) {
+            true => invalid("extension without bytes"),
+            false => Ok(()),
+        }
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.header(1, MAP)?;
+        self.serialize_str(variant)?;
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Compound<'a>, Error> {
+        self.compound(len, ARRAY)
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Compound<'a>, Error> {
+        self.compound(Some(len), ARRAY)
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Compound<'a>, Error> {
+        self.compound(Some(len), ARRAY)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Compound<'a>, Error> {
+        self.header(1, MAP)?;
+        self.serialize_str(variant)?;
+        self.compound(Some(len), ARRAY)
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Compound<'a>, Error> {
+        self.compound(len, MAP)
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Compound<'a>, Error> {
+        self.compound(Some(len), MAP)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Compound<'a>, Error> {
+        self.header(1, MAP)?;
+        self.serialize_str(variant)?;
+        self.compound(Some(len), MAP)
+    }
+}
+
+impl ser::SerializeSeq for Compound<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.item(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeTuple for Compound<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.item(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for Compound<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.item(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeTupleVariant for Compound<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.item(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeMap for Compound<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.item(key)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        // The entry was counted with its key
+        value.serialize(&mut *self.ser)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeStruct for Compound<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.item(key)?;
+        value.serialize(&mut *self.ser)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeStructVariant for Compound<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.item(key)?;
+        value.serialize(&mut *self.ser)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Compound::end(self)
+    }
+}
+
+struct Deserializer<'de> {
+    data: &'de [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl<'de> Deserializer<'de> {
+    fn peek(&self) -> Result<u8, Error> {
+        match self.data.get(self.pos) {
+            Some(marker) => Ok(*marker),
+            None => invalid("unexpected end of data"),
+        }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'de [u8], Error> {
+        let Some(bytes) = self.data.get(self.pos..self.pos.saturating_add(len)) else {
+            return invalid("unexpected end of data");
+        };
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn uint(&mut self, len: usize) -> Result<u64, Error> {
+        let bytes = self.bytes(len)?;
+        Ok(bytes.iter().fold(0, |n, b| (n << 8) | *b as u64))
+    }
+
+    fn int(&mut self, len: usize) -> Result<i64, Error> {
+        let n = self.uint(len)?;
+        // Sign extend from the width of the integer
+        let shift = 64 - 8 * len as u32;
+        Ok(((n << shift) as i64) >> shift)
+    }
+
+    fn str(&mut self, len: usize) -> Result<&'de str, Error> {
+        match std::str::from_utf8(self.bytes(len)?) {
+            Ok(s) => Ok(s),
+            Err(_) => invalid("string isn't utf-8"),
+        }
+    }
+
+    /// Reads the bytes of a `bin` if that's the next value
+    fn binary(&mut self) -> Result<Option<&'de [u8]>, Error> {
+        let len = match self.peek()? {
+            0xc4 => 1,
+            0xc5 => 2,
+            0xc6 => 4,
+            _ => return Ok(None),
+        };
+        self.pos += 1;
+        let len = self.uint(len)? as usize;
+        self.bytes(len).map(Some)
+    }
+
+    /// Reads an extension, returning its type followed by its data
+    fn ext(&mut self) -> Result<Vec<u8>, Error> {
+        let len = match self.bytes(1)?[0] {
+            0xd4 => 1,
+            0xd5 => 2,
+            0xd6 => 4,
+            0xd7 => 8,
+            0xd8 => 16,
+            0xc7 => self.uint(1)? as usize,
+            0xc8 => self.uint(2)? as usize,
+            0xc9 => self.uint(4)? as usize,
+            _ => return invalid("expected an extension"),
+        };
+        Ok(self.bytes(len.saturating_add(1))?.to_vec())
+    }
+
+    fn nested<V: Visitor<'de>>(
+        &mut self,
+        len: usize,
+        map: bool,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        if self.depth >= MAX_DEPTH {
+            return invalid("too deeply nested");
+        }
+        self.depth += 1;
+        let mut access = Access {
+            de: self,
+            left: len,
+        };
+        let value = match map {
+            true => visitor.visit_map(&mut access)?,
+            false => visitor.visit_seq(&mut access)?,
+        };
+        if access.left > 0 {
+            return invalid("map or array with items left");
+        }
+        self.depth -= 1;
+        Ok(value)
+    }
+}
+
+/// Reads the items of an array or the entries of a map
+struct Access<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+    left: usize,
+}
+
+impl<'de> de::SeqAccess<'de> for Access<'_, 'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        if self.left == 0 {
+            return Ok(None);
+        }
+        self.left -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        // Every item takes at least a byte, so a bogus length can't allocate much
+        Some(self.left.min(self.de.data.len() - self.de.pos))
+    }
+}
+
+impl<'de> de::MapAccess<'de> for Access<'_, 'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Error> {
+        if self.left == 0 {
+            return Ok(None);
+        }
+        self.left -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Error> {
+        seed.deserialize(&mut *self.de)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.left.min(self.de.data.len() - self.de.pos))
+    }
+}
+
+/// Reads an enum variant given as a map of its name to its content
+struct Variant<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+}
+
+impl<'de> de::EnumAccess<'de> for Variant<'_, 'de> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self), Error> {
+        let variant = seed.deserialize(&mut *self.de)?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Variant<'_, 'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        de::Deserialize::deserialize(self.de)
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(self.de)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+        self.de.deserialize_seq(visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.de.deserialize_map(visitor)
+    }
+}
+
+impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        if let Some(bytes) = self.binary()? {
+            return visitor.visit_borrowed_bytes(bytes);
+        }
+        let marker = self.bytes(1)?[0];
+        match marker {
+            0x00..=0x7f => visitor.visit_u64(marker as u64),
+            0x80..=0x8f => self.nested((marker & 0x0f) as usize, true, visitor),
+            0x90..=0x9f => self.nested((marker & 0x0f) as usize, false, visitor),
+            0xa0..=0xbf => visitor.visit_borrowed_str(self.str((marker & 0x1f) as usize)?),
+            0xc0 => visitor.visit_unit(),
+            0xc2 => visitor.visit_bool(false),
+            0xc3 => visitor.visit_bool(true),
+            0xca => visitor.visit_f32(f32::from_bits(self.uint(4)? as u32)),
+            0xcb => visitor.visit_f64(f64::from_bits(self.uint(8)?)),
+            0xcc => visitor.visit_u64(self.uint(1)?),
+            0xcd => visitor.visit_u64(self.uint(2)?),
+            0xce => visitor.visit_u64(self.uint(4)?),
+            0xcf => visitor.visit_u64(self.uint(8)?),
+            0xd0 => visitor.visit_i64(self.int(1)?),
+            0xd1 => visitor.visit_i64(self.int(2)?),
+            0xd2 => visitor.visit_i64(self.int(4)?),
+            0xd3 => visitor.visit_i64(self.int(8)?),
+            0xd9 => {
+                let len = self.uint(1)? as usize;
+                visitor.visit_borrowed_str(self.str(len)?)
+            }
+            0xda => {
+                let len = self.uint(2)? as usize;
+                visitor.visit_borrowed_str(self.str(len)?)
+            }
+            0xdb => {
+                let len = self.uint(4)? as usize;
+                visitor.visit_borrowed_str(self.str(len)?)
+            }
+            0xdc => {
+                let len = self.uint(2)? as usize;
+                self.nested(len, false, visitor)
+            }
+            0xdd => {
+                let len = self.uint(4)? as usize;
+                self.nested(len, false, visitor)
+            }
+            0xde => {
+                let len = self.uint(2)? as usize;
+                self.nested(len, true, visitor)
+            }
+            0xdf => {
+                let len = self.uint(4)? as usize;
+                self.nested(len, true, visitor)
+            }
+            0xe0..=0xff => visitor.visit_i64(marker as i8 as i64),
+            0xc7..=0xc9 | 0xd4..=0xd8 => invalid("extension values need a msgpack::Ext"),
+            _ => invalid("invalid marker"),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.peek()? {
+            0xc0 => {
+                self.pos += 1;
+                visitor.visit_none()
+            }
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    /// Binaries are sequences of bytes too, like for a `Vec<u8>`
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match self.binary()? {
+            Some(bytes) => {
+                let mut seq = SeqDeserializer::new(bytes.iter().copied());
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            }
+            None => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        if name == EXT_TOKEN {
+            let ext = self.ext()?;
+            return visitor.visit_newtype_struct(ext.into_deserializer());
+        }
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self.peek()? {
+            0x81 => {
+                self.pos += 1;
+                visitor.visit_enum(Variant { de: self })
+            }
+            0xa0..=0xbf | 0xd9..=0xdb => {
+                let variant = <&str>::deserialize(&mut *self)?;
+                visitor.visit_enum(BorrowedStrDeserializer::new(variant))
+            }
+            _ => invalid("expected an enum variant name or a map with one entry"),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        unit unit_struct tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_round_trip() {
+        let long = "x".repeat(300);
+        let value = json!({
+            "null": null,
+            "bools": [true, false],
+            "ints": [0, 127, 128, 255, 256, 65536, u64::MAX, -1, -32, -33, -129, -40000, i64::MIN],
+            "float": 1.5,
+            "strings": ["", "héllo", "y".repeat(40), long],
+            "nested": {"a": {"b": [[]]}},
+            "big": (0..20).collect::<Vec<_>>(),
+        });
+        let bytes = to_vec(&value).unwrap();
+        assert_eq!(from_slice::<Value>(&bytes).unwrap(), value);
+    }
+
+    #[test]
+    fn test_encoding() {
+        let encoded = |value: Value| to_vec(&value).unwrap();
+        assert_eq!(encoded(json!(-1)), [0xff]);
+        assert_eq!(encoded(json!(-33)), [0xd0, 0xdf]);
+        assert_eq!(encoded(json!(200)), [0xcc, 200]);
+        assert_eq!(encoded(json!(1.5)), [0xcb, 0x3f, 0xf8, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(encoded(json!("y".repeat(40)))[..2], [0xd9, 40]);
+        assert_eq!(
+            encoded(json!((0..16).collect::<Vec<_>>()))[..3],
+            [0xdc, 0, 16]
+        );
+        assert_eq!(to_vec(&1.5f32).unwrap(), [0xca, 0x3f, 0xc0, 0, 0]);
+        assert_eq!(to_vec(&(1u8, "a")).unwrap(), [0x92, 0x01, 0xa1, b'a']);
+    }
+
+    #[test]
+    fn test_integer_edges() {
+        let mut bytes = vec![0xcf];
+        bytes.extend(u64::MAX.to_be_bytes());
+        assert_eq!(to_vec(&u64::MAX).unwrap(), bytes);
+        assert_eq!(from_slice::<u64>(&bytes).unwrap(), u64::MAX);
+        assert!(from_slice::<i64>(&bytes).is_err());
+
+        assert_eq!(
+            from_slice::<i64>(&to_vec(&i64::MIN).unwrap()).unwrap(),
+            i64::MIN
+        );
+        assert_eq!(to_vec(&(u32::MAX as u64 + 1)).unwrap()[0], 0xcf);
+        assert_eq!(to_vec(&u32::MAX).unwrap()[0], 0xce);
+        // Non-negative signed values use the unsigned formats
+        assert_eq!(to_vec(&200i64).unwrap(), [0xcc, 200]);
+        assert_eq!(from_slice::<u8>(b"\xd0\x05").unwrap(), 5);
+        assert!(from_slice::<u8>(b"\xcd\x01\x00").is_err());
+        assert!(from_slice::<u8>(b"\xff").is_err());
+    }
+
+    #[test]
+    fn test_binary_and_extensions() {
+        let data: Vec<u8> = (0..=255).collect();
+        let bytes = to_vec(&Binary(data.clone())).unwrap();
+        assert_eq!(bytes[..3], [0xc5, 0x01, 0x00]);
+        assert_eq!(from_slice::<Binary>(&bytes).unwrap().0, data);
+        // A Vec<u8> is an array, but reads a binary too
+        assert_eq!(to_vec(&vec![1u8, 2]).unwrap(), [0x92, 1, 2]);
+        assert_eq!(from_slice::<Vec<u8>>(b"\xc4\x02\x01\x02").unwrap(), [1, 2]);
+        assert_eq!(from_slice::<Binary>(b"\x92\x01\x02").unwrap().0, [1, 2]);
+        assert!(from_slice::<Value>(b"\xc4\x02\x01\x02").is_err());
+
+        let ext = Ext {
+            kind: 5,
+            data: vec![7; 3],
+        };
+        let bytes = to_vec(&ext).unwrap();
+        assert_eq!(bytes, [0xc7, 3, 5, 7, 7, 7]);
+        assert_eq!(from_slice::<Ext>(&bytes).unwrap(), ext);
+        assert_eq!(
+            to_vec(&Ext {
+                kind: 1,
+                data: vec![9]
+            })
+            .unwrap(),
+            [0xd4, 1, 9]
+        );
+        assert!(from_slice::<Value>(&bytes).is_err());
+
+        let mut entries = BTreeMap::new();
+        entries.insert("raw", Binary(vec![1]));
+        let bytes = to_vec(&entries).unwrap();
+        assert_eq!(bytes, b"\x81\xa3raw\xc4\x01\x01");
+        assert_eq!(
+            from_slice::<BTreeMap<String, Binary>>(&bytes).unwrap()["raw"].0,
+            [1]
+        );
+    }
+
+    #[test]
+    fn test_unknown_lengths_and_enums() {
+        // Iterators without an exact length get their header once they end
+        let items = (0..20).filter(|n| n % 2 == 0);
+        let bytes = to_vec(&serde_json::to_value(items.collect::<Vec<_>>()).unwrap()).unwrap();
+        let unsized_seq = SeqFromIter((0..20).filter(|n| n % 2 == 0));
+        assert_eq!(to_vec(&unsized_seq).unwrap(), bytes);
+
+        let value: Result<u8, String> = Err("bad".into());
+        let bytes = to_vec(&value).unwrap();
+        assert_eq!(bytes, b"\x81\xa3Err\xa3bad");
+        assert_eq!(from_slice::<Result<u8, String>>(&bytes).unwrap(), value);
+        assert_eq!(from_slice::<Option<u8>>(b"\xc0").unwrap(), None);
+        assert_eq!(from_slice::<Option<u8>>(b"\x01").unwrap(), Some(1));
+    }
+
+    /// Serializes the items of an iterator as a sequence without a length
+    struct SeqFromIter<I>(I);
+
+    impl<I: Iterator<Item = i32> + Clone> Serialize for SeqFromIter<I> {
+        fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            use serde::ser::SerializeSeq;
+            let mut seq = serializer.serialize_seq(None)?;
+            for item in self.0.clone() {
+                seq.serialize_element(&item)?;
+            }
+            seq.end()
+        }
+    }
+
+    #[test]
+    fn test_decoding() {
+        assert_eq!(
+            from_slice::<Value>(b"\xca\x3f\xc0\x00\x00").unwrap(),
+            json!(1.5)
+        );
+
+        for bytes in [
+            &b"\xa5abc"[..],
+            b"\x81\x01",
+            b"\xc1",
+            b"\x01\x02",
+            b"\xdd\xff\xff\xff\xff",
+        ] {
+            assert!(matches!(
+                from_slice::<Value>(bytes),
+                Err(ApiErr::InvalidMsgpack(_))
+            ));
+        }
+        let nested = vec![0x91; 1000];
+        assert!(from_slice::<Value>(&nested).is_err());
+        assert!(from_slice::<u8>(b"\xa1a").is_err());
+    }
+}
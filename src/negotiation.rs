@@ -0,0 +1,265 @@
+/// Parses a header made of a list of values with optional quality weights,
+/// like `Accept`, `Accept-Language` or `Accept-Encoding`.
+/// Values are returned lowercased, without their parameters, in the order they were sent.
+/// Values with an invalid weight are skipped.
+/// # Example
+/// ```
+/// use HTTP_Server::negotiation::parse_quality_list;
+///
+/// let values = parse_quality_list("text/html, application/json;q=0.5, */*;q=0");
+/// assert_eq!(values, vec![
+///     ("text/html".to_string(), 1.0),
+///     ("application/json".to_string(), 0.5),
+///     ("*/*".to_string(), 0.0),
+/// ]);
+/// ```
+pub fn parse_quality_list(header: &str) -> Vec<(String, f32)> {
+    header
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let value = parts.next()?.trim().to_ascii_lowercase();
+            if value.is_empty() {
+                return None;
+            }
+            let mut quality = 1.0;
+            for param in parts {
+                if let Some((key, q)) = param.split_once('=') {
+                    if key.trim().eq_ignore_ascii_case("q") {
+                        quality = q.trim().parse::<f32>().ok()?;
+                    }
+                }
+            }
+            (0.0..=1.0).contains(&quality).then_some((value, quality))
+        })
+        .collect()
+}
+
+/// Returns how specifically the media range matches the media type:
+/// 3 for an exact match, 2 for `type/*`, 1 for `*/*` and `None` if it doesn't match.
+fn media_range_match(range: &str, media_type: &str) -> Option<u8> {
+    if range == media_type {
+        return Some(3);
+    }
+    if range == "*/*" {
+        return Some(1);
+    }
+    let (range_type, range_subtype) = range.split_once('/')?;
+    let (media_type, _) = media_type.split_once('/')?;
+    (range_subtype == "*" && range_type == media_type).then_some(2)
+}
+
+/// Picks the media type the client prefers among the `offered` ones according to
+/// its `Accept` header, breaking ties by the order of `offered`.
+/// Without an `Accept` header the first offered type is returned.
+/// Returns `None` if the client accepts none of them.
+/// # Example
+/// ```
+/// use HTTP_Server::negotiation::negotiate_media_type;
+///
+/// let offered = ["application/json", "text/html"];
+/// assert_eq!(negotiate_media_type(Some("text/*, application/json;q=0.8"), &offered), Some("text/html"));
+/// assert_eq!(negotiate_media_type(None, &offered), Some("application/json"));
+/// assert_eq!(negotiate_media_type(Some("image/png"), &offered), None);
+/// ```
+pub fn negotiate_media_type<'a>(accept: Option<&str>, offered: &[&'a str]) -> Option<&'a str> {
+    let accept = match accept {
+        Some(accept) if !accept.trim().is_empty() => accept,
+        _ => return offered.first().copied(),
+    };
+    let ranges = parse_quality_list(accept);
+
+    let mut best: Option<(&str, f32)> = None;
+    for offer in offered {
+        let media_type = offer.to_ascii_lowercase();
+        // The most specific matching range decides the quality of the offer
+        let quality = ranges
+            .iter()
+            .filter_map(|(range, q)| media_range_match(range, &media_type).map(|s| (s, *q)))
+            .max_by(|(a, _), (b, _)| a.cmp(b))
+            .map(|(_, q)| q)
+            .unwrap_or(0.0);
+        if quality > 0.0 && best.is_none_or(|(_, best)| quality > best) {
+            best = Some((offer, quality));
+        }
+    }
+    best.map(|(offer, _)| offer)
+}
+
+/// Returns how specifically the language range matches the language tag, `None` if it
+/// doesn't match. A range like `en` matches the tag `en-US` by its prefix, and as a fallback
+/// a range like `en-US` also matches the more general tag `en`, with less priority.
+/// `*` matches every tag with the least priority.
+fn language_range_match(range: &str, tag: &str) -> Option<(u8, usize)> {
+    let is_prefix = |prefix: &str, of: &str| {
+        of.starts_with(prefix) && of.as_bytes().get(prefix.len()) == Some(&b'-')
+    };
+    if range == "*" {
+        Some((0, 0))
+    } else if range == tag || is_prefix(range, tag) {
+        Some((2, range.len()))
+    } else if is_prefix(tag, range) {
+        Some((1, tag.len()))
+    } else {
+        None
+    }
+}
+
+/// Picks the language the client prefers among the `available` ones according to
+/// its `Accept-Language` header, where a range like `en` also matches `en-US`
+/// and `en-US` falls back to `en`.
+/// Ties are broken by the order of `available`.
+/// Returns `None` if there's no header or the client accepts none of them.
+/// # Example
+/// ```
+/// use HTTP_Server::negotiation::negotiate_language;
+///
+/// let available = ["en-US", "es", "pt-BR"];
+/// assert_eq!(negotiate_language(Some("pt, es;q=0.8"), &available), Some("pt-BR"));
+/// assert_eq!(negotiate_language(Some("es-MX"), &available), Some("es"));
+/// assert_eq!(negotiate_language(Some("fr"), &available), None);
+/// assert_eq!(negotiate_language(None, &available), None);
+/// ```
+pub fn negotiate_language<'a>(
+    accept_language: Option<&str>,
+    available: &[&'a str],
+) -> Option<&'a str> {
+    let ranges = parse_quality_list(accept_language?);
+
+    let mut best: Option<(&str, f32)> = None;
+    for language in available {
+        let tag = language.to_ascii_lowercase();
+        let quality = ranges
+            .iter()
+            .filter_map(|(range, q)| language_range_match(range, &tag).map(|s| (s, *q)))
+            .max_by(|(a, _), (b, _)| a.cmp(b))
+            .map(|(_, q)| q)
+            .unwrap_or(0.0);
+        if quality > 0.0 && best.is_none_or(|(_, best)| quality > best) {
+            best = Some((language, quality));
+        }
+    }
+    best.map(|(language, _)| language)
+}
+
+/// Picks the content coding the client prefers among the `offered` ones according to
+/// its `Accept-Encoding` header, breaking ties by the order of `offered`.
+/// A coding named by the header takes its quality over the one of `*`.
+/// Returns `None` if there's no header or the client accepts none of them,
+/// in which case the response isn't encoded.
+/// # Example
+/// ```
+/// use HTTP_Server::negotiation::negotiate_encoding;
+///
+/// let offered = ["gzip", "deflate"];
+/// assert_eq!(negotiate_encoding(Some("deflate, gzip;q=0.5"), &offered), Some("deflate"));
+/// assert_eq!(negotiate_encoding(Some("*, gzip;q=0"), &offered), Some("deflate"));
+/// assert_eq!(negotiate_encoding(Some("br"), &offered), None);
+/// ```
+pub fn negotiate_encoding<'a>(
+    accept_encoding: Option<&str>,
+    offered: &[&'a str],
+) -> Option<&'a str> {
+    let codings = parse_quality_list(accept_encoding?);
+    let quality_of = |name: &str| codings.iter().find(|(c, _)| c == name).map(|(_, q)| *q);
+
+    let mut best: Option<(&str, f32)> = None;
+    for offer in offered {
+        let coding = offer.to_ascii_lowercase();
+        let quality = quality_of(&coding)
+            .or_else(|| quality_of("*"))
+            .unwrap_or(0.0);
+        if quality > 0.0 && best.is_none_or(|(_, best)| quality > best) {
+            best = Some((offer, quality));
+        }
+    }
+    best.map(|(coding, _)| coding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_quality_list_skips_invalid_values() {
+        let values = parse_quality_list("a;q=2, b;q=x, , c;level=1;q=0.3");
+        assert_eq!(values, vec![("c".to_string(), 0.3)]);
+    }
+
+    #[test]
+    fn test_negotiate_prefers_higher_quality() {
+        let offered = ["application/json", "text/plain", "text/html"];
+        assert_eq!(
+            negotiate_media_type(Some("text/plain;q=0.9, text/html"), &offered),
+            Some("text/html")
+        );
+    }
+
+    #[test]
+    fn test_negotiate_ties_use_offered_order() {
+        let offered = ["application/json", "text/plain"];
+        assert_eq!(
+            negotiate_media_type(Some("text/plain, application/json"), &offered),
+            Some("application/json")
+        );
+        assert_eq!(
+            negotiate_media_type(Some("*/*"), &offered),
+            Some("application/json")
+        );
+    }
+
+    #[test]
+    fn test_negotiate_most_specific_range_wins() {
+        let offered = ["application/json", "text/html"];
+        assert_eq!(
+            negotiate_media_type(Some("*/*;q=0.1, application/json;q=0"), &offered),
+            Some("text/html")
+        );
+    }
+
+    #[test]
+    fn test_negotiate_nothing_acceptable() {
+        let offered = ["application/json"];
+        assert_eq!(negotiate_media_type(Some("text/html"), &offered), None);
+        assert_eq!(negotiate_media_type(Some("*/*;q=0"), &offered), None);
+    }
+
+    #[test]
+    fn test_negotiate_language() {
+        let available = ["en", "es-AR", "es"];
+        assert_eq!(
+            negotiate_language(Some("es-AR, es;q=0.9"), &available),
+            Some("es-AR")
+        );
+        assert_eq!(negotiate_language(Some("es"), &available), Some("es-AR"));
+        assert_eq!(
+            negotiate_language(Some("EN-gb, en"), &available),
+            Some("en")
+        );
+        assert_eq!(
+            negotiate_language(Some("*;q=0.5, es-AR;q=0"), &available),
+            Some("en")
+        );
+        assert_eq!(
+            negotiate_language(Some("es-MX, en;q=0.5"), &available),
+            Some("es")
+        );
+        assert_eq!(negotiate_language(Some("e"), &available), None);
+    }
+
+    #[test]
+    fn test_negotiate_encoding() {
+        let offered = ["gzip", "deflate"];
+        assert_eq!(
+            negotiate_encoding(Some("gzip, deflate, br"), &offered),
+            Some("gzip")
+        );
+        assert_eq!(
+            negotiate_encoding(Some("GZIP;q=0.1, *;q=0.5"), &offered),
+            Some("deflate")
+        );
+        assert_eq!(negotiate_encoding(Some("identity"), &offered), None);
+        assert_eq!(negotiate_encoding(Some(""), &offered), None);
+        assert_eq!(negotiate_encoding(None, &offered), None);
+    }
+}
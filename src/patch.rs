@@ -0,0 +1,322 @@
+//! Bodies of `PATCH` requests: JSON Patch ([RFC 6902](https://www.rfc-editor.org/rfc/rfc6902))
+//! lists of operations and JSON Merge Patch ([RFC 7386](https://www.rfc-editor.org/rfc/rfc7386))
+//! documents, see [`Context::patch`](crate::context::Context::patch).
+
+use crate::api_err::ApiErr;
+use serde_json::{Map, Value};
+
+pub const JSON_PATCH: &str = "application/json-patch+json";
+pub const MERGE_PATCH: &str = "application/merge-patch+json";
+
+/// An operation of a JSON Patch, with its paths as JSON Pointers like `/tags/0`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Value },
+}
+
+/// The changes a `PATCH` request asks for
+/// # Example
+/// ```
+/// use HTTP_Server::patch::Patch;
+/// use serde_json::json;
+///
+/// let patch = Patch::parse(
+///     "application/json-patch+json",
+///     r#"[{"op": "replace", "path": "/name", "value": "bob"}, {"op": "remove", "path": "/age"}]"#,
+/// )
+/// .unwrap();
+/// let user = patch.apply(json!({"name": "alice", "age": 30})).unwrap();
+/// assert_eq!(user, json!({"name": "bob"}));
+///
+/// let patch = Patch::parse("application/merge-patch+json", r#"{"age": null, "admin": true}"#).unwrap();
+/// let user = patch.apply(json!({"name": "alice", "age": 30})).unwrap();
+/// assert_eq!(user, json!({"name": "alice", "admin": true}));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum Patch {
+    Json(Vec<Operation>),
+    Merge(Value),
+}
+
+impl Patch {
+    /// Parses the body according to its media type, either [`JSON_PATCH`] or [`MERGE_PATCH`].
+    /// Fails with `ApiErr::MediaTypeNotSupported` for other types, `ApiErr::InvalidJson`
+    /// if the body isn't json and `ApiErr::InvalidPatch` (400) for malformed operations
+    pub fn parse(media_type: &str, body: &str) -> Result<Patch, ApiErr> {
+        if media_type != JSON_PATCH && media_type != MERGE_PATCH {
+            return Err(ApiErr::MediaTypeNotSupported);
+        }
+        let value: Value = serde_json::from_str(body).map_err(ApiErr::InvalidJson)?;
+        if media_type == MERGE_PATCH {
+            return Ok(Patch::Merge(value));
+        }
+        let Value::Array(operations) = value else {
+            return Err(ApiErr::InvalidPatch(
+                "expected an array of operations".into(),
+            ));
+        };
+        operations
+            .iter()
+            .map(parse_operation)
+            .collect::<Result<_, _>>()
+            .map(Patch::Json)
+    }
+
+    /// Applies the patch to the document, returning the patched one.
+    /// JSON Patches are all or nothing: if an operation fails, like a `test` that doesn't
+    /// hold or a path that doesn't exist, the error is `ApiErr::PatchFailed` (422)
+    pub fn apply(&self, document: Value) -> Result<Value, ApiErr> {
+        match self {
+            Patch::Json(operations) => operations.iter().try_fold(document, apply_operation),
+            Patch::Merge(patch) => Ok(merge(document, patch)),
+        }
+    }
+}
+
+fn parse_operation(operation: &Value) -> Result<Operation, ApiErr> {
+    let field = |name: &str| -> Result<String, ApiErr> {
+        match operation.get(name) {
+            Some(Value::String(field)) => Ok(field.clone()),
+            _ => Err(ApiErr::InvalidPatch(format!(
+                "missing `{name}` in {operation}"
+            ))),
+        }
+    };
+    let value = || -> Result<Value, ApiErr> {
+        operation
+            .get("value")
+            .cloned()
+            .ok_or_else(|| ApiErr::InvalidPatch(format!("missing `value` in {operation}")))
+    };
+    let pointer = |name: &str| -> Result<String, ApiErr> {
+        let pointer = field(name)?;
+        match pointer.is_empty() || pointer.starts_with('/') {
+            true => Ok(pointer),
+            false => Err(ApiErr::InvalidPatch(format!("invalid pointer `{pointer}`"))),
+        }
+    };
+
+    let path = pointer("path")?;
+    match field("op")?.as_str() {
+        "add" => Ok(Operation::Add {
+            path,
+            value: value()?,
+        }),
+        "remove" => Ok(Operation::Remove { path }),
+        "replace" => Ok(Operation::Replace {
+            path,
+            value: value()?,
+        }),
+        "move" => Ok(Operation::Move {
+            from: pointer("from")?,
+            path,
+        }),
+        "copy" => Ok(Operation::Copy {
+            from: pointer("from")?,
+            path,
+        }),
+        "test" => Ok(Operation::Test {
+            path,
+            value: value()?,
+        }),
+        op => Err(ApiErr::InvalidPatch(format!("unknown operation `{op}`"))),
+    }
+}
+
+/// Splits a pointer into the pointer of its parent and its last token, unescaped
+fn split_pointer(pointer: &str) -> Option<(&str, String)> {
+    let (parent, token) = pointer.rsplit_once('/')?;
+    Some((parent, token.replace("~1", "/").replace("~0", "~")))
+}
+
+/// Returns the index of an array for the token, which can be one past the end for `add`
+fn array_index(token: &str, len: usize, add: bool) -> Option<usize> {
+    if add && token == "-" {
+        return Some(len);
+    }
+    if token.is_empty() || (token.len() > 1 && token.starts_with('0')) {
+        return None;
+    }
+    let index: usize = token.parse().ok()?;
+    let max = if add { len } else { len.saturating_sub(1) };
+    (index <= max && (add || len > 0)).then_some(index)
+}
+
+fn not_found(pointer: &str) -> ApiErr {
+    ApiErr::PatchFailed(format!("path `{pointer}` not found"))
+}
+
+fn add(mut document: Value, path: &str, value: Value) -> Result<Value, ApiErr> {
+    let Some((parent, token)) = split_pointer(path) else {
+        return Ok(value);
+    };
+    match document.pointer_mut(parent) {
+        Some(Value::Object(object)) => {
+            object.insert(token, value);
+        }
+        Some(Value::Array(array)) => {
+            let index = array_index(&token, array.len(), true).ok_or_else(|| not_found(path))?;
+            array.insert(index, value);
+        }
+        _ => return Err(not_found(path)),
+    }
+    Ok(document)
+}
+
+fn remove(mut document: Value, path: &str) -> Result<(Value, Value), ApiErr> {
+    let Some((parent, token)) = split_pointer(path) else {
+        return Ok((Value::Null, document));
+    };
+    let removed = match document.pointer_mut(parent) {
+        Some(Value::Object(object)) => object.remove(&token),
+        Some(Value::Array(array)) => {
+            array_index(&token, array.len(), false).map(|index| array.remove(index))
+        }
+        _ => None,
+    };
+    let removed = removed.ok_or_else(|| not_found(path))?;
+    Ok((document, removed))
+}
+
+fn apply_operation(document: Value, operation: &Operation) -> Result<Value, ApiErr> {
+    match operation {
+        Operation::Add { path, value } => add(document, path, value.clone()),
+        Operation::Remove { path } => remove(document, path).map(|(document, _)| document),
+        Operation::Replace { path, value } => {
+            let (document, _) = remove(document, path)?;
+            add(document, path, value.clone())
+        }
+        Operation::Move { from, path } => {
+            // A value can't be moved into one of its children
+            if path.starts_with(&format!("{from}/")) {
+                return Err(ApiErr::PatchFailed(format!(
+                    "can't move `{from}` into `{path}`"
+                )));
+            }
+            let (document, value) = remove(document, from)?;
+            add(document, path, value)
+        }
+        Operation::Copy { from, path } => {
+            let value = document
+                .pointer(from)
+                .cloned()
+                .ok_or_else(|| not_found(from))?;
+            add(document, path, value)
+        }
+        Operation::Test { path, value } => match document.pointer(path) {
+            Some(current) if current == value => Ok(document),
+            _ => Err(ApiErr::PatchFailed(format!("test of `{path}` failed"))),
+        },
+    }
+}
+
+fn merge(document: Value, patch: &Value) -> Value {
+    let Value::Object(patch) = patch else {
+        return patch.clone();
+    };
+    let mut document = match document {
+        Value::Object(document) => document,
+        _ => Map::new(),
+    };
+    for (key, value) in patch {
+        if value.is_null() {
+            document.remove(key);
+        } else {
+            let current = document.remove(key).unwrap_or(Value::Null);
+            document.insert(key.clone(), merge(current, value));
+        }
+    }
+    Value::Object(document)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn json_patch(operations: Value) -> Result<Value, ApiErr> {
+        let document = json!({"foo": {"bar": [1, 2]}, "a/b": 0, "m~n": 1});
+        Patch::parse(JSON_PATCH, &operations.to_string())?.apply(document)
+    }
+
+    #[test]
+    fn test_json_patch() {
+        let patched = json_patch(json!([
+            {"op": "add", "path": "/foo/bar/1", "value": 5},
+            {"op": "add", "path": "/foo/bar/-", "value": 9},
+            {"op": "replace", "path": "/a~1b", "value": 2},
+            {"op": "remove", "path": "/m~0n"},
+            {"op": "copy", "from": "/foo/bar", "path": "/copy"},
+            {"op": "move", "from": "/foo/bar/0", "path": "/first"},
+            {"op": "test", "path": "/first", "value": 1},
+        ]))
+        .unwrap();
+        assert_eq!(
+            patched,
+            json!({"foo": {"bar": [5, 2, 9]}, "a/b": 2, "copy": [1, 5, 2, 9], "first": 1})
+        );
+        assert_eq!(
+            json_patch(json!([{"op": "replace", "path": "", "value": [1]}])).unwrap(),
+            json!([1])
+        );
+    }
+
+    #[test]
+    fn test_json_patch_failures() {
+        for operations in [
+            json!([{"op": "test", "path": "/a~1b", "value": 1}]),
+            json!([{"op": "remove", "path": "/missing"}]),
+            json!([{"op": "replace", "path": "/foo/bar/2", "value": 1}]),
+            json!([{"op": "add", "path": "/foo/bar/01", "value": 1}]),
+            json!([{"op": "add", "path": "/missing/child", "value": 1}]),
+            json!([{"op": "move", "from": "/foo", "path": "/foo/child"}]),
+        ] {
+            let err = json_patch(operations.clone()).unwrap_err();
+            assert!(matches!(err, ApiErr::PatchFailed(_)), "{operations}");
+        }
+        for operations in [
+            json!({"op": "add"}),
+            json!([{"op": "add", "path": "/x"}]),
+            json!([{"op": "rename", "path": "/x"}]),
+            json!([{"op": "remove", "path": "x"}]),
+            json!([{"op": "copy", "path": "/x"}]),
+        ] {
+            let err = json_patch(operations.clone()).unwrap_err();
+            assert!(matches!(err, ApiErr::InvalidPatch(_)), "{operations}");
+        }
+        assert!(matches!(
+            Patch::parse("application/json", "[]"),
+            Err(ApiErr::MediaTypeNotSupported)
+        ));
+    }
+
+    #[test]
+    fn test_merge_patch() {
+        let patch = Patch::parse(
+            MERGE_PATCH,
+            r#"{"title": "Hello!", "author": {"familyName": null}, "tags": ["example"], "phone": "555"}"#,
+        )
+        .unwrap();
+        let document = json!({
+            "title": "Goodbye!",
+            "author": {"givenName": "John", "familyName": "Doe"},
+            "tags": ["example", "sample"],
+        });
+        assert_eq!(
+            patch.apply(document).unwrap(),
+            json!({
+                "title": "Hello!",
+                "author": {"givenName": "John"},
+                "tags": ["example"],
+                "phone": "555",
+            })
+        );
+        let patch = Patch::parse(MERGE_PATCH, r#"{"a": {"b": 1}}"#).unwrap();
+        assert_eq!(patch.apply(json!([1])).unwrap(), json!({"a": {"b": 1}}));
+    }
+}
@@ -0,0 +1,355 @@
+//! Multi-process mode: a supervisor process runs copies of the program, each binding
+//! the same address with `SO_REUSEPORT` so the kernel spreads the connections between
+//! them. See [`Server::start_prefork`](crate::server::Server::start_prefork).
+
+use crate::signals;
+use std::ffi::c_void;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+use std::{env, thread};
+
+/// Set on the processes started by the supervisor
+pub(crate) const WORKER_ENV: &str = "HTTP_SERVER_PREFORK_WORKER";
+
+/// How often the supervisor checks on its workers
+const SUPERVISE_INTERVAL: Duration = Duration::from_millis(100);
+/// Wait before restarting a worker that crashed, doubled for each crash in a row
+const RESTART_DELAY: Duration = Duration::from_millis(500);
+/// Longest wait before restarting a worker that keeps crashing
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(30);
+/// How long a worker runs before its crashes stop counting as in a row
+const STABLE_AFTER: Duration = Duration::from_secs(10);
+
+const SIGTERM: i32 = 15;
+
+/// Socket constants of the architectures using the generic Linux values, the others
+/// like mips or sparc number them differently
+#[cfg(any(
+    target_arch = "x86",
+    target_arch = "x86_64",
+    target_arch = "arm",
+    target_arch = "aarch64",
+    target_arch = "riscv32",
+    target_arch = "riscv64",
+    target_arch = "powerpc",
+    target_arch = "powerpc64",
+    target_arch = "s390x",
+    target_arch = "loongarch64",
+))]
+mod sys {
+    pub const AF_INET: i32 = 2;
+    pub const AF_INET6: i32 = 10;
+    pub const SOCK_STREAM: i32 = 1;
+    pub const SOCK_CLOEXEC: i32 = 0o2000000;
+    pub const SOL_SOCKET: i32 = 1;
+    pub const SO_REUSEADDR: i32 = 2;
+    pub const SO_REUSEPORT: i32 = 15;
+}
+
+const BACKLOG: i32 = 128;
+
+extern "C" {
+    fn socket(domain: i32, ty: i32, protocol: i32) -> i32;
+    fn setsockopt(fd: i32, level: i32, name: i32, value: *const c_void, len: u32) -> i32;
+    fn bind(fd: i32, addr: *const c_void, len: u32) -> i32;
+    fn listen(fd: i32, backlog: i32) -> i32;
+    fn close(fd: i32) -> i32;
+    fn kill(pid: i32, sig: i32) -> i32;
+}
+
+/// Returns the address as a `sockaddr_in` or `sockaddr_in6`
+#[cfg(any(
+    target_arch = "x86",
+    target_arch = "x86_64",
+    target_arch = "arm",
+    target_arch = "aarch64",
+    target_arch = "riscv32",
+    target_arch = "riscv64",
+    target_arch = "powerpc",
+    target_arch = "powerpc64",
+    target_arch = "s390x",
+    target_arch = "loongarch64",
+))]
+fn sockaddr(addr: &SocketAddr) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(28);
+    match addr {
+        SocketAddr::V4(addr) => {
+            bytes.extend((sys::AF_INET as u16).to_ne_bytes());
+            bytes.extend(addr.port().to_be_bytes());
+            bytes.extend(addr.ip().octets());
+            bytes.extend([0; 8]);
+        }
+        SocketAddr::V6(addr) => {
+            bytes.extend((sys::AF_INET6 as u16).to_ne_bytes());
+            bytes.extend(addr.port().to_be_bytes());
+            bytes.extend(addr.flowinfo().to_be_bytes());
+            bytes.extend(addr.ip().octets());
+            bytes.extend(addr.scope_id().to_ne_bytes());
+        }
+    }
+    bytes
+}
+
+/// Binds a listener with `SO_REUSEPORT`, so other processes can bind the same address
+#[cfg(any(
+    target_arch = "x86",
+    target_arch = "x86_64",
+    target_arch = "arm",
+    target_arch = "aarch64",
+    target_arch = "riscv32",
+    target_arch = "riscv64",
+    target_arch = "powerpc",
+    target_arch = "powerpc64",
+    target_arch = "s390x",
+    target_arch = "loongarch64",
+))]
+pub fn bind_reuseport(addr: SocketAddr) -> io::Result<TcpListener> {
+    use std::os::fd::FromRawFd;
+    use sys::*;
+
+    let domain = if addr.is_ipv4() { AF_INET } else { AF_INET6 };
+    let address = sockaddr(&addr);
+    let enable: i32 = 1;
+    let option = &enable as *const i32 as *const c_void;
+    let option_len = std::mem::size_of::<i32>() as u32;
+
+    // SAFETY: the pointers are valid for the lengths given, and the descriptor is owned
+    // by the listener once everything succeeds or closed otherwise
+    unsafe {
+        let fd = socket(domain, SOCK_STREAM | SOCK_CLOEXEC, 0);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if setsockopt(fd, SOL_SOCKET, SO_REUSEADDR, option, option_len) < 0
+            || setsockopt(fd, SOL_SOCKET, SO_REUSEPORT, option, option_len) < 0
+            || bind(fd, address.as_ptr() as *const c_void, address.len() as u32) < 0
+            || listen(fd, BACKLOG) < 0
+        {
+            let err = io::Error::last_os_error();
+            close(fd);
+            return Err(err);
+        }
+        Ok(TcpListener::from_raw_fd(fd))
+    }
+}
+
+/// Binds a listener with `SO_REUSEPORT`, unsupported on the architectures whose socket
+/// constants aren't the generic ones
+#[cfg(not(any(
+    target_arch = "x86",
+    target_arch = "x86_64",
+    target_arch = "arm",
+    target_arch = "aarch64",
+    target_arch = "riscv32",
+    target_arch = "riscv64",
+    target_arch = "powerpc",
+    target_arch = "powerpc64",
+    target_arch = "s390x",
+    target_arch = "loongarch64",
+)))]
+pub fn bind_reuseport(_addr: SocketAddr) -> io::Result<TcpListener> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "SO_REUSEPORT isn't supported on this architecture",
+    ))
+}
+
+/// Starts a copy of the running program as a worker
+fn spawn_worker(id: usize) -> io::Result<Child> {
+    Command::new(env::current_exe()?)
+        .args(env::args_os().skip(1))
+        .env(WORKER_ENV, id.to_string())
+        .spawn()
+}
+
+/// Returns how long to wait before restarting a worker after its crashes in a row
+fn restart_delay(crashes: u32) -> Duration {
+    let factor = 2u32.saturating_pow(crashes.saturating_sub(1));
+    RESTART_DELAY.saturating_mul(factor).min(MAX_RESTART_DELAY)
+}
+
+/// A worker process, or the time it's restarted at after crashing
+struct Worker {
+    id: usize,
+    child: Option<Child>,
+    started: Instant,
+    crashes: u32,
+    restart_at: Option<Instant>,
+}
+
+/// The workers of the supervisor. Dropping them kills and reaps the ones still running,
+/// so they don't outlive a supervisor that failed
+struct Workers(Vec<Worker>);
+
+impl Workers {
+    fn children(&mut self) -> impl Iterator<Item = &mut Child> {
+        self.0.iter_mut().filter_map(|w| w.child.as_mut())
+    }
+
+    /// Asks the workers to shut down with a `SIGTERM`, giving them up to `grace` to
+    /// finish their requests before they are killed
+    fn terminate(mut self, grace: Duration) {
+        for child in self.children() {
+            // SAFETY: the pid is of a child that wasn't reaped yet, so it wasn't reused
+            unsafe { kill(child.id() as i32, SIGTERM) };
+        }
+        let deadline = Instant::now() + grace;
+        while Instant::now() < deadline {
+            for worker in &mut self.0 {
+                if let Some(Ok(Some(_))) = worker.child.as_mut().map(|c| c.try_wait()) {
+                    worker.child = None;
+                }
+            }
+            if self.children().next().is_none() {
+                return;
+            }
+            thread::sleep(SUPERVISE_INTERVAL);
+        }
+    }
+}
+
+impl Drop for Workers {
+    fn drop(&mut self) {
+        for child in self.children() {
+            _ = child.kill();
+            _ = child.wait();
+        }
+    }
+}
+
+/// Runs the workers, restarting the ones that crash with a growing delay, until all of
+/// them exit successfully. A `SIGINT` or `SIGTERM` is passed on to the workers, which
+/// get up to `grace` to finish before they are killed
+pub(crate) fn supervise(processes: usize, grace: Duration) -> io::Result<()> {
+    signals::install()?;
+    let mut workers = Workers(Vec::new());
+    for id in 0..processes.max(1) {
+        workers.0.push(Worker {
+            id,
+            child: Some(spawn_worker(id)?),
+            started: Instant::now(),
+            crashes: 0,
+            restart_at: None,
+        });
+    }
+
+    while workers
+        .0
+        .iter()
+        .any(|w| w.child.is_some() || w.restart_at.is_some())
+    {
+        thread::sleep(SUPERVISE_INTERVAL);
+        if signals::received() {
+            println!("Shutting down the workers");
+            workers.terminate(grace);
+            return Ok(());
+        }
+        let now = Instant::now();
+        for worker in &mut workers.0 {
+            if let Some(child) = &mut worker.child {
+                match child.try_wait()? {
+                    None if now - worker.started >= STABLE_AFTER => worker.crashes = 0,
+                    None => {}
+                    Some(status) if status.success() => worker.child = None,
+                    Some(status) => {
+                        worker.crashes += 1;
+                        let delay = restart_delay(worker.crashes);
+                        eprintln!(
+                            "Worker {} exited with {status}, restarting in {delay:?}",
+                            child.id()
+                        );
+                        worker.child = None;
+                        worker.restart_at = Some(now + delay);
+                    }
+                }
+            } else if worker.restart_at.is_some_and(|at| at <= now) {
+                worker.child = Some(spawn_worker(worker.id)?);
+                worker.started = now;
+                worker.restart_at = None;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    #[test]
+    fn test_bind_reuseport_shares_the_address() {
+        let first = bind_reuseport("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = first.local_addr().unwrap();
+        let second = bind_reuseport(addr).unwrap();
+        assert_eq!(second.local_addr().unwrap(), addr);
+
+        // A plain listener can't join them
+        assert!(TcpListener::bind(addr).is_err());
+
+        drop(second);
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(b"hi").unwrap();
+        let (mut server, _) = first.accept().unwrap();
+        let mut received = [0; 2];
+        server.read_exact(&mut received).unwrap();
+        assert_eq!(&received, b"hi");
+    }
+
+    #[test]
+    fn test_restart_delay() {
+        assert_eq!(restart_delay(1), Duration::from_millis(500));
+        assert_eq!(restart_delay(2), Duration::from_secs(1));
+        assert_eq!(restart_delay(4), Duration::from_secs(4));
+        assert_eq!(restart_delay(7), MAX_RESTART_DELAY);
+        assert_eq!(restart_delay(u32::MAX), MAX_RESTART_DELAY);
+    }
+
+    fn sleeping(id: usize) -> Worker {
+        Worker {
+            id,
+            child: Some(Command::new("sleep").arg("30").spawn().unwrap()),
+            started: Instant::now(),
+            crashes: 0,
+            restart_at: None,
+        }
+    }
+
+    fn reaped(pid: u32) -> bool {
+        !std::path::Path::new(&format!("/proc/{pid}")).exists()
+    }
+
+    #[test]
+    fn test_workers_are_killed_when_dropped() {
+        let workers = Workers(vec![sleeping(0), sleeping(1)]);
+        let pids: Vec<u32> = workers
+            .0
+            .iter()
+            .flat_map(|w| &w.child)
+            .map(|c| c.id())
+            .collect();
+        drop(workers);
+        assert!(pids.into_iter().all(reaped));
+    }
+
+    #[test]
+    fn test_workers_terminate() {
+        let workers = Workers(vec![sleeping(0)]);
+        let pid = workers.0[0].child.as_ref().unwrap().id();
+        let started = Instant::now();
+        workers.terminate(Duration::from_secs(10));
+        // sleep exits on the SIGTERM, long before the grace period
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(reaped(pid));
+    }
+
+    #[test]
+    fn test_bind_reuseport_ipv6() {
+        if let Ok(listener) = bind_reuseport("[::1]:0".parse().unwrap()) {
+            assert!(listener.local_addr().unwrap().is_ipv6());
+        }
+    }
+}
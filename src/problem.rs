@@ -0,0 +1,119 @@
+//! Machine readable errors in the Problem Details format
+//! ([RFC 9457](https://www.rfc-editor.org/rfc/rfc9457), formerly RFC 7807).
+
+use crate::http_status::HttpStatus;
+use serde_json::{json, Map, Value};
+
+pub const MEDIA_TYPE: &str = "application/problem+json";
+
+/// An error sent as an `application/problem+json` body, see [`Context::problem`](crate::context::Context::problem).
+/// The `type` is `about:blank` unless one is set, in which case the `title` should be
+/// the same for every problem of the type.
+/// # Example
+/// ```
+/// use HTTP_Server::http_status::HttpStatus;
+/// use HTTP_Server::problem::Problem;
+/// use serde_json::json;
+///
+/// let problem = Problem::new(HttpStatus::Forbidden)
+///     .type_uri("https://example.com/probs/out-of-credit")
+///     .title("You do not have enough credit.")
+///     .detail("Your current balance is 30, but that costs 50.")
+///     .instance("/account/12345/msgs/abc")
+///     .extension("balance", json!(30));
+/// assert_eq!(problem.to_value()["status"], 403);
+/// assert_eq!(problem.to_value()["balance"], 30);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    status: HttpStatus,
+    type_uri: String,
+    title: String,
+    detail: Option<String>,
+    instance: Option<String>,
+    extensions: Map<String, Value>,
+}
+
+impl Problem {
+    /// A problem of the status, titled with its reason phrase like `Not Found`
+    pub fn new(status: HttpStatus) -> Problem {
+        let status_line = status.to_string();
+        let reason = status_line.split_once(' ').map_or("", |(_, reason)| reason);
+        Problem {
+            status,
+            type_uri: "about:blank".to_string(),
+            title: reason.to_string(),
+            detail: None,
+            instance: None,
+            extensions: Map::new(),
+        }
+    }
+
+    /// Set the uri identifying the type of problem
+    pub fn type_uri(mut self, type_uri: &str) -> Self {
+        self.type_uri = type_uri.to_string();
+        self
+    }
+
+    /// Set the short summary of the type of problem
+    pub fn title(mut self, title: &str) -> Self {
+        self.title = title.to_string();
+        self
+    }
+
+    /// Set the explanation of this occurrence of the problem
+    pub fn detail(mut self, detail: &str) -> Self {
+        self.detail = Some(detail.to_string());
+        self
+    }
+
+    /// Set the uri identifying this occurrence of the problem
+    pub fn instance(mut self, instance: &str) -> Self {
+        self.instance = Some(instance.to_string());
+        self
+    }
+
+    /// Add a member of its own to the problem, like the fields that failed a validation.
+    /// The standard members can't be replaced this way
+    pub fn extension(mut self, key: &str, value: Value) -> Self {
+        self.extensions.insert(key.to_string(), value);
+        self
+    }
+
+    pub fn status(&self) -> HttpStatus {
+        self.status
+    }
+
+    /// Returns the problem as the json object of its body
+    pub fn to_value(&self) -> Value {
+        let mut value = self.extensions.clone();
+        let status_line = self.status.to_string();
+        let code: u16 = status_line[..3].parse().unwrap_or_default();
+        value.insert("type".to_string(), json!(self.type_uri));
+        value.insert("title".to_string(), json!(self.title));
+        value.insert("status".to_string(), json!(code));
+        if let Some(detail) = &self.detail {
+            value.insert("detail".to_string(), json!(detail));
+        }
+        if let Some(instance) = &self.instance {
+            value.insert("instance".to_string(), json!(instance));
+        }
+        Value::Object(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_value() {
+        let problem = Problem::new(HttpStatus::NotFound)
+            .extension("status", json!("ignored"))
+            .extension("id", json!(7));
+        assert_eq!(
+            problem.to_value(),
+            json!({"type": "about:blank", "title": "Not Found", "status": 404, "id": 7})
+        );
+    }
+}
@@ -0,0 +1,246 @@
+use crate::headers::Headers;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+/// A range of ip addresses in CIDR notation, like `10.0.0.0/8` or `fd00::/8`.
+/// A single address is a range of one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidIpRange(String);
+
+impl fmt::Display for InvalidIpRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid ip range: {}", self.0)
+    }
+}
+
+impl IpRange {
+    /// Returns whether the address is inside the range.
+    /// IPv4-mapped IPv6 addresses are compared as IPv4.
+    /// # Example
+    /// ```
+    /// use HTTP_Server::proxy::IpRange;
+    ///
+    /// let range: IpRange = "10.0.0.0/8".parse().unwrap();
+    /// assert!(range.contains("10.1.2.3".parse().unwrap()));
+    /// assert!(range.contains("::ffff:10.1.2.3".parse().unwrap()));
+    /// assert!(!range.contains("11.0.0.1".parse().unwrap()));
+    /// ```
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        let addr = addr.to_canonical();
+        match (self.addr, addr) {
+            (IpAddr::V4(range), IpAddr::V4(addr)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(range) & mask == u32::from(addr) & mask
+            }
+            (IpAddr::V6(range), IpAddr::V6(addr)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(range) & mask == u128::from(addr) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = InvalidIpRange;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidIpRange(s.to_string());
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr = addr
+            .parse::<IpAddr>()
+            .map_err(|_| invalid())?
+            .to_canonical();
+        let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().map_err(|_| invalid())?,
+            None => max_prefix,
+        };
+        if prefix > max_prefix {
+            return Err(invalid());
+        }
+        Ok(IpRange { addr, prefix })
+    }
+}
+
+/// Parses a node of the `Forwarded` `for` parameter or of `X-Forwarded-For`, which may be
+/// quoted and carry a port, like `"[2001:db8::1]:4711"` or `192.0.2.43:47011`.
+/// Obfuscated identifiers and `unknown` return `None`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(addr) = node.parse::<IpAddr>() {
+        return Some(addr);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    // IPv6 between brackets without a port
+    let node = node.strip_prefix('[')?.strip_suffix(']')?;
+    node.parse::<IpAddr>().ok()
+}
+
+/// Returns the addresses the request was forwarded for, from the client to the last proxy.
+/// The standard `Forwarded` header is preferred over `X-Forwarded-For`.
+/// Nodes that aren't an address are returned as `None`.
+fn forwarded_chain(headers: &Headers) -> Vec<Option<IpAddr>> {
+    if let Some(forwarded) = headers.get("Forwarded") {
+        return forwarded
+            .split(',')
+            .filter_map(|element| {
+                element.split(';').find_map(|pair| {
+                    let (key, value) = pair.split_once('=')?;
+                    key.trim()
+                        .eq_ignore_ascii_case("for")
+                        .then(|| parse_node(value))
+                })
+            })
+            .collect();
+    }
+    match headers.get("X-Forwarded-For") {
+        Some(forwarded_for) => forwarded_for.split(',').map(parse_node).collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Resolves the address of the client that sent the request.
+/// The forwarding headers are only trusted when the peer is one of the trusted proxies,
+/// in which case the chain is walked from the nearest hop back, skipping trusted proxies,
+/// up to the first untrusted address. A hop that isn't an address stops the walk.
+/// # Example
+/// ```
+/// use HTTP_Server::headers::Headers;
+/// use HTTP_Server::proxy::{client_ip, IpRange};
+/// use std::net::IpAddr;
+///
+/// let trusted: Vec<IpRange> = vec!["10.0.0.0/8".parse().unwrap()];
+/// let mut headers = Headers::new();
+/// headers.insert("X-Forwarded-For", "203.0.113.7, 10.0.0.2");
+///
+/// let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+/// let client: IpAddr = "203.0.113.7".parse().unwrap();
+/// assert_eq!(client_ip(proxy, &headers, &trusted), client);
+/// let stranger: IpAddr = "198.51.100.1".parse().unwrap();
+/// assert_eq!(client_ip(stranger, &headers, &trusted), stranger);
+/// ```
+pub fn client_ip(peer: IpAddr, headers: &Headers, trusted_proxies: &[IpRange]) -> IpAddr {
+    let is_trusted = |addr: IpAddr| trusted_proxies.iter().any(|range| range.contains(addr));
+
+    let mut client = peer;
+    for hop in forwarded_chain(headers).into_iter().rev() {
+        if !is_trusted(client) {
+            break;
+        }
+        match hop {
+            Some(addr) => client = addr,
+            None => break,
+        }
+    }
+    client
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    fn with_header(name: &str, value: &str) -> Headers {
+        let mut headers = Headers::new();
+        headers.insert(name, value);
+        headers
+    }
+
+    fn trusted() -> Vec<IpRange> {
+        vec!["10.0.0.0/8".parse().unwrap(), "fd00::/8".parse().unwrap()]
+    }
+
+    #[test]
+    fn test_ip_range_parse() {
+        assert!("192.168.0.0/16".parse::<IpRange>().is_ok());
+        assert!("::1".parse::<IpRange>().is_ok());
+        assert!("0.0.0.0/0".parse::<IpRange>().is_ok());
+        assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+        assert!("10.0.0/8".parse::<IpRange>().is_err());
+        assert!("fd00::/129".parse::<IpRange>().is_err());
+    }
+
+    #[test]
+    fn test_ip_range_contains() {
+        let all: IpRange = "0.0.0.0/0".parse().unwrap();
+        assert!(all.contains(ip("1.2.3.4")));
+        assert!(!all.contains(ip("::2")));
+
+        let single: IpRange = "192.168.1.1".parse().unwrap();
+        assert!(single.contains(ip("192.168.1.1")));
+        assert!(!single.contains(ip("192.168.1.2")));
+
+        let v6: IpRange = "fd00::/8".parse().unwrap();
+        assert!(v6.contains(ip("fd12::1")));
+        assert!(!v6.contains(ip("fe80::1")));
+    }
+
+    #[test]
+    fn test_client_ip_skips_trusted_proxies() {
+        let headers = with_header(
+            "X-Forwarded-For",
+            "1.1.1.1, 203.0.113.7, 10.0.0.3, 10.0.0.2",
+        );
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), &headers, &trusted()),
+            ip("203.0.113.7")
+        );
+    }
+
+    #[test]
+    fn test_client_ip_ignores_headers_from_untrusted_peers() {
+        let headers = with_header("X-Forwarded-For", "203.0.113.7");
+        assert_eq!(
+            client_ip(ip("8.8.8.8"), &headers, &trusted()),
+            ip("8.8.8.8")
+        );
+        assert_eq!(client_ip(ip("10.0.0.1"), &headers, &[]), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn test_client_ip_from_forwarded_header() {
+        let mut headers = with_header(
+            "Forwarded",
+            r#"for="[2001:db8:cafe::17]:4711";proto=https, for=fd00::2;by=fd00::1"#,
+        );
+        headers.insert("X-Forwarded-For", "1.1.1.1");
+        assert_eq!(
+            client_ip(ip("fd00::1"), &headers, &trusted()),
+            ip("2001:db8:cafe::17")
+        );
+
+        let headers = with_header(
+            "Forwarded",
+            "for=192.0.2.43:47011, for=unknown, for=10.0.0.2",
+        );
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), &headers, &trusted()),
+            ip("10.0.0.2")
+        );
+    }
+
+    #[test]
+    fn test_client_ip_all_hops_trusted() {
+        let headers = with_header("X-Forwarded-For", "10.0.0.3, 10.0.0.2");
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), &headers, &trusted()),
+            ip("10.0.0.3")
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use crate::api_err::ApiErr;
+use crate::utils::percent;
+use serde::de::value::{Error, MapDeserializer, StringDeserializer};
+use serde::de::{DeserializeOwned, Deserializer, IntoDeserializer, Visitor};
+use serde::forward_to_deserialize_any;
+
+/// Parses a form encoded query string like `page=2&q=hello+world` into its pairs in order.
+/// Keys without a value, like `download` in `?download`, get an empty one.
+/// Fails with `ApiErr::InvalidQuery` if a pair has a malformed escape.
+/// # Example
+/// ```
+/// use HTTP_Server::query;
+///
+/// let pairs = query::parse("q=hello+world&tag=a%26b&download").unwrap();
+/// assert_eq!(pairs[0], ("q".to_string(), "hello world".to_string()));
+/// assert_eq!(pairs[1], ("tag".to_string(), "a&b".to_string()));
+/// assert_eq!(pairs[2], ("download".to_string(), "".to_string()));
+/// ```
+pub fn parse(query: &str) -> Result<Vec<(String, String)>, ApiErr> {
+    let decode = |component: &str| {
+        percent::decode(&component.replace('+', " "))
+            .ok_or_else(|| ApiErr::InvalidQuery(format!("invalid encoding in `{component}`")))
+    };
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Ok((decode(key)?, decode(value)?))
+        })
+        .collect()
+}
+
+/// Deserializes the pairs of a query string, like a struct with a field per key.
+/// Values are parsed into the type of their field, a missing key leaves an
+/// `Option` field as `None` and structs reject repeated keys.
+pub fn deserialize<T: DeserializeOwned>(query: &str) -> Result<T, ApiErr> {
+    let pairs = parse(query)?
+        .into_iter()
+        .map(|(key, value)| (key, QueryValue(value)));
+    T::deserialize(MapDeserializer::<_, Error>::new(pairs))
+        .map_err(|e| ApiErr::InvalidQuery(e.to_string()))
+}
+
+/// A single value of a query string, parsed into whatever type is asked for
+struct QueryValue(String);
+
+impl<'de> IntoDeserializer<'de, Error> for QueryValue {
+    type Deserializer = QueryValue;
+
+    fn into_deserializer(self) -> QueryValue {
+        self
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident),* $(,)?) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+                match self.0.parse() {
+                    Ok(value) => visitor.$visit(value),
+                    Err(_) => Err(serde::de::Error::custom(format!("invalid value `{}`", self.0))),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for QueryValue {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_string(self.0)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let value: StringDeserializer<Error> = self.0.into_deserializer();
+        value.deserialize_enum(name, variants, visitor)
+    }
+
+    deserialize_parsed! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 str string bytes byte_buf unit unit_struct newtype_struct seq
+        tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("").unwrap(), vec![]);
+        assert_eq!(
+            parse("a=1&&b=x%20y&a=2").unwrap(),
+            vec![
+                ("a".to_string(), "1".to_string()),
+                ("b".to_string(), "x y".to_string()),
+                ("a".to_string(), "2".to_string()),
+            ]
+        );
+        // `+` is a space but an escaped one is kept
+        assert_eq!(parse("q=1%2B1+2").unwrap()[0].1, "1+1 2");
+        assert!(parse("q=%zz").is_err());
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let numbers: HashMap<String, u32> = deserialize("page=2&per_page=50").unwrap();
+        assert_eq!(numbers["page"], 2);
+        assert_eq!(numbers["per_page"], 50);
+
+        let flags: HashMap<String, Option<bool>> = deserialize("active=true").unwrap();
+        assert_eq!(flags["active"], Some(true));
+
+        let words: HashMap<String, String> = deserialize("q=hello+world").unwrap();
+        assert_eq!(words["q"], "hello world");
+    }
+
+    #[test]
+    fn test_deserialize_invalid_value() {
+        let err = deserialize::<HashMap<String, u32>>("page=two").unwrap_err();
+        assert_eq!(err.to_string(), "Invalid query: invalid value `two`.");
+        assert_eq!(
+            err.http_status(),
+            crate::http_status::HttpStatus::BadRequest
+        );
+    }
+}
@@ -0,0 +1,94 @@
+//! The `Range` header of requests for part of a body
+//! ([RFC 9110](https://www.rfc-editor.org/rfc/rfc9110#section-14.2)), so clients can
+//! resume downloads and seek in videos.
+
+/// The part of a body a `Range` header asks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    /// The whole body, for headers that are malformed or ask for several ranges
+    Full,
+    /// The bytes from the first position to the second, both included
+    Partial(u64, u64),
+    /// A range that starts after the end of the body, answered with a `416`
+    Unsatisfiable,
+}
+
+/// Parses a `Range` header for a body of `len` bytes, like `bytes=0-499`, `bytes=500-`
+/// or the last 500 bytes with `bytes=-500`. Ends past the body are cut to its length.
+/// # Example
+/// ```
+/// use HTTP_Server::range::{self, ByteRange};
+///
+/// assert_eq!(range::parse("bytes=0-499", 1000), ByteRange::Partial(0, 499));
+/// assert_eq!(range::parse("bytes=-100", 1000), ByteRange::Partial(900, 999));
+/// assert_eq!(range::parse("bytes=1000-", 1000), ByteRange::Unsatisfiable);
+/// assert_eq!(range::parse("lines=1-2", 1000), ByteRange::Full);
+/// ```
+pub fn parse(range: &str, len: u64) -> ByteRange {
+    let Some((unit, spec)) = range.trim().split_once('=') else {
+        return ByteRange::Full;
+    };
+    // Several ranges would need a multipart body, the whole one is also a valid answer
+    if !unit.trim().eq_ignore_ascii_case("bytes") || spec.contains(',') {
+        return ByteRange::Full;
+    }
+    let Some((first, last)) = spec.trim().split_once('-') else {
+        return ByteRange::Full;
+    };
+    let number = |n: &str| match n.bytes().all(|b| b.is_ascii_digit()) {
+        true => n.parse::<u64>().ok(),
+        false => None,
+    };
+
+    match (first, last) {
+        ("", suffix) => match number(suffix) {
+            Some(0) => ByteRange::Unsatisfiable,
+            Some(_) if len == 0 => ByteRange::Unsatisfiable,
+            Some(suffix) => ByteRange::Partial(len.saturating_sub(suffix), len - 1),
+            None => ByteRange::Full,
+        },
+        (first, "") => match number(first) {
+            Some(first) if first >= len => ByteRange::Unsatisfiable,
+            Some(first) => ByteRange::Partial(first, len - 1),
+            None => ByteRange::Full,
+        },
+        (first, last) => match (number(first), number(last)) {
+            (Some(first), Some(last)) if first > last => ByteRange::Full,
+            (Some(first), Some(_)) if first >= len => ByteRange::Unsatisfiable,
+            (Some(first), Some(last)) => ByteRange::Partial(first, last.min(len - 1)),
+            _ => ByteRange::Full,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("bytes=0-0", 10), ByteRange::Partial(0, 0));
+        assert_eq!(parse("bytes=2-5", 10), ByteRange::Partial(2, 5));
+        assert_eq!(parse("bytes=2-500", 10), ByteRange::Partial(2, 9));
+        assert_eq!(parse("bytes=7-", 10), ByteRange::Partial(7, 9));
+        assert_eq!(parse("Bytes = -3", 10), ByteRange::Partial(7, 9));
+        assert_eq!(parse("bytes=-30", 10), ByteRange::Partial(0, 9));
+
+        assert_eq!(parse("bytes=10-", 10), ByteRange::Unsatisfiable);
+        assert_eq!(parse("bytes=10-20", 10), ByteRange::Unsatisfiable);
+        assert_eq!(parse("bytes=-0", 10), ByteRange::Unsatisfiable);
+        assert_eq!(parse("bytes=-5", 0), ByteRange::Unsatisfiable);
+        assert_eq!(parse("bytes=0-", 0), ByteRange::Unsatisfiable);
+
+        for header in [
+            "bytes=5-2",
+            "bytes=a-b",
+            "bytes=0-1,4-5",
+            "bytes=+1-2",
+            "bytes",
+            "items=0-1",
+        ] {
+            assert_eq!(parse(header, 10), ByteRange::Full, "{header}");
+        }
+    }
+}
@@ -1,26 +1,236 @@
+use std::cmp::Reverse;
 use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::ops::{Deref, DerefMut};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
 
-use super::{
-    context::Context, http_method::HttpMethod, http_request::HttpRequest, http_status::HttpStatus,
-};
+use super::context::{Context, ResponseBuffer};
+use super::http_request::HttpRequest;
+use super::http_response::HttpResponse;
+use super::links;
+use super::static_files::StaticDir;
+use super::utils::percent;
+use super::utils::regex::{Regex, RegexError};
+use super::{http_method::HttpMethod, http_status::HttpStatus};
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct Route {
     pub method: HttpMethod,
     pub path: Vec<String>,
-    pub handler: Handler,
+    pub handler: RouteHandler,
+    /// Checks the values of the path params, the route only matches if it returns true
+    params_guard: Option<ParamsGuard>,
+    /// Regex matched against the whole path instead of the segments of `path`
+    pattern: Option<Arc<Regex>>,
+    name: Option<String>,
+    /// Run in order after the router middlewares, only for this route
+    middlewares: Vec<Middleware>,
+    metadata: HashMap<String, String>,
+    /// Checks on the value of a single path param, the route only matches if all pass
+    constraints: Vec<(String, Constraint)>,
 }
 
 type Handler = fn(ctx: &mut Context);
+pub type RouteHandler = Arc<dyn Fn(&mut Context) + Send + Sync>;
+type ParamsGuard = Arc<dyn Fn(&[&str]) -> bool + Send + Sync>;
+type Constraint = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+type ResponseHandler = fn(ctx: &mut Context) -> HttpResponse;
+
+/// Path params parsed into typed values for the handlers of typed routes.
+/// It's implemented for tuples of up to six `FromStr` types, taking the params in
+/// the order they appear in the route path.
+pub trait FromParams: Sized {
+    /// Parses the param values, returning `None` if any of them can't be parsed
+    /// or there isn't exactly one value for each element.
+    fn from_params(values: &[&str]) -> Option<Self>;
+}
+
+macro_rules! impl_from_params {
+    ($len:literal, $($t:ident),+) => {
+        impl<$($t: FromStr),+> FromParams for ($($t,)+) {
+            fn from_params(values: &[&str]) -> Option<Self> {
+                let [$($t),+] = values else {
+                    return None;
+                };
+                Some(($($t.parse::<$t>().ok()?,)+))
+            }
+        }
+    };
+}
+
+#[allow(non_snake_case)]
+mod from_params_tuples {
+    use super::*;
+
+    impl_from_params!(1, A);
+    impl_from_params!(2, A, B);
+    impl_from_params!(3, A, B, C);
+    impl_from_params!(4, A, B, C, D);
+    impl_from_params!(5, A, B, C, D, E);
+    impl_from_params!(6, A, B, C, D, E, F);
+}
+
+impl fmt::Debug for Route {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Route")
+            .field("method", &self.method)
+            .field("path", &self.path)
+            .field("typed", &self.params_guard.is_some())
+            .field("pattern", &self.pattern.as_ref().map(|p| p.as_str()))
+            .field("name", &self.name)
+            .field("middlewares", &self.middlewares.len())
+            .field("metadata", &self.metadata)
+            .finish()
+    }
+}
+
 impl Route {
     pub fn new(method: HttpMethod, path: &str, handler: Handler) -> Route {
+        Route::with_handler(method, path, Arc::new(handler))
+    }
+
+    fn with_handler(method: HttpMethod, path: &str, handler: RouteHandler) -> Route {
         let path = path.trim_end_matches("/").trim_start_matches("/");
         let path = path.split("/").map(|p| p.to_string()).collect();
         Route {
             method,
             path,
             handler,
+            params_guard: None,
+            pattern: None,
+            name: None,
+            middlewares: Vec::new(),
+            metadata: HashMap::new(),
+            constraints: Vec::new(),
+        }
+    }
+
+    /// Create a route whose handler returns the response instead of writing it,
+    /// so the response middlewares of the router can modify it before it's sent
+    pub fn returning(method: HttpMethod, path: &str, handler: ResponseHandler) -> Route {
+        let handler = move |ctx: &mut Context| ctx.response = Some(handler(ctx));
+        Route::with_handler(method, path, Arc::new(handler))
+    }
+
+    /// Create a route matched with a regex against the whole request path.
+    /// The named capture groups are set as the path params.
+    /// # Example
+    /// ```
+    /// use HTTP_Server::context::Context;
+    /// use HTTP_Server::http_method::HttpMethod;
+    /// use HTTP_Server::router::Route;
+    ///
+    /// fn handler(ctx: &mut Context) {}
+    ///
+    /// let route = Route::regex(HttpMethod::Get, r"^/files/(?P<year>\d{4})/(?P<name>.+)$", handler);
+    /// assert!(route.is_ok());
+    /// assert!(Route::regex(HttpMethod::Get, r"^/files/(?P<year>", handler).is_err());
+    /// ```
+    pub fn regex(method: HttpMethod, pattern: &str, handler: Handler) -> Result<Route, RegexError> {
+        Ok(Route {
+            method,
+            path: Vec::new(),
+            handler: Arc::new(handler),
+            params_guard: None,
+            pattern: Some(Arc::new(Regex::new(pattern)?)),
+            name: None,
+            middlewares: Vec::new(),
+            metadata: HashMap::new(),
+            constraints: Vec::new(),
+        })
+    }
+
+    /// Create a route whose handler receives the path params already parsed as `T`.
+    /// The route only matches requests whose params can be parsed, so a request
+    /// with an invalid param falls through to the other routes.
+    /// # Example
+    /// ```
+    /// use HTTP_Server::context::Context;
+    /// use HTTP_Server::http_method::HttpMethod;
+    /// use HTTP_Server::router::Route;
+    ///
+    /// fn handler(ctx: &mut Context, (id, slug): (u32, String)) {}
+    ///
+    /// let route = Route::typed(HttpMethod::Get, "/users/{id}/posts/{slug}", handler);
+    /// assert!(route.accepts(&["users", "1", "posts", "hello"]));
+    /// assert!(!route.accepts(&["users", "one", "posts", "hello"]));
+    /// ```
+    pub fn typed<T: FromParams + 'static>(
+        method: HttpMethod,
+        path: &str,
+        handler: fn(&mut Context, T),
+    ) -> Route {
+        let names: Vec<String> = Route::with_handler(method, path, Arc::new(|_| {}))
+            .param_names()
+            .map(|name| name.to_string())
+            .collect();
+        let typed_handler = move |ctx: &mut Context| {
+            let values: Vec<String> = names
+                .iter()
+                .map(|name| ctx.param(name).unwrap_or_default())
+                .collect();
+            let values: Vec<&str> = values.iter().map(|v| v.as_str()).collect();
+            match T::from_params(&values) {
+                Some(params) => handler(ctx, params),
+                None => ctx.string(HttpStatus::NotFound, "Not Found"),
+            }
+        };
+
+        let mut route = Route::with_handler(method, path, Arc::new(typed_handler));
+        route.params_guard = Some(Arc::new(|values| T::from_params(values).is_some()));
+        route
+    }
+
+    fn is_param(segment: &str) -> bool {
+        segment.starts_with("{") && segment.ends_with("}")
+    }
+
+    /// Returns the names of the path params in the order they appear in the route
+    pub fn param_names(&self) -> impl Iterator<Item = &str> {
+        self.path
+            .iter()
+            .filter(|p| Route::is_param(p))
+            .map(|p| p.trim_start_matches("{").trim_end_matches("}"))
+    }
+
+    /// Returns the name given with [`RouteHandle::name`]
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Returns the value set with [`RouteHandle::metadata`]
+    pub fn metadata(&self, key: &str) -> Option<&str> {
+        self.metadata.get(key).map(|v| v.as_str())
+    }
+
+    /// Returns whether the values the path has for the route params are valid for the route
+    pub fn accepts(&self, path: &[&str]) -> bool {
+        let params: Vec<(&str, &str)> = self
+            .path
+            .iter()
+            .zip(path)
+            .filter(|(p, _)| Route::is_param(p))
+            .map(|(p, value)| (p.trim_start_matches("{").trim_end_matches("}"), *value))
+            .collect();
+        if !self.meets_constraints(|name| params.iter().find(|(n, _)| *n == name).map(|p| p.1)) {
+            return false;
         }
+        let guard = match &self.params_guard {
+            Some(guard) => guard,
+            None => return true,
+        };
+        let values: Vec<&str> = params.iter().map(|(_, value)| *value).collect();
+        guard(&values)
+    }
+
+    /// Returns whether every constraint passes for the value of its param.
+    /// A param without a value doesn't pass.
+    fn meets_constraints<'v>(&self, value: impl Fn(&str) -> Option<&'v str>) -> bool {
+        self.constraints
+            .iter()
+            .all(|(name, check)| value(name).is_some_and(|v| check(v)))
     }
 
     /// Compare the route at the index with the path
@@ -46,7 +256,7 @@ impl Route {
             return false;
         }
 
-        if self.path[index].starts_with("{") && self.path[index].ends_with("}") {
+        if Route::is_param(&self.path[index]) {
             return true;
         }
 
@@ -79,11 +289,20 @@ impl Route {
         matches
     }
 
+    /// Runs the route middlewares and, if none of them stopped the request, the handler
+    fn run(self, ctx: &mut Context) {
+        if self.middlewares.iter().all(|middleware| middleware(ctx)) {
+            let handler = Arc::clone(&self.handler);
+            ctx.route = Some(self);
+            handler(ctx);
+        }
+    }
+
     /// Set the path params in the context
     pub fn set_path_params(&self, path: &[&str], ctx: &mut Context) {
         let mut params = HashMap::new();
         for (i, p) in path.iter().enumerate() {
-            if self.path[i].starts_with("{") && self.path[i].ends_with("}") {
+            if Route::is_param(&self.path[i]) {
                 params.insert(
                     self.path[i]
                         .trim_start_matches("{")
@@ -97,14 +316,276 @@ impl Route {
     }
 }
 
+/// Runs before the route handler, returning false to stop the request.
+/// A middleware that stops the request must answer it.
+pub type Middleware = Arc<dyn Fn(&mut Context) -> bool + Send + Sync>;
+
+/// Runs after a handler that returned an [`HttpResponse`], before it's sent
+pub type ResponseMiddleware = Arc<dyn Fn(&Context, &mut HttpResponse) + Send + Sync>;
+
+/// A route just added to a [`Router`], to set its options while registering it.
+/// It derefs to the router, so more routes can be chained after it.
+/// # Example
+/// ```
+/// use HTTP_Server::context::Context;
+/// use HTTP_Server::router::Router;
+///
+/// fn handler(ctx: &mut Context) {}
+///
+/// let mut router = Router::new();
+/// router
+///     .get("/users/{id}", handler)
+///     .name("user")
+///     .constraint("id", |id| id.parse::<u32>().is_ok())
+///     .metadata("cache", "private")
+///     .middleware(|ctx: &mut Context| ctx.header("Authorization").is_some())
+///     .post("/users", handler)
+///     .name("create_user");
+///
+/// assert_eq!(router.route_named("user").unwrap().metadata("cache"), Some("private"));
+/// ```
+pub struct RouteHandle<'r> {
+    router: &'r mut Router,
+    index: usize,
+}
+
+impl RouteHandle<'_> {
+    fn route_mut(&mut self) -> &mut Route {
+        &mut self.router.routes[self.index]
+    }
+
+    /// Returns the route
+    pub fn route(&self) -> &Route {
+        &self.router.routes[self.index]
+    }
+
+    /// Name the route, to look it up with [`Router::route_named`]
+    pub fn name(mut self, name: &str) -> Self {
+        self.route_mut().name = Some(name.to_string());
+        self
+    }
+
+    /// Add a middleware that runs only for this route, after the router middlewares.
+    /// A middleware that stops the request must answer it.
+    pub fn middleware<M>(mut self, middleware: M) -> Self
+    where
+        M: Fn(&mut Context) -> bool + Send + Sync + 'static,
+    {
+        self.route_mut().middlewares.push(Arc::new(middleware));
+        self
+    }
+
+    /// Attach a value to the route, readable by the handlers and middlewares with
+    /// [`Context::route`]
+    pub fn metadata<K: Display, V: Display>(mut self, key: K, value: V) -> Self {
+        self.route_mut()
+            .metadata
+            .insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Only match the route if the check passes for the value of the path param.
+    /// Requests that fail it fall through to the other routes.
+    pub fn constraint<C>(mut self, param: &str, check: C) -> Self
+    where
+        C: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.route_mut()
+            .constraints
+            .push((param.to_string(), Arc::new(check)));
+        self
+    }
+}
+
+impl Deref for RouteHandle<'_> {
+    type Target = Router;
+
+    fn deref(&self) -> &Router {
+        self.router
+    }
+}
+
+impl DerefMut for RouteHandle<'_> {
+    fn deref_mut(&mut self) -> &mut Router {
+        self.router
+    }
+}
+
+/// Returns how many segments of the path a regex match covers without any capture
+/// group in them, its specificity like the literal segments of a segment route.
+/// `/files/latest/a.txt` has two for `^/files/latest/(?P<name>.+)$`
+fn literal_segments(path: &str, captures: &[Option<(usize, usize)>]) -> usize {
+    let Some(Some((start, end))) = captures.first() else {
+        return 0;
+    };
+    let mut literal = 0;
+    let mut offset = 0;
+    for segment in path.split('/') {
+        let (from, to) = (offset, offset + segment.len());
+        offset = to + 1;
+        let captured = captures[1..]
+            .iter()
+            .flatten()
+            .any(|(s, e)| *s < to && *e > from);
+        if !segment.is_empty() && from >= *start && to <= *end && !captured {
+            literal += 1;
+        }
+    }
+    literal
+}
+
+#[derive(Default)]
 pub struct Router {
     pub routes: Vec<Route>,
+    middlewares: Vec<Middleware>,
+    response_middlewares: Vec<ResponseMiddleware>,
+    /// Directories served under a path prefix, given as its segments
+    static_dirs: Vec<(Vec<String>, Arc<StaticDir>)>,
+    /// Handlers for the requests of a method no route matches
+    fallbacks: Vec<(HttpMethod, Handler)>,
 }
 
 impl Router {
     /// Create a new router
     pub fn new() -> Router {
-        Router { routes: Vec::new() }
+        Router {
+            routes: Vec::new(),
+            middlewares: Vec::new(),
+            response_middlewares: Vec::new(),
+            static_dirs: Vec::new(),
+            fallbacks: Vec::new(),
+        }
+    }
+
+    /// Add a middleware that runs for every request before routing it,
+    /// in the order they were added
+    /// # Example
+    /// ```
+    /// use HTTP_Server::context::Context;
+    /// use HTTP_Server::http_status::HttpStatus;
+    /// use HTTP_Server::router::Router;
+    ///
+    /// let mut router = Router::new();
+    /// router.use_middleware(|ctx: &mut Context| {
+    ///     if ctx.header("Authorization").is_none() {
+    ///         ctx.string(HttpStatus::Forbidden, "Forbidden");
+    ///         return false;
+    ///     }
+    ///     true
+    /// });
+    /// ```
+    pub fn use_middleware<M>(&mut self, middleware: M) -> &mut Self
+    where
+        M: Fn(&mut Context) -> bool + Send + Sync + 'static,
+    {
+        self.middlewares.push(Arc::new(middleware));
+        self
+    }
+
+    /// Add a middleware that can modify the responses returned by the handlers of
+    /// [`Router::route_response`] routes, in the order they were added.
+    /// Responses written directly with the context methods are already sent.
+    /// # Example
+    /// ```
+    /// use HTTP_Server::context::Context;
+    /// use HTTP_Server::http_response::HttpResponse;
+    /// use HTTP_Server::router::Router;
+    ///
+    /// let mut router = Router::new();
+    /// router.use_response_middleware(|_ctx: &Context, response: &mut HttpResponse| {
+    ///     response.set_header("Cache-Control", "no-store");
+    /// });
+    /// ```
+    pub fn use_response_middleware<M>(&mut self, middleware: M) -> &mut Self
+    where
+        M: Fn(&Context, &mut HttpResponse) + Send + Sync + 'static,
+    {
+        self.response_middlewares.push(Arc::new(middleware));
+        self
+    }
+
+    /// Add the route, returning a handle to set its options
+    pub fn add_route(&mut self, route: Route) -> RouteHandle<'_> {
+        self.routes.push(route);
+        RouteHandle {
+            index: self.routes.len() - 1,
+            router: self,
+        }
+    }
+
+    /// Returns the route with the name, see [`RouteHandle::name`]
+    pub fn route_named(&self, name: &str) -> Option<&Route> {
+        self.routes.iter().find(|r| r.name() == Some(name))
+    }
+
+    /// Returns the path of the named route with its params replaced by the values,
+    /// percent encoded. `None` if there's no such route, it's a regex route or a
+    /// param has no value
+    /// # Example
+    /// ```
+    /// use HTTP_Server::context::Context;
+    /// use HTTP_Server::router::Router;
+    ///
+    /// fn post(ctx: &mut Context) {}
+    ///
+    /// let mut router = Router::new();
+    /// router.get("/users/{name}/posts/{id}", post).name("post");
+    /// let url = router.url_for("post", &[("name", "ana maria"), ("id", "7")]);
+    /// assert_eq!(url.as_deref(), Some("/users/ana%20maria/posts/7"));
+    /// assert_eq!(router.url_for("post", &[("id", "7")]), None);
+    /// ```
+    pub fn url_for(&self, name: &str, params: &[(&str, &str)]) -> Option<String> {
+        let route = self.route_named(name).filter(|r| r.pattern.is_none())?;
+        let segments: Option<Vec<String>> = route
+            .path
+            .iter()
+            .map(|segment| match Route::is_param(segment) {
+                true => {
+                    let param = &segment[1..segment.len() - 1];
+                    let (_, value) = params.iter().find(|(name, _)| *name == param)?;
+                    Some(percent::encode(value))
+                }
+                false => Some(segment.clone()),
+            })
+            .collect();
+        Some(format!("/{}", segments?.join("/")))
+    }
+
+    /// Adds the links the route asks for in its metadata to the context, see [`links`]
+    fn add_links(&self, route: &Route, ctx: &mut Context) {
+        let Some(spec) = route.metadata(links::METADATA_KEY) else {
+            return;
+        };
+        let request = &ctx.request;
+        let mut found = Vec::new();
+        for link in spec.split(',').map(str::trim) {
+            match link.split_once('=') {
+                Some((rel, name)) => {
+                    let params: Vec<(&str, &str)> = ctx
+                        .path_params
+                        .iter()
+                        .map(|(k, v)| (k.as_str(), v.as_str()))
+                        .collect();
+                    if let Some(url) = self.url_for(name.trim(), &params) {
+                        found.push((rel.trim().to_string(), url));
+                    }
+                }
+                None if link == "self" => {
+                    let url = match request.query() {
+                        Some(query) => format!("{}?{query}", request.path()),
+                        None => request.path().to_string(),
+                    };
+                    found.push(("self".to_string(), url));
+                }
+                None if link == "pagination" => {
+                    found.extend(links::pagination(request.path(), request.query()))
+                }
+                None => {}
+            }
+        }
+        for (rel, url) in found {
+            ctx.link(&rel, &url);
+        }
     }
 
     /// Add a new get route to the router
@@ -118,9 +599,8 @@ impl Router {
     /// let mut router = Router::new();
     /// router.get("/test", handler);
     /// ```
-    pub fn get(&mut self, path: &str, handler: Handler) -> &mut Self {
-        self.routes.push(Route::new(HttpMethod::Get, path, handler));
-        self
+    pub fn get(&mut self, path: &str, handler: Handler) -> RouteHandle<'_> {
+        self.add_route(Route::new(HttpMethod::Get, path, handler))
     }
 
     /// Add a new post route to the router
@@ -134,29 +614,286 @@ impl Router {
     /// let mut router = Router::new();
     /// router.post("/test", handler);
     /// ```
-    pub fn post(&mut self, path: &str, handler: Handler) -> &mut Self {
-        self.routes
-            .push(Route::new(HttpMethod::Post, path, handler));
-        self
+    pub fn post(&mut self, path: &str, handler: Handler) -> RouteHandle<'_> {
+        self.add_route(Route::new(HttpMethod::Post, path, handler))
     }
 
-    pub fn put(&mut self, path: &str, handler: Handler) -> &mut Self {
-        self.routes.push(Route::new(HttpMethod::Put, path, handler));
-        self
+    pub fn put(&mut self, path: &str, handler: Handler) -> RouteHandle<'_> {
+        self.add_route(Route::new(HttpMethod::Put, path, handler))
+    }
+
+    pub fn delete(&mut self, path: &str, handler: Handler) -> RouteHandle<'_> {
+        self.add_route(Route::new(HttpMethod::Delete, path, handler))
+    }
+
+    pub fn patch(&mut self, path: &str, handler: Handler) -> RouteHandle<'_> {
+        self.add_route(Route::new(HttpMethod::Patch, path, handler))
+    }
+
+    /// Add a new route whose handler returns its response, see [`Route::returning`]
+    pub fn route_response(
+        &mut self,
+        method: HttpMethod,
+        path: &str,
+        handler: ResponseHandler,
+    ) -> RouteHandle<'_> {
+        self.add_route(Route::returning(method, path, handler))
+    }
+
+    /// Add a new get route whose handler returns its response
+    /// # Example
+    /// ```
+    /// use HTTP_Server::context::Context;
+    /// use HTTP_Server::http_response::HttpResponse;
+    /// use HTTP_Server::router::Router;
+    ///
+    /// fn handler(ctx: &mut Context) -> HttpResponse {
+    ///     HttpResponse::ok().text("pong")
+    /// }
+    ///
+    /// let mut router = Router::new();
+    /// router.get_response("/ping", handler);
+    /// ```
+    pub fn get_response(&mut self, path: &str, handler: ResponseHandler) -> RouteHandle<'_> {
+        self.route_response(HttpMethod::Get, path, handler)
+    }
+
+    pub fn post_response(&mut self, path: &str, handler: ResponseHandler) -> RouteHandle<'_> {
+        self.route_response(HttpMethod::Post, path, handler)
+    }
+
+    pub fn put_response(&mut self, path: &str, handler: ResponseHandler) -> RouteHandle<'_> {
+        self.route_response(HttpMethod::Put, path, handler)
+    }
+
+    pub fn delete_response(&mut self, path: &str, handler: ResponseHandler) -> RouteHandle<'_> {
+        self.route_response(HttpMethod::Delete, path, handler)
+    }
+
+    pub fn patch_response(&mut self, path: &str, handler: ResponseHandler) -> RouteHandle<'_> {
+        self.route_response(HttpMethod::Patch, path, handler)
+    }
+
+    /// Add a new typed route to the router, see [`Route::typed`]
+    pub fn route_typed<T: FromParams + 'static>(
+        &mut self,
+        method: HttpMethod,
+        path: &str,
+        handler: fn(&mut Context, T),
+    ) -> RouteHandle<'_> {
+        self.add_route(Route::typed(method, path, handler))
+    }
+
+    /// Add a new get route whose handler receives the path params parsed as `T`.
+    /// Requests whose params can't be parsed fall through to the other routes.
+    /// # Example
+    /// ```
+    /// use HTTP_Server::context::Context;
+    /// use HTTP_Server::router::Router;
+    ///
+    /// fn handler(ctx: &mut Context, (id, slug): (u32, String)) {}
+    ///
+    /// let mut router = Router::new();
+    /// router.get_typed::<(u32, String)>("/users/{id}/posts/{slug}", handler);
+    /// ```
+    pub fn get_typed<T: FromParams + 'static>(
+        &mut self,
+        path: &str,
+        handler: fn(&mut Context, T),
+    ) -> RouteHandle<'_> {
+        self.route_typed(HttpMethod::Get, path, handler)
+    }
+
+    pub fn post_typed<T: FromParams + 'static>(
+        &mut self,
+        path: &str,
+        handler: fn(&mut Context, T),
+    ) -> RouteHandle<'_> {
+        self.route_typed(HttpMethod::Post, path, handler)
+    }
+
+    pub fn put_typed<T: FromParams + 'static>(
+        &mut self,
+        path: &str,
+        handler: fn(&mut Context, T),
+    ) -> RouteHandle<'_> {
+        self.route_typed(HttpMethod::Put, path, handler)
+    }
+
+    pub fn delete_typed<T: FromParams + 'static>(
+        &mut self,
+        path: &str,
+        handler: fn(&mut Context, T),
+    ) -> RouteHandle<'_> {
+        self.route_typed(HttpMethod::Delete, path, handler)
+    }
+
+    pub fn patch_typed<T: FromParams + 'static>(
+        &mut self,
+        path: &str,
+        handler: fn(&mut Context, T),
+    ) -> RouteHandle<'_> {
+        self.route_typed(HttpMethod::Patch, path, handler)
+    }
+
+    /// Add a new regex route to the router, see [`Route::regex`].
+    /// Regex routes are only tried, in the order they were added, when no
+    /// segment route matches the request.
+    /// # Panics
+    /// If the pattern is not a valid regex
+    pub fn route_regex(
+        &mut self,
+        method: HttpMethod,
+        pattern: &str,
+        handler: Handler,
+    ) -> RouteHandle<'_> {
+        match Route::regex(method, pattern, handler) {
+            Ok(route) => self.add_route(route),
+            Err(e) => panic!("{e} in route {pattern}"),
+        }
+    }
+
+    /// Add a new get route matched with a regex, for paths the segment templates
+    /// can't express. The named capture groups are set as the path params.
+    /// It's ranked with the segment routes by the segments of the path it matches outside
+    /// of capture groups, a segment route with as many literal segments wins.
+    /// # Panics
+    /// If the pattern is not a valid regex
+    /// # Example
+    /// ```
+    /// use HTTP_Server::context::Context;
+    /// use HTTP_Server::router::Router;
+    ///
+    /// fn handler(ctx: &mut Context) {}
+    ///
+    /// let mut router = Router::new();
+    /// router.get_regex(r"^/files/(?P<year>\d{4})/(?P<name>.+)$", handler);
+    /// ```
+    pub fn get_regex(&mut self, pattern: &str, handler: Handler) -> RouteHandle<'_> {
+        self.route_regex(HttpMethod::Get, pattern, handler)
+    }
+
+    pub fn post_regex(&mut self, pattern: &str, handler: Handler) -> RouteHandle<'_> {
+        self.route_regex(HttpMethod::Post, pattern, handler)
+    }
+
+    pub fn put_regex(&mut self, pattern: &str, handler: Handler) -> RouteHandle<'_> {
+        self.route_regex(HttpMethod::Put, pattern, handler)
+    }
+
+    pub fn delete_regex(&mut self, pattern: &str, handler: Handler) -> RouteHandle<'_> {
+        self.route_regex(HttpMethod::Delete, pattern, handler)
+    }
+
+    pub fn patch_regex(&mut self, pattern: &str, handler: Handler) -> RouteHandle<'_> {
+        self.route_regex(HttpMethod::Patch, pattern, handler)
+    }
+
+    /// Serve the files under the directory for get requests to the paths under the prefix,
+    /// see [`StaticDir`]. Static directories are only tried, from the longest prefix,
+    /// when no other route matches the request.
+    /// # Example
+    /// ```
+    /// use HTTP_Server::router::Router;
+    ///
+    /// let mut router = Router::new();
+    /// // GET /assets/css/site.css serves ./public/css/site.css
+    /// router.static_dir("/assets", "./public");
+    /// ```
+    pub fn static_dir<P: Into<PathBuf>>(&mut self, prefix: &str, dir: P) -> &mut Self {
+        self.mount_static(prefix, StaticDir::new(dir))
     }
 
-    pub fn delete(&mut self, path: &str, handler: Handler) -> &mut Self {
-        self.routes
-            .push(Route::new(HttpMethod::Delete, path, handler));
+    /// Serve a directory configured with [`StaticDir`] under the prefix
+    pub fn mount_static(&mut self, prefix: &str, dir: StaticDir) -> &mut Self {
+        let prefix = prefix
+            .trim_end_matches("/")
+            .trim_start_matches("/")
+            .split("/")
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string())
+            .collect();
+        self.static_dirs.push((prefix, Arc::new(dir)));
+        self.static_dirs
+            .sort_by_key(|(prefix, _)| Reverse(prefix.len()));
         self
     }
 
-    pub fn patch(&mut self, path: &str, handler: Handler) -> &mut Self {
-        self.routes
-            .push(Route::new(HttpMethod::Patch, path, handler));
+    /// Set the handler for the requests of the method that no route or static directory
+    /// matches, instead of answering them with `404 Not Found`.
+    /// Setting it again for the same method replaces it.
+    /// # Example
+    /// ```
+    /// use HTTP_Server::context::Context;
+    /// use HTTP_Server::http_method::HttpMethod;
+    /// use HTTP_Server::http_status::HttpStatus;
+    /// use HTTP_Server::router::Router;
+    ///
+    /// fn legacy(ctx: &mut Context) {
+    ///     let path = ctx.request.path().to_string();
+    ///     ctx.string(HttpStatus::Ok, &format!("legacy {path}"))
+    /// }
+    ///
+    /// let mut router = Router::new();
+    /// router.fallback(HttpMethod::Get, legacy);
+    /// ```
+    pub fn fallback(&mut self, method: HttpMethod, handler: Handler) -> &mut Self {
+        self.fallbacks.retain(|(m, _)| *m != method);
+        self.fallbacks.push((method, handler));
         self
     }
 
+    /// Get the static directory mounted at the longest prefix of the path,
+    /// with the segments of the path under it
+    fn get_static_dir<'p>(&self, path: &'p [&'p str]) -> Option<(Arc<StaticDir>, &'p [&'p str])> {
+        self.static_dirs.iter().find_map(|(prefix, dir)| {
+            let matches =
+                prefix.len() <= path.len() && prefix.iter().zip(path).all(|(p, s)| p == s);
+            matches.then(|| (Arc::clone(dir), &path[prefix.len()..]))
+        })
+    }
+
+    /// Get the regex route that matches the method and path with the most literal
+    /// segments, the first one added on ties, with its path params and that number
+    /// of segments, see [`literal_segments`]
+    fn get_regex_route(
+        &self,
+        method: HttpMethod,
+        path: &str,
+    ) -> Option<(Route, HashMap<String, String>, usize)> {
+        let mut best: Option<(Route, HashMap<String, String>, usize)> = None;
+        for r in self.routes.iter().filter(|r| r.method == method) {
+            let Some(pattern) = &r.pattern else {
+                continue;
+            };
+            let Some(captures) = pattern.captures(path) else {
+                continue;
+            };
+            let named: Vec<(&str, &str)> = pattern
+                .group_names()
+                .zip(&captures)
+                .filter_map(|(name, range)| {
+                    let (start, end) = (*range)?;
+                    Some((name?, &path[start..end]))
+                })
+                .collect();
+            let value = |name: &str| named.iter().find(|(n, _)| *n == name).map(|c| c.1);
+            if !r.meets_constraints(value) {
+                continue;
+            }
+            let literal = literal_segments(path, &captures);
+            if best.as_ref().is_some_and(|(_, _, most)| *most >= literal) {
+                continue;
+            }
+            let params = named
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect();
+            best = Some((r.clone(), params, literal));
+        }
+        best
+    }
+
     /// Get the route that matches the method and path
     fn get_route(&self, method: HttpMethod, path: &[&str]) -> Option<Route> {
         let mut r = self.routes.clone();
@@ -167,26 +904,149 @@ impl Router {
                 return None;
             }
         }
+        r.retain(|r| r.accepts(path));
         // get the route with the most matches
-        r.iter().max_by(|a, b| a.matches(path).cmp(&b.matches(path))).cloned()
+        r.iter()
+            .max_by(|a, b| a.matches(path).cmp(&b.matches(path)))
+            .cloned()
     }
 
-    /// Route the request to the appropriate handler
+    /// Route the request to the appropriate handler.
+    /// The path is percent-decoded before matching, so routes and params can hold any UTF-8.
+    /// Paths with malformed escapes or that aren't valid UTF-8 once decoded get a 400.
     pub fn handle_request(&self, ctx: &mut Context) {
-        let path = ctx.request.clone().path;
-        let path: Vec<&str> = path
+        for middleware in &self.middlewares {
+            if !middleware(ctx) {
+                return;
+            }
+        }
+
+        // Segments are decoded after splitting so an encoded "/" stays inside its segment
+        let segments: Option<Vec<String>> = ctx
+            .request
+            .path
             .trim_end_matches("/")
             .trim_start_matches("/")
             .split("/")
+            .map(percent::decode)
             .collect();
-        let route = self.get_route(ctx.request.method, &path);
+        let (Some(segments), Some(decoded_path)) = (segments, percent::decode(&ctx.request.path))
+        else {
+            ctx.string(HttpStatus::BadRequest, "Invalid path encoding");
+            return;
+        };
+        let path: Vec<&str> = segments.iter().map(|s| s.as_str()).collect();
+        let method = ctx.request.method.routed_as();
+        let route = self.get_route(method, &path);
+        // Regex routes are ranked with the others by the segments they match literally,
+        // segment routes win the ties
+        let regex_route = self
+            .get_regex_route(method, &decoded_path)
+            .filter(|(_, _, literal)| route.as_ref().is_none_or(|r| *literal > r.matches(&path)));
 
-        if let Some(route) = route {
+        if let Some((route, params, _)) = regex_route {
+            ctx.path_params = params;
+            self.add_links(&route, ctx);
+            route.run(ctx);
+        } else if let Some(route) = route {
             route.set_path_params(&path, ctx);
-            (route.handler)(ctx);
+            self.add_links(&route, ctx);
+            route.run(ctx);
+        } else if let Some((dir, rest)) = self
+            .get_static_dir(&path)
+            .filter(|_| method == HttpMethod::Get)
+        {
+            dir.serve(ctx, rest);
+        } else if let Some((_, fallback)) = self.fallbacks.iter().find(|(m, _)| *m == method) {
+            fallback(ctx);
         } else {
             ctx.string(HttpStatus::NotFound, "Not Found");
         }
+
+        if let Some(mut response) = ctx.response.take() {
+            for middleware in &self.response_middlewares {
+                middleware(ctx, &mut response);
+            }
+            ctx.send(response);
+        }
+    }
+
+    /// Handle the request without a connection and return the response it got, like in
+    /// tests. The request goes through the middlewares, with the default
+    /// [`ServerConfig`](crate::config::ServerConfig) and no client address. Sub-requests
+    /// made from a handler should use [`Router::dispatch_from`] instead.
+    /// If the handler doesn't write a response, it's a `500 Internal Server Error`.
+    /// # Example
+    /// ```
+    /// use HTTP_Server::context::Context;
+    /// use HTTP_Server::headers::Headers;
+    /// use HTTP_Server::http_method::HttpMethod;
+    /// use HTTP_Server::http_request::HttpRequest;
+    /// use HTTP_Server::http_status::HttpStatus;
+    /// use HTTP_Server::router::Router;
+    ///
+    /// let mut router = Router::new();
+    /// router.get("/ping", |ctx: &mut Context| ctx.string(HttpStatus::Ok, "pong"));
+    ///
+    /// let request = HttpRequest::new(HttpMethod::Get, "/ping".into(), Headers::new(), "".into());
+    /// let response = router.dispatch(request);
+    /// assert_eq!(response.status(), HttpStatus::Ok);
+    /// assert_eq!(response.body(), b"pong");
+    /// ```
+    pub fn dispatch(&self, request: HttpRequest) -> HttpResponse {
+        let writer = ResponseBuffer::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.request = request;
+        self.dispatch_in(ctx, writer)
+    }
+
+    /// Handle a sub-request made while handling the request of `parent`, like each
+    /// request of a batch endpoint, and return the response it got. It runs with the
+    /// server config, client address, logger and latency budget of the parent request.
+    /// See [`Router::dispatch`]
+    /// # Example
+    /// ```
+    /// use HTTP_Server::context::Context;
+    /// use HTTP_Server::headers::Headers;
+    /// use HTTP_Server::http_method::HttpMethod;
+    /// use HTTP_Server::http_request::HttpRequest;
+    /// use HTTP_Server::http_status::HttpStatus;
+    /// use HTTP_Server::router::Router;
+    /// use std::sync::Arc;
+    ///
+    /// fn batch(router: &Arc<Router>, ctx: &mut Context) {
+    ///     let paths = ["/users/1", "/users/2"];
+    ///     let found: Vec<bool> = paths
+    ///         .iter()
+    ///         .map(|path| {
+    ///             let request =
+    ///                 HttpRequest::new(HttpMethod::Get, path.to_string(), Headers::new(), "".into());
+    ///             router.dispatch_from(ctx, request).status().is_success()
+    ///         })
+    ///         .collect();
+    ///     ctx.json(HttpStatus::Ok, serde_json::json!(found));
+    /// }
+    /// ```
+    pub fn dispatch_from(&self, parent: &Context, request: HttpRequest) -> HttpResponse {
+        let writer = ResponseBuffer::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.request = request;
+        ctx.config = Arc::clone(&parent.config);
+        ctx.logger = parent.logger.clone();
+        ctx.remote_addr = parent.remote_addr;
+        ctx.agent_class = parent.agent_class;
+        ctx.load = parent.load;
+        ctx.received = parent.received;
+        ctx.queue_time = parent.queue_time;
+        self.dispatch_in(ctx, writer)
+    }
+
+    /// Handles the request of the context, returning the response written to `writer`
+    fn dispatch_in(&self, mut ctx: Context, writer: ResponseBuffer) -> HttpResponse {
+        self.handle_request(&mut ctx);
+        drop(ctx);
+        HttpResponse::parse(&writer.take())
+            .unwrap_or_else(|| HttpResponse::new(HttpStatus::InternalServerError))
     }
 }
 
@@ -194,11 +1054,31 @@ impl Router {
 mod tests {
     use super::*;
     use crate::context::Context;
+    use crate::headers::Headers;
     use crate::http_method::HttpMethod;
     use crate::http_request::HttpRequest;
+    use crate::utils::mock_stream::MockWriter;
+    use serde_json::{json, Value};
 
     fn dummy_handler(_ctx: &mut Context) {}
 
+    fn user_post(ctx: &mut Context, (id, slug): (u32, String)) {
+        ctx.string(HttpStatus::Ok, &format!("user {id} post {slug}"))
+    }
+
+    fn user_by_name(ctx: &mut Context) {
+        let name = ctx.param("name").unwrap_or_default();
+        ctx.string(HttpStatus::Ok, &format!("user named {name}"))
+    }
+
+    fn request(router: &Router, method: HttpMethod, path: &str) -> String {
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.request = HttpRequest::new(method, path.into(), Headers::new(), "".into());
+        router.handle_request(&mut ctx);
+        writer.contents()
+    }
+
     #[test]
     fn test_router_get_route() {
         let mut router = Router::new();
@@ -308,8 +1188,409 @@ mod tests {
         let path = vec!["test", "1"];
         let mut ctx = Context::new(Vec::new());
         ctx.request =
-            HttpRequest::new(HttpMethod::Get, "/test/1".into(), HashMap::new(), "".into());
+            HttpRequest::new(HttpMethod::Get, "/test/1".into(), Headers::new(), "".into());
         route.set_path_params(&path, &mut ctx);
         assert_eq!(ctx.param("param"), Some("1".to_string()));
     }
+
+    #[test]
+    fn test_from_params() {
+        assert_eq!(<(u32,)>::from_params(&["1"]), Some((1,)));
+        assert_eq!(
+            <(u32, String)>::from_params(&["1", "a"]),
+            Some((1, "a".to_string()))
+        );
+        assert_eq!(<(u32,)>::from_params(&["a"]), None);
+        assert_eq!(<(u32,)>::from_params(&["1", "2"]), None);
+        assert_eq!(<(u32, u32)>::from_params(&["1"]), None);
+    }
+
+    #[test]
+    fn test_router_typed_route() {
+        let mut router = Router::new();
+        router.get_typed::<(u32, String)>("/users/{id}/posts/{slug}", user_post);
+        let response = request(&router, HttpMethod::Get, "/users/7/posts/hello");
+        assert!(response.ends_with("user 7 post hello"));
+    }
+
+    #[test]
+    fn test_router_typed_route_falls_through() {
+        let mut router = Router::new();
+        router
+            .get_typed::<(u32, String)>("/users/{id}/posts/{slug}", user_post)
+            .get("/users/{name}/posts/{slug}", user_by_name);
+        let response = request(&router, HttpMethod::Get, "/users/bob/posts/hello");
+        assert!(response.ends_with("user named bob"));
+
+        let mut router = Router::new();
+        router.get_typed::<(u32, String)>("/users/{id}/posts/{slug}", user_post);
+        let response = request(&router, HttpMethod::Get, "/users/bob/posts/hello");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found"));
+    }
+
+    fn file(ctx: &mut Context) {
+        let year = ctx.param("year").unwrap_or_default();
+        let name = ctx.param("name").unwrap_or_default();
+        ctx.string(HttpStatus::Ok, &format!("file {name} from {year}"))
+    }
+
+    #[test]
+    fn test_router_regex_route() {
+        let mut router = Router::new();
+        router.get_regex(r"^/files/(?P<year>\d{4})/(?P<name>.+)$", file);
+        let response = request(&router, HttpMethod::Get, "/files/2024/docs/a.txt");
+        assert!(response.ends_with("file docs/a.txt from 2024"));
+
+        let response = request(&router, HttpMethod::Get, "/files/24/a.txt");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found"));
+        let response = request(&router, HttpMethod::Post, "/files/2024/a.txt");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found"));
+    }
+
+    #[test]
+    fn test_router_ranks_regex_routes_with_segment_routes() {
+        let mut router = Router::new();
+        router
+            .get("/files/{year}/{name}", user_by_name)
+            .get("/{kind}/{id}", user_by_name)
+            .get_regex(r"^/files/latest/(?P<name>.+)$", latest_file)
+            .get_regex(r"^/files/(?P<name>[a-z]+\.txt)$", latest_file);
+
+        let response = request(&router, HttpMethod::Get, "/files/latest/a.txt");
+        assert!(response.ends_with("latest a.txt"));
+        let response = request(&router, HttpMethod::Get, "/files/2024/a.txt");
+        assert!(response.ends_with("user named a.txt"));
+        let response = request(&router, HttpMethod::Get, "/files/b.txt");
+        assert!(response.ends_with("latest b.txt"));
+        let response = request(&router, HttpMethod::Get, "/users/b.txt");
+        assert!(response.ends_with("user named "));
+
+        let captures =
+            |pattern: &str, path: &str| Regex::new(pattern).unwrap().captures(path).unwrap();
+        let path = "/files/latest/a.txt";
+        assert_eq!(
+            literal_segments(path, &captures(r"^/files/latest/(?P<n>.+)$", path)),
+            2
+        );
+        assert_eq!(
+            literal_segments(path, &captures(r"^/(\w+)/latest/.+$", path)),
+            2
+        );
+        assert_eq!(literal_segments(path, &captures(r"latest", path)), 1);
+        assert_eq!(literal_segments(path, &captures(r"^/.*$", path)), 3);
+    }
+
+    #[test]
+    fn test_router_segment_routes_before_regex_routes() {
+        let mut router = Router::new();
+        router
+            .get_regex(r"^/files/(?P<year>\d{4})/(?P<name>.+)$", file)
+            .get("/files/{year}/{name}", user_by_name);
+        let response = request(&router, HttpMethod::Get, "/files/2024/a.txt");
+        assert!(response.ends_with("user named a.txt"));
+    }
+
+    fn latest_file(ctx: &mut Context) {
+        let name = ctx.param("name").unwrap_or_default();
+        ctx.string(HttpStatus::Ok, &format!("latest {name}"))
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_router_invalid_regex_route() {
+        Router::new().get_regex(r"^/files/(?P<year>", file);
+    }
+
+    #[test]
+    fn test_router_utf8_paths() {
+        let mut router = Router::new();
+        router
+            .get("/tags/日本語/{name}", user_by_name)
+            .get_regex(r"^/files/(?P<year>\d{4})/(?P<name>.+)$", file);
+
+        let response = request(&router, HttpMethod::Get, "/tags/日本語/😀");
+        assert!(response.ends_with("user named 😀"));
+        let response = request(
+            &router,
+            HttpMethod::Get,
+            "/tags/%E6%97%A5%E6%9C%AC%E8%AA%9E/%F0%9F%98%80",
+        );
+        assert!(response.ends_with("user named 😀"));
+        let response = request(&router, HttpMethod::Get, "/tags/日本語/a%2Fb");
+        assert!(response.ends_with("user named a/b"));
+        let response = request(&router, HttpMethod::Get, "/files/2024/%E4%BE%8B.txt");
+        assert!(response.ends_with("file 例.txt from 2024"));
+    }
+
+    fn created_user(ctx: &mut Context) -> HttpResponse {
+        let name = ctx.param("name").unwrap_or_default();
+        HttpResponse::created()
+            .header("Location", format!("/users/{name}"))
+            .text(&format!("created {name}"))
+    }
+
+    #[test]
+    fn test_router_response_route() {
+        let mut router = Router::new();
+        router
+            .post_response("/users/{name}", created_user)
+            .use_response_middleware(|ctx: &Context, response: &mut HttpResponse| {
+                let name = ctx.param("name").unwrap_or_default();
+                response.set_header("X-User", name);
+                if response.status() == HttpStatus::Created {
+                    response.set_body("done");
+                }
+            });
+        let response = request(&router, HttpMethod::Post, "/users/john");
+        assert!(response.starts_with("HTTP/1.1 201 Created\r\n"));
+        assert!(response.contains("Location: /users/john\r\n"));
+        assert!(response.contains("X-User: john\r\n"));
+        assert!(response.contains("Content-Length: 4\r\n"));
+        assert!(response.ends_with("\r\n\r\ndone"));
+    }
+
+    fn route_info(ctx: &mut Context) {
+        let route = ctx.route().unwrap();
+        let info = format!("{:?} {:?}", route.name(), route.metadata("owner"));
+        ctx.string(HttpStatus::Ok, &info)
+    }
+
+    #[test]
+    fn test_router_route_handle() {
+        let mut router = Router::new();
+        router
+            .get("/users/{name}", route_info)
+            .name("user")
+            .metadata("owner", "accounts")
+            .middleware(|ctx: &mut Context| {
+                if ctx.param("name").as_deref() == Some("root") {
+                    ctx.string(HttpStatus::Forbidden, "Forbidden");
+                    return false;
+                }
+                true
+            })
+            .get("/users/{name}", user_by_name)
+            .constraint("name", |name| name.starts_with('#'))
+            .get_regex(r"^/files/(?P<year>\d+)/(?P<name>.+)$", file)
+            .constraint("year", |year| year.len() == 4);
+
+        assert!(router.route_named("user").is_some());
+        assert!(router.route_named("other").is_none());
+        let response = request(&router, HttpMethod::Get, "/users/john");
+        assert!(response.ends_with(r#"Some("user") Some("accounts")"#));
+        let response = request(&router, HttpMethod::Get, "/users/root");
+        assert!(response.starts_with("HTTP/1.1 403 Forbidden"));
+
+        // The constrained route wins only when its check passes
+        let response = request(&router, HttpMethod::Get, "/users/%23john");
+        assert!(response.ends_with("user named #john"));
+        let response = request(&router, HttpMethod::Get, "/files/2024/a.txt");
+        assert!(response.ends_with("file a.txt from 2024"));
+        let response = request(&router, HttpMethod::Get, "/files/24/a.txt");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found"));
+    }
+
+    fn legacy(ctx: &mut Context) {
+        let path = ctx.request.path().to_string();
+        ctx.string(HttpStatus::Ok, &format!("legacy {path}"))
+    }
+
+    #[test]
+    fn test_router_fallback() {
+        let mut router = Router::new();
+        router
+            .get("/users/{name}", user_by_name)
+            .fallback(HttpMethod::Get, dummy_handler)
+            .fallback(HttpMethod::Get, legacy);
+
+        let response = request(&router, HttpMethod::Get, "/users/john");
+        assert!(response.ends_with("user named john"));
+        let response = request(&router, HttpMethod::Get, "/old/page.php?id=1");
+        assert!(response.ends_with("legacy /old/page.php"));
+        let response = request(&router, HttpMethod::Post, "/old/page.php");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found"));
+    }
+
+    fn chunked(ctx: &mut Context) {
+        ctx.stream_with(HttpStatus::Ok, "text/plain", |writer| {
+            writer.write_all(b"part 1, ")?;
+            writer.write_all(b"part 2")
+        })
+    }
+
+    fn hinted(ctx: &mut Context) {
+        ctx.early_hints(&[("Link", "</app.css>; rel=preload; as=style")]);
+        ctx.html(HttpStatus::Ok, "<html></html>")
+    }
+
+    fn whoami(ctx: &mut Context) {
+        let ip = ctx.remote_addr().map(|a| a.ip().to_string());
+        ctx.json(HttpStatus::Ok, json!({ "ip": ip }))
+    }
+
+    #[test]
+    fn test_router_dispatch_from() {
+        let mut router = Router::new();
+        router.get("/whoami", whoami);
+        let request =
+            || HttpRequest::new(HttpMethod::Get, "/whoami".into(), Headers::new(), "".into());
+
+        let mut parent = Context::new(MockWriter::default());
+        parent.remote_addr = Some("10.0.0.7:4000".parse().unwrap());
+        parent.config = Arc::new(crate::config::ServerConfig {
+            pretty_json: true,
+            ..Default::default()
+        });
+        let response = router.dispatch_from(&parent, request());
+        assert_eq!(response.body(), b"{\n  \"ip\": \"10.0.0.7\"\n}");
+
+        let response = router.dispatch(request());
+        assert_eq!(response.body(), b"{\"ip\":null}");
+    }
+
+    #[test]
+    fn test_router_dispatch() {
+        let mut router = Router::new();
+        router
+            .get("/users/{name}", user_by_name)
+            .post_response("/users/{name}", created_user)
+            .get("/stream", chunked)
+            .get("/hinted", hinted)
+            .get("/silent", dummy_handler);
+        let dispatch = |method, path: &str| {
+            router.dispatch(HttpRequest::new(
+                method,
+                path.into(),
+                Headers::new(),
+                "".into(),
+            ))
+        };
+
+        let response = dispatch(HttpMethod::Get, "/users/john");
+        assert_eq!(response.status(), HttpStatus::Ok);
+        assert_eq!(response.get_header("Content-Type"), Some("text/plain"));
+        assert_eq!(response.body(), b"user named john");
+        let response = dispatch(HttpMethod::Post, "/users/john");
+        assert_eq!(response.status(), HttpStatus::Created);
+        assert_eq!(response.get_header("Location"), Some("/users/john"));
+        assert_eq!(
+            dispatch(HttpMethod::Get, "/stream").body(),
+            b"part 1, part 2"
+        );
+        let response = dispatch(HttpMethod::Get, "/hinted");
+        assert_eq!(response.status(), HttpStatus::Ok);
+        assert_eq!(response.get_header("Link"), None);
+        assert_eq!(response.body(), b"<html></html>");
+        assert_eq!(
+            dispatch(HttpMethod::Get, "/missing").status(),
+            HttpStatus::NotFound
+        );
+        assert_eq!(
+            dispatch(HttpMethod::Get, "/silent").status(),
+            HttpStatus::InternalServerError
+        );
+    }
+
+    fn user_json(ctx: &mut Context) {
+        let name = ctx.param("name").unwrap_or_default();
+        if name == "last" {
+            ctx.remove_link("next");
+        }
+        ctx.json(HttpStatus::Ok, json!({ "name": name }))
+    }
+
+    #[test]
+    fn test_router_links() {
+        let mut router = Router::new();
+        router
+            .get("/users/{name}", user_json)
+            .name("user")
+            .get("/users/{name}/posts", user_json)
+            .metadata("links", "self, pagination, author=user, missing=nothing")
+            .get("/about/{name}", user_json);
+
+        let response = request(&router, HttpMethod::Get, "/users/ana%20maria/posts?page=2");
+        let body: Value = serde_json::from_str(response.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        assert_eq!(
+            body["_links"],
+            json!({
+                "self": {"href": "/users/ana%20maria/posts?page=2"},
+                "first": {"href": "/users/ana%20maria/posts?page=1"},
+                "prev": {"href": "/users/ana%20maria/posts?page=1"},
+                "next": {"href": "/users/ana%20maria/posts?page=3"},
+                "author": {"href": "/users/ana%20maria"},
+            })
+        );
+        let response = request(&router, HttpMethod::Get, "/users/last/posts");
+        assert!(!response.contains("next"));
+        assert!(response.contains(r#""first":{"href":"/users/last/posts?page=1"}"#));
+        let response = request(&router, HttpMethod::Get, "/about/bob");
+        assert!(response.ends_with(r#"{"name":"bob"}"#));
+    }
+
+    #[test]
+    fn test_router_ignores_query() {
+        let mut router = Router::new();
+        router
+            .get("/users/{name}", user_by_name)
+            .get_regex(r"^/files/(?P<year>\d{4})/(?P<name>.+)$", file);
+        let response = request(&router, HttpMethod::Get, "/users/john?tab=posts");
+        assert!(response.ends_with("user named john"));
+        let response = request(&router, HttpMethod::Get, "/files/2024/a.txt?download");
+        assert!(response.ends_with("file a.txt from 2024"));
+    }
+
+    #[test]
+    fn test_router_invalid_path_encoding() {
+        let mut router = Router::new();
+        router.get("/tags/{name}", user_by_name);
+        for path in ["/tags/%zz", "/tags/%FF", "/tags/%E6%97"] {
+            let response = request(&router, HttpMethod::Get, path);
+            assert!(response.starts_with("HTTP/1.1 400 Bad Request"), "{path}");
+        }
+    }
+
+    #[test]
+    fn test_router_middlewares() {
+        let mut router = Router::new();
+        router
+            .use_middleware(|ctx: &mut Context| {
+                ctx.add_response_header("X-First", "1");
+                true
+            })
+            .use_middleware(|ctx: &mut Context| {
+                if ctx.request.path.starts_with("/admin") {
+                    ctx.string(HttpStatus::Forbidden, "Forbidden");
+                    return false;
+                }
+                true
+            })
+            .get("/{name}", user_by_name)
+            .get("/admin/{name}", user_by_name);
+
+        let response = request(&router, HttpMethod::Get, "/bob");
+        assert!(response.contains("X-First: 1\r\n"));
+        assert!(response.ends_with("user named bob"));
+        let response = request(&router, HttpMethod::Get, "/admin/bob");
+        assert!(response.starts_with("HTTP/1.1 403 Forbidden"));
+    }
+
+    #[test]
+    fn test_router_head() {
+        let mut router = Router::new();
+        router
+            .get("/{name}", user_by_name)
+            .fallback(HttpMethod::Get, |ctx: &mut Context| {
+                ctx.string(HttpStatus::NotFound, "Nothing here")
+            });
+
+        let response = request(&router, HttpMethod::Head, "/bob");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Length: 14\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+        let response = request(&router, HttpMethod::Head, "/a/b");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(response.contains("Content-Length: 12\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+    }
 }
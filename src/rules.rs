@@ -0,0 +1,408 @@
+use crate::api_err::ApiErr;
+use crate::context::Context;
+use crate::http_request::HttpRequest;
+use crate::http_status::HttpStatus;
+use crate::utils::regex::Regex;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+/// What happens to a request matched by a rule
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuleAction {
+    /// Answer `403 Forbidden` without calling the handler
+    Block,
+    /// Send the match to the server logger
+    Log,
+    /// Add a tag handlers can check with [`Context::has_tag`]
+    Tag(String),
+}
+
+/// Bytes of the body checked by `body ~ <regex>` conditions by default, see
+/// [`RuleSet::max_scan_bytes`]
+pub const DEFAULT_MAX_SCAN_BYTES: usize = 64 * 1024;
+
+/// Returns the first `max` bytes of the text, cut at a character boundary
+fn scanned(text: &str, max: usize) -> &str {
+    let mut end = max.min(text.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+#[derive(Debug, Clone)]
+enum Condition {
+    Path(Regex),
+    Header(String, Regex),
+    Body(Regex),
+    BodySizeOver(usize),
+}
+
+impl Condition {
+    fn parse(condition: &str) -> Result<Condition, ApiErr> {
+        let invalid = || ApiErr::InvalidRule(format!("invalid condition `{condition}`"));
+        let regex = |pattern: &str| {
+            Regex::new(pattern.trim()).map_err(|e| ApiErr::InvalidRule(e.to_string()))
+        };
+
+        let (target, value) = condition.trim().split_once(' ').ok_or_else(invalid)?;
+        let value = value.trim_start();
+        match target {
+            "path" => Ok(Condition::Path(regex(
+                value.strip_prefix('~').ok_or_else(invalid)?,
+            )?)),
+            "body" => Ok(Condition::Body(regex(
+                value.strip_prefix('~').ok_or_else(invalid)?,
+            )?)),
+            "body-size" => {
+                let size = value.strip_prefix('>').ok_or_else(invalid)?;
+                Ok(Condition::BodySizeOver(
+                    size.trim().parse().map_err(|_| invalid())?,
+                ))
+            }
+            "header" => {
+                let (name, pattern) = value.split_once(' ').ok_or_else(invalid)?;
+                let pattern = pattern.trim_start().strip_prefix('~').ok_or_else(invalid)?;
+                Ok(Condition::Header(name.to_string(), regex(pattern)?))
+            }
+            _ => Err(invalid()),
+        }
+    }
+
+    fn matches(&self, request: &HttpRequest, max_scan_bytes: usize) -> bool {
+        match self {
+            Condition::Path(regex) => regex.is_match(&request.path),
+            Condition::Header(name, regex) => request
+                .headers
+                .get(name)
+                .is_some_and(|value| regex.is_match(value)),
+            Condition::Body(regex) => regex.is_match(scanned(&request.body, max_scan_bytes)),
+            Condition::BodySizeOver(size) => request.body.len() > *size,
+        }
+    }
+}
+
+/// A named rule that applies its action to the requests matching all of its conditions
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub name: String,
+    pub action: RuleAction,
+    conditions: Vec<Condition>,
+}
+
+impl Rule {
+    /// Whether the request matches all the conditions, scanning the first
+    /// [`DEFAULT_MAX_SCAN_BYTES`] of its body
+    pub fn matches(&self, request: &HttpRequest) -> bool {
+        self.matches_scanning(request, DEFAULT_MAX_SCAN_BYTES)
+    }
+
+    fn matches_scanning(&self, request: &HttpRequest, max_scan_bytes: usize) -> bool {
+        self.conditions
+            .iter()
+            .all(|c| c.matches(request, max_scan_bytes))
+    }
+}
+
+/// The rules a request matched, see [`RuleSet::evaluate`]
+#[derive(Debug, Default, PartialEq)]
+pub struct Verdict {
+    /// Name of the rule that blocked the request
+    pub blocked_by: Option<String>,
+    /// Names of the matched log rules
+    pub logged: Vec<String>,
+    pub tags: Vec<String>,
+}
+
+/// An ordered list of rules, written one per line as
+/// `<action> <name> <condition> [&& <condition>...]`, where the action is `block`,
+/// `log` or `tag=<tag>` and the conditions are:
+/// - `path ~ <regex>`
+/// - `header <name> ~ <regex>`
+/// - `body ~ <regex>`
+/// - `body-size > <bytes>`
+///
+/// Empty lines and lines starting with `#` are ignored.
+///
+/// Body conditions only look at the first [`DEFAULT_MAX_SCAN_BYTES`] of the body, so a
+/// big body can't make every request slow to screen. Change it with
+/// [`RuleSet::max_scan_bytes`].
+/// # Example
+/// ```
+/// use HTTP_Server::rules::RuleSet;
+///
+/// let rules = RuleSet::parse(r"
+/// ## Vulnerability scanners probing for wordpress
+/// block wp-probe path ~ ^/(wp-admin|wp-login\.php)
+/// tag=scanner sqlmap header User-Agent ~ (?i)sqlmap
+/// log big-upload body-size > 1048576 && path ~ ^/upload
+/// ").unwrap();
+/// assert_eq!(rules.len(), 3);
+/// ```
+#[derive(Debug, Clone)]
+pub struct RuleSet {
+    rules: Vec<Rule>,
+    max_scan_bytes: usize,
+}
+
+impl Default for RuleSet {
+    fn default() -> Self {
+        RuleSet {
+            rules: Vec::new(),
+            max_scan_bytes: DEFAULT_MAX_SCAN_BYTES,
+        }
+    }
+}
+
+impl RuleSet {
+    pub fn parse(source: &str) -> Result<RuleSet, ApiErr> {
+        let mut rules = Vec::new();
+        for line in source.lines().map(|l| l.trim()) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid =
+                || ApiErr::InvalidRule(format!("expected action, name and condition in `{line}`"));
+
+            let (action, rest) = line.split_once(' ').ok_or_else(invalid)?;
+            let (name, conditions) = rest.trim_start().split_once(' ').ok_or_else(invalid)?;
+            let action = match action {
+                "block" => RuleAction::Block,
+                "log" => RuleAction::Log,
+                action => match action.strip_prefix("tag=") {
+                    Some(tag) if !tag.is_empty() => RuleAction::Tag(tag.to_string()),
+                    _ => return Err(ApiErr::InvalidRule(format!("unknown action `{action}`"))),
+                },
+            };
+            let conditions = conditions
+                .split(" && ")
+                .map(Condition::parse)
+                .collect::<Result<Vec<_>, _>>()?;
+            rules.push(Rule {
+                name: name.to_string(),
+                action,
+                conditions,
+            });
+        }
+        Ok(RuleSet {
+            rules,
+            ..RuleSet::default()
+        })
+    }
+
+    /// Set how many bytes of the body the `body ~ <regex>` conditions look at,
+    /// [`DEFAULT_MAX_SCAN_BYTES`] by default. Matching takes time and memory
+    /// proportional to the bytes scanned times the size of the pattern
+    pub fn max_scan_bytes(mut self, bytes: usize) -> Self {
+        self.max_scan_bytes = bytes;
+        self
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<RuleSet, ApiErr> {
+        let source = fs::read_to_string(path).map_err(ApiErr::StreamError)?;
+        RuleSet::parse(&source)
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Applies the rules in order until one blocks the request
+    pub fn evaluate(&self, request: &HttpRequest) -> Verdict {
+        let mut verdict = Verdict::default();
+        let matching = self
+            .rules
+            .iter()
+            .filter(|r| r.matches_scanning(request, self.max_scan_bytes));
+        for rule in matching {
+            match &rule.action {
+                RuleAction::Block => {
+                    verdict.blocked_by = Some(rule.name.clone());
+                    break;
+                }
+                RuleAction::Log => verdict.logged.push(rule.name.clone()),
+                RuleAction::Tag(tag) => verdict.tags.push(tag.clone()),
+            }
+        }
+        verdict
+    }
+}
+
+/// The rules checked before every request reaches the router.
+/// Clones share the same rules, so they can be reloaded at runtime from any of them,
+/// requests that already started keep the rules they started with.
+#[derive(Debug, Clone, Default)]
+pub struct Rules {
+    current: Arc<RwLock<Arc<RuleSet>>>,
+}
+
+impl Rules {
+    pub fn new(rules: RuleSet) -> Rules {
+        Rules {
+            current: Arc::new(RwLock::new(Arc::new(rules))),
+        }
+    }
+
+    pub fn current(&self) -> Arc<RuleSet> {
+        let current = self.current.read().unwrap_or_else(|e| e.into_inner());
+        Arc::clone(&current)
+    }
+
+    pub fn reload(&self, rules: RuleSet) {
+        let mut current = self.current.write().unwrap_or_else(|e| e.into_inner());
+        *current = Arc::new(rules);
+    }
+
+    /// Replaces the rules with the ones of the file, keeping the current ones if it's invalid
+    pub fn reload_from_file<P: AsRef<Path>>(&self, path: P) -> Result<(), ApiErr> {
+        self.reload(RuleSet::from_file(path)?);
+        Ok(())
+    }
+}
+
+/// Applies the server rules to the request of the context.
+/// Returns false if the request was blocked, in which case it was already answered.
+pub(crate) fn screen(ctx: &mut Context) -> bool {
+    let rules = ctx.config.rules.current();
+    if rules.is_empty() {
+        return true;
+    }
+    let verdict = rules.evaluate(&ctx.request);
+
+    let request_line = ctx.config.scrubber.request_line(&ctx.request);
+    if let Some(logger) = &ctx.logger {
+        for name in verdict.logged.iter().chain(&verdict.blocked_by) {
+            _ = logger.send(format!("Rule {name} matched {request_line}"));
+        }
+    }
+    ctx.tags.extend(verdict.tags);
+
+    if verdict.blocked_by.is_some() {
+        ctx.add_response_header("Connection", "close");
+        ctx.string(HttpStatus::Forbidden, "Forbidden");
+        return false;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::Headers;
+    use crate::http_method::HttpMethod;
+
+    fn request(path: &str, user_agent: &str, body: &str) -> HttpRequest {
+        let mut headers = Headers::new();
+        headers.insert("User-Agent", user_agent);
+        HttpRequest::new(HttpMethod::Post, path.into(), headers, body.into())
+    }
+
+    fn rules() -> RuleSet {
+        RuleSet::parse(
+            r"
+            block wp-probe path ~ ^/(wp-admin|wp-login\.php)
+            tag=scanner sqlmap header User-Agent ~ (?i)sqlmap
+            log big-upload body-size > 10 && path ~ ^/upload
+            block injection body ~ (?i)union\s+select
+            ",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_rule_set_parse_errors() {
+        for source in [
+            "block",
+            "block name",
+            "deny name path ~ ^/",
+            "tag= name path ~ ^/",
+            "block name path ^/",
+            "block name path ~ (",
+            "block name body-size > big",
+            "block name query ~ a",
+        ] {
+            assert!(RuleSet::parse(source).is_err(), "{source}");
+        }
+    }
+
+    #[test]
+    fn test_rule_set_evaluate() {
+        let rules = rules();
+        assert_eq!(
+            rules.evaluate(&request("/", "curl", "")),
+            Verdict::default()
+        );
+
+        let verdict = rules.evaluate(&request("/wp-login.php", "curl", ""));
+        assert_eq!(verdict.blocked_by.as_deref(), Some("wp-probe"));
+
+        let verdict = rules.evaluate(&request("/upload", "SQLMap/1.7", "a long body here"));
+        assert_eq!(verdict.blocked_by, None);
+        assert_eq!(verdict.tags, vec!["scanner"]);
+        assert_eq!(verdict.logged, vec!["big-upload"]);
+
+        let verdict = rules.evaluate(&request("/search", "curl", "1 UNION  SELECT pass"));
+        assert_eq!(verdict.blocked_by.as_deref(), Some("injection"));
+    }
+
+    #[test]
+    fn test_rule_set_scans_a_prefix_of_the_body() {
+        let rules = RuleSet::parse("block injection body ~ union select").unwrap();
+        let body = format!("{}union select", "é".repeat(10));
+        assert!(rules
+            .evaluate(&request("/", "curl", &body))
+            .blocked_by
+            .is_some());
+
+        let rules = rules.max_scan_bytes(25);
+        assert_eq!(
+            rules.evaluate(&request("/", "curl", &body)),
+            Verdict::default()
+        );
+        assert_eq!(scanned(&body, 19), "é".repeat(9));
+        assert_eq!(scanned("abc", 10), "abc");
+
+        let body = "a".repeat(10 * 1024 * 1024);
+        let started = std::time::Instant::now();
+        assert_eq!(
+            rules.evaluate(&request("/", "curl", &body)),
+            Verdict::default()
+        );
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_rules_reload() {
+        let rules = Rules::default();
+        let shared = rules.clone();
+        assert!(rules.current().is_empty());
+        shared.reload(RuleSet::parse("block all path ~ .").unwrap());
+        assert_eq!(rules.current().len(), 1);
+
+        assert!(rules.reload_from_file("/nonexistent/rules").is_err());
+        assert_eq!(rules.current().len(), 1);
+    }
+
+    #[test]
+    fn test_screen_blocks_and_tags() {
+        let writer = crate::utils::mock_stream::MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.config = Arc::new(crate::config::ServerConfig {
+            rules: Rules::new(rules()),
+            ..Default::default()
+        });
+
+        ctx.request = request("/", "sqlmap", "");
+        assert!(screen(&mut ctx));
+        assert!(ctx.has_tag("scanner"));
+
+        ctx.request = request("/wp-admin/setup", "curl", "");
+        assert!(!screen(&mut ctx));
+        assert!(writer.contents().starts_with("HTTP/1.1 403 Forbidden"));
+    }
+}
@@ -0,0 +1,337 @@
+use crate::http_method::HttpMethod;
+use crate::http_request::HttpRequest;
+use crate::utils::percent;
+use serde_json::Value;
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Redacts sensitive data from requests before they are logged.
+/// The default redacts credentials from the headers, query and body,
+/// see [`ServerConfig::access_log`](crate::config::ServerConfig::access_log).
+///
+/// Body fields are only looked up in json bodies, form encoded bodies are scrubbed
+/// like the query and any other body is logged by its size alone.
+/// A field name like `password` is redacted at any depth, while a dotted path like
+/// `card.number` is matched from the root, going through arrays.
+/// All the names are case insensitive.
+/// # Example
+/// ```
+/// use HTTP_Server::headers::Headers;
+/// use HTTP_Server::http_method::HttpMethod;
+/// use HTTP_Server::http_request::HttpRequest;
+/// use HTTP_Server::scrub::Scrubber;
+///
+/// let mut headers = Headers::new();
+/// headers.insert("Content-Type", "application/json");
+/// let body = r#"{"card":{"number":"4242"},"password":"hunter2"}"#;
+/// let request = HttpRequest::new(HttpMethod::Post, "/pay?token=abc".into(), headers, body.into());
+///
+/// let scrubber = Scrubber::default().body_field("card.number");
+/// assert_eq!(scrubber.request_line(&request), "POST /pay?token=[REDACTED]");
+/// assert_eq!(
+///     scrubber.body(&request),
+///     r#"{"card":{"number":"[REDACTED]"},"password":"[REDACTED]"}"#
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct Scrubber {
+    headers: Vec<String>,
+    query_params: Vec<String>,
+    body_fields: Vec<String>,
+}
+
+impl Default for Scrubber {
+    fn default() -> Self {
+        Scrubber::empty()
+            .header("Authorization")
+            .header("Proxy-Authorization")
+            .header("Cookie")
+            .header("X-Api-Key")
+            .query_param("token")
+            .query_param("access_token")
+            .query_param("api_key")
+            .query_param("password")
+            .query_param("secret")
+            .body_field("password")
+            .body_field("token")
+            .body_field("secret")
+    }
+}
+
+impl Scrubber {
+    /// Create a scrubber that doesn't redact anything
+    pub fn empty() -> Scrubber {
+        Scrubber {
+            headers: Vec::new(),
+            query_params: Vec::new(),
+            body_fields: Vec::new(),
+        }
+    }
+
+    pub fn header(mut self, name: &str) -> Self {
+        self.headers.push(name.to_ascii_lowercase());
+        self
+    }
+
+    pub fn query_param(mut self, name: &str) -> Self {
+        self.query_params.push(name.to_ascii_lowercase());
+        self
+    }
+
+    pub fn body_field(mut self, path: &str) -> Self {
+        self.body_fields.push(path.to_ascii_lowercase());
+        self
+    }
+
+    /// Returns the method, path and scrubbed query of the request
+    pub fn request_line(&self, request: &HttpRequest) -> String {
+        match request.query() {
+            Some(query) => format!(
+                "{} {}?{}",
+                request.method,
+                request.path(),
+                self.form(query, &self.query_params)
+            ),
+            None => format!("{} {}", request.method, request.path()),
+        }
+    }
+
+    /// Returns the headers of the request in order, with the sensitive values redacted
+    pub fn headers(&self, request: &HttpRequest) -> Vec<(String, String)> {
+        request
+            .headers
+            .iter()
+            .map(|(name, value)| {
+                let value = match self.headers.contains(&name.to_ascii_lowercase()) {
+                    true => REDACTED,
+                    false => value,
+                };
+                (name.to_string(), value.to_string())
+            })
+            .collect()
+    }
+
+    /// Returns the body of the request with the sensitive fields redacted
+    pub fn body(&self, request: &HttpRequest) -> String {
+        match self.scrubbed_body(request) {
+            Some(body) => body,
+            None => format!("<{} bytes>", request.body.len()),
+        }
+    }
+
+    /// Returns the scrubbed body, or `None` if it can't be scrubbed and is left out
+    fn scrubbed_body(&self, request: &HttpRequest) -> Option<String> {
+        let body = &request.body;
+        if body.is_empty() {
+            return Some(String::new());
+        }
+        let content_type = request
+            .headers
+            .get("Content-Type")
+            .map(|c| c.to_ascii_lowercase())
+            .unwrap_or_default();
+        let media_type = content_type.split(';').next().unwrap_or_default().trim();
+
+        if media_type == "application/x-www-form-urlencoded" {
+            return Some(self.form(body, &self.body_fields));
+        }
+        if media_type == "application/json" || media_type.ends_with("+json") {
+            if let Ok(mut value) = serde_json::from_str::<Value>(body) {
+                for field in &self.body_fields {
+                    let path: Vec<&str> = field.split('.').collect();
+                    redact_json(&mut value, &path, path.len() == 1);
+                }
+                return Some(value.to_string());
+            }
+        }
+        None
+    }
+
+    /// Returns the request as a single log line, with everything sensitive redacted
+    pub fn describe(&self, request: &HttpRequest) -> String {
+        let headers = self
+            .headers(request)
+            .iter()
+            .map(|(name, value)| format!("{name}: {value}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut line = format!("{} [{headers}]", self.request_line(request));
+        let body = self.body(request);
+        if !body.is_empty() {
+            line.push(' ');
+            line.push_str(&body);
+        }
+        line
+    }
+
+    /// Returns a `curl` command sending the request again, with everything sensitive
+    /// redacted, to reproduce the requests seen in the logs. Bodies that can't be
+    /// scrubbed are left out, with a comment giving their size
+    /// # Example
+    /// ```
+    /// use HTTP_Server::headers::Headers;
+    /// use HTTP_Server::http_method::HttpMethod;
+    /// use HTTP_Server::http_request::HttpRequest;
+    /// use HTTP_Server::scrub::Scrubber;
+    ///
+    /// let mut headers = Headers::new();
+    /// headers.insert("Host", "example.com");
+    /// headers.insert("Content-Type", "application/json");
+    /// let request = HttpRequest::new(HttpMethod::Post, "/users".into(), headers, r#"{"name":"O'Brien"}"#.into());
+    /// assert_eq!(
+    ///     Scrubber::default().curl(&request),
+    ///     r#"curl -X POST 'http://example.com/users' -H 'Content-Type: application/json' --data-binary '{"name":"O'\''Brien"}'"#
+    /// );
+    /// ```
+    pub fn curl(&self, request: &HttpRequest) -> String {
+        let host = request
+            .headers
+            .get("Host")
+            .map_or("localhost", |h| h.as_str());
+        let target = self.request_line(request);
+        let target = target.split_once(' ').map_or("", |(_, target)| target);
+        let mut command = match request.method {
+            HttpMethod::Get if request.body.is_empty() => "curl".to_string(),
+            HttpMethod::Head if request.body.is_empty() => "curl --head".to_string(),
+            method => format!("curl -X {method}"),
+        };
+        command += &format!(" {}", shell_quote(&format!("http://{host}{target}")));
+        for (name, value) in self.headers(request) {
+            // curl sets them from the url and the body
+            if !name.eq_ignore_ascii_case("Host") && !name.eq_ignore_ascii_case("Content-Length") {
+                command += &format!(" -H {}", shell_quote(&format!("{name}: {value}")));
+            }
+        }
+        match self.scrubbed_body(request) {
+            Some(body) if body.is_empty() => {}
+            Some(body) => command += &format!(" --data-binary {}", shell_quote(&body)),
+            None => command += &format!(" # body of {} bytes left out", request.body.len()),
+        }
+        command
+    }
+
+    /// Redacts the values of the names in a form encoded string, keeping it encoded
+    fn form(&self, form: &str, names: &[String]) -> String {
+        form.split('&')
+            .map(|pair| match pair.split_once('=') {
+                Some((key, _)) => {
+                    // Compare the decoded name, so escaping it doesn't reveal the value
+                    let name = percent::decode(&key.replace('+', " ")).unwrap_or_default();
+                    match names.contains(&name.to_ascii_lowercase()) {
+                        true => format!("{key}={REDACTED}"),
+                        false => pair.to_string(),
+                    }
+                }
+                None => pair.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+}
+
+/// Redacts the value at the path, looking for it at any depth if `anywhere` is set
+fn redact_json(value: &mut Value, path: &[&str], anywhere: bool) {
+    match value {
+        Value::Object(map) => {
+            for (key, child) in map.iter_mut() {
+                if key.eq_ignore_ascii_case(path[0]) {
+                    match path.len() {
+                        1 => *child = Value::from(REDACTED),
+                        _ => redact_json(child, &path[1..], false),
+                    }
+                } else if anywhere {
+                    redact_json(child, path, true);
+                }
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                redact_json(item, path, anywhere);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Quotes the text for a POSIX shell, closing the quotes around its own single quotes
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::Headers;
+    use crate::http_method::HttpMethod;
+
+    fn request(path: &str, content_type: &str, body: &str) -> HttpRequest {
+        let mut headers = Headers::new();
+        headers.insert("Content-Type", content_type);
+        headers.insert("Authorization", "Bearer secret");
+        HttpRequest::new(HttpMethod::Post, path.into(), headers, body.into())
+    }
+
+    #[test]
+    fn test_scrub_headers_and_query() {
+        let request = request("/a?Token=1&page=2&password&pass%77ord=x", "text/plain", "");
+        let scrubber = Scrubber::default();
+        assert_eq!(
+            scrubber.request_line(&request),
+            "POST /a?Token=[REDACTED]&page=2&password&pass%77ord=[REDACTED]"
+        );
+        assert_eq!(
+            scrubber.headers(&request)[1],
+            ("Authorization".to_string(), REDACTED.to_string())
+        );
+        assert_eq!(Scrubber::empty().headers(&request)[1].1, "Bearer secret");
+    }
+
+    #[test]
+    fn test_scrub_body() {
+        let scrubber = Scrubber::default().body_field("cards.number");
+        let body =
+            r#"{"user":{"Password":"a"},"cards":[{"number":"1"},{"number":"2"}],"number":"3"}"#;
+        assert_eq!(
+            scrubber.body(&request("/", "application/json; charset=utf-8", body)),
+            r#"{"cards":[{"number":"[REDACTED]"},{"number":"[REDACTED]"}],"number":"3","user":{"Password":"[REDACTED]"}}"#
+        );
+
+        let form = request(
+            "/",
+            "application/x-www-form-urlencoded",
+            "user=a&password=b",
+        );
+        assert_eq!(scrubber.body(&form), "user=a&password=[REDACTED]");
+
+        let text = request("/", "text/plain", "password: hunter2");
+        assert_eq!(scrubber.body(&text), "<17 bytes>");
+    }
+
+    #[test]
+    fn test_curl() {
+        let login = request(
+            "/login?next=/&token=abc",
+            "application/json",
+            r#"{"password":"a"}"#,
+        );
+        assert_eq!(
+            Scrubber::default().curl(&login),
+            r#"curl -X POST 'http://localhost/login?next=/&token=[REDACTED]' -H 'Content-Type: application/json' -H 'Authorization: [REDACTED]' --data-binary '{"password":"[REDACTED]"}'"#
+        );
+        let upload = request("/upload", "image/png", "\u{89}PNG");
+        assert!(Scrubber::default()
+            .curl(&upload)
+            .ends_with("-H 'Authorization: [REDACTED]' # body of 5 bytes left out"));
+        let get = HttpRequest::new(HttpMethod::Get, "/".into(), Headers::new(), "".into());
+        assert_eq!(Scrubber::default().curl(&get), "curl 'http://localhost/'");
+    }
+
+    #[test]
+    fn test_describe() {
+        let request = request("/login", "application/json", r#"{"password":"a"}"#);
+        assert_eq!(
+            Scrubber::default().describe(&request),
+            r#"POST /login [Content-Type: application/json, Authorization: [REDACTED]] {"password":"[REDACTED]"}"#
+        );
+    }
+}
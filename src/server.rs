@@ -1,25 +1,359 @@
+use crate::accept;
 use crate::api_err::ApiErr;
+use crate::config::{AccessLogFormat, HeaderParsing, ListenerOptions, ParseErrors, ServerConfig};
+use crate::diagnostics::{ReportFormat, StartupReport};
+use crate::headers::{is_token, Headers};
 use crate::http_method::HttpMethod;
-use crate::http_status::HttpStatus;
-use std::collections::HashMap;
-use std::io::{Read, Write};
-use std::sync::mpsc::Sender;
-use std::{
-    io,
-    net::{TcpListener, TcpStream},
-    sync::Arc,
-};
-
-use crate::utils::thread_pool::ThreadPool;
+use crate::http_version::HttpVersion;
+#[cfg(target_os = "linux")]
+use crate::prefork;
+use crate::rules;
+#[cfg(all(unix, feature = "signals"))]
+use crate::signals;
+use std::any::Any;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::{io, net::TcpListener, sync::Arc};
+
+use crate::utils::deadline_stream::DeadlineStream;
+use crate::utils::inflate::InflateError;
+use crate::utils::punycode;
+use crate::utils::thread_pool::{PoolHandle, QueueTimeStats, QueueTimes, ThreadPool};
 
 use super::{context::Context, http_request::HttpRequest, router::Router};
 
 const MAX_THREADS: usize = 40;
 
+/// TLS record with a fatal `protocol_version` alert
+const TLS_PROTOCOL_VERSION_ALERT: [u8; 7] = [0x15, 0x03, 0x01, 0x00, 0x02, 0x02, 0x46];
+
+/// Returns whether the bytes start like a TLS handshake record, as sent by clients
+/// trying to connect with https. Requests can't start with `0x16` as it isn't a token character.
+fn is_tls_handshake(bytes: &[u8]) -> bool {
+    matches!(bytes, [0x16, 0x03, ..] | [0x16])
+}
+
+/// Returns whether the last transfer coding of the request is `chunked`, the only one
+/// its body length can be known from
+fn is_chunked(headers: &Headers) -> bool {
+    headers
+        .get_all("Transfer-Encoding")
+        .last()
+        .and_then(|encoding| encoding.rsplit(',').next())
+        .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"))
+}
+
+/// Longest chunk size line accepted in a chunked body, extensions included
+const MAX_CHUNK_LINE: u64 = 1024;
+
+/// Reads a chunked body up to its last chunk and trailers, returning the data of its
+/// chunks. Fails with `InvalidData` if it's malformed and with `FileTooLarge` if its
+/// chunks add up to more than `limit` bytes.
+fn read_chunked_body<R: BufRead>(reader: &mut R, limit: usize) -> io::Result<Vec<u8>> {
+    let invalid = || io::Error::from(io::ErrorKind::InvalidData);
+    let mut line = Vec::new();
+    let mut read_line = |reader: &mut R| -> io::Result<String> {
+        line.clear();
+        reader
+            .by_ref()
+            .take(MAX_CHUNK_LINE)
+            .read_until(b'\n', &mut line)?;
+        match line.strip_suffix(b"\r\n") {
+            Some(text) => Ok(String::from_utf8_lossy(text).to_string()),
+            None => Err(invalid()),
+        }
+    };
+
+    let mut body = Vec::new();
+    loop {
+        let size_line = read_line(reader)?;
+        let size = size_line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16).map_err(|_| invalid())?;
+        if size == 0 {
+            break;
+        }
+        if body.len().saturating_add(size) > limit {
+            return Err(io::Error::from(io::ErrorKind::FileTooLarge));
+        }
+        let read = reader.by_ref().take(size as u64).read_to_end(&mut body)?;
+        if read != size || !read_line(reader)?.is_empty() {
+            return Err(invalid());
+        }
+    }
+    // Trailers end with an empty line
+    while !read_line(reader)?.is_empty() {}
+    Ok(body)
+}
+
+/// Sets up a [`Server`]: its address, number of workers, logger and the most common
+/// settings of its [`ServerConfig`]. The rest of the config can be set with
+/// [`ServerBuilder::config`] first.
+/// # Example
+/// ```no_run
+/// use HTTP_Server::context::Context;
+/// use HTTP_Server::http_status::HttpStatus;
+/// use HTTP_Server::router::Router;
+/// use HTTP_Server::server::Server;
+/// use std::time::Duration;
+///
+/// let mut router = Router::new();
+/// router.get("/ping", |ctx: &mut Context| ctx.string(HttpStatus::Ok, "pong"));
+///
+/// Server::builder(router)
+///     .addr("0.0.0.0:8080")
+///     .threads(16)
+///     .max_body_size(1024 * 1024)
+///     .keep_alive_timeout(Duration::from_secs(15))
+///     .build()
+///     .run()
+///     .expect("Error starting server");
+/// ```
+pub struct ServerBuilder {
+    router: Router,
+    addr: String,
+    threads: Option<usize>,
+    logger: Option<Sender<String>>,
+    config: ServerConfig,
+    listeners: Vec<(TcpListener, ListenerOptions)>,
+}
+
+impl ServerBuilder {
+    /// Set the address the server listens on, `127.0.0.1:8080` by default
+    pub fn addr(mut self, addr: &str) -> Self {
+        self.addr = addr.to_string();
+        self
+    }
+
+    /// Add listeners already bound, like the ones passed by systemd (see
+    /// [`activation::listeners`](crate::activation::listeners)), served instead of
+    /// binding the address
+    pub fn listeners(mut self, listeners: Vec<TcpListener>) -> Self {
+        let options = ListenerOptions::default();
+        self.listeners
+            .extend(listeners.into_iter().map(|l| (l, options)));
+        self
+    }
+
+    /// Add a listener already bound whose connections are served with its own
+    /// [`ListenerOptions`], see [`ServerBuilder::listeners`]
+    pub fn listener_with(mut self, listener: TcpListener, options: ListenerOptions) -> Self {
+        self.listeners.push((listener, options));
+        self
+    }
+
+    /// Set the number of workers serving connections, with a minimum of 1.
+    /// By default it's 5 for each route, up to 40
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// Set where the server sends its log lines
+    pub fn logger(mut self, logger: Sender<String>) -> Self {
+        self.logger = Some(logger);
+        self
+    }
+
+    /// Replace the whole config, the settings of the builder set after it apply on top
+    pub fn config(mut self, config: ServerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// See [`ServerConfig::header_read_timeout`]
+    pub fn header_read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.config.header_read_timeout = timeout;
+        self
+    }
+
+    /// See [`ServerConfig::body_read_timeout`]
+    pub fn body_read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.config.body_read_timeout = timeout;
+        self
+    }
+
+    /// See [`ServerConfig::write_timeout`]
+    pub fn write_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.config.write_timeout = timeout;
+        self
+    }
+
+    /// See [`ServerConfig::max_header_size`]
+    pub fn max_header_size(mut self, size: usize) -> Self {
+        self.config.max_header_size = size;
+        self
+    }
+
+    /// See [`ServerConfig::max_body_size`]
+    pub fn max_body_size(mut self, size: usize) -> Self {
+        self.config.max_body_size = size;
+        self
+    }
+
+    /// See [`ServerConfig::keep_alive`]
+    pub fn keep_alive(mut self, keep_alive: bool) -> Self {
+        self.config.keep_alive = keep_alive;
+        self
+    }
+
+    /// See [`ServerConfig::keep_alive_timeout`]
+    pub fn keep_alive_timeout(mut self, timeout: Duration) -> Self {
+        self.config.keep_alive_timeout = timeout;
+        self
+    }
+
+    /// See [`ServerConfig::max_requests_per_connection`]
+    pub fn max_requests_per_connection(mut self, requests: usize) -> Self {
+        self.config.max_requests_per_connection = requests;
+        self
+    }
+
+    /// See [`ServerConfig::startup_report`]
+    pub fn startup_report(mut self, format: ReportFormat) -> Self {
+        self.config.startup_report = Some(format);
+        self
+    }
+
+    pub fn build(self) -> Server {
+        let threads = self
+            .threads
+            .unwrap_or((self.router.routes.len() * 5).min(MAX_THREADS));
+        Server {
+            router: Arc::new(self.router),
+            pool: ThreadPool::new(threads),
+            logger: self.logger,
+            config: self.config,
+            addr: self.addr,
+            listeners: self.listeners,
+        }
+    }
+}
+
+/// Handle of a server started with [`Server::spawn`]
+#[derive(Debug)]
+pub struct ServerHandle {
+    addr: SocketAddr,
+    stopping: Arc<AtomicBool>,
+    queue_times: Arc<QueueTimes>,
+    pool: PoolHandle,
+    /// Gets the result of the server once its workers are done
+    finished: mpsc::Receiver<io::Result<()>>,
+}
+
+impl ServerHandle {
+    /// Returns the address the server listens on, with the port the system picked
+    /// when it was started on port 0
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// See [`Server::queue_times`]
+    pub fn queue_times(&self) -> QueueTimeStats {
+        self.queue_times.stats()
+    }
+
+    /// Returns the number of workers of the server
+    pub fn threads(&self) -> usize {
+        self.pool.size()
+    }
+
+    /// Changes the number of workers of the running server, with a minimum of 1.
+    /// The extra workers retire after the connections they serve and the ones already
+    /// waiting for a worker, see [`ThreadPool::resize`]
+    /// # Example
+    /// ```
+    /// use HTTP_Server::router::Router;
+    /// use HTTP_Server::server::Server;
+    ///
+    /// let server = Server::builder(Router::new()).threads(2).build();
+    /// let handle = server.spawn("127.0.0.1:0").unwrap();
+    /// handle.resize(8);
+    /// assert_eq!(handle.threads(), 8);
+    /// handle.shutdown().unwrap();
+    /// ```
+    pub fn resize(&self, threads: usize) {
+        self.pool.resize(threads)
+    }
+
+    /// Stops accepting connections and waits for the requests being served to finish.
+    /// Connections kept alive are closed after their current request, idle ones once
+    /// `keep_alive_timeout` expires
+    pub fn shutdown(self) -> io::Result<()> {
+        self.stop();
+        self.finished
+            .recv()
+            .unwrap_or_else(|_| Err(io::Error::other("the server thread panicked")))
+    }
+
+    /// Like [`ServerHandle::shutdown`], but gives up waiting for the requests being
+    /// served after the timeout, failing with `TimedOut`. They still finish in the
+    /// background
+    pub fn shutdown_timeout(self, timeout: Duration) -> io::Result<()> {
+        self.stop();
+        match self.finished.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "the server didn't finish its requests in time",
+            )),
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                Err(io::Error::other("the server thread panicked"))
+            }
+        }
+    }
+
+    /// Tells the server to stop
+    fn stop(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        wake(self.addr);
+    }
+}
+
+/// Wakes up a server blocked accepting the next connection by connecting to it,
+/// so it sees it has to stop
+fn wake(mut addr: SocketAddr) {
+    if addr.ip().is_unspecified() {
+        addr.set_ip(match addr.ip() {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        });
+    }
+    _ = TcpStream::connect_timeout(&addr, Duration::from_secs(1));
+}
+
+/// How busy the server is, to compress less when it's loaded
+#[derive(Default)]
+struct Load {
+    /// Connections accepted and not closed yet, the ones waiting for a worker included
+    connections: AtomicUsize,
+    workers: AtomicUsize,
+}
+
+impl Load {
+    /// Returns the open connections for each worker
+    fn ratio(&self) -> f64 {
+        let workers = self.workers.load(Ordering::Relaxed).max(1);
+        self.connections.load(Ordering::Relaxed) as f64 / workers as f64
+    }
+}
+
+/// Address [`Server::run`] listens on unless the builder sets another one
+const DEFAULT_ADDR: &str = "127.0.0.1:8080";
+
 pub struct Server {
     pub router: Arc<Router>,
     pub pool: ThreadPool,
     pub logger: Option<Sender<String>>,
+    pub config: ServerConfig,
+    /// Address [`Server::run`] listens on
+    addr: String,
+    /// Listeners already bound that [`Server::run`] serves instead of binding `addr`
+    listeners: Vec<(TcpListener, ListenerOptions)>,
 }
 
 impl Server {
@@ -29,100 +363,907 @@ impl Server {
             router: Arc::new(router),
             pool: ThreadPool::new(threads),
             logger,
+            config: ServerConfig::default(),
+            addr: DEFAULT_ADDR.to_string(),
+            listeners: Vec::new(),
+        }
+    }
+
+    /// Creates a server that [`Server::run`] serves on a listener bound by the caller,
+    /// like one inherited from the previous process during a restart
+    pub fn from_listener(router: Router, listener: TcpListener) -> Server {
+        Server::builder(router).listeners(vec![listener]).build()
+    }
+
+    /// Returns what the server runs with when serving the listeners on the addresses
+    pub fn startup_report(&self, listeners: &[SocketAddr]) -> StartupReport {
+        StartupReport::new(
+            listeners,
+            self.router.routes.len(),
+            self.pool.size(),
+            &self.config,
+        )
+    }
+
+    /// Returns how long the connections waited in the queue for a worker, a sample for
+    /// each connection. Requests kept alive after the first one of their connection
+    /// don't wait in the queue, the worker serving the connection reads them, so they
+    /// aren't counted. A worker is busy while one of its connections is kept alive, so
+    /// long queue times with few busy handlers point to a `keep_alive_timeout` too long
+    /// for the number of workers
+    /// # Example
+    /// ```
+    /// use HTTP_Server::router::Router;
+    /// use HTTP_Server::server::Server;
+    ///
+    /// let handle = Server::new(Router::new(), None).spawn("127.0.0.1:0").unwrap();
+    /// let stats = handle.queue_times();
+    /// println!("{} connections waited {:?} on average", stats.count, stats.mean());
+    /// handle.shutdown().unwrap();
+    /// ```
+    pub fn queue_times(&self) -> QueueTimeStats {
+        self.pool.queue_times().stats()
+    }
+
+    /// Returns a builder to set up the server, see [`ServerBuilder`]
+    pub fn builder(router: Router) -> ServerBuilder {
+        ServerBuilder {
+            router,
+            addr: DEFAULT_ADDR.to_string(),
+            threads: None,
+            logger: None,
+            config: ServerConfig::default(),
+            listeners: Vec::new(),
+        }
+    }
+
+    /// Starts the server on the listeners set with [`ServerBuilder::listeners`] or,
+    /// without them, on the address set with [`ServerBuilder::addr`],
+    /// `127.0.0.1:8080` by default. See [`Server::start`].
+    pub fn run(mut self) -> io::Result<()> {
+        match std::mem::take(&mut self.listeners) {
+            listeners if listeners.is_empty() => self.start(&self.addr),
+            listeners => self.serve_listening(listeners),
         }
     }
 
     /// Starts the server on the specified address.
-    pub(crate) fn start(&self, addr: &str) -> io::Result<()> {
+    /// On port 0 the system picks a free port, the one printed. To know it from code,
+    /// use [`Server::spawn`] and [`ServerHandle::local_addr`].
+    ///
+    /// With the `signals` feature, a `SIGINT` or `SIGTERM` stops it from accepting
+    /// connections, and it returns once the requests being served finish or
+    /// `config.shutdown_timeout` passes.
+    pub fn start(&self, addr: &str) -> io::Result<()> {
+        self.start_all(&[addr])
+    }
+
+    /// Serves the listeners, printing their addresses, until a signal arrives with the
+    /// `signals` feature
+    fn serve_listening(&self, listeners: Vec<(TcpListener, ListenerOptions)>) -> io::Result<()> {
+        for (listener, _) in &listeners {
+            println!("Server listening on port {}", listener.local_addr()?);
+        }
+        #[cfg(all(unix, feature = "signals"))]
+        return self.serve_until_signal(listeners);
+        #[cfg(not(all(unix, feature = "signals")))]
+        self.serve_with(listeners)
+    }
+
+    /// Serves the connections of the listeners until a `SIGINT` or `SIGTERM` arrives
+    #[cfg(all(unix, feature = "signals"))]
+    fn serve_until_signal(&self, listeners: Vec<(TcpListener, ListenerOptions)>) -> io::Result<()> {
+        signals::install()?;
+        let addrs = listeners
+            .iter()
+            .map(|(listener, _)| listener.local_addr())
+            .collect::<io::Result<Vec<_>>>()?;
+        let stopping = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&stopping);
+        thread::spawn(move || {
+            while !signals::received() {
+                thread::sleep(Duration::from_millis(50));
+            }
+            println!("Shutting down, finishing the requests being served");
+            flag.store(true, Ordering::SeqCst);
+            addrs.into_iter().for_each(wake);
+        });
+        self.serve_until(listeners, &stopping)
+    }
+
+    /// Starts the server on the specified address in a thread of its own, returning a
+    /// handle to shut it down. Dropping the handle leaves the server running
+    /// # Example
+    /// ```
+    /// use HTTP_Server::context::Context;
+    /// use HTTP_Server::http_status::HttpStatus;
+    /// use HTTP_Server::router::Router;
+    /// use HTTP_Server::server::Server;
+    /// use std::time::Duration;
+    ///
+    /// let mut router = Router::new();
+    /// router.get("/ping", |ctx: &mut Context| ctx.string(HttpStatus::Ok, "pong"));
+    ///
+    /// let handle = Server::new(router, None).spawn("127.0.0.1:0").unwrap();
+    /// println!("Listening on {}", handle.local_addr());
+    /// handle.shutdown_timeout(Duration::from_secs(10)).unwrap();
+    /// ```
+    pub fn spawn(self, addr: &str) -> io::Result<ServerHandle> {
         let listener = TcpListener::bind(addr)?;
-        println!("Server listening on port {}", addr);
+        let addr = listener.local_addr()?;
+        let stopping = Arc::new(AtomicBool::new(false));
+        let (done, finished) = mpsc::channel();
+        let flag = Arc::clone(&stopping);
+        let queue_times = self.pool.queue_times();
+        let pool = self.pool.handle();
+        thread::spawn(move || {
+            let result = self.serve_until(vec![(listener, ListenerOptions::default())], &flag);
+            // Dropping the pool waits for the workers to finish their connections
+            drop(self);
+            _ = done.send(result);
+        });
+        Ok(ServerHandle {
+            addr,
+            stopping,
+            queue_times,
+            pool,
+            finished,
+        })
+    }
+
+    /// Starts the server in `processes` processes sharing the address, each with its own
+    /// thread pool, restarting the ones that crash after a delay that grows while they
+    /// keep crashing. A `SIGINT` or `SIGTERM` to the supervisor is passed on to the
+    /// workers, killed if they don't exit within `config.shutdown_timeout`.
+    ///
+    /// The calling process becomes a supervisor that runs the program again for each
+    /// worker, so everything before this call runs in every process and it must be
+    /// reached the same way, with the same arguments.
+    #[cfg(target_os = "linux")]
+    pub fn start_prefork(&self, addr: &str, processes: usize) -> io::Result<()> {
+        if std::env::var_os(prefork::WORKER_ENV).is_none() {
+            println!(
+                "Server listening on port {} with {} processes",
+                addr, processes
+            );
+            return prefork::supervise(processes, self.config.shutdown_timeout);
+        }
+        let addr = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::AddrNotAvailable))?;
+        self.serve(prefork::bind_reuseport(addr)?)
+    }
+
+    /// Serves the connections accepted by a listener already bound,
+    /// like one bound to port 0 to let the system pick a free port.
+    pub fn serve(&self, listener: TcpListener) -> io::Result<()> {
+        self.serve_all(vec![listener])
+    }
+
+    /// Starts the server on all the addresses, like `0.0.0.0:8080` and `[::]:8080`.
+    /// The connections accepted on any of them are served by the same router and
+    /// workers. See [`Server::start`].
+    pub fn start_all(&self, addrs: &[&str]) -> io::Result<()> {
+        let listeners = addrs
+            .iter()
+            .map(|addr| Ok((TcpListener::bind(addr)?, ListenerOptions::default())))
+            .collect::<io::Result<Vec<_>>>()?;
+        self.serve_listening(listeners)
+    }
+
+    /// Serves the connections accepted by several listeners already bound,
+    /// see [`Server::start_all`]
+    pub fn serve_all(&self, listeners: Vec<TcpListener>) -> io::Result<()> {
+        let options = ListenerOptions::default();
+        self.serve_with(listeners.into_iter().map(|l| (l, options)).collect())
+    }
+
+    /// Serves the connections accepted by several listeners already bound, each with
+    /// its own [`ListenerOptions`], like generic parse errors on the public listener and
+    /// detailed ones on an internal one. See [`Server::serve_all`]
+    /// # Example
+    /// ```no_run
+    /// use HTTP_Server::config::{ListenerOptions, ParseErrors};
+    /// use HTTP_Server::router::Router;
+    /// use HTTP_Server::server::Server;
+    /// use std::net::TcpListener;
+    ///
+    /// let public = TcpListener::bind("0.0.0.0:8080").unwrap();
+    /// let admin = TcpListener::bind("127.0.0.1:9090").unwrap();
+    /// let generic = ListenerOptions {
+    ///     parse_errors: Some(ParseErrors::Generic),
+    /// };
+    /// Server::new(Router::new(), None)
+    ///     .serve_with(vec![(public, generic), (admin, ListenerOptions::default())])
+    ///     .unwrap();
+    /// ```
+    pub fn serve_with(&self, listeners: Vec<(TcpListener, ListenerOptions)>) -> io::Result<()> {
+        self.serve_until(listeners, &Arc::new(AtomicBool::new(false)))
+    }
+
+    /// Serves the connections of the listeners until `stopping` is set and a last
+    /// connection wakes each of them up, then waits up to `config.shutdown_timeout`
+    /// for the connections being served to close.
+    /// If a listener fails, the others are stopped too and its error is returned
+    fn serve_until(
+        &self,
+        listeners: Vec<(TcpListener, ListenerOptions)>,
+        stopping: &Arc<AtomicBool>,
+    ) -> io::Result<()> {
+        let config = Arc::new(self.config.clone());
+        for degraded in config.subsystems.degraded() {
+            let warning = format!(
+                "DEGRADED: {} is disabled, it failed to start: {}",
+                degraded.name, degraded.error
+            );
+            println!("{warning}");
+            if let Some(logger) = &self.logger {
+                _ = logger.send(warning);
+            }
+        }
+        let addrs = listeners
+            .iter()
+            .map(|(listener, _)| listener.local_addr())
+            .collect::<io::Result<Vec<_>>>()?;
+        if let Some(format) = config.startup_report {
+            println!("{}", self.startup_report(&addrs).render(format));
+        }
+        let load = Arc::new(Load::default());
+        let result = thread::scope(|scope| {
+            let accepting: Vec<_> = listeners
+                .into_iter()
+                .map(|(listener, options)| {
+                    let config = options.apply(&config);
+                    let (load, addrs) = (&load, &addrs);
+                    scope.spawn(move || {
+                        let result = self.accept(listener, &config, load, stopping);
+                        if result.is_err() && !stopping.swap(true, Ordering::SeqCst) {
+                            addrs.iter().for_each(|addr| wake(*addr));
+                        }
+                        result
+                    })
+                })
+                .collect();
+            accepting
+                .into_iter()
+                .map(|accepting| accepting.join().unwrap_or(Ok(())))
+                .fold(Ok(()), io::Result::and)
+        });
+
+        let deadline = Instant::now() + config.shutdown_timeout;
+        while load.connections.load(Ordering::Relaxed) > 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        result
+    }
+
+    /// Accepts the connections of the listener, handing them to the workers, until
+    /// `stopping` is set
+    fn accept(
+        &self,
+        listener: TcpListener,
+        config: &Arc<ServerConfig>,
+        load: &Arc<Load>,
+        stopping: &Arc<AtomicBool>,
+    ) -> io::Result<()> {
         for stream in listener.incoming() {
-            let mut stream = stream?;
+            let stream = stream?;
+            if stopping.load(Ordering::SeqCst) {
+                break;
+            }
+            let Ok(peer) = stream.peer_addr() else {
+                continue;
+            };
+            // Dropping a rejected stream closes it before it costs a worker
+            let Some(admission) = accept::admit(&config.accept_filters, peer) else {
+                continue;
+            };
+            if let Some(tarpit) = config.tarpit.as_ref().filter(|t| t.traps_ip(peer.ip())) {
+                tarpit.trap(stream);
+                continue;
+            }
             let router = Arc::clone(&self.router);
             let logger = self.logger.clone();
+            let config = Arc::clone(config);
+
+            load.workers.store(self.pool.size(), Ordering::Relaxed);
+            load.connections.fetch_add(1, Ordering::Relaxed);
+            let load = Arc::clone(load);
+            let stopping = Arc::clone(stopping);
 
             // Submit the connection handling task to the thread pool
+            let queued = Instant::now();
             self.pool.execute(move || {
-                match Server::handle_connection(&mut stream) {
-                    Ok(request) => {
-                        let mut ctx = Context::new(stream);
-                        // Handle the request in the router layer
-                        ctx.request = request;
-                        ctx.logger = logger;
-                        router.handle_request(&mut ctx);
-                    }
-                    Err(e) => {
-                        let mut ctx = Context::new(stream);
-                        if let Some(logger) = logger {
-                            _ = logger.send(e.to_string());
+                let waiting = queued.elapsed();
+                Server::serve_connection(
+                    stream, waiting, &router, logger, &config, &load, &stopping,
+                );
+                load.connections.fetch_sub(1, Ordering::Relaxed);
+                drop(admission);
+            });
+        }
+        Ok(())
+    }
+
+    /// Serves the requests sent over a connection one after the other until the client
+    /// asks to close it, stays idle for longer than `config.keep_alive_timeout` or
+    /// `config.max_requests_per_connection` requests have been served.
+    /// Once `stopping` is set, the connection is closed after the current request.
+    /// `waiting` is how long the connection waited in the queue for a worker, the
+    /// queue time of its first request.
+    fn serve_connection(
+        stream: TcpStream,
+        waiting: Duration,
+        router: &Router,
+        logger: Option<Sender<String>>,
+        config: &Arc<ServerConfig>,
+        load: &Load,
+        stopping: &AtomicBool,
+    ) {
+        if stream.set_write_timeout(config.write_timeout).is_err() {
+            return;
+        }
+        let remote_addr = stream.peer_addr().ok();
+        let mut reader =
+            BufReader::with_capacity(config.read_buffer_size, DeadlineStream::new(stream));
+        let mut served = 0;
+
+        loop {
+            if served == 0 {
+                reader.get_mut().set_timeout(config.header_read_timeout);
+                if let Ok(buf) = reader.fill_buf() {
+                    if is_tls_handshake(buf) {
+                        if let (Some(logger), Some(addr)) = (&logger, remote_addr) {
+                            _ = logger
+                                .send(format!("TLS handshake from {addr} on a plaintext port"));
                         }
-                        ctx.string(HttpStatus::BadRequest, &e.to_string());
+                        // A fatal alert the client reports clearly, instead of an http response
+                        // it would fail to parse as a tls record
+                        _ = reader
+                            .get_ref()
+                            .get_ref()
+                            .write_all(&TLS_PROTOCOL_VERSION_ALERT);
+                        return;
                     }
                 }
+            } else {
+                // Wait for the next request, giving up if the client stays idle for too long
+                reader
+                    .get_mut()
+                    .set_timeout(Some(config.keep_alive_timeout));
+                match reader.fill_buf() {
+                    Ok(buf) if !buf.is_empty() => {}
+                    // The client closed the connection or the idle timeout expired
+                    _ => return,
+                }
+                reader.get_mut().set_timeout(config.header_read_timeout);
+            }
+            let received = Instant::now();
+
+            let writer = match reader.get_ref().get_ref().try_clone() {
+                Ok(writer) => writer,
+                Err(_) => return,
+            };
+            let result = Server::parse_head(&mut reader, config).and_then(|mut request| {
+                Server::check_host(&request, config)?;
+                if Server::expects_continue(&request, config)? {
+                    (&writer)
+                        .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")
+                        .map_err(ApiErr::StreamError)?;
+                }
+                reader.get_mut().set_timeout(config.body_read_timeout);
+                Server::read_body(&mut reader, &mut request, config)?;
+                Ok(request)
             });
+            reader.get_mut().set_timeout(None);
+            served += 1;
+
+            let mut ctx = Context::new(writer);
+            ctx.config = Arc::clone(config);
+            ctx.remote_addr = remote_addr;
+            ctx.load = load.ratio();
+            ctx.received = received;
+            // Later requests of the connection are read as soon as they arrive
+            ctx.queue_time = if served == 1 { waiting } else { Duration::ZERO };
+            if config.queue_time_header {
+                let millis = ctx.queue_time.as_secs_f64() * 1000.0;
+                ctx.add_response_header("X-Queue-Time", format!("{millis:.3}"));
+            }
+            if let Some(alt_svc) = &config.alt_svc {
+                ctx.add_response_header("Alt-Svc", alt_svc.header_value());
+            }
+            match result {
+                Ok(request) => {
+                    let keep_alive = config.keep_alive
+                        && !stopping.load(Ordering::SeqCst)
+                        && served < config.max_requests_per_connection
+                        && request.keep_alive();
+                    let connection = if keep_alive { "keep-alive" } else { "close" };
+                    ctx.add_response_header("Connection", connection);
+
+                    // Handle the request in the router layer
+                    if let Some(logger) = &logger {
+                        for line in request.skipped_headers() {
+                            _ = logger.send(format!("Skipped malformed header line `{line}`"));
+                        }
+                    }
+                    ctx.request = request;
+                    ctx.logger = logger.clone();
+                    if let (true, Some(logger)) = (config.access_log, &logger) {
+                        let client = ctx.client_ip().map(|ip| ip.to_string());
+                        let client = client.unwrap_or_default();
+                        _ = logger.send(match config.access_log_format {
+                            AccessLogFormat::Line => {
+                                let request = config.scrubber.describe(&ctx.request);
+                                format!("{client} {request}")
+                            }
+                            AccessLogFormat::Curl => {
+                                let command = config.scrubber.curl(&ctx.request);
+                                // A comment keeps the line a valid command
+                                format!("{command} # from {client}")
+                            }
+                        });
+                    }
+                    if !rules::screen(&mut ctx) {
+                        return;
+                    }
+                    if let Some(tarpit) = config.tarpit.as_ref().filter(|t| t.traps(&ctx)) {
+                        if let (Some(logger), Some(ip)) = (&logger, ctx.client_ip()) {
+                            _ = logger.send(format!("Tarpitting {ip}"));
+                        }
+                        // The worker is freed while the tarpit keeps the connection
+                        if let Ok(stream) = reader.get_ref().get_ref().try_clone() {
+                            tarpit.trap(stream);
+                        }
+                        return;
+                    }
+                    let handled = panic::catch_unwind(AssertUnwindSafe(|| match &config.alt_svc {
+                        Some(alt_svc) if alt_svc.serve_well_known(&mut ctx) => {}
+                        _ => router.handle_request(&mut ctx),
+                    }));
+                    if let Err(panic) = handled {
+                        Server::answer_panic(&mut ctx, panic);
+                    }
+                    if let (Some(e), Some(logger)) = (ctx.write_error(), &logger) {
+                        _ = logger.send(format!("Error writing response: {e}"));
+                    }
+                    ctx.run_deferred();
+                    if !keep_alive || ctx.close_connection || stopping.load(Ordering::SeqCst) {
+                        return;
+                    }
+                }
+                Err(e) => {
+                    if let Some(logger) = &logger {
+                        _ = logger.send(e.to_string());
+                    }
+                    if e.is_disconnect() || (e.is_malicious() && config.drop_malicious_requests) {
+                        return;
+                    }
+                    // The rest of the stream can't be trusted after a failed parse
+                    ctx.add_response_header("Connection", "close");
+                    match config.parse_errors {
+                        ParseErrors::Detailed => ctx.send_error(&e),
+                        ParseErrors::Generic => ctx.send_generic_error(&e),
+                    }
+                    return;
+                }
+            }
         }
+    }
 
-        Ok(())
+    /// Answers the request of a handler that panicked with a `500 Internal Server Error`,
+    /// unless it already sent its response, and closes the connection as the handler
+    /// may have left it in any state
+    fn answer_panic(ctx: &mut Context, panic: Box<dyn Any + Send>) {
+        let message = match panic.downcast_ref::<&str>() {
+            Some(message) => message.to_string(),
+            None => panic.downcast_ref::<String>().cloned().unwrap_or_default(),
+        };
+        if let Some(logger) = &ctx.logger {
+            _ = logger.send(format!("Handler panicked: {message}"));
+        }
+        ctx.close_connection = true;
+        if !ctx.is_committed() {
+            ctx.add_response_header("Connection", "close");
+            ctx.send_error(&ApiErr::InternalError("Internal server error.".into()));
+        }
     }
 
-    fn read_head<S: Read>(mut stream: &mut S) -> Result<String, ApiErr> {
-        let mut buffer = Vec::new();
-        let mut buf = [0; 1];
+    /// Reads the request line and headers up to the empty line that ends them.
+    /// Fails with `ApiErr::HeadersTooLarge` as soon as the head grows past
+    /// `config.max_header_size` bytes.
+    /// Bytes following the head stay in the reader, ready to be read as the body.
+    fn read_head<R: BufRead>(reader: &mut R, config: &ServerConfig) -> Result<Vec<u8>, ApiErr> {
+        let mut buffer = Vec::with_capacity(config.header_buffer_size.min(config.max_header_size));
 
         loop {
-            stream.read_exact(&mut buf).map_err(ApiErr::StreamError)?;
-            buffer.push(buf[0]);
+            // Never read more than one byte past the limit, so an endless line can't grow the buffer
+            let limit = (config.max_header_size + 1 - buffer.len()) as u64;
+            let read = reader
+                .by_ref()
+                .take(limit)
+                .read_until(b'\n', &mut buffer)
+                .map_err(ApiErr::StreamError)?;
+            if buffer.len() > config.max_header_size {
+                return Err(ApiErr::HeadersTooLarge);
+            }
+            if read == 0 {
+                return Err(ApiErr::StreamError(io::ErrorKind::UnexpectedEof.into()));
+            }
             if buffer.ends_with(b"\r\n\r\n") {
                 // Read until double newline is encountered
                 break;
             }
         }
 
-        let head = String::from_utf8_lossy(&buffer);
-        Ok(head.trim().to_string())
+        Ok(buffer)
     }
 
-    fn handle_connection<S: Read>(mut stream: &mut S) -> Result<HttpRequest, ApiErr> {
-        let head = Server::read_head(&mut stream)?;
-        let mut head_lines = head.split("\r\n").collect::<Vec<&str>>();
-        let start_line = head_lines
-            .remove(0)
-            .split_whitespace()
-            .collect::<Vec<&str>>();
-        let verb = start_line.get(0).ok_or(ApiErr::InvalidRequest)?;
-        let path = start_line.get(1).ok_or(ApiErr::InvalidRequest)?;
-        let mut headers: HashMap<String, String> = HashMap::new();
+    /// Parses the request line and headers, leaving the body unread.
+    fn parse_head<R: BufRead>(
+        reader: &mut R,
+        config: &ServerConfig,
+    ) -> Result<HttpRequest, ApiErr> {
+        let raw_head = Server::read_head(reader, config)?;
+        let head = String::from_utf8_lossy(&raw_head);
+        let mut head_lines = head.trim().split("\r\n").collect::<Vec<&str>>();
+        // The request line must be exactly `method target version`
+        let start_line = head_lines.remove(0).split(' ').collect::<Vec<&str>>();
+        let [verb, path, version] = start_line[..] else {
+            return Err(ApiErr::InvalidRequest);
+        };
+        if path.is_empty() {
+            return Err(ApiErr::InvalidRequest);
+        }
+        let version = HttpVersion::from_string(version)?;
+        let mut headers = Headers::new();
+        let mut skipped_headers = Vec::new();
         for line in &head_lines {
-            let (key, value) = match line.split_once(":") {
-                Some((key, value)) => (key, value),
-                None => continue,
-            };
-            headers.insert(key.to_string(), value.trim().to_string());
+            // Folded lines could be read as a header by a proxy and as a continuation here
+            if line.starts_with([' ', '\t']) {
+                return Err(ApiErr::AmbiguousRequest("obsolete line folding".into()));
+            }
+            match line.split_once(":") {
+                Some((key, value)) if is_token(key) => headers.append(key, value.trim())?,
+                _ if config.header_parsing == HeaderParsing::Lenient => {
+                    skipped_headers.push(line.to_string())
+                }
+                _ => return Err(ApiErr::InvalidHeader(line.to_string())),
+            }
+        }
+        // The length of the body depends on which of the two headers is trusted
+        if headers.contains("Content-Length") && headers.contains("Transfer-Encoding") {
+            return Err(ApiErr::AmbiguousRequest(
+                "both Content-Length and Transfer-Encoding".into(),
+            ));
         }
+        if headers.contains("Transfer-Encoding") && !is_chunked(&headers) {
+            return Err(ApiErr::AmbiguousRequest(
+                "Transfer-Encoding without chunked as the final coding".into(),
+            ));
+        }
+
+        // Absolute-form targets, sent to proxies, carry the host that replaces the Host header
+        let path = match HttpRequest::split_absolute_form(path) {
+            Some(("", _)) => return Err(ApiErr::InvalidRequest),
+            Some((authority, target)) => {
+                headers.insert("Host", authority);
+                target
+            }
+            None => path.to_string(),
+        };
 
-        let mut body = String::new();
-        if let Some(content_length) = headers.get("Content-Length") {
-            let content_length = content_length
-                .parse::<usize>()
-                .map_err(|_| ApiErr::InvalidRequest)?;
+        let mut request =
+            HttpRequest::new(HttpMethod::from_string(verb)?, path, headers, String::new());
+        request.version = version;
+        request.raw_head = raw_head;
+        request.skipped_headers = skipped_headers;
+        Ok(request)
+    }
+
+    /// Reads the body of the request, of the size announced by its Content-Length header
+    /// or in chunks, and decodes its `Content-Encoding`.
+    fn read_body<R: BufRead>(
+        reader: &mut R,
+        request: &mut HttpRequest,
+        config: &ServerConfig,
+    ) -> Result<(), ApiErr> {
+        let buff = if is_chunked(&request.headers) {
+            Server::read_chunked(reader, request, config)?
+        } else {
+            let content_length = Server::content_length(request, config)?;
             let mut buff = vec![0; content_length];
-            stream.read_exact(&mut buff).map_err(ApiErr::StreamError)?;
-            body = String::from_utf8_lossy(&buff).to_string();
+            reader.read_exact(&mut buff).map_err(ApiErr::StreamError)?;
+            buff
+        };
+        if !buff.is_empty() {
+            let decoded = Server::decode_content(request, buff.clone(), config)?;
+            request.body = String::from_utf8_lossy(&decoded).to_string();
+            request.body_bytes = decoded;
+            request.raw_body = buff;
+        }
+        Ok(())
+    }
+
+    /// Reads a chunked body and undoes the transfer codings applied before `chunked`,
+    /// replacing the `Transfer-Encoding` of the request with the `Content-Length` of the
+    /// body. Fails with `413 Payload Too Large` if it's over `config.max_body_size`.
+    fn read_chunked<R: BufRead>(
+        reader: &mut R,
+        request: &mut HttpRequest,
+        config: &ServerConfig,
+    ) -> Result<Vec<u8>, ApiErr> {
+        let body = read_chunked_body(reader, config.max_body_size).map_err(|e| match e.kind() {
+            io::ErrorKind::InvalidData => ApiErr::InvalidRequest,
+            io::ErrorKind::FileTooLarge => ApiErr::PayloadTooLarge,
+            _ => ApiErr::StreamError(e),
+        })?;
+        let codings = request.headers.get_all("Transfer-Encoding").join(",");
+        // The last coding is chunked, the one just undone
+        let codings = codings.rsplit_once(',').map_or("", |(codings, _)| codings);
+        let body = match body.is_empty() {
+            true => body,
+            false => Server::decode(codings, body, config)?,
+        };
+
+        request.headers.remove("Transfer-Encoding");
+        request
+            .headers
+            .insert("Content-Length", &body.len().to_string());
+        Ok(body)
+    }
+
+    /// Returns the size of the request body announced by its Content-Length header.
+    /// A list of identical values like `5, 5` is accepted as a single one, values that
+    /// aren't only digits, like `+5`, are invalid.
+    /// Fails with `ApiErr::AmbiguousRequest` if the values conflict and with
+    /// `ApiErr::PayloadTooLarge` if it's over `config.max_body_size`.
+    fn content_length(request: &HttpRequest, config: &ServerConfig) -> Result<usize, ApiErr> {
+        let content_length = match request.headers.get("Content-Length") {
+            Some(content_length) => {
+                let mut values = content_length.split(',').map(|v| v.trim());
+                let first = values.next().unwrap_or_default();
+                if values.any(|v| v != first) {
+                    return Err(ApiErr::AmbiguousRequest(
+                        "conflicting Content-Length values".into(),
+                    ));
+                }
+                // A proxy could read a signed or spaced value differently
+                if first.is_empty() || !first.bytes().all(|b| b.is_ascii_digit()) {
+                    return Err(ApiErr::InvalidRequest);
+                }
+                first.parse::<usize>().map_err(|_| ApiErr::InvalidRequest)?
+            }
+            None => 0,
+        };
+        if content_length > config.max_body_size {
+            return Err(ApiErr::PayloadTooLarge);
+        }
+        Ok(content_length)
+    }
+
+    /// Checks the Expect header of the request, returning whether the client waits for a
+    /// `100 Continue` response before sending the body.
+    /// Fails with `ApiErr::ExpectationFailed` for expectations other than `100-continue`,
+    /// and with `ApiErr::PayloadTooLarge` before the client sends a body that is too big.
+    fn expects_continue(request: &HttpRequest, config: &ServerConfig) -> Result<bool, ApiErr> {
+        // HTTP/1.0 clients don't know about expectations, so they must be ignored
+        if request.version == HttpVersion::Http10 {
+            return Ok(false);
+        }
+        match request.headers.get("Expect") {
+            Some(expect) if expect.eq_ignore_ascii_case("100-continue") => {
+                Ok(is_chunked(&request.headers) || Server::content_length(request, config)? > 0)
+            }
+            Some(_) => Err(ApiErr::ExpectationFailed),
+            None => Ok(false),
+        }
+    }
+
+    /// Checks the request is for one of the `config.allowed_hosts`
+    fn check_host(request: &HttpRequest, config: &ServerConfig) -> Result<(), ApiErr> {
+        if config.allowed_hosts.is_empty() {
+            return Ok(());
+        }
+        let host = request.host().ok_or(ApiErr::InvalidRequest)?;
+        let allowed = config.allowed_hosts.iter().any(|pattern| {
+            let subdomains = pattern.starts_with('.');
+            let Some(pattern) = punycode::domain_to_ascii(pattern.trim_start_matches('.')) else {
+                return false;
+            };
+            host == pattern
+                || (subdomains
+                    && host
+                        .strip_suffix(&pattern)
+                        .is_some_and(|sub| sub.ends_with('.')))
+        });
+        if !allowed {
+            return Err(ApiErr::HostNotAllowed(host));
+        }
+        Ok(())
+    }
+
+    /// Undoes the `Content-Encoding` of the body, in the reverse order the codings were applied.
+    /// The decoded body can't be bigger than `config.max_body_size`.
+    /// Fails with `415 Unsupported Media Type` for unknown codings, and for every coding
+    /// other than `identity` without the `decompression` feature.
+    fn decode_content(
+        request: &mut HttpRequest,
+        body: Vec<u8>,
+        config: &ServerConfig,
+    ) -> Result<Vec<u8>, ApiErr> {
+        let Some(encoding) = request.headers.get("Content-Encoding") else {
+            return Ok(body);
+        };
+        let body = Server::decode(encoding, body, config)?;
+
+        request.headers.remove("Content-Encoding");
+        request
+            .headers
+            .insert("Content-Length", &body.len().to_string());
+        Ok(body)
+    }
+
+    /// Undoes the codings of the comma separated list, from the last one to the first.
+    /// The decoded body can't be bigger than `config.max_body_size`.
+    fn decode(codings: &str, mut body: Vec<u8>, config: &ServerConfig) -> Result<Vec<u8>, ApiErr> {
+        let codings = codings.to_ascii_lowercase();
+        for coding in codings.split(',').map(|c| c.trim()).rev() {
+            if coding.is_empty() || coding == "identity" {
+                continue;
+            }
+            body = Server::decompress(coding, &body, config.max_body_size)
+                .ok_or_else(|| ApiErr::UnsupportedEncoding(coding.to_string()))?
+                .map_err(|e| match e {
+                    InflateError::TooLarge => ApiErr::PayloadTooLarge,
+                    InflateError::Invalid => ApiErr::InvalidRequest,
+                })?;
+        }
+        Ok(body)
+    }
+
+    /// Decompresses the body with the coding, `None` if the coding isn't supported
+    #[cfg(feature = "decompression")]
+    fn decompress(
+        coding: &str,
+        body: &[u8],
+        max_size: usize,
+    ) -> Option<Result<Vec<u8>, InflateError>> {
+        use crate::utils::inflate;
+
+        match coding {
+            "gzip" | "x-gzip" => Some(inflate::gunzip(body, max_size)),
+            // Deflate should come zlib wrapped, but some clients send it raw
+            "deflate" => Some(
+                inflate::zlib_decompress(body, max_size)
+                    .or_else(|_| inflate::inflate(body, max_size)),
+            ),
+            _ => None,
         }
+    }
+
+    #[cfg(not(feature = "decompression"))]
+    fn decompress(
+        _coding: &str,
+        _body: &[u8],
+        _max_size: usize,
+    ) -> Option<Result<Vec<u8>, InflateError>> {
+        None
+    }
 
-        Ok(HttpRequest::new(
-            HttpMethod::from_string(verb)?,
-            path.to_string(),
-            headers,
-            body,
-        ))
+    /// Parses a whole request at once, without the per phase timeouts of `serve_connection`.
+    #[cfg(test)]
+    fn handle_connection<R: BufRead>(
+        reader: &mut R,
+        config: &ServerConfig,
+    ) -> Result<HttpRequest, ApiErr> {
+        let mut request = Server::parse_head(reader, config)?;
+        Server::read_body(reader, &mut request, config)?;
+        Ok(request)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::api_err::ErrorRenderer;
+    use crate::context::Context;
+    use crate::http_response::HttpResponse;
+    use crate::http_status::HttpStatus;
+    use crate::rules::{RuleSet, Rules};
+    use crate::tarpit::Tarpit;
     use crate::utils::mock_stream::MockTcpStream;
+    use std::io::Write;
+    use std::thread;
+    use std::time::Duration;
+
+    fn pong(ctx: &mut Context) {
+        ctx.string(HttpStatus::Ok, "pong")
+    }
+
+    fn echo(ctx: &mut Context) {
+        let body = ctx.body();
+        ctx.string(HttpStatus::Ok, &body)
+    }
+
+    fn panics(_: &mut Context) {
+        panic!("handler bug");
+    }
+
+    fn whoami(ctx: &mut Context) {
+        let addr = ctx.remote_addr().map(|addr| addr.to_string());
+        ctx.string(HttpStatus::Ok, &addr.unwrap_or_default())
+    }
+
+    /// Serves a single connection on an ephemeral port with the given config
+    /// and returns a client connected to it.
+    fn connect(config: ServerConfig) -> (TcpStream, thread::JoinHandle<()>) {
+        connect_with_logger(config, None)
+    }
+
+    fn connect_with_logger(
+        config: ServerConfig,
+        logger: Option<Sender<String>>,
+    ) -> (TcpStream, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let mut router = Router::new();
+            router
+                .get("/ping", pong)
+                .get("/whoami", whoami)
+                .get("/panic", panics)
+                .post("/echo", echo);
+            let (stream, _) = listener.accept().unwrap();
+            let config = Arc::new(config);
+            let stopping = AtomicBool::new(false);
+            Server::serve_connection(
+                stream,
+                Duration::ZERO,
+                &router,
+                logger,
+                &config,
+                &Load::default(),
+                &stopping,
+            );
+        });
+        (TcpStream::connect(addr).unwrap(), handle)
+    }
+
+    #[test]
+    fn builder_sets_up_the_server() {
+        let mut router = Router::new();
+        router.get("/ping", pong);
+        let server = Server::builder(router).build();
+        assert_eq!(server.pool.size(), 5);
+        assert_eq!(server.addr, DEFAULT_ADDR);
+
+        let (logger, logs) = mpsc::channel();
+        let server = Server::builder(Router::new())
+            .max_body_size(1)
+            .config(ServerConfig {
+                access_log: true,
+                max_body_size: 2,
+                ..ServerConfig::default()
+            })
+            .addr("0.0.0.0:80")
+            .threads(3)
+            .logger(logger)
+            .keep_alive(false)
+            .write_timeout(None)
+            .startup_report(ReportFormat::Json)
+            .build();
+        assert_eq!(server.pool.size(), 3);
+        assert_eq!(server.config.startup_report, Some(ReportFormat::Json));
+        assert_eq!(server.startup_report(&[]).workers, 3);
+        assert_eq!(server.addr, "0.0.0.0:80");
+        assert!(server.config.access_log && !server.config.keep_alive);
+        assert_eq!(server.config.max_body_size, 2);
+        assert_eq!(server.config.write_timeout, None);
+        server.logger.unwrap().send("line".into()).unwrap();
+        assert_eq!(logs.recv().unwrap(), "line");
+    }
 
     #[test]
     fn handle_message_without_body() {
@@ -133,7 +1274,9 @@ mod tests {
             write_data: vec![],
         };
 
-        let request = Server::handle_connection(&mut stream).unwrap();
+        let request =
+            Server::handle_connection(&mut BufReader::new(&mut stream), &ServerConfig::default())
+                .unwrap();
         assert_eq!(request.method, HttpMethod::Get);
         assert_eq!(request.path, "/");
         assert_eq!(request.headers.len(), 1);
@@ -153,7 +1296,9 @@ mod tests {
             write_data: vec![],
         };
 
-        let request = Server::handle_connection(&mut stream).unwrap();
+        let request =
+            Server::handle_connection(&mut BufReader::new(&mut stream), &ServerConfig::default())
+                .unwrap();
         assert_eq!(request.method, HttpMethod::Post);
         assert_eq!(request.path, "/");
         assert_eq!(request.headers.len(), 3);
@@ -181,7 +1326,9 @@ mod tests {
             write_data: vec![],
         };
 
-        let request = Server::handle_connection(&mut stream).unwrap();
+        let request =
+            Server::handle_connection(&mut BufReader::new(&mut stream), &ServerConfig::default())
+                .unwrap();
         assert_eq!(request.method, HttpMethod::Post);
         assert_eq!(request.path, "/");
         assert_eq!(request.headers.len(), 3);
@@ -209,7 +1356,9 @@ mod tests {
             write_data: vec![],
         };
 
-        let request = Server::handle_connection(&mut stream).unwrap();
+        let request =
+            Server::handle_connection(&mut BufReader::new(&mut stream), &ServerConfig::default())
+                .unwrap();
         assert_eq!(request.method, HttpMethod::Post);
         assert_eq!(request.path, "/");
         assert_eq!(request.headers.len(), 3);
@@ -227,4 +1376,876 @@ mod tests {
         );
         assert_eq!(request.body, "Hel");
     }
+
+    #[test]
+    fn handle_message_with_headers_at_max_size() {
+        let bytes = b"GET / HTTP/1.1\r\nCookie: abcdefghij\r\n\r\n";
+        let mut stream = MockTcpStream {
+            read_data: bytes.to_vec(),
+            position: 0,
+            write_data: vec![],
+        };
+        let config = ServerConfig {
+            header_buffer_size: 4,
+            max_header_size: bytes.len(),
+            ..ServerConfig::default()
+        };
+
+        let request = Server::handle_connection(&mut BufReader::new(&mut stream), &config).unwrap();
+        assert_eq!(
+            request.headers.get("Cookie"),
+            Some(&"abcdefghij".to_string())
+        );
+    }
+
+    #[test]
+    fn handle_message_with_headers_over_max_size() {
+        let bytes = b"GET / HTTP/1.1\r\nCookie: abcdefghij\r\n\r\n";
+        let mut stream = MockTcpStream {
+            read_data: bytes.to_vec(),
+            position: 0,
+            write_data: vec![],
+        };
+        let config = ServerConfig {
+            header_buffer_size: 4,
+            max_header_size: bytes.len() - 1,
+            ..ServerConfig::default()
+        };
+
+        let err = Server::handle_connection(&mut BufReader::new(&mut stream), &config).unwrap_err();
+        assert_eq!(err.http_status(), HttpStatus::RequestHeaderFieldsTooLarge);
+    }
+
+    #[test]
+    fn handle_message_leaves_next_request_in_reader() {
+        let bytes = b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nHelloGET /next HTTP/1.1\r\n\r\n";
+        let mut stream = MockTcpStream {
+            read_data: bytes.to_vec(),
+            position: 0,
+            write_data: vec![],
+        };
+        let mut reader = BufReader::new(&mut stream);
+
+        let first = Server::handle_connection(&mut reader, &ServerConfig::default()).unwrap();
+        let second = Server::handle_connection(&mut reader, &ServerConfig::default()).unwrap();
+        assert_eq!(first.body, "Hello");
+        assert_eq!(second.method, HttpMethod::Get);
+        assert_eq!(second.path, "/next");
+    }
+
+    #[test]
+    fn handle_message_with_unterminated_head() {
+        let bytes = b"GET / HTTP/1.1\r\nHost: localhost:8080\r\n";
+        let mut stream = MockTcpStream {
+            read_data: bytes.to_vec(),
+            position: 0,
+            write_data: vec![],
+        };
+
+        let err =
+            Server::handle_connection(&mut BufReader::new(&mut stream), &ServerConfig::default())
+                .unwrap_err();
+        assert!(matches!(err, ApiErr::StreamError(_)));
+    }
+
+    #[test]
+    fn handle_message_with_repeated_list_header() {
+        let bytes = b"GET / HTTP/1.1\r\nAccept: text/html\r\nAccept: application/json\r\n\r\n";
+        let mut stream = MockTcpStream {
+            read_data: bytes.to_vec(),
+            position: 0,
+            write_data: vec![],
+        };
+
+        let request =
+            Server::handle_connection(&mut BufReader::new(&mut stream), &ServerConfig::default())
+                .unwrap();
+        assert_eq!(
+            request.headers.get("Accept"),
+            Some(&"text/html, application/json".to_string())
+        );
+        assert_eq!(
+            request.headers.get_all("Accept"),
+            vec!["text/html", "application/json"]
+        );
+    }
+
+    #[test]
+    fn handle_message_with_repeated_content_length() {
+        let bytes = b"POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 5\r\n\r\nHello";
+        let mut stream = MockTcpStream {
+            read_data: bytes.to_vec(),
+            position: 0,
+            write_data: vec![],
+        };
+
+        let err =
+            Server::handle_connection(&mut BufReader::new(&mut stream), &ServerConfig::default())
+                .unwrap_err();
+        assert_eq!(err.http_status(), HttpStatus::BadRequest);
+    }
+
+    fn handle_head(head: &str) -> Result<HttpRequest, ApiErr> {
+        let mut stream = MockTcpStream {
+            read_data: head.as_bytes().to_vec(),
+            position: 0,
+            write_data: vec![],
+        };
+        Server::handle_connection(&mut BufReader::new(&mut stream), &ServerConfig::default())
+    }
+
+    #[test]
+    fn handle_message_with_absolute_form_target() {
+        let request =
+            handle_head("GET http://Example.com:8080/users?id=1 HTTP/1.1\r\nHost: other\r\n\r\n")
+                .unwrap();
+        assert_eq!(request.path(), "/users");
+        assert_eq!(request.query(), Some("id=1"));
+        assert_eq!(request.host().as_deref(), Some("example.com"));
+
+        let err = handle_head("GET http:///users HTTP/1.1\r\n\r\n").unwrap_err();
+        assert_eq!(err.http_status(), HttpStatus::BadRequest);
+    }
+
+    #[test]
+    fn handle_message_keeps_raw_head() {
+        let head = "GET /a?b=1 HTTP/1.1\r\nX-Signature:  abc \r\nhost: x\r\n\r\n";
+        let request = handle_head(head).unwrap();
+        assert_eq!(request.request_line(), Some("GET /a?b=1 HTTP/1.1"));
+        assert_eq!(request.raw_head(), head.as_bytes());
+        assert_eq!(request.headers.get("X-Signature"), Some(&"abc".to_string()));
+    }
+
+    #[test]
+    fn handle_message_with_content_length_list() {
+        let request = handle_head("POST / HTTP/1.1\r\nContent-Length: 5, 5\r\n\r\nHello").unwrap();
+        assert_eq!(request.body, "Hello");
+
+        let err = handle_head("POST / HTTP/1.1\r\nContent-Length: 5, 6\r\n\r\nHello").unwrap_err();
+        assert!(matches!(err, ApiErr::AmbiguousRequest(_)));
+    }
+
+    #[test]
+    fn handle_message_with_non_digit_content_length() {
+        for length in [
+            "+3",
+            "-0",
+            "3 3",
+            "0x3",
+            "",
+            "3, +3",
+            "99999999999999999999999",
+        ] {
+            let head = format!("POST / HTTP/1.1\r\nContent-Length: {length}\r\n\r\nabc");
+            let err = handle_head(&head).unwrap_err();
+            assert_eq!(err.http_status(), HttpStatus::BadRequest, "{length:?}");
+        }
+        let request = handle_head("POST / HTTP/1.1\r\nContent-Length: 03\r\n\r\nabc").unwrap();
+        assert_eq!(request.body, "abc");
+    }
+
+    #[test]
+    fn handle_message_with_transfer_encoding_not_ending_in_chunked() {
+        for encoding in ["gzip", "chunked, gzip", "identity"] {
+            let head = format!("POST / HTTP/1.1\r\nTransfer-Encoding: {encoding}\r\n\r\nabc");
+            let err = handle_head(&head).unwrap_err();
+            assert!(matches!(err, ApiErr::AmbiguousRequest(_)), "{encoding}");
+            assert_eq!(err.http_status(), HttpStatus::BadRequest);
+        }
+        let head = "POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n0\r\n\r\n";
+        assert!(handle_head(head).is_ok());
+    }
+
+    #[test]
+    fn handle_message_with_chunked_body() {
+        let head = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n";
+        let request = handle_head(&format!("{head}3\r\nabc\r\n2;ext=1\r\nde\r\n0\r\n\r\n"));
+        let request = request.unwrap();
+        assert_eq!(request.body, "abcde");
+        assert_eq!(request.headers.get("Transfer-Encoding"), None);
+        assert_eq!(
+            request.headers.get("Content-Length"),
+            Some(&"5".to_string())
+        );
+
+        let request = handle_head(&format!("{head}0\r\n\r\n")).unwrap();
+        assert_eq!(request.body, "");
+
+        for malformed in ["3\r\nabcd\r\n0\r\n\r\n", "x\r\n\r\n", "3\nabc\n0\n\n"] {
+            let err = handle_head(&format!("{head}{malformed}")).unwrap_err();
+            assert!(matches!(err, ApiErr::InvalidRequest), "{malformed:?}");
+        }
+
+        let config = ServerConfig {
+            max_body_size: 4,
+            ..ServerConfig::default()
+        };
+        let body = format!("{head}3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n");
+        let mut stream = MockTcpStream {
+            read_data: body.into_bytes(),
+            position: 0,
+            write_data: vec![],
+        };
+        let err = Server::handle_connection(&mut BufReader::new(&mut stream), &config);
+        assert!(matches!(err, Err(ApiErr::PayloadTooLarge)));
+    }
+
+    #[test]
+    #[cfg(feature = "decompression")]
+    fn handle_message_with_compressed_transfer_coding() {
+        let body = crate::utils::deflate::gzip(b"Hello", 6);
+        let head = format!(
+            "POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n{:x}\r\n",
+            body.len()
+        );
+        let mut stream = MockTcpStream {
+            read_data: [head.as_bytes(), &body, b"\r\n0\r\n\r\n"].concat(),
+            position: 0,
+            write_data: vec![],
+        };
+        let request =
+            Server::handle_connection(&mut BufReader::new(&mut stream), &ServerConfig::default());
+        assert_eq!(request.unwrap().body, "Hello");
+    }
+
+    #[test]
+    fn handle_message_with_content_length_and_transfer_encoding() {
+        let head = "POST / HTTP/1.1\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n";
+        let err = handle_head(&format!("{head}0\r\n\r\n")).unwrap_err();
+        assert!(matches!(err, ApiErr::AmbiguousRequest(_)));
+        assert_eq!(err.http_status(), HttpStatus::BadRequest);
+    }
+
+    #[test]
+    fn handle_message_with_obsolete_line_folding() {
+        for folded in ["X-Long: one\r\n two", "X-Long: one\r\n\ttwo"] {
+            let err = handle_head(&format!("GET / HTTP/1.1\r\n{folded}\r\n\r\n")).unwrap_err();
+            assert!(matches!(err, ApiErr::AmbiguousRequest(_)));
+        }
+    }
+
+    #[test]
+    fn handle_message_with_malformed_header_lines() {
+        let head = "GET / HTTP/1.1\r\nBad Header\r\nX-Id : 1\r\nHost: x\r\n\r\n";
+        let err = handle_head(head).unwrap_err();
+        assert!(matches!(err, ApiErr::InvalidHeader(line) if line == "Bad Header"));
+
+        let config = ServerConfig {
+            header_parsing: HeaderParsing::Lenient,
+            ..ServerConfig::default()
+        };
+        let mut stream = MockTcpStream {
+            read_data: head.as_bytes().to_vec(),
+            position: 0,
+            write_data: vec![],
+        };
+        let request = Server::handle_connection(&mut BufReader::new(&mut stream), &config).unwrap();
+        assert_eq!(request.skipped_headers(), ["Bad Header", "X-Id : 1"]);
+        assert_eq!(request.headers.get("Host"), Some(&"x".to_string()));
+    }
+
+    fn handle_encoded_body(
+        encoding: &str,
+        hex_body: &str,
+        config: &ServerConfig,
+    ) -> Result<HttpRequest, ApiErr> {
+        let body: Vec<u8> = (0..hex_body.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex_body[i..i + 2], 16).unwrap())
+            .collect();
+        let head = format!(
+            "POST / HTTP/1.1\r\nContent-Encoding: {encoding}\r\nContent-Length: {}\r\n\r\n",
+            body.len()
+        );
+        let mut stream = MockTcpStream {
+            read_data: [head.as_bytes(), &body].concat(),
+            position: 0,
+            write_data: vec![],
+        };
+        Server::handle_connection(&mut BufReader::new(&mut stream), config)
+    }
+
+    /// Parses a request with the body compressed with gzip
+    #[cfg(feature = "decompression")]
+    fn handle_gzip_body(content_type: &str, body: &[u8]) -> HttpRequest {
+        let body = crate::utils::deflate::gzip(body, 6);
+        let head = format!(
+            "POST / HTTP/1.1\r\nContent-Type: {content_type}\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n",
+            body.len()
+        );
+        let mut stream = MockTcpStream {
+            read_data: [head.as_bytes(), &body].concat(),
+            position: 0,
+            write_data: vec![],
+        };
+        Server::handle_connection(&mut BufReader::new(&mut stream), &ServerConfig::default())
+            .unwrap()
+    }
+
+    #[test]
+    #[cfg(all(feature = "decompression", feature = "msgpack"))]
+    fn binds_a_compressed_msgpack_body() {
+        use crate::msgpack::Binary;
+
+        // Bytes that aren't utf-8, lost if decoded through the text of the body
+        let body = b"\x92\x07\xc4\x03\xff\x00\x80";
+        let mut ctx = Context::new(Vec::new());
+        ctx.request = handle_gzip_body("application/msgpack", body);
+        assert_eq!(ctx.request.body_bytes(), body);
+        let (id, blob): (u8, Binary) = ctx.bind_msgpack().unwrap();
+        assert_eq!((id, blob), (7, Binary(vec![0xff, 0x00, 0x80])));
+    }
+
+    #[test]
+    #[cfg(feature = "decompression")]
+    fn sniffs_the_decompressed_body() {
+        use crate::sniff::BodySniffer;
+        use crate::utils::mock_stream::MockWriter;
+
+        let sniff = BodySniffer::new().middleware();
+        let mut ctx = Context::new(Vec::new());
+        ctx.request = handle_gzip_body("image/gif", b"GIF89a\x01\x00\x01\x00");
+        assert!(sniff(&mut ctx));
+
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.request = handle_gzip_body("image/png", b"<script>alert(1)</script>");
+        assert!(!sniff(&mut ctx));
+        assert!(writer.contents().starts_with("HTTP/1.1 415"));
+    }
+
+    /// "Hello" compressed with gzip
+    const GZIP_HELLO: &str = "1f8b0800000000000203f348cdc9c907008289d1f705000000";
+
+    #[test]
+    #[cfg(feature = "decompression")]
+    fn handle_message_with_compressed_body() {
+        let config = ServerConfig::default();
+        let request = handle_encoded_body("gzip", GZIP_HELLO, &config).unwrap();
+        assert_eq!(request.body, "Hello");
+        assert_eq!(request.body_bytes(), b"Hello");
+        assert_eq!(request.raw_body().len(), GZIP_HELLO.len() / 2);
+        assert_eq!(request.headers.get("Content-Encoding"), None);
+        assert_eq!(
+            request.headers.get("Content-Length"),
+            Some(&"5".to_string())
+        );
+
+        let request = handle_encoded_body("deflate", "789cf348cdc9c90700058c01f5", &config);
+        assert_eq!(request.unwrap().body, "Hello");
+        let request = handle_encoded_body("deflate", "f348cdc9c90700", &config);
+        assert_eq!(request.unwrap().body, "Hello");
+        let request = handle_encoded_body("identity, GZIP", GZIP_HELLO, &config);
+        assert_eq!(request.unwrap().body, "Hello");
+    }
+
+    #[test]
+    #[cfg(feature = "decompression")]
+    fn handle_message_with_invalid_compressed_body() {
+        let config = ServerConfig::default();
+        let err = handle_encoded_body("gzip", "789cf348cdc9c90700058c01f5", &config).unwrap_err();
+        assert_eq!(err.http_status(), HttpStatus::BadRequest);
+
+        // 2000 bytes once decompressed
+        let bomb = "1f8b08000000000002034b4c1c05a360148c8251300a46c1500700393e13a8d0070000";
+        let config = ServerConfig {
+            max_body_size: 1000,
+            ..ServerConfig::default()
+        };
+        let err = handle_encoded_body("gzip", bomb, &config).unwrap_err();
+        assert_eq!(err.http_status(), HttpStatus::PayloadTooLarge);
+    }
+
+    #[test]
+    fn handle_message_with_unsupported_encoding() {
+        let config = ServerConfig::default();
+        let err = handle_encoded_body("br", GZIP_HELLO, &config).unwrap_err();
+        assert_eq!(err.http_status(), HttpStatus::UnsupportedMediaType);
+        #[cfg(not(feature = "decompression"))]
+        {
+            let err = handle_encoded_body("gzip", GZIP_HELLO, &config).unwrap_err();
+            assert_eq!(err.http_status(), HttpStatus::UnsupportedMediaType);
+        }
+    }
+
+    fn request_for_host(host: Option<&str>) -> HttpRequest {
+        let mut headers = Headers::new();
+        if let Some(host) = host {
+            headers.insert("Host", host);
+        }
+        HttpRequest::new(HttpMethod::Get, "/".into(), headers, "".into())
+    }
+
+    #[test]
+    fn check_host_with_allowed_hosts() {
+        let config = ServerConfig {
+            allowed_hosts: vec!["example.com".into(), ".bücher.example".into()],
+            ..ServerConfig::default()
+        };
+        for host in [
+            "example.com",
+            "EXAMPLE.com:8080",
+            "xn--bcher-kva.example",
+            "shop.bücher.example",
+        ] {
+            let request = request_for_host(Some(host));
+            assert!(Server::check_host(&request, &config).is_ok(), "{host}");
+        }
+
+        for host in ["api.example.com", "evil.com", "notbücher.example"] {
+            let err = Server::check_host(&request_for_host(Some(host)), &config).unwrap_err();
+            assert_eq!(err.http_status(), HttpStatus::MisdirectedRequest, "{host}");
+        }
+        let err = Server::check_host(&request_for_host(None), &config).unwrap_err();
+        assert_eq!(err.http_status(), HttpStatus::BadRequest);
+        assert!(Server::check_host(&request_for_host(None), &ServerConfig::default()).is_ok());
+    }
+
+    #[test]
+    fn serve_connection_rejects_not_allowed_host() {
+        let (mut client, handle) = connect(ServerConfig {
+            allowed_hosts: vec!["localhost".into()],
+            ..ServerConfig::default()
+        });
+        client
+            .write_all(b"GET /ping HTTP/1.1\r\nHost: evil.com\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        handle.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 421 Misdirected Request"));
+        assert!(response.contains("Content-Type: application/json\r\n"));
+        assert!(response.ends_with(r#"{"message":"Host evil.com not allowed."}"#));
+    }
+
+    #[test]
+    fn serve_connection_renders_errors() {
+        let renderer = ErrorRenderer::new(|err| {
+            HttpResponse::new(err.http_status()).text(&format!("oops: {err}"))
+        });
+        let (mut client, handle) = connect(ServerConfig {
+            allowed_hosts: vec!["localhost".into()],
+            error_renderer: Some(renderer),
+            ..ServerConfig::default()
+        });
+        client
+            .write_all(b"GET /ping HTTP/1.1\r\nHost: evil.com\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        handle.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 421 Misdirected Request"));
+        assert!(response.ends_with("\r\n\r\noops: Host evil.com not allowed."));
+    }
+
+    #[test]
+    fn serve_connection_answers_parse_errors() {
+        let answer = |config: ServerConfig, request: &[u8]| {
+            let (mut client, handle) = connect(config);
+            client.write_all(request).unwrap();
+            let mut response = String::new();
+            client.read_to_string(&mut response).unwrap();
+            handle.join().unwrap();
+            response
+        };
+        let bad_header = b"GET /ping HTTP/1.1\r\nBad Header\r\n\r\n";
+        let response = answer(ServerConfig::default(), bad_header);
+        assert!(response.ends_with(r#"{"message":"Invalid header line `Bad Header`."}"#));
+
+        let generic = || ServerConfig {
+            parse_errors: ParseErrors::Generic,
+            ..ServerConfig::default()
+        };
+        let response = answer(generic(), bad_header);
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(response.ends_with(r#"{"message":"Bad Request."}"#));
+        let response = answer(generic(), &[b'a'; 40 * 1024]);
+        assert!(response.ends_with(r#"{"message":"Request Header Fields Too Large."}"#));
+
+        let smuggling =
+            b"POST /echo HTTP/1.1\r\nContent-Length: 1\r\nTransfer-Encoding: chunked\r\n\r\n";
+        let response = answer(generic(), smuggling);
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        let config = ServerConfig {
+            drop_malicious_requests: true,
+            ..generic()
+        };
+        assert_eq!(answer(config, smuggling), "");
+    }
+
+    #[test]
+    fn serve_connection_answers_panicking_handler() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let (mut client, handle) = connect_with_logger(ServerConfig::default(), Some(sender));
+        client
+            .write_all(b"GET /panic HTTP/1.1\r\nHost: localhost\r\n\r\nGET /ping HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        handle.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 500 Internal Server Error"));
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(response.ends_with(r#"{"message":"Internal server error."}"#));
+        let logs: Vec<String> = receiver.try_iter().collect();
+        assert!(logs.contains(&"Handler panicked: handler bug".to_string()));
+    }
+
+    #[test]
+    fn serve_connection_does_not_answer_closed_connection() {
+        let (mut client, handle) = connect(ServerConfig::default());
+        client.write_all(b"GET /ping HTTP/1.1\r\nHo").unwrap();
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        handle.join().unwrap();
+        assert_eq!(response, "");
+    }
+
+    #[test]
+    fn serve_connection_tarpits_tagged_requests() {
+        let (mut client, handle) = connect(ServerConfig {
+            rules: Rules::new(RuleSet::parse("tag=tarpit probe path ~ ^/wp-admin").unwrap()),
+            tarpit: Some(
+                Tarpit::new()
+                    .interval(Duration::from_millis(5))
+                    .max_duration(Duration::from_millis(300)),
+            ),
+            ..ServerConfig::default()
+        });
+        client
+            .write_all(b"GET /wp-admin HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        // The worker is done with the connection while the client is still waiting
+        handle.join().unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"));
+    }
+
+    #[test]
+    fn serve_connection_writes_scrubbed_access_log() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let config = ServerConfig {
+            access_log: true,
+            ..ServerConfig::default()
+        };
+        let (mut client, handle) = connect_with_logger(config, Some(sender));
+        client
+            .write_all(b"GET /ping?token=abc HTTP/1.1\r\nCookie: id=1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        handle.join().unwrap();
+        assert_eq!(
+            receiver.recv().unwrap(),
+            "127.0.0.1 GET /ping?token=[REDACTED] [Cookie: [REDACTED], Connection: close]"
+        );
+    }
+
+    #[test]
+    fn serve_connection_writes_curl_access_log() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let config = ServerConfig {
+            access_log: true,
+            access_log_format: AccessLogFormat::Curl,
+            ..ServerConfig::default()
+        };
+        let (mut client, handle) = connect_with_logger(config, Some(sender));
+        client
+            .write_all(b"GET /ping HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        handle.join().unwrap();
+        assert_eq!(
+            receiver.recv().unwrap(),
+            "curl 'http://localhost/ping' -H 'Connection: close' # from 127.0.0.1"
+        );
+    }
+
+    #[test]
+    fn serve_connection_answers_tls_handshake_with_alert() {
+        let (mut client, handle) = connect(ServerConfig::default());
+        client
+            .write_all(&[0x16, 0x03, 0x01, 0x00, 0xc8, 0x01])
+            .unwrap();
+        let mut response = Vec::new();
+        client.read_to_end(&mut response).unwrap();
+        handle.join().unwrap();
+        assert_eq!(response, TLS_PROTOCOL_VERSION_ALERT);
+    }
+
+    #[test]
+    fn serve_connection_sets_remote_addr() {
+        let (mut client, handle) = connect(ServerConfig::default());
+        client
+            .write_all(b"GET /whoami HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        handle.join().unwrap();
+        let local_addr = client.local_addr().unwrap().to_string();
+        assert!(response.ends_with(&local_addr));
+    }
+
+    #[test]
+    fn serve_connection_keeps_connection_alive() {
+        let (mut client, handle) = connect(ServerConfig::default());
+        client
+            .write_all(b"GET /ping HTTP/1.1\r\n\r\nGET /ping HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        handle.join().unwrap();
+        assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 2);
+        assert_eq!(response.matches("Connection: keep-alive").count(), 1);
+        assert_eq!(response.matches("Connection: close").count(), 1);
+    }
+
+    #[test]
+    fn serve_connection_closes_after_max_requests() {
+        let config = ServerConfig {
+            max_requests_per_connection: 2,
+            ..ServerConfig::default()
+        };
+        let (mut client, handle) = connect(config);
+        client
+            .write_all(
+                b"GET /ping HTTP/1.1\r\n\r\nGET /ping HTTP/1.1\r\n\r\nGET /ping HTTP/1.1\r\n\r\n",
+            )
+            .unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        handle.join().unwrap();
+        assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 2);
+        assert_eq!(response.matches("Connection: keep-alive").count(), 1);
+        assert_eq!(response.matches("Connection: close").count(), 1);
+    }
+
+    #[test]
+    fn serve_connection_reads_chunked_body() {
+        let (mut client, handle) = connect(ServerConfig::default());
+        let upload = "POST /echo HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n";
+        let chunks = "5;name=a\r\nGET /\r\n3\r\nabc\r\n0\r\nX-Sum: 1\r\n\r\n";
+        let last = "GET /ping HTTP/1.1\r\nConnection: close\r\n\r\n";
+        client
+            .write_all(format!("{upload}{chunks}{last}").as_bytes())
+            .unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        handle.join().unwrap();
+        assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 2);
+        assert!(response.contains("\r\n\r\nGET /abcHTTP/1.1 200 OK"));
+        assert!(response.ends_with("\r\n\r\npong"));
+    }
+
+    #[test]
+    fn serve_connection_rejects_chunked_body_over_max_body_size() {
+        let config = ServerConfig {
+            max_body_size: 2,
+            ..ServerConfig::default()
+        };
+        let (mut client, handle) = connect(config);
+        let upload =
+            "POST /echo HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n";
+        client
+            .write_all(format!("{upload}GET /ping HTTP/1.1\r\n\r\n").as_bytes())
+            .unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        handle.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+        assert!(!response.contains("pong"));
+    }
+
+    #[test]
+    fn serve_connection_closes_idle_connection() {
+        let config = ServerConfig {
+            keep_alive_timeout: Duration::from_millis(100),
+            ..ServerConfig::default()
+        };
+        let (mut client, handle) = connect(config);
+        client.write_all(b"GET /ping HTTP/1.1\r\n\r\n").unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        handle.join().unwrap();
+        assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 1);
+        assert!(response.contains("Connection: keep-alive"));
+    }
+
+    #[test]
+    fn serve_connection_times_out_slow_headers() {
+        let config = ServerConfig {
+            header_read_timeout: Some(Duration::from_millis(200)),
+            ..ServerConfig::default()
+        };
+        let (mut client, handle) = connect(config);
+        // Trickle the head so every single read arrives well within the deadline
+        for byte in b"GET /ping HTTP/1.1\r\n" {
+            if client.write_all(&[*byte]).is_err() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        handle.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout"));
+    }
+
+    #[test]
+    fn serve_connection_times_out_slow_body() {
+        let config = ServerConfig {
+            body_read_timeout: Some(Duration::from_millis(100)),
+            ..ServerConfig::default()
+        };
+        let (mut client, handle) = connect(config);
+        client
+            .write_all(b"GET /ping HTTP/1.1\r\nContent-Length: 10\r\n\r\nabc")
+            .unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        handle.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout"));
+    }
+
+    #[test]
+    fn serve_connection_sends_100_continue() {
+        let (mut client, handle) = connect(ServerConfig::default());
+        client
+            .write_all(b"POST /echo HTTP/1.1\r\nContent-Length: 5\r\nExpect: 100-continue\r\nConnection: close\r\n\r\n")
+            .unwrap();
+
+        let mut interim = [0; 25];
+        client.read_exact(&mut interim).unwrap();
+        assert_eq!(&interim, b"HTTP/1.1 100 Continue\r\n\r\n");
+
+        client.write_all(b"Hello").unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        handle.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("\r\n\r\nHello"));
+    }
+
+    #[test]
+    fn serve_connection_sends_100_continue_for_chunked_body() {
+        let (mut client, handle) = connect(ServerConfig::default());
+        client
+            .write_all(b"POST /echo HTTP/1.1\r\nTransfer-Encoding: chunked\r\nExpect: 100-continue\r\nConnection: close\r\n\r\n")
+            .unwrap();
+
+        let mut interim = [0; 25];
+        client.read_exact(&mut interim).unwrap();
+        assert_eq!(&interim, b"HTTP/1.1 100 Continue\r\n\r\n");
+
+        client.write_all(b"5\r\nHello\r\n0\r\n\r\n").unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        handle.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("\r\n\r\nHello"));
+    }
+
+    #[test]
+    fn serve_connection_rejects_too_large_body_before_continue() {
+        let config = ServerConfig {
+            max_body_size: 4,
+            ..ServerConfig::default()
+        };
+        let (mut client, handle) = connect(config);
+        client
+            .write_all(b"POST /echo HTTP/1.1\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\n")
+            .unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        handle.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large"));
+        assert!(!response.contains("100 Continue"));
+    }
+
+    #[test]
+    fn serve_connection_rejects_unknown_expectation() {
+        let (mut client, handle) = connect(ServerConfig::default());
+        client
+            .write_all(b"POST /echo HTTP/1.1\r\nContent-Length: 5\r\nExpect: something\r\n\r\n")
+            .unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        handle.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 417 Expectation Failed"));
+    }
+
+    #[test]
+    fn handle_message_with_http_1_0() {
+        let bytes = b"GET / HTTP/1.0\r\n\r\n";
+        let mut stream = MockTcpStream {
+            read_data: bytes.to_vec(),
+            position: 0,
+            write_data: vec![],
+        };
+
+        let request =
+            Server::handle_connection(&mut BufReader::new(&mut stream), &ServerConfig::default())
+                .unwrap();
+        assert_eq!(request.version, HttpVersion::Http10);
+        assert!(!request.keep_alive());
+    }
+
+    #[test]
+    fn handle_message_with_invalid_request_line() {
+        let lines: [&[u8]; 5] = [
+            b"GET /\r\n\r\n",
+            b"GET / HTTP/1.1 extra\r\n\r\n",
+            b"GET  / HTTP/1.1\r\n\r\n",
+            b"GET / FOO/1.1\r\n\r\n",
+            b"GET / HTTP/1.1.1\r\n\r\n",
+        ];
+        for bytes in lines {
+            let mut stream = MockTcpStream {
+                read_data: bytes.to_vec(),
+                position: 0,
+                write_data: vec![],
+            };
+
+            let err = Server::handle_connection(
+                &mut BufReader::new(&mut stream),
+                &ServerConfig::default(),
+            )
+            .unwrap_err();
+            assert_eq!(err.http_status(), HttpStatus::BadRequest);
+        }
+    }
+
+    #[test]
+    fn handle_message_with_unsupported_version() {
+        let bytes = b"GET / HTTP/2.0\r\n\r\n";
+        let mut stream = MockTcpStream {
+            read_data: bytes.to_vec(),
+            position: 0,
+            write_data: vec![],
+        };
+
+        let err =
+            Server::handle_connection(&mut BufReader::new(&mut stream), &ServerConfig::default())
+                .unwrap_err();
+        assert_eq!(err.http_status(), HttpStatus::HttpVersionNotSupported);
+    }
+
+    #[test]
+    fn serve_connection_closes_http_1_0_connection() {
+        let (mut client, handle) = connect(ServerConfig::default());
+        client.write_all(b"GET /ping HTTP/1.0\r\n\r\n").unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        handle.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("Connection: close"));
+    }
 }
@@ -0,0 +1,56 @@
+//! Graceful shutdown on `SIGINT` and `SIGTERM`. With the `signals` feature,
+//! [`Server::start`](crate::server::Server::start) stops accepting connections when
+//! one arrives and returns once the requests being served finish, instead of the
+//! process being killed in the middle of a response.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+const SIGINT: i32 = 2;
+const SIGTERM: i32 = 15;
+/// Returned by `signal` when the handler can't be installed
+const SIG_ERR: usize = usize::MAX;
+
+extern "C" {
+    fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+}
+
+/// Set once a signal arrives, a handler can't do much more
+static RECEIVED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_signal(_: i32) {
+    RECEIVED.store(true, Ordering::SeqCst);
+}
+
+/// Installs the handlers of `SIGINT` and `SIGTERM`
+pub(crate) fn install() -> io::Result<()> {
+    for signum in [SIGINT, SIGTERM] {
+        // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+        if unsafe { signal(signum, on_signal) } == SIG_ERR {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Returns whether a `SIGINT` or `SIGTERM` arrived since the handlers were installed
+pub(crate) fn received() -> bool {
+    RECEIVED.load(Ordering::SeqCst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern "C" {
+        fn raise(signum: i32) -> i32;
+    }
+
+    #[test]
+    fn test_received() {
+        install().unwrap();
+        assert!(!received());
+        assert_eq!(unsafe { raise(SIGTERM) }, 0);
+        assert!(received());
+    }
+}
@@ -0,0 +1,154 @@
+//! Checks that request bodies look like the `Content-Type` they declare, by their
+//! leading bytes, so an upload route storing files can't be tricked into keeping an
+//! html page declared as an image and serving it back with that type later.
+
+use crate::api_err::ApiErr;
+use crate::context::Context;
+
+/// Leading bytes of the binary formats, by media type
+const SIGNATURES: &[(&str, &[&[u8]])] = &[
+    ("image/png", &[b"\x89PNG\r\n\x1a\n"]),
+    ("image/jpeg", &[b"\xff\xd8\xff"]),
+    ("image/gif", &[b"GIF87a", b"GIF89a"]),
+    ("image/bmp", &[b"BM"]),
+    ("image/tiff", &[b"II*\x00", b"MM\x00*"]),
+    ("image/x-icon", &[b"\x00\x00\x01\x00"]),
+    ("image/vnd.microsoft.icon", &[b"\x00\x00\x01\x00"]),
+    ("application/pdf", &[b"%PDF-"]),
+    ("application/zip", &[b"PK\x03\x04", b"PK\x05\x06"]),
+    ("application/gzip", &[b"\x1f\x8b"]),
+    ("application/wasm", &[b"\x00asm"]),
+];
+
+/// Returns whether the body looks like the media type, or `None` if the type has no
+/// known signature. Empty bodies always match
+/// # Example
+/// ```
+/// use HTTP_Server::sniff;
+///
+/// assert_eq!(sniff::matches("image/png", b"\x89PNG\r\n\x1a\n..."), Some(true));
+/// assert_eq!(sniff::matches("image/png", b"<html><script>"), Some(false));
+/// assert_eq!(sniff::matches("application/json", b" {\"id\": 1}"), Some(true));
+/// assert_eq!(sniff::matches("text/plain", b"hello"), None);
+/// ```
+pub fn matches(media_type: &str, body: &[u8]) -> Option<bool> {
+    if body.is_empty() {
+        return Some(true);
+    }
+    let media_type = media_type.to_ascii_lowercase();
+    if let Some((_, signatures)) = SIGNATURES.iter().find(|(t, _)| *t == media_type) {
+        return Some(signatures.iter().any(|s| body.starts_with(s)));
+    }
+    let text = body.strip_prefix(b"\xef\xbb\xbf").unwrap_or(body);
+    let first = text.iter().find(|b| !b.is_ascii_whitespace());
+    match media_type.as_str() {
+        "image/webp" => Some(body.starts_with(b"RIFF") && body.get(8..12) == Some(b"WEBP")),
+        "video/mp4" | "image/avif" | "image/heic" => Some(body.get(4..8) == Some(b"ftyp")),
+        "application/json" => Some(matches!(first, Some(b'{' | b'['))),
+        t if t.ends_with("+json") => Some(matches!(first, Some(b'{' | b'['))),
+        "image/svg+xml" | "application/xml" | "text/xml" => Some(first == Some(&b'<')),
+        _ => None,
+    }
+}
+
+/// Rejects the bodies that don't look like their `Content-Type` with
+/// `415 Unsupported Media Type`, meant for the routes of uploads
+/// # Example
+/// ```
+/// use HTTP_Server::context::Context;
+/// use HTTP_Server::http_status::HttpStatus;
+/// use HTTP_Server::router::Router;
+/// use HTTP_Server::sniff::BodySniffer;
+///
+/// fn upload(ctx: &mut Context) {
+///     ctx.string(HttpStatus::Created, "Stored");
+/// }
+///
+/// let mut router = Router::new();
+/// router
+///     .post("/avatars", upload)
+///     .middleware(BodySniffer::new().strict(true).middleware());
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BodySniffer {
+    strict: bool,
+}
+
+impl BodySniffer {
+    pub fn new() -> BodySniffer {
+        BodySniffer::default()
+    }
+
+    /// Also reject the bodies without a `Content-Type` or whose type has no known
+    /// signature, so only types that can be checked get through
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Returns whether the body of the request can be let through
+    pub fn accepts(&self, media_type: Option<&str>, body: &[u8]) -> bool {
+        let media_type = media_type.map(|t| t.split(';').next().unwrap_or_default().trim());
+        match media_type.map(|t| matches(t, body)) {
+            Some(Some(matches)) => matches,
+            _ => !self.strict || body.is_empty(),
+        }
+    }
+
+    /// Returns a middleware that rejects the requests whose body doesn't pass
+    pub fn middleware(self) -> impl Fn(&mut Context) -> bool + Send + Sync + 'static {
+        move |ctx: &mut Context| {
+            let media_type = ctx.request.headers.get("Content-Type").cloned();
+            if self.accepts(media_type.as_deref(), ctx.request.body_bytes()) {
+                return true;
+            }
+            let declared = media_type.unwrap_or_else(|| "no type".to_string());
+            ctx.send_error(&ApiErr::ContentTypeMismatch(declared));
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        assert_eq!(matches("IMAGE/GIF", b"GIF89a\x01\x00"), Some(true));
+        assert_eq!(matches("image/jpeg", b"\x89PNG\r\n\x1a\n"), Some(false));
+        assert_eq!(
+            matches("image/webp", b"RIFF\x10\x00\x00\x00WEBPVP8 "),
+            Some(true)
+        );
+        assert_eq!(
+            matches("image/webp", b"RIFF\x10\x00\x00\x00WAVE"),
+            Some(false)
+        );
+        assert_eq!(
+            matches("video/mp4", b"\x00\x00\x00\x20ftypisom"),
+            Some(true)
+        );
+        assert_eq!(matches("application/json", b"\xef\xbb\xbf[1]"), Some(true));
+        assert_eq!(matches("application/json", b"<html>"), Some(false));
+        assert_eq!(matches("application/merge-patch+json", b"{}"), Some(true));
+        assert_eq!(matches("image/svg+xml", b"\n<svg>"), Some(true));
+        assert_eq!(matches("image/png", b""), Some(true));
+        assert_eq!(matches("application/octet-stream", b"\x00"), None);
+    }
+
+    #[test]
+    fn test_accepts() {
+        let lenient = BodySniffer::new();
+        let strict = BodySniffer::new().strict(true);
+        for sniffer in [lenient, strict] {
+            assert!(sniffer.accepts(Some("image/png; name=a"), b"\x89PNG\r\n\x1a\n"));
+            assert!(!sniffer.accepts(Some("image/png"), b"<script>"));
+            assert!(sniffer.accepts(None, b""));
+        }
+        assert!(lenient.accepts(Some("text/plain"), b"hi"));
+        assert!(lenient.accepts(None, b"hi"));
+        assert!(!strict.accepts(Some("text/plain"), b"hi"));
+        assert!(!strict.accepts(None, b"hi"));
+    }
+}
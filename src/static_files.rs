@@ -0,0 +1,278 @@
+use crate::context::{escape_html, Context};
+use crate::http_status::HttpStatus;
+#[cfg(feature = "markdown")]
+use crate::markdown::Markdown;
+use crate::utils::percent;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Serves the files under a directory, see [`Router::static_dir`](crate::router::Router::static_dir).
+///
+/// Request paths can't leave the directory: segments like `..` get a `404 Not Found`
+/// and so do symlinks pointing outside of it.
+/// A directory is answered with its index file if it has one, or with a listing of
+/// its entries if listings are enabled. Hidden files, starting with a dot, aren't served.
+/// # Example
+/// ```
+/// use HTTP_Server::router::Router;
+/// use HTTP_Server::static_files::StaticDir;
+///
+/// let mut router = Router::new();
+/// router.static_dir("/assets", "./public");
+/// router.mount_static("/downloads", StaticDir::new("./downloads").index(None).listing(true));
+/// ```
+#[derive(Debug, Clone)]
+pub struct StaticDir {
+    root: PathBuf,
+    index: Option<String>,
+    listing: bool,
+    #[cfg(feature = "markdown")]
+    markdown: Option<Markdown>,
+}
+
+impl StaticDir {
+    /// Serve the directory with `index.html` as the index file and no listings
+    pub fn new<P: Into<PathBuf>>(root: P) -> StaticDir {
+        StaticDir {
+            root: root.into(),
+            index: Some("index.html".to_string()),
+            listing: false,
+            #[cfg(feature = "markdown")]
+            markdown: None,
+        }
+    }
+
+    /// Set the file served for the directories, `None` to never serve one
+    pub fn index(mut self, index: Option<&str>) -> Self {
+        self.index = index.map(|i| i.to_string());
+        self
+    }
+
+    /// Whether directories without an index file are answered with a listing
+    pub fn listing(mut self, listing: bool) -> Self {
+        self.listing = listing;
+        self
+    }
+
+    /// Render the `.md` files to html pages. They are also served without the extension,
+    /// `/guide` serves `guide.md`, and a directory's `index.md` comes before its index file
+    #[cfg(feature = "markdown")]
+    pub fn markdown(mut self, markdown: Markdown) -> Self {
+        self.markdown = Some(markdown);
+        self
+    }
+
+    /// Returns the Markdown file for the decoded segments: the file itself, the file
+    /// with the `.md` extension or the `index.md` of a directory
+    #[cfg(feature = "markdown")]
+    fn markdown_page(&self, segments: &[&str]) -> Option<PathBuf> {
+        let is_markdown = |path: &Path| path.is_file() && path.extension() == Some("md".as_ref());
+        if let Some(path) = self.resolve(segments) {
+            let page = match path.is_dir() {
+                true => self.resolve(&[segments, &["index.md"]].concat())?,
+                false => path,
+            };
+            return is_markdown(&page).then_some(page);
+        }
+        let (last, parent) = segments.split_last()?;
+        let page = self.resolve(&[parent, &[&format!("{last}.md")]].concat())?;
+        is_markdown(&page).then_some(page)
+    }
+
+    /// Returns the path of the file for the decoded segments under the mount point,
+    /// or `None` if it would be outside of the directory or hidden
+    fn resolve(&self, segments: &[&str]) -> Option<PathBuf> {
+        let mut path = self.root.clone();
+        for segment in segments.iter().filter(|s| !s.is_empty() && **s != ".") {
+            if segment.starts_with('.') || segment.contains(['/', '\\', '\0']) {
+                return None;
+            }
+            path.push(segment);
+        }
+        // Symlinks may point anywhere, so check where the path really is
+        let root = self.root.canonicalize().ok()?;
+        let path = path.canonicalize().ok()?;
+        let relative = path.strip_prefix(&root).ok()?;
+        let hidden = relative
+            .components()
+            .any(|c| c.as_os_str().to_string_lossy().starts_with('.'));
+        (!hidden).then_some(path)
+    }
+
+    /// Answers the request for the decoded segments under the mount point
+    pub(crate) fn serve(&self, ctx: &mut Context, segments: &[&str]) {
+        #[cfg(feature = "markdown")]
+        if let Some(markdown) = &self.markdown {
+            if let Some(page) = self.markdown_page(segments) {
+                return markdown.serve(ctx, &page);
+            }
+        }
+        let Some(path) = self.resolve(segments) else {
+            return ctx.string(HttpStatus::NotFound, "Not Found");
+        };
+        if !path.is_dir() {
+            return ctx.file(HttpStatus::Ok, path);
+        }
+        if let Some(index) = self.index.as_ref().map(|i| path.join(i)) {
+            if index.is_file() {
+                return ctx.file(HttpStatus::Ok, index);
+            }
+        }
+        match self.listing {
+            true => self.list(ctx, &path),
+            false => ctx.string(HttpStatus::NotFound, "Not Found"),
+        }
+    }
+
+    /// Answers with an html page linking to the entries of the directory
+    fn list(&self, ctx: &mut Context, dir: &Path) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return ctx.string(HttpStatus::Forbidden, "Forbidden");
+        };
+        let mut names: Vec<String> = entries
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let name = entry.file_name().into_string().ok()?;
+                let is_dir = entry.file_type().ok()?.is_dir();
+                (!name.starts_with('.')).then(|| if is_dir { name + "/" } else { name })
+            })
+            .collect();
+        names.sort();
+
+        let base = ctx.request.path().trim_end_matches('/').to_string();
+        let title = escape_html(&percent::decode(&base).unwrap_or_default());
+        let items: String = names
+            .iter()
+            .map(|name| {
+                let href = format!("{base}/{}", percent::encode(name.trim_end_matches('/')));
+                format!(
+                    "<li><a href=\"{}\">{}</a></li>",
+                    escape_html(&href),
+                    escape_html(name)
+                )
+            })
+            .collect();
+        ctx.html(
+            HttpStatus::Ok,
+            &format!(
+                "<!DOCTYPE html>\n<html><head><title>{title}/</title></head><body><h1>{title}/</h1><ul>{items}</ul></body></html>"
+            ),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::Headers;
+    use crate::http_method::HttpMethod;
+    use crate::http_request::HttpRequest;
+    use crate::router::Router;
+    use crate::utils::mock_stream::MockWriter;
+
+    fn public_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("static-{name}-{}", std::process::id()));
+        fs::create_dir_all(dir.join("public/docs")).unwrap();
+        fs::write(dir.join("public/index.html"), "<h1>home</h1>").unwrap();
+        fs::write(dir.join("public/app.js"), "run()").unwrap();
+        fs::write(dir.join("public/docs/a b.txt"), "a").unwrap();
+        fs::write(dir.join("public/.env"), "SECRET=1").unwrap();
+        fs::write(dir.join("secret.txt"), "secret").unwrap();
+        dir
+    }
+
+    fn get(router: &Router, path: &str) -> String {
+        let writer = MockWriter::default();
+        let mut ctx = Context::new(writer.clone());
+        ctx.request = HttpRequest::new(HttpMethod::Get, path.into(), Headers::new(), "".into());
+        router.handle_request(&mut ctx);
+        writer.contents()
+    }
+
+    #[test]
+    fn test_static_dir() {
+        let dir = public_dir("serve");
+        let mut router = Router::new();
+        router.static_dir("/assets", dir.join("public"));
+
+        let response = get(&router, "/assets/app.js");
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("Content-Type: text/javascript; charset=utf-8\r\n"));
+        assert!(response.ends_with("run()"));
+
+        assert!(get(&router, "/assets").ends_with("<h1>home</h1>"));
+        assert!(get(&router, "/assets/").ends_with("<h1>home</h1>"));
+        assert!(get(&router, "/assets/docs/a%20b.txt").ends_with("\r\n\r\na"));
+        // Directories without index aren't listed by default
+        assert!(get(&router, "/assets/docs").starts_with("HTTP/1.1 404"));
+        assert!(get(&router, "/other/app.js").starts_with("HTTP/1.1 404"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_static_dir_traversal() {
+        let dir = public_dir("traversal");
+        let mut router = Router::new();
+        router.static_dir("/assets", dir.join("public"));
+
+        for path in [
+            "/assets/../secret.txt",
+            "/assets/%2E%2E/secret.txt",
+            "/assets/docs/..%2F..%2Fsecret.txt",
+            "/assets/.env",
+            "/assets/..%5Csecret.txt",
+            "/assets/docs%2F..%2F.env",
+            "/assets/docs%2Fa%20b.txt",
+        ] {
+            assert!(get(&router, path).starts_with("HTTP/1.1 404"), "{path}");
+        }
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.join("secret.txt"), dir.join("public/link")).unwrap();
+            assert!(get(&router, "/assets/link").starts_with("HTTP/1.1 404"));
+            std::os::unix::fs::symlink(dir.join("public/.env"), dir.join("public/env")).unwrap();
+            assert!(get(&router, "/assets/env").starts_with("HTTP/1.1 404"));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "markdown")]
+    #[test]
+    fn test_static_dir_markdown() {
+        let dir = public_dir("markdown");
+        fs::write(dir.join("public/docs/index.md"), "# Docs").unwrap();
+        fs::write(dir.join("public/docs/guide.md"), "*Guide*").unwrap();
+        let mut router = Router::new();
+        router.mount_static(
+            "/files",
+            StaticDir::new(dir.join("public")).markdown(Markdown::new().template("{content}")),
+        );
+
+        let page = get(&router, "/files/docs/guide");
+        assert!(page.contains("Content-Type: text/html; charset=utf-8\r\n"));
+        assert!(page.ends_with("\r\n\r\n<p><em>Guide</em></p>\n"));
+        assert!(get(&router, "/files/docs/guide.md").ends_with("<em>Guide</em></p>\n"));
+        assert!(get(&router, "/files/docs/").ends_with("<h1 id=\"docs\">Docs</h1>\n"));
+        assert!(get(&router, "/files/app.js").ends_with("run()"));
+        assert!(get(&router, "/files/missing").starts_with("HTTP/1.1 404"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_static_dir_listing() {
+        let dir = public_dir("listing");
+        let mut router = Router::new();
+        router.mount_static(
+            "/files",
+            StaticDir::new(dir.join("public")).index(None).listing(true),
+        );
+
+        let listing = get(&router, "/files/");
+        assert!(listing.contains(r#"<a href="/files/docs">docs/</a>"#));
+        assert!(listing.contains(r#"<a href="/files/index.html">index.html</a>"#));
+        assert!(!listing.contains(".env"));
+        assert!(get(&router, "/files/docs").contains(r#"<a href="/files/docs/a%20b.txt">"#));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
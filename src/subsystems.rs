@@ -0,0 +1,134 @@
+//! Optional subsystems, like a GeoIP database or a metrics exporter, and what happens
+//! when they fail to start: either the server doesn't start, or it runs without them
+//! and reports itself as degraded.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+
+/// What happens when an optional subsystem fails to start
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FailurePolicy {
+    /// The error is returned, so the server doesn't start
+    #[default]
+    FailStartup,
+    /// The subsystem is disabled and the server runs degraded
+    Degrade,
+}
+
+/// A subsystem disabled because it failed to start
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Degraded {
+    pub name: String,
+    pub error: String,
+}
+
+/// Starts the optional subsystems following their [`FailurePolicy`] and keeps track of
+/// the ones that were disabled. The server logs them when it starts, and handlers can
+/// report them with [`Context::degraded`](crate::context::Context::degraded), like in a
+/// health check. Clones share the disabled subsystems.
+/// # Example
+/// ```
+/// use HTTP_Server::config::ServerConfig;
+/// use HTTP_Server::subsystems::{FailurePolicy, Subsystems};
+///
+/// let subsystems = Subsystems::new(FailurePolicy::FailStartup)
+///     .policy("geoip", FailurePolicy::Degrade);
+///
+/// let geoip = subsystems
+///     .start("geoip", || std::fs::read("/missing/GeoLite2-City.mmdb"))
+///     .unwrap();
+/// assert!(geoip.is_none());
+/// assert_eq!(subsystems.degraded()[0].name, "geoip");
+///
+/// let config = ServerConfig {
+///     subsystems,
+///     ..ServerConfig::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Subsystems {
+    default_policy: FailurePolicy,
+    policies: HashMap<String, FailurePolicy>,
+    degraded: Arc<Mutex<Vec<Degraded>>>,
+}
+
+impl Subsystems {
+    pub fn new(default_policy: FailurePolicy) -> Subsystems {
+        Subsystems {
+            default_policy,
+            ..Subsystems::default()
+        }
+    }
+
+    /// Set the policy of a subsystem, instead of the default one
+    pub fn policy(mut self, name: &str, policy: FailurePolicy) -> Self {
+        self.policies.insert(name.to_string(), policy);
+        self
+    }
+
+    /// Starts a subsystem. If it fails and its policy is `Degrade`, it's recorded as
+    /// degraded and `None` is returned, with `FailStartup` the error is returned
+    pub fn start<T, E, F>(&self, name: &str, start: F) -> Result<Option<T>, E>
+    where
+        E: Display,
+        F: FnOnce() -> Result<T, E>,
+    {
+        let policy = self.policies.get(name).unwrap_or(&self.default_policy);
+        match (start(), policy) {
+            (Ok(subsystem), _) => Ok(Some(subsystem)),
+            (Err(e), FailurePolicy::FailStartup) => Err(e),
+            (Err(e), FailurePolicy::Degrade) => {
+                let mut degraded = self.degraded.lock().unwrap_or_else(|e| e.into_inner());
+                degraded.retain(|d| d.name != name);
+                degraded.push(Degraded {
+                    name: name.to_string(),
+                    error: e.to_string(),
+                });
+                Ok(None)
+            }
+        }
+    }
+
+    /// Returns the subsystems disabled because they failed to start
+    pub fn degraded(&self) -> Vec<Degraded> {
+        let degraded = self.degraded.lock().unwrap_or_else(|e| e.into_inner());
+        degraded.clone()
+    }
+
+    /// Returns whether every subsystem started
+    pub fn is_healthy(&self) -> bool {
+        self.degraded().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_start() {
+        let subsystems = Subsystems::default().policy("metrics", FailurePolicy::Degrade);
+        assert_eq!(
+            subsystems.start("geoip", || Ok::<_, String>(1)),
+            Ok(Some(1))
+        );
+        assert_eq!(
+            subsystems.start("geoip", || Err::<u8, _>("missing".to_string())),
+            Err("missing".to_string())
+        );
+        assert!(subsystems.is_healthy());
+
+        let clone = subsystems.clone();
+        assert_eq!(clone.start("metrics", || Err::<u8, _>("refused")), Ok(None));
+        assert_eq!(clone.start("metrics", || Err::<u8, _>("timeout")), Ok(None));
+        assert!(!subsystems.is_healthy());
+        assert_eq!(
+            subsystems.degraded(),
+            [Degraded {
+                name: "metrics".to_string(),
+                error: "timeout".to_string(),
+            }]
+        );
+    }
+}
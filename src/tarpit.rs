@@ -0,0 +1,269 @@
+use crate::context::Context;
+use crate::proxy::IpRange;
+use std::io::{ErrorKind, Write};
+use std::net::{IpAddr, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Start of a response that never finishes, followed by `FILLER` forever
+const PRELUDE: &[u8] = b"HTTP/1.1 200 OK\r\n";
+const FILLER: &[u8] = b"X-Wait: please hold the line\r\n";
+
+/// Answers abusive clients extremely slowly, one byte every `interval`, wasting
+/// their time without holding a server worker. Trapped connections are handed to a
+/// small set of dedicated threads that drip to all of them, and closed after
+/// `max_duration`.
+///
+/// A connection is trapped when its client ip is in one of the ranges, or when a request
+/// gets the tarpit tag, which is `tarpit` by default and can be added with a rule like
+/// `tag=tarpit scanner path ~ ^/wp-admin`. See [`RuleSet`](crate::rules::RuleSet).
+///
+/// Clones share the same threads, which are started when the first connection is trapped.
+/// # Example
+/// ```
+/// use HTTP_Server::config::ServerConfig;
+/// use HTTP_Server::tarpit::Tarpit;
+/// use std::time::Duration;
+///
+/// let config = ServerConfig {
+///     tarpit: Some(
+///         Tarpit::new()
+///             .ip("203.0.113.0/24".parse().unwrap())
+///             .interval(Duration::from_secs(5)),
+///     ),
+///     ..ServerConfig::default()
+/// };
+/// ```
+#[derive(Debug, Clone)]
+pub struct Tarpit {
+    ips: Vec<IpRange>,
+    tag: String,
+    interval: Duration,
+    max_duration: Duration,
+    workers: usize,
+    /// Maximum number of connections trapped by each worker, more are closed right away
+    max_connections: usize,
+    senders: Arc<OnceLock<Vec<Sender<TcpStream>>>>,
+    next_worker: Arc<AtomicUsize>,
+}
+
+impl Default for Tarpit {
+    fn default() -> Self {
+        Tarpit {
+            ips: Vec::new(),
+            tag: "tarpit".to_string(),
+            interval: Duration::from_secs(10),
+            max_duration: Duration::from_secs(10 * 60),
+            workers: 1,
+            max_connections: 1024,
+            senders: Arc::new(OnceLock::new()),
+            next_worker: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+struct Trapped {
+    stream: TcpStream,
+    since: Instant,
+    next_drip: Instant,
+    sent: usize,
+}
+
+impl Trapped {
+    /// Writes the next byte, returning false if the connection is gone
+    fn drip(&mut self) -> bool {
+        let byte = match PRELUDE.get(self.sent) {
+            Some(byte) => *byte,
+            None => FILLER[(self.sent - PRELUDE.len()) % FILLER.len()],
+        };
+        match self.stream.write(&[byte]) {
+            Ok(1) => {
+                self.sent += 1;
+                true
+            }
+            // The client isn't even reading, which is fine
+            Err(e) if e.kind() == ErrorKind::WouldBlock => true,
+            _ => false,
+        }
+    }
+}
+
+impl Tarpit {
+    pub fn new() -> Tarpit {
+        Tarpit::default()
+    }
+
+    /// Trap the connections of the clients in the range
+    pub fn ip(mut self, range: IpRange) -> Self {
+        self.ips.push(range);
+        self
+    }
+
+    /// Trap the requests with the tag
+    pub fn tag(mut self, tag: &str) -> Self {
+        self.tag = tag.to_string();
+        self
+    }
+
+    /// Time between each byte sent
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Time after which a trapped connection is closed
+    pub fn max_duration(mut self, max_duration: Duration) -> Self {
+        self.max_duration = max_duration;
+        self
+    }
+
+    /// Number of threads dripping to the trapped connections, at least 1
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// Maximum number of connections trapped by each worker
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections;
+        self
+    }
+
+    /// Returns whether the ip is in one of the trapped ranges
+    pub fn traps_ip(&self, ip: IpAddr) -> bool {
+        self.ips.iter().any(|range| range.contains(ip))
+    }
+
+    /// Returns whether the request of the context should be trapped
+    pub fn traps(&self, ctx: &Context) -> bool {
+        ctx.has_tag(&self.tag) || ctx.client_ip().is_some_and(|ip| self.traps_ip(ip))
+    }
+
+    /// Hands the connection to one of the tarpit workers
+    pub fn trap(&self, stream: TcpStream) {
+        if stream.set_nonblocking(true).is_err() {
+            return;
+        }
+        let senders = self.senders.get_or_init(|| {
+            (0..self.workers)
+                .map(|_| {
+                    let (sender, receiver) = mpsc::channel();
+                    let tarpit = self.clone();
+                    thread::spawn(move || tarpit.drip(receiver));
+                    sender
+                })
+                .collect()
+        });
+        let worker = self.next_worker.fetch_add(1, Ordering::Relaxed) % senders.len();
+        _ = senders[worker].send(stream);
+    }
+
+    fn drip(&self, receiver: Receiver<TcpStream>) {
+        let mut trapped: Vec<Trapped> = Vec::new();
+        loop {
+            let now = Instant::now();
+            let wait = trapped
+                .iter()
+                .map(|t| t.next_drip.saturating_duration_since(now))
+                .min()
+                .unwrap_or(self.interval);
+            match receiver.recv_timeout(wait) {
+                Ok(stream) if trapped.len() < self.max_connections => {
+                    let now = Instant::now();
+                    trapped.push(Trapped {
+                        stream,
+                        since: now,
+                        next_drip: now,
+                        sent: 0,
+                    });
+                }
+                // Dropping the stream closes the connection
+                Ok(_) | Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+
+            let now = Instant::now();
+            trapped.retain_mut(|t| {
+                if now.duration_since(t.since) >= self.max_duration {
+                    return false;
+                }
+                if now < t.next_drip {
+                    return true;
+                }
+                t.next_drip = now + self.interval;
+                t.drip()
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headers::Headers;
+    use crate::http_method::HttpMethod;
+    use crate::http_request::HttpRequest;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    /// Returns both ends of a local connection
+    fn connection() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (server, client)
+    }
+
+    #[test]
+    fn test_traps() {
+        let tarpit = Tarpit::new().ip("10.0.0.0/8".parse().unwrap());
+        assert!(tarpit.traps_ip("10.1.2.3".parse().unwrap()));
+        assert!(!tarpit.traps_ip("192.168.0.1".parse().unwrap()));
+
+        let mut ctx = Context::new(Vec::new());
+        ctx.request = HttpRequest::new(HttpMethod::Get, "/".into(), Headers::new(), "".into());
+        ctx.remote_addr = Some("192.168.0.1:4000".parse().unwrap());
+        assert!(!tarpit.traps(&ctx));
+        ctx.tags.push("tarpit".to_string());
+        assert!(tarpit.traps(&ctx));
+
+        ctx.remote_addr = Some("10.0.0.1:4000".parse().unwrap());
+        ctx.tags.clear();
+        assert!(tarpit.traps(&ctx));
+    }
+
+    #[test]
+    fn test_trap_drips_until_max_duration() {
+        let tarpit = Tarpit::new()
+            .interval(Duration::from_millis(5))
+            .max_duration(Duration::from_millis(200));
+        let (server, mut client) = connection();
+        tarpit.trap(server);
+
+        let mut start = [0; 9];
+        client.read_exact(&mut start).unwrap();
+        assert_eq!(&start, b"HTTP/1.1 ");
+
+        // The connection is closed once the max duration is reached
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).unwrap();
+        assert!(rest.len() < 100);
+    }
+
+    #[test]
+    fn test_trap_over_max_connections() {
+        let tarpit = Tarpit::new().max_connections(0);
+        let (server, mut client) = connection();
+        tarpit.trap(server);
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(client.read(&mut [0; 1]).unwrap(), 0);
+    }
+}
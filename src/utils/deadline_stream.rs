@@ -0,0 +1,94 @@
+use std::io;
+use std::io::Read;
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+/// A `TcpStream` whose reads fail once a deadline has passed,
+/// no matter how many reads were needed until then.
+/// Unlike a plain read timeout, a client trickling one byte at a time can't keep it alive.
+pub struct DeadlineStream {
+    stream: TcpStream,
+    deadline: Option<Instant>,
+}
+
+impl DeadlineStream {
+    pub fn new(stream: TcpStream) -> DeadlineStream {
+        DeadlineStream {
+            stream,
+            deadline: None,
+        }
+    }
+
+    /// Sets the deadline `timeout` from now, or removes it with `None`.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.deadline = timeout.map(|timeout| Instant::now() + timeout);
+    }
+
+    pub fn get_ref(&self) -> &TcpStream {
+        &self.stream
+    }
+}
+
+impl Read for DeadlineStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let timeout = match self.deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "read deadline exceeded",
+                    ));
+                }
+                Some(remaining)
+            }
+            None => None,
+        };
+        self.stream.set_read_timeout(timeout)?;
+        self.stream.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    fn pair() -> (TcpStream, DeadlineStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (client, DeadlineStream::new(server))
+    }
+
+    #[test]
+    fn test_read_before_deadline() {
+        let (mut client, mut stream) = pair();
+        stream.set_timeout(Some(Duration::from_secs(5)));
+        client.write_all(b"hi").unwrap();
+
+        let mut buf = [0; 2];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hi");
+    }
+
+    #[test]
+    fn test_read_after_deadline() {
+        let (_client, mut stream) = pair();
+        stream.set_timeout(Some(Duration::from_millis(50)));
+
+        let start = Instant::now();
+        let mut buf = [0; 1];
+        let err = stream.read(&mut buf).unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        ));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(
+            stream.read(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+    }
+}
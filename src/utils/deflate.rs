@@ -0,0 +1,261 @@
+//! Compression into deflate ([RFC 1951](https://www.rfc-editor.org/rfc/rfc1951)) streams
+//! and their zlib and gzip wrappers, the counterpart of [`inflate`](super::inflate).
+//! Matches are searched with hash chains over a 32 KiB window and encoded with the
+//! fixed Huffman codes, which is fast and good enough for text like html and json.
+//! The level, from 1 to 9, sets how long the chains searched are, trading speed for size.
+
+use super::checksum::{adler32, crc32};
+use super::inflate::{DIST_BASE, DIST_EXTRA, LENGTH_BASE, LENGTH_EXTRA};
+
+const WINDOW_SIZE: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// Most positions compared while looking for the longest match, for each level
+const MAX_CHAIN: [usize; 10] = [1, 4, 8, 16, 32, 48, 64, 128, 256, 1024];
+/// Level used by the content codings when none is given
+pub const DEFAULT_LEVEL: u8 = 6;
+const HASH_BITS: u32 = 15;
+const END_OF_BLOCK: u16 = 256;
+
+struct BitWriter {
+    output: Vec<u8>,
+    buffer: u32,
+    count: u8,
+}
+
+impl BitWriter {
+    /// Writes the low `count` bits of the value, least significant first
+    fn bits(&mut self, value: u32, count: u8) {
+        for i in 0..count {
+            self.buffer |= ((value >> i) & 1) << self.count;
+            self.count += 1;
+            if self.count == 8 {
+                self.output.push(self.buffer as u8);
+                self.buffer = 0;
+                self.count = 0;
+            }
+        }
+    }
+
+    /// Writes a Huffman code, which goes most significant bit first
+    fn code(&mut self, code: u32, length: u8) {
+        let reversed = code.reverse_bits() >> (32 - length);
+        self.bits(reversed, length);
+    }
+
+    /// Writes a literal, length or end of block symbol with the fixed codes
+    fn symbol(&mut self, symbol: u16) {
+        let symbol = symbol as u32;
+        match symbol {
+            0..=143 => self.code(0x30 + symbol, 8),
+            144..=255 => self.code(0x190 + symbol - 144, 9),
+            256..=279 => self.code(symbol - 256, 7),
+            _ => self.code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn length(&mut self, length: usize) {
+        let index = LENGTH_BASE
+            .iter()
+            .rposition(|base| *base as usize <= length)
+            .unwrap();
+        self.symbol(257 + index as u16);
+        self.bits(
+            (length - LENGTH_BASE[index] as usize) as u32,
+            LENGTH_EXTRA[index],
+        );
+    }
+
+    fn distance(&mut self, distance: usize) {
+        let index = DIST_BASE
+            .iter()
+            .rposition(|base| *base as usize <= distance)
+            .unwrap();
+        // Distance codes are all 5 bits long
+        self.code(index as u32, 5);
+        self.bits(
+            (distance - DIST_BASE[index] as usize) as u32,
+            DIST_EXTRA[index],
+        );
+    }
+
+    /// Pads the last byte with zeros and returns the output
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.output.push(self.buffer as u8);
+        }
+        self.output
+    }
+}
+
+fn hash(data: &[u8]) -> usize {
+    let value = (data[0] as u32) << 16 | (data[1] as u32) << 8 | data[2] as u32;
+    (value.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+/// Compresses the data into raw deflate, as a single block.
+/// Levels above 9 are the same as 9
+pub fn deflate(data: &[u8], level: u8) -> Vec<u8> {
+    let max_chain = MAX_CHAIN[level.min(9) as usize];
+    let mut writer = BitWriter {
+        output: Vec::with_capacity(data.len() / 2),
+        buffer: 0,
+        count: 0,
+    };
+    // Last block, fixed Huffman codes
+    writer.bits(1, 1);
+    writer.bits(1, 2);
+
+    // Position + 1 of the last occurrence of each hash and of the previous one
+    // with the same hash, so 0 means none
+    let mut head = vec![0usize; 1 << HASH_BITS];
+    let mut previous = vec![0usize; WINDOW_SIZE];
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let (length, distance) = longest_match(data, pos, &head, &previous, max_chain);
+        if length >= MIN_MATCH {
+            writer.length(length);
+            writer.distance(distance);
+            for p in pos..pos + length {
+                insert(data, p, &mut head, &mut previous);
+            }
+            pos += length;
+        } else {
+            writer.symbol(data[pos] as u16);
+            insert(data, pos, &mut head, &mut previous);
+            pos += 1;
+        }
+    }
+    writer.symbol(END_OF_BLOCK);
+    writer.finish()
+}
+
+/// Adds the position to the chain of its hash
+fn insert(data: &[u8], pos: usize, head: &mut [usize], previous: &mut [usize]) {
+    if pos + MIN_MATCH <= data.len() {
+        let h = hash(&data[pos..]);
+        previous[pos % WINDOW_SIZE] = head[h];
+        head[h] = pos + 1;
+    }
+}
+
+/// Returns the length and distance of the longest earlier match of the data at the position
+fn longest_match(
+    data: &[u8],
+    pos: usize,
+    head: &[usize],
+    previous: &[usize],
+    max_chain: usize,
+) -> (usize, usize) {
+    if pos + MIN_MATCH > data.len() {
+        return (0, 0);
+    }
+    let max = MAX_MATCH.min(data.len() - pos);
+    let (mut best_length, mut best_distance) = (0, 0);
+    let mut candidate = head[hash(&data[pos..])];
+    for _ in 0..max_chain {
+        // Stop at the end of the chain or once it leaves the window
+        if candidate == 0 || pos - (candidate - 1) > WINDOW_SIZE {
+            break;
+        }
+        let start = candidate - 1;
+        let length = data[start..]
+            .iter()
+            .zip(&data[pos..pos + max])
+            .take_while(|(a, b)| a == b)
+            .count();
+        if length > best_length {
+            (best_length, best_distance) = (length, pos - start);
+            if length == max {
+                break;
+            }
+        }
+        let next = previous[start % WINDOW_SIZE];
+        // Older entries of the slot were overwritten by newer positions
+        if next >= candidate {
+            break;
+        }
+        candidate = next;
+    }
+    (best_length, best_distance)
+}
+
+/// Compresses the data into the zlib format, used by the `deflate` content coding
+pub fn zlib_compress(data: &[u8], level: u8) -> Vec<u8> {
+    let mut output = vec![0x78, 0x9c];
+    output.extend(deflate(data, level));
+    output.extend(adler32(data).to_be_bytes());
+    output
+}
+
+/// Compresses the data into a single gzip member
+pub fn gzip(data: &[u8], level: u8) -> Vec<u8> {
+    // No flags nor modification time, unknown operating system
+    let mut output = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+    output.extend(deflate(data, level));
+    output.extend(crc32(data).to_le_bytes());
+    output.extend((data.len() as u32).to_le_bytes());
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::inflate::{gunzip, inflate, zlib_decompress};
+
+    fn samples() -> Vec<Vec<u8>> {
+        let mut noise = Vec::new();
+        let mut state = 1u32;
+        for _ in 0..100_000 {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            noise.push((state >> 16) as u8);
+        }
+        vec![
+            Vec::new(),
+            b"a".to_vec(),
+            b"abcabcabcabcabcabcabc".to_vec(),
+            vec![0; 70_000],
+            br#"{"id":1,"name":"user","tags":["a","b"]},"#.repeat(2_000),
+            noise,
+        ]
+    }
+
+    #[test]
+    fn test_deflate_round_trip() {
+        for data in samples() {
+            for level in [1, DEFAULT_LEVEL, 9] {
+                let compressed = deflate(&data, level);
+                assert_eq!(inflate(&compressed, data.len()).unwrap(), data);
+            }
+        }
+    }
+
+    #[test]
+    fn test_deflate_compresses_repetitions() {
+        let data = b"hello world ".repeat(1000);
+        assert!(deflate(&data, DEFAULT_LEVEL).len() < data.len() / 20);
+    }
+
+    #[test]
+    fn test_deflate_levels() {
+        let mut data = Vec::new();
+        for i in 0..20_000u32 {
+            data.extend(format!("{{\"id\":{},\"name\":\"user{}\"}},", i, i % 97).bytes());
+        }
+        let fast = deflate(&data, 1).len();
+        let best = deflate(&data, 9).len();
+        assert!(best < fast, "{best} < {fast}");
+    }
+
+    #[test]
+    fn test_zlib_and_gzip_round_trip() {
+        for data in samples() {
+            assert_eq!(
+                zlib_decompress(&zlib_compress(&data, DEFAULT_LEVEL), data.len()).unwrap(),
+                data
+            );
+            assert_eq!(gunzip(&gzip(&data, 1), data.len()).unwrap(), data);
+        }
+    }
+}
@@ -0,0 +1,425 @@
+//! Decompression of deflate ([RFC 1951](https://www.rfc-editor.org/rfc/rfc1951)) streams
+//! and their zlib ([RFC 1950](https://www.rfc-editor.org/rfc/rfc1950)) and gzip
+//! ([RFC 1952](https://www.rfc-editor.org/rfc/rfc1952)) wrappers.
+//! Every function takes the maximum size of the output, so a small compressed body
+//! can't expand into an unbounded amount of memory.
+
+use super::checksum::{adler32, crc32};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum InflateError {
+    /// The data isn't a valid stream
+    Invalid,
+    /// The output would be bigger than the allowed size
+    TooLarge,
+}
+
+const MAX_BITS: usize = 15;
+
+/// Base length and extra bits of the length symbols 257..=285
+pub(super) const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+pub(super) const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+/// Base distance and extra bits of the distance symbols 0..=29
+pub(super) const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+pub(super) const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order in which the code length code lengths are sent in a dynamic block
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u8,
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, count: u8) -> Result<u32, InflateError> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = self.data.get(self.pos).ok_or(InflateError::Invalid)?;
+            value |= (((byte >> self.bit) & 1) as u32) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+        Ok(value)
+    }
+
+    /// Skips to the next byte boundary
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], InflateError> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + count)
+            .ok_or(InflateError::Invalid)?;
+        self.pos += count;
+        Ok(bytes)
+    }
+}
+
+/// Canonical Huffman code, decoded one bit at a time
+struct Huffman {
+    /// Number of codes of each length
+    counts: [u16; MAX_BITS + 1],
+    /// Symbols ordered by their code
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Huffman, InflateError> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for length in lengths {
+            counts[*length as usize] += 1;
+        }
+        // Reject over-subscribed codes, incomplete ones are allowed
+        let mut left: i32 = 1;
+        for count in &counts[1..] {
+            left = left * 2 - *count as i32;
+            if left < 0 {
+                return Err(InflateError::Invalid);
+            }
+        }
+
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for length in 1..=MAX_BITS {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; offsets[MAX_BITS + 1] as usize];
+        for (symbol, length) in lengths.iter().enumerate() {
+            if *length != 0 {
+                symbols[offsets[*length as usize] as usize] = symbol as u16;
+                offsets[*length as usize] += 1;
+            }
+        }
+        counts[0] = 0;
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, InflateError> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for length in 1..=MAX_BITS {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(InflateError::Invalid)
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    let literals = Huffman::new(&lengths).expect("fixed literal code is valid");
+    let distances = Huffman::new(&[5; 30]).expect("fixed distance code is valid");
+    (literals, distances)
+}
+
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), InflateError> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+    if literal_count > 286 || distance_count > 30 {
+        return Err(InflateError::Invalid);
+    }
+
+    let mut code_lengths = [0u8; 19];
+    for i in 0..code_length_count {
+        code_lengths[CODE_LENGTH_ORDER[i]] = reader.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths)?;
+
+    let mut lengths = vec![0u8; literal_count + distance_count];
+    let mut i = 0;
+    while i < lengths.len() {
+        let symbol = code_length_code.decode(reader)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths[..i].last().ok_or(InflateError::Invalid)?;
+                (previous, 3 + reader.bits(2)? as usize)
+            }
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+        if i + repeat > lengths.len() {
+            return Err(InflateError::Invalid);
+        }
+        lengths[i..i + repeat].fill(value);
+        i += repeat;
+    }
+    // The end of block code must be present
+    if lengths[256] == 0 {
+        return Err(InflateError::Invalid);
+    }
+
+    let literals = Huffman::new(&lengths[..literal_count])?;
+    let distances = Huffman::new(&lengths[literal_count..])?;
+    Ok((literals, distances))
+}
+
+fn inflate_block(
+    reader: &mut BitReader,
+    output: &mut Vec<u8>,
+    (literals, distances): (Huffman, Huffman),
+    max_size: usize,
+) -> Result<(), InflateError> {
+    loop {
+        let symbol = literals.decode(reader)? as usize;
+        match symbol {
+            0..=255 => output.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let index = symbol - 257;
+                if index >= LENGTH_BASE.len() {
+                    return Err(InflateError::Invalid);
+                }
+                let length =
+                    LENGTH_BASE[index] as usize + reader.bits(LENGTH_EXTRA[index])? as usize;
+                let index = distances.decode(reader)? as usize;
+                if index >= DIST_BASE.len() {
+                    return Err(InflateError::Invalid);
+                }
+                let distance = DIST_BASE[index] as usize + reader.bits(DIST_EXTRA[index])? as usize;
+                if distance > output.len() {
+                    return Err(InflateError::Invalid);
+                }
+                // Copy byte by byte, the match may overlap what it's writing
+                let start = output.len() - distance;
+                for i in 0..length {
+                    output.push(output[start + i]);
+                }
+            }
+        }
+        if output.len() > max_size {
+            return Err(InflateError::TooLarge);
+        }
+    }
+}
+
+/// Decompresses the deflate stream the reader is at, leaving the reader after its last byte
+fn inflate_stream(reader: &mut BitReader, max_size: usize) -> Result<Vec<u8>, InflateError> {
+    let mut output = Vec::new();
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+                let header = reader.bytes(4)?;
+                let length = u16::from_le_bytes([header[0], header[1]]);
+                let complement = u16::from_le_bytes([header[2], header[3]]);
+                if length != !complement {
+                    return Err(InflateError::Invalid);
+                }
+                if output.len() + length as usize > max_size {
+                    return Err(InflateError::TooLarge);
+                }
+                output.extend_from_slice(reader.bytes(length as usize)?);
+            }
+            1 => inflate_block(reader, &mut output, fixed_codes(), max_size)?,
+            2 => {
+                let codes = dynamic_codes(reader)?;
+                inflate_block(reader, &mut output, codes, max_size)?
+            }
+            _ => return Err(InflateError::Invalid),
+        }
+        if last {
+            reader.align();
+            return Ok(output);
+        }
+    }
+}
+
+/// Decompresses raw deflate data
+pub fn inflate(data: &[u8], max_size: usize) -> Result<Vec<u8>, InflateError> {
+    let mut reader = BitReader {
+        data,
+        pos: 0,
+        bit: 0,
+    };
+    inflate_stream(&mut reader, max_size)
+}
+
+/// Decompresses zlib data, checking its Adler-32 checksum
+pub fn zlib_decompress(data: &[u8], max_size: usize) -> Result<Vec<u8>, InflateError> {
+    let [cmf, flg, ..] = *data else {
+        return Err(InflateError::Invalid);
+    };
+    let has_dictionary = flg & 0x20 != 0;
+    if cmf & 0x0f != 8 || !(cmf as u16 * 256 + flg as u16).is_multiple_of(31) || has_dictionary {
+        return Err(InflateError::Invalid);
+    }
+
+    let mut reader = BitReader {
+        data: &data[2..],
+        pos: 0,
+        bit: 0,
+    };
+    let output = inflate_stream(&mut reader, max_size)?;
+    let checksum = reader.bytes(4)?;
+    if u32::from_be_bytes(checksum.try_into().unwrap()) != adler32(&output) {
+        return Err(InflateError::Invalid);
+    }
+    Ok(output)
+}
+
+/// Decompresses gzip data made of one or more members, checking their CRC-32
+pub fn gunzip(data: &[u8], max_size: usize) -> Result<Vec<u8>, InflateError> {
+    const FHCRC: u8 = 0x02;
+    const FEXTRA: u8 = 0x04;
+    const FNAME: u8 = 0x08;
+    const FCOMMENT: u8 = 0x10;
+
+    let mut output = Vec::new();
+    let mut reader = BitReader {
+        data,
+        pos: 0,
+        bit: 0,
+    };
+    loop {
+        let header = reader.bytes(10)?;
+        if header[..3] != [0x1f, 0x8b, 8] {
+            return Err(InflateError::Invalid);
+        }
+        let flags = header[3];
+        if flags & FEXTRA != 0 {
+            let length = reader.bytes(2)?;
+            reader.bytes(u16::from_le_bytes([length[0], length[1]]) as usize)?;
+        }
+        for flag in [FNAME, FCOMMENT] {
+            if flags & flag != 0 {
+                // Zero terminated string
+                while reader.bytes(1)? != [0] {}
+            }
+        }
+        if flags & FHCRC != 0 {
+            reader.bytes(2)?;
+        }
+
+        let member = inflate_stream(&mut reader, max_size - output.len())?;
+        let trailer = reader.bytes(8)?;
+        let crc = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+        let size = u32::from_le_bytes(trailer[4..].try_into().unwrap());
+        if crc != crc32(&member) || size != member.len() as u32 {
+            return Err(InflateError::Invalid);
+        }
+        output.extend_from_slice(&member);
+
+        if reader.pos == data.len() {
+            return Ok(output);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn text() -> Vec<u8> {
+        b"hello hello hello hello world\n".repeat(3)
+    }
+
+    const GZIP: &str =
+        "1f8b0800000000000203cb48cdc9c957c8c020cbf38b7252b832289005001363aae55a000000";
+    const ZLIB: &str = "789ccb48cdc9c957c8c020cbf38b7252b830c589970500eaa62107";
+    const RAW: &str = "cb48cdc9c957c8c020cbf38b7252b83228900500";
+
+    #[test]
+    fn test_inflate_raw() {
+        assert_eq!(inflate(&hex(RAW), 1024).unwrap(), text());
+    }
+
+    #[test]
+    fn test_zlib_decompress() {
+        assert_eq!(zlib_decompress(&hex(ZLIB), 1024).unwrap(), text());
+        // Stored blocks
+        assert_eq!(
+            zlib_decompress(&hex("7801010000ffff00000001"), 1024).unwrap(),
+            b""
+        );
+        assert_eq!(
+            zlib_decompress(&hex("7801010300fcff616263024d0127"), 1024).unwrap(),
+            b"abc"
+        );
+    }
+
+    #[test]
+    fn test_gunzip() {
+        assert_eq!(gunzip(&hex(GZIP), 1024).unwrap(), text());
+        let two_members = [hex(GZIP), hex(GZIP)].concat();
+        assert_eq!(gunzip(&two_members, 1024).unwrap(), text().repeat(2));
+    }
+
+    #[test]
+    fn test_dynamic_huffman_block() {
+        // Compressed with zlib level 9, it's a single dynamic block
+        let data = hex(concat!(
+            "78da95cbd71983200045e1556e16c89762da16797001509a52a489307d5821cfe73fa364f0594d",
+            "2b6870c582bb034b365b84db5940ea599356313b71c6f80ffe92ee4c05eda8a824c1d5ce7a6acc",
+            "422b9f5de8af88275caeb7fbf078bede1f103acd8c0ba996551beb361f62ca7b396afb01d21b3c45"
+        ));
+        let expected = [
+            b"The quick brown fox jumps over the lazy dog. ".repeat(2),
+            b"Pack my box with five dozen liquor jugs! 0123456789 abcdefghijklmnopqrstuvwxyz"
+                .to_vec(),
+        ]
+        .concat();
+        assert_eq!(zlib_decompress(&data, 1024).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_inflate_corrupt_data() {
+        let mut corrupt = hex(GZIP);
+        corrupt[20] ^= 0xff;
+        assert!(gunzip(&corrupt, 1024).is_err());
+        assert_eq!(gunzip(&hex(&GZIP[..40]), 1024), Err(InflateError::Invalid));
+        assert_eq!(
+            zlib_decompress(&hex(GZIP), 1024),
+            Err(InflateError::Invalid)
+        );
+        assert_eq!(inflate(&[0xff], 1024), Err(InflateError::Invalid));
+    }
+
+    #[test]
+    fn test_inflate_limits_output_size() {
+        assert_eq!(gunzip(&hex(GZIP), 10), Err(InflateError::TooLarge));
+        assert_eq!(
+            zlib_decompress(&hex("7801010300fcff616263024d0127"), 2),
+            Err(InflateError::TooLarge)
+        );
+    }
+}
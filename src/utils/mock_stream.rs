@@ -1,5 +1,7 @@
+use std::cell::RefCell;
 use std::io;
 use std::io::{Read, Write};
+use std::rc::Rc;
 
 pub struct MockTcpStream {
     pub read_data: Vec<u8>,
@@ -41,3 +43,29 @@ impl Write for MockTcpStream {
         Ok(())
     }
 }
+
+/// A writer that keeps everything written to it, readable through any of its clones.
+/// Useful to inspect what a `Context` wrote after it took ownership of the writer.
+#[derive(Clone, Default)]
+pub struct MockWriter(Rc<RefCell<Vec<u8>>>);
+
+impl MockWriter {
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).to_string()
+    }
+
+    pub fn bytes(&self) -> Vec<u8> {
+        self.0.borrow().clone()
+    }
+}
+
+impl Write for MockWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
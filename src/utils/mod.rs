@@ -1,2 +1,12 @@
+pub mod base64;
+pub mod checksum;
+pub mod crypto;
+pub mod deadline_stream;
+pub mod deflate;
+pub mod http_date;
+pub mod inflate;
+pub mod mock_stream;
+pub mod percent;
+pub mod punycode;
+pub mod regex;
 pub mod thread_pool;
-pub mod mock_stream;
\ No newline at end of file
@@ -0,0 +1,71 @@
+/// Decodes the `%XX` escapes of a URL component.
+/// Returns `None` if an escape is malformed or the decoded bytes aren't valid UTF-8.
+/// `+` is left as is, it only means a space in form encoded query strings.
+pub fn decode(input: &str) -> Option<String> {
+    if !input.contains('%') {
+        return Some(input.to_string());
+    }
+
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Encodes a URL path segment, escaping every byte but the unreserved characters
+pub fn encode(input: &str) -> String {
+    input
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode("plain"), Some("plain".to_string()));
+        assert_eq!(decode("a%20b%2Fc"), Some("a b/c".to_string()));
+        assert_eq!(decode("%E6%97%A5%E6%9C%AC"), Some("日本".to_string()));
+        assert_eq!(decode("%F0%9F%98%80"), Some("😀".to_string()));
+        assert_eq!(decode("日本"), Some("日本".to_string()));
+        assert_eq!(decode("a+b"), Some("a+b".to_string()));
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode("a b/c.txt"), "a%20b%2Fc.txt");
+        assert_eq!(encode("日本"), "%E6%97%A5%E6%9C%AC");
+        assert_eq!(decode(&encode("100% ~ok?")), Some("100% ~ok?".to_string()));
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        assert_eq!(decode("%"), None);
+        assert_eq!(decode("%2"), None);
+        assert_eq!(decode("%zz"), None);
+        assert_eq!(decode("%+1"), None);
+        assert_eq!(decode("%FF"), None); // not utf-8
+        assert_eq!(decode("%E6%97"), None); // truncated utf-8
+    }
+}
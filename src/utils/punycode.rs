@@ -0,0 +1,218 @@
+//! Punycode ([RFC 3492](https://www.rfc-editor.org/rfc/rfc3492)) used by
+//! internationalized domain names, e.g. `bücher.example` is sent as `xn--bcher-kva.example`.
+
+const BASE: u32 = 36;
+const T_MIN: u32 = 1;
+const T_MAX: u32 = 26;
+const SKEW: u32 = 38;
+const DAMP: u32 = 700;
+const INITIAL_BIAS: u32 = 72;
+const INITIAL_N: u32 = 128;
+const ACE_PREFIX: &str = "xn--";
+
+fn adapt(mut delta: u32, num_points: u32, first_time: bool) -> u32 {
+    delta /= if first_time { DAMP } else { 2 };
+    delta += delta / num_points;
+    let mut k = 0;
+    while delta > ((BASE - T_MIN) * T_MAX) / 2 {
+        delta /= BASE - T_MIN;
+        k += BASE;
+    }
+    k + (((BASE - T_MIN + 1) * delta) / (delta + SKEW))
+}
+
+fn threshold(k: u32, bias: u32) -> u32 {
+    if k <= bias {
+        T_MIN
+    } else if k >= bias + T_MAX {
+        T_MAX
+    } else {
+        k - bias
+    }
+}
+
+fn encode_digit(d: u32) -> char {
+    match d {
+        0..=25 => (b'a' + d as u8) as char,
+        _ => (b'0' + (d - 26) as u8) as char,
+    }
+}
+
+fn decode_digit(c: char) -> Option<u32> {
+    match c {
+        'a'..='z' => Some(c as u32 - 'a' as u32),
+        'A'..='Z' => Some(c as u32 - 'A' as u32),
+        '0'..='9' => Some(c as u32 - '0' as u32 + 26),
+        _ => None,
+    }
+}
+
+/// Encodes a label as punycode, without the `xn--` prefix.
+/// Returns `None` if the encoding overflows.
+pub fn encode(label: &str) -> Option<String> {
+    let input: Vec<u32> = label.chars().map(|c| c as u32).collect();
+    let mut output: String = label.chars().filter(char::is_ascii).collect();
+    let basic = output.len() as u32;
+    if basic > 0 {
+        output.push('-');
+    }
+
+    let mut n = INITIAL_N;
+    let mut delta: u32 = 0;
+    let mut bias = INITIAL_BIAS;
+    let mut handled = basic;
+    while (handled as usize) < input.len() {
+        let m = *input.iter().filter(|c| **c >= n).min()?;
+        delta = delta.checked_add((m - n).checked_mul(handled + 1)?)?;
+        n = m;
+        for &c in &input {
+            if c < n {
+                delta = delta.checked_add(1)?;
+            }
+            if c == n {
+                let mut q = delta;
+                let mut k = BASE;
+                loop {
+                    let t = threshold(k, bias);
+                    if q < t {
+                        break;
+                    }
+                    output.push(encode_digit(t + (q - t) % (BASE - t)));
+                    q = (q - t) / (BASE - t);
+                    k += BASE;
+                }
+                output.push(encode_digit(q));
+                bias = adapt(delta, handled + 1, handled == basic);
+                delta = 0;
+                handled += 1;
+            }
+        }
+        delta += 1;
+        n += 1;
+    }
+    Some(output)
+}
+
+/// Decodes a punycode label, without the `xn--` prefix.
+/// Returns `None` if the label isn't valid punycode.
+pub fn decode(label: &str) -> Option<String> {
+    let (basic, extended) = match label.rfind('-') {
+        Some(i) => (&label[..i], &label[i + 1..]),
+        None => ("", label),
+    };
+    if !basic.is_ascii() {
+        return None;
+    }
+    let mut output: Vec<char> = basic.chars().collect();
+
+    let mut n = INITIAL_N;
+    let mut i: u32 = 0;
+    let mut bias = INITIAL_BIAS;
+    let mut digits = extended.chars().peekable();
+    while digits.peek().is_some() {
+        let old_i = i;
+        let mut w: u32 = 1;
+        let mut k = BASE;
+        loop {
+            let digit = decode_digit(digits.next()?)?;
+            i = i.checked_add(digit.checked_mul(w)?)?;
+            let t = threshold(k, bias);
+            if digit < t {
+                break;
+            }
+            w = w.checked_mul(BASE - t)?;
+            k += BASE;
+        }
+        let len = output.len() as u32 + 1;
+        bias = adapt(i - old_i, len, old_i == 0);
+        n = n.checked_add(i / len)?;
+        i %= len;
+        output.insert(i as usize, char::from_u32(n)?);
+        i += 1;
+    }
+    Some(output.into_iter().collect())
+}
+
+/// Converts a domain to its ASCII form, lowercased and with its non-ASCII labels
+/// punycode encoded. This is the form used to compare hosts.
+/// # Example
+/// ```
+/// use HTTP_Server::utils::punycode::domain_to_ascii;
+///
+/// assert_eq!(domain_to_ascii("Bücher.Example"), Some("xn--bcher-kva.example".to_string()));
+/// assert_eq!(domain_to_ascii("example.com"), Some("example.com".to_string()));
+/// ```
+pub fn domain_to_ascii(domain: &str) -> Option<String> {
+    let labels: Option<Vec<String>> = domain
+        .split('.')
+        .map(|label| {
+            let label = label.to_lowercase();
+            if label.is_ascii() {
+                Some(label)
+            } else {
+                Some(format!("{ACE_PREFIX}{}", encode(&label)?))
+            }
+        })
+        .collect();
+    Some(labels?.join("."))
+}
+
+/// Converts a domain to its Unicode form, decoding its `xn--` labels.
+/// # Example
+/// ```
+/// use HTTP_Server::utils::punycode::domain_to_unicode;
+///
+/// assert_eq!(domain_to_unicode("xn--bcher-kva.example"), Some("bücher.example".to_string()));
+/// assert_eq!(domain_to_unicode("xn--bcher-kva!.example"), None);
+/// ```
+pub fn domain_to_unicode(domain: &str) -> Option<String> {
+    let labels: Option<Vec<String>> = domain
+        .split('.')
+        .map(|label| {
+            let label = label.to_lowercase();
+            match label.strip_prefix(ACE_PREFIX) {
+                Some(encoded) => decode(encoded),
+                None => Some(label),
+            }
+        })
+        .collect();
+    Some(labels?.join("."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VECTORS: [(&str, &str); 6] = [
+        ("bücher", "bcher-kva"),
+        ("münchen", "mnchen-3ya"),
+        ("日本語", "wgv71a119e"),
+        ("☃", "n3h"),
+        ("例え", "r8jz45g"),
+        ("😀", "e28h"),
+    ];
+
+    #[test]
+    fn test_encode() {
+        for (unicode, encoded) in VECTORS {
+            assert_eq!(encode(unicode).as_deref(), Some(encoded), "{unicode}");
+        }
+    }
+
+    #[test]
+    fn test_decode() {
+        for (unicode, encoded) in VECTORS {
+            assert_eq!(decode(encoded).as_deref(), Some(unicode), "{encoded}");
+        }
+        assert_eq!(decode("bcher-kv!"), None);
+        assert_eq!(decode("99999999999"), None);
+    }
+
+    #[test]
+    fn test_domain_round_trip() {
+        let ascii = domain_to_ascii("☃.日本語.com").unwrap();
+        assert_eq!(ascii, "xn--n3h.xn--wgv71a119e.com");
+        assert_eq!(domain_to_unicode(&ascii).unwrap(), "☃.日本語.com");
+        assert_eq!(domain_to_unicode("XN--N3H.COM").unwrap(), "☃.com");
+    }
+}
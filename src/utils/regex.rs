@@ -0,0 +1,651 @@
+use std::fmt;
+
+/// A small backtracking regular expression engine, enough for routing patterns.
+/// It supports literals, `.`, character classes (`[a-z]`, `[^/]`, `\d`, `\w`, `\s`),
+/// anchors (`^`, `$`), groups (`(...)`, `(?:...)`, `(?P<name>...)`, `(?<name>...)`),
+/// alternation and the quantifiers `*`, `+`, `?`, `{n}`, `{n,}` and `{n,m}`,
+/// all of them with a lazy `?` variant. A leading `(?i)` makes the whole pattern
+/// case insensitive.
+/// Matching keeps track of the visited states so it runs in `O(pattern * text)`.
+#[derive(Debug, Clone)]
+pub struct Regex {
+    pattern: String,
+    program: Vec<Inst>,
+    /// Name of every capture group, the whole match being group 0
+    names: Vec<Option<String>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegexError(String);
+
+impl fmt::Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid regex: {}", self.0)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Class {
+    ranges: Vec<(char, char)>,
+    negated: bool,
+    ignore_case: bool,
+}
+
+impl Class {
+    fn new(ranges: &[(char, char)], negated: bool) -> Class {
+        Class {
+            ranges: ranges.to_vec(),
+            negated,
+            ignore_case: false,
+        }
+    }
+
+    fn contains(&self, c: char) -> bool {
+        self.ranges.iter().any(|(lo, hi)| *lo <= c && c <= *hi)
+    }
+
+    fn matches(&self, c: char) -> bool {
+        let contains = self.contains(c)
+            || (self.ignore_case
+                && (c.to_lowercase().any(|c| self.contains(c))
+                    || c.to_uppercase().any(|c| self.contains(c))));
+        contains != self.negated
+    }
+}
+
+const DIGIT: &[(char, char)] = &[('0', '9')];
+const WORD: &[(char, char)] = &[('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')];
+const SPACE: &[(char, char)] = &[('\t', '\r'), (' ', ' ')];
+
+#[derive(Debug, Clone)]
+enum Node {
+    Empty,
+    Char(char),
+    Any,
+    Class(Class),
+    Start,
+    End,
+    Group(Box<Node>, Option<usize>),
+    Concat(Vec<Node>),
+    Alt(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+        greedy: bool,
+    },
+}
+
+#[derive(Debug, Clone)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(Class),
+    Start,
+    End,
+    Save(usize),
+    /// Try the first branch, backtracking to the second one
+    Split(usize, usize),
+    Jmp(usize),
+    Match,
+}
+
+/// Upper bound for counted repetitions, to keep the compiled program small
+const MAX_REPEAT: u32 = 1000;
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    names: Vec<Option<String>>,
+}
+
+impl Parser<'_> {
+    fn error<T>(message: &str) -> Result<T, RegexError> {
+        Err(RegexError(message.to_string()))
+    }
+
+    fn parse_alt(&mut self) -> Result<Node, RegexError> {
+        let mut branches = vec![self.parse_concat()?];
+        while self.chars.peek() == Some(&'|') {
+            self.chars.next();
+            branches.push(self.parse_concat()?);
+        }
+        if branches.len() == 1 {
+            return Ok(branches.remove(0));
+        }
+        Ok(Node::Alt(branches))
+    }
+
+    fn parse_concat(&mut self) -> Result<Node, RegexError> {
+        let mut nodes = Vec::new();
+        while let Some(&c) = self.chars.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.parse_atom()?;
+            nodes.push(self.parse_quantifier(atom)?);
+        }
+        match nodes.len() {
+            0 => Ok(Node::Empty),
+            1 => Ok(nodes.remove(0)),
+            _ => Ok(Node::Concat(nodes)),
+        }
+    }
+
+    fn parse_atom(&mut self) -> Result<Node, RegexError> {
+        let Some(c) = self.chars.next() else {
+            return Parser::error("unexpected end of pattern");
+        };
+        match c {
+            '.' => Ok(Node::Any),
+            '^' => Ok(Node::Start),
+            '$' => Ok(Node::End),
+            '(' => self.parse_group(),
+            '[' => self.parse_class(),
+            '\\' => self.parse_escape(),
+            '*' | '+' | '?' | '{' => Parser::error("quantifier without a preceding expression"),
+            c => Ok(Node::Char(c)),
+        }
+    }
+
+    fn parse_group(&mut self) -> Result<Node, RegexError> {
+        let mut index = None;
+        if self.chars.peek() == Some(&'?') {
+            self.chars.next();
+            match self.chars.next() {
+                Some(':') => {}
+                Some('P') if self.chars.next() == Some('<') => index = Some(self.parse_name()?),
+                Some('<') => index = Some(self.parse_name()?),
+                _ => return Parser::error("unsupported group syntax"),
+            }
+        } else {
+            self.names.push(None);
+            index = Some(self.names.len() - 1);
+        }
+
+        let node = self.parse_alt()?;
+        if self.chars.next() != Some(')') {
+            return Parser::error("unclosed group");
+        }
+        Ok(Node::Group(Box::new(node), index))
+    }
+
+    fn parse_name(&mut self) -> Result<usize, RegexError> {
+        let mut name = String::new();
+        loop {
+            match self.chars.next() {
+                Some('>') => break,
+                Some(c) if c.is_alphanumeric() || c == '_' => name.push(c),
+                _ => return Parser::error("invalid group name"),
+            }
+        }
+        if name.is_empty() || self.names.contains(&Some(name.clone())) {
+            return Parser::error("empty or duplicated group name");
+        }
+        self.names.push(Some(name));
+        Ok(self.names.len() - 1)
+    }
+
+    fn parse_escape(&mut self) -> Result<Node, RegexError> {
+        match self.chars.next() {
+            Some('d') => Ok(Node::Class(Class::new(DIGIT, false))),
+            Some('D') => Ok(Node::Class(Class::new(DIGIT, true))),
+            Some('w') => Ok(Node::Class(Class::new(WORD, false))),
+            Some('W') => Ok(Node::Class(Class::new(WORD, true))),
+            Some('s') => Ok(Node::Class(Class::new(SPACE, false))),
+            Some('S') => Ok(Node::Class(Class::new(SPACE, true))),
+            Some(c) => Ok(Node::Char(Parser::escaped_char(c)?)),
+            None => Parser::error("trailing backslash"),
+        }
+    }
+
+    fn escaped_char(c: char) -> Result<char, RegexError> {
+        match c {
+            'n' => Ok('\n'),
+            'r' => Ok('\r'),
+            't' => Ok('\t'),
+            c if c.is_ascii_alphanumeric() => Parser::error("unsupported escape sequence"),
+            c => Ok(c),
+        }
+    }
+
+    fn parse_class(&mut self) -> Result<Node, RegexError> {
+        let negated = self.chars.peek() == Some(&'^');
+        if negated {
+            self.chars.next();
+        }
+
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+            let start = match self.chars.next() {
+                Some(']') if !first => break,
+                Some('\\') => match self.chars.next() {
+                    Some('d') => {
+                        ranges.extend_from_slice(DIGIT);
+                        first = false;
+                        continue;
+                    }
+                    Some('w') => {
+                        ranges.extend_from_slice(WORD);
+                        first = false;
+                        continue;
+                    }
+                    Some('s') => {
+                        ranges.extend_from_slice(SPACE);
+                        first = false;
+                        continue;
+                    }
+                    Some(c) => Parser::escaped_char(c)?,
+                    None => return Parser::error("unclosed character class"),
+                },
+                Some(c) => c,
+                None => return Parser::error("unclosed character class"),
+            };
+            first = false;
+
+            let mut lookahead = self.chars.clone();
+            if lookahead.next() == Some('-') && !matches!(lookahead.next(), Some(']') | None) {
+                self.chars.next();
+                let end = match self.chars.next() {
+                    Some('\\') => Parser::escaped_char(self.chars.next().unwrap_or('\\'))?,
+                    Some(c) => c,
+                    None => return Parser::error("unclosed character class"),
+                };
+                if end < start {
+                    return Parser::error("invalid character class range");
+                }
+                ranges.push((start, end));
+            } else {
+                ranges.push((start, start));
+            }
+        }
+        Ok(Node::Class(Class {
+            ranges,
+            negated,
+            ignore_case: false,
+        }))
+    }
+
+    fn parse_quantifier(&mut self, atom: Node) -> Result<Node, RegexError> {
+        let (min, max) = match self.chars.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => {
+                self.chars.next();
+                return self.parse_counted(atom);
+            }
+            _ => return Ok(atom),
+        };
+        self.chars.next();
+        self.repeat(atom, min, max)
+    }
+
+    fn parse_counted(&mut self, atom: Node) -> Result<Node, RegexError> {
+        let mut spec = String::new();
+        loop {
+            match self.chars.next() {
+                Some('}') => break,
+                Some(c) => spec.push(c),
+                None => return Parser::error("unclosed counted repetition"),
+            }
+        }
+        let parse = |n: &str| {
+            n.trim()
+                .parse::<u32>()
+                .ok()
+                .filter(|n| *n <= MAX_REPEAT)
+                .ok_or(RegexError("invalid counted repetition".to_string()))
+        };
+        let (min, max) = match spec.split_once(',') {
+            None => (parse(&spec)?, Some(parse(&spec)?)),
+            Some((min, "")) => (parse(min)?, None),
+            Some((min, max)) => (parse(min)?, Some(parse(max)?)),
+        };
+        if max.is_some_and(|max| max < min) {
+            return Parser::error("invalid counted repetition");
+        }
+        self.repeat(atom, min, max)
+    }
+
+    fn repeat(&mut self, atom: Node, min: u32, max: Option<u32>) -> Result<Node, RegexError> {
+        if matches!(atom, Node::Start | Node::End | Node::Empty) {
+            return Parser::error("quantifier without a preceding expression");
+        }
+        let greedy = self.chars.peek() != Some(&'?');
+        if !greedy {
+            self.chars.next();
+        }
+        Ok(Node::Repeat {
+            node: Box::new(atom),
+            min,
+            max,
+            greedy,
+        })
+    }
+}
+
+fn compile(node: &Node, program: &mut Vec<Inst>, ignore_case: bool) {
+    let compile = |node: &Node, program: &mut Vec<Inst>| compile(node, program, ignore_case);
+    match node {
+        Node::Empty => {}
+        Node::Char(c) if ignore_case => program.push(Inst::Class(Class {
+            ranges: vec![(*c, *c)],
+            negated: false,
+            ignore_case,
+        })),
+        Node::Char(c) => program.push(Inst::Char(*c)),
+        Node::Any => program.push(Inst::Any),
+        Node::Class(class) => program.push(Inst::Class(Class {
+            ignore_case,
+            ..class.clone()
+        })),
+        Node::Start => program.push(Inst::Start),
+        Node::End => program.push(Inst::End),
+        Node::Group(node, index) => {
+            if let Some(index) = index {
+                program.push(Inst::Save(index * 2));
+            }
+            compile(node, program);
+            if let Some(index) = index {
+                program.push(Inst::Save(index * 2 + 1));
+            }
+        }
+        Node::Concat(nodes) => nodes.iter().for_each(|node| compile(node, program)),
+        Node::Alt(branches) => {
+            let mut jumps = Vec::new();
+            for (i, branch) in branches.iter().enumerate() {
+                if i == branches.len() - 1 {
+                    compile(branch, program);
+                    break;
+                }
+                let split = program.len();
+                program.push(Inst::Split(split + 1, 0));
+                compile(branch, program);
+                jumps.push(program.len());
+                program.push(Inst::Jmp(0));
+                program[split] = Inst::Split(split + 1, program.len());
+            }
+            let end = program.len();
+            for jump in jumps {
+                program[jump] = Inst::Jmp(end);
+            }
+        }
+        Node::Repeat {
+            node,
+            min,
+            max,
+            greedy,
+        } => {
+            for _ in 0..*min {
+                compile(node, program);
+            }
+            let split = |first: usize, second: usize| match greedy {
+                true => Inst::Split(first, second),
+                false => Inst::Split(second, first),
+            };
+            match max {
+                None => {
+                    let start = program.len();
+                    program.push(Inst::Jmp(0));
+                    compile(node, program);
+                    program.push(Inst::Jmp(start));
+                    program[start] = split(start + 1, program.len());
+                }
+                Some(max) => {
+                    let mut splits = Vec::new();
+                    for _ in *min..*max {
+                        splits.push(program.len());
+                        program.push(Inst::Jmp(0));
+                        compile(node, program);
+                    }
+                    let end = program.len();
+                    for s in splits {
+                        program[s] = split(s + 1, end);
+                    }
+                }
+            }
+        }
+    }
+}
+
+enum Job {
+    Run(usize, usize),
+    /// Restore a capture slot when backtracking
+    Restore(usize, Option<usize>),
+}
+
+impl Regex {
+    /// Compile the pattern
+    /// # Example
+    /// ```
+    /// use HTTP_Server::utils::regex::Regex;
+    ///
+    /// let re = Regex::new(r"^/files/(?P<year>\d{4})/(?P<name>.+)$").unwrap();
+    /// assert!(re.is_match("/files/2024/report.pdf"));
+    /// assert!(!re.is_match("/files/24/report.pdf"));
+    /// assert!(Regex::new("(unclosed").is_err());
+    /// ```
+    pub fn new(pattern: &str) -> Result<Regex, RegexError> {
+        let (ignore_case, expression) = match pattern.strip_prefix("(?i)") {
+            Some(expression) => (true, expression),
+            None => (false, pattern),
+        };
+        let mut parser = Parser {
+            chars: expression.chars().peekable(),
+            names: vec![None],
+        };
+        let node = parser.parse_alt()?;
+        if parser.chars.next().is_some() {
+            return Parser::error("unmatched closing parenthesis");
+        }
+
+        let mut program = vec![Inst::Save(0)];
+        compile(&node, &mut program, ignore_case);
+        program.push(Inst::Save(1));
+        program.push(Inst::Match);
+        Ok(Regex {
+            pattern: pattern.to_string(),
+            program,
+            names: parser.names,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Returns the name of every capture group, in the order of [`Regex::captures`]
+    pub fn group_names(&self) -> impl Iterator<Item = Option<&str>> {
+        self.names.iter().map(|name| name.as_deref())
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.captures(text).is_some()
+    }
+
+    /// Returns the byte range of every capture group of the leftmost match,
+    /// the whole match being group 0. Groups that didn't participate are `None`.
+    pub fn captures(&self, text: &str) -> Option<Vec<Option<(usize, usize)>>> {
+        let mut offsets: Vec<usize> = text.char_indices().map(|(i, _)| i).collect();
+        offsets.push(text.len());
+        let chars: Vec<char> = text.chars().collect();
+
+        let len = chars.len() + 1;
+        // One bit per state, as the program runs at every position of the text
+        let mut visited = vec![0u64; (self.program.len() * len).div_ceil(64)];
+        let mut slots = vec![None; self.names.len() * 2];
+        for start in 0..len {
+            if self.run(&chars, start, &mut visited, &mut slots) {
+                return Some(
+                    slots
+                        .chunks(2)
+                        .map(|slot| match (slot[0], slot[1]) {
+                            (Some(s), Some(e)) => Some((offsets[s], offsets[e])),
+                            _ => None,
+                        })
+                        .collect(),
+                );
+            }
+        }
+        None
+    }
+
+    /// Returns the value of every named group that participated in the match
+    /// # Example
+    /// ```
+    /// use HTTP_Server::utils::regex::Regex;
+    ///
+    /// let re = Regex::new(r"^/files/(?P<year>\d{4})/(?P<name>.+)$").unwrap();
+    /// let captures = re.named_captures("/files/2024/a.txt").unwrap();
+    /// assert_eq!(captures, vec![("year", "2024"), ("name", "a.txt")]);
+    /// ```
+    pub fn named_captures<'t>(&self, text: &'t str) -> Option<Vec<(&str, &'t str)>> {
+        let captures = self.captures(text)?;
+        Some(
+            self.names
+                .iter()
+                .zip(captures)
+                .filter_map(|(name, range)| Some((name.as_deref()?, &text[range?.0..range?.1])))
+                .collect(),
+        )
+    }
+
+    fn run(
+        &self,
+        chars: &[char],
+        start: usize,
+        visited: &mut [u64],
+        slots: &mut [Option<usize>],
+    ) -> bool {
+        let len = chars.len() + 1;
+        let mut jobs = vec![Job::Run(0, start)];
+        while let Some(job) = jobs.pop() {
+            let (mut pc, mut pos) = match job {
+                Job::Run(pc, pos) => (pc, pos),
+                Job::Restore(slot, value) => {
+                    slots[slot] = value;
+                    continue;
+                }
+            };
+            loop {
+                // A state that was already explored failed, no matter the captures
+                let (word, bit) = ((pc * len + pos) / 64, 1 << ((pc * len + pos) % 64));
+                if visited[word] & bit != 0 {
+                    break;
+                }
+                visited[word] |= bit;
+                match &self.program[pc] {
+                    Inst::Char(c) if chars.get(pos) == Some(c) => pos += 1,
+                    Inst::Any if chars.get(pos).is_some_and(|c| *c != '\n') => pos += 1,
+                    Inst::Class(class) if chars.get(pos).is_some_and(|c| class.matches(*c)) => {
+                        pos += 1
+                    }
+                    Inst::Start if pos == 0 => {}
+                    Inst::End if pos == chars.len() => {}
+                    Inst::Save(slot) => {
+                        jobs.push(Job::Restore(*slot, slots[*slot]));
+                        slots[*slot] = Some(pos);
+                    }
+                    Inst::Split(first, second) => {
+                        jobs.push(Job::Run(*second, pos));
+                        pc = *first;
+                        continue;
+                    }
+                    Inst::Jmp(target) => {
+                        pc = *target;
+                        continue;
+                    }
+                    Inst::Match => return true,
+                    _ => break,
+                }
+                pc += 1;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find<'t>(pattern: &str, text: &'t str) -> Option<&'t str> {
+        let re = Regex::new(pattern).unwrap();
+        re.captures(text)
+            .map(|captures| captures[0].unwrap())
+            .map(|(s, e)| &text[s..e])
+    }
+
+    #[test]
+    fn test_regex_literals_and_anchors() {
+        assert_eq!(find("abc", "xxabcxx"), Some("abc"));
+        assert_eq!(find("^abc$", "abc"), Some("abc"));
+        assert_eq!(find("^abc$", "abcd"), None);
+        assert_eq!(find(r"a\.b", "a.b"), Some("a.b"));
+        assert_eq!(find(r"a\.b", "axb"), None);
+    }
+
+    #[test]
+    fn test_regex_classes() {
+        assert_eq!(find(r"\d+", "ab123c"), Some("123"));
+        assert_eq!(find(r"[a-c]+", "xxabcabd"), Some("abcab"));
+        assert_eq!(find(r"[^/]+", "/abc/d"), Some("abc"));
+        assert_eq!(find(r"[\d_-]+", "a1_-2b"), Some("1_-2"));
+        assert_eq!(find(r"\w+\s\w+", "hello world"), Some("hello world"));
+    }
+
+    #[test]
+    fn test_regex_quantifiers() {
+        assert_eq!(find("a*", "aaa"), Some("aaa"));
+        assert_eq!(find("a*?", "aaa"), Some(""));
+        assert_eq!(find("a+?", "aaa"), Some("a"));
+        assert_eq!(find("ab?c", "ac"), Some("ac"));
+        assert_eq!(find(r"\d{2,3}", "12345"), Some("123"));
+        assert_eq!(find(r"^\d{4}$", "123"), None);
+        assert_eq!(find(r"\d{2,}", "1 12345"), Some("12345"));
+        assert_eq!(find("(a*)*b", "aaab"), Some("aaab"));
+    }
+
+    #[test]
+    fn test_regex_alternation_and_groups() {
+        assert_eq!(find("cat|dog", "hotdog"), Some("dog"));
+        assert_eq!(find("^(?:cat|dog)s$", "cats"), Some("cats"));
+        assert_eq!(find("^(?:cat|dog)s$", "cows"), None);
+
+        let re = Regex::new(r"^/(\w+)/(?<id>\d+)?$").unwrap();
+        assert_eq!(
+            re.captures("/users/12").unwrap(),
+            vec![Some((0, 9)), Some((1, 6)), Some((7, 9))]
+        );
+        assert_eq!(re.captures("/users/").unwrap()[2], None);
+        assert_eq!(re.named_captures("/users/").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_regex_ignore_case() {
+        assert_eq!(find("(?i)sqlmap", "SQLMap/1.0"), Some("SQLMap"));
+        assert_eq!(find("(?i)[a-c]+", "xABcd"), Some("ABc"));
+        assert_eq!(find("(?i)[^a]", "Ab"), Some("b"));
+        assert_eq!(find("(?i)ñ", "Ñ"), Some("Ñ"));
+        assert_eq!(find("sqlmap", "SQLMap"), None);
+    }
+
+    #[test]
+    fn test_regex_unicode() {
+        let re = Regex::new(r"^/tags/(?P<tag>.+)$").unwrap();
+        assert_eq!(
+            re.named_captures("/tags/日本").unwrap(),
+            vec![("tag", "日本")]
+        );
+    }
+
+    #[test]
+    fn test_regex_invalid_patterns() {
+        for pattern in [
+            "(a", "a)", "[a", "*a", r"\", "a{2,1}", "(?P<>a)", r"\q", "(?x)",
+        ] {
+            assert!(Regex::new(pattern).is_err(), "{pattern}");
+        }
+    }
+}
@@ -1,13 +1,139 @@
 use std::{
-    sync::{mpsc, Arc, Mutex},
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc, Mutex,
+    },
     thread,
+    time::{Duration, Instant},
 };
 
 type Job = Box<dyn FnOnce() + Send + 'static>;
 
+enum Message {
+    Job(Job),
+    /// Stops the worker that receives it, after the jobs queued before it
+    Retire,
+}
+
+type Receiver = Arc<Mutex<mpsc::Receiver<Message>>>;
+
+/// Upper bounds of the buckets of the [`QueueTimes`] histogram, a last one counts the
+/// jobs that waited longer
+pub const QUEUE_TIME_BUCKETS: [Duration; 8] = [
+    Duration::from_millis(1),
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_secs(5),
+];
+
+/// Histogram of how long the jobs of a pool waited in the queue for a thread, updated
+/// as each job is taken
+#[derive(Debug, Default)]
+pub struct QueueTimes {
+    buckets: [AtomicU64; QUEUE_TIME_BUCKETS.len() + 1],
+    total_micros: AtomicU64,
+    max_micros: AtomicU64,
+}
+
+impl QueueTimes {
+    fn record(&self, waited: Duration) {
+        let bucket = QUEUE_TIME_BUCKETS
+            .iter()
+            .position(|bound| waited <= *bound)
+            .unwrap_or(QUEUE_TIME_BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        let micros = u64::try_from(waited.as_micros()).unwrap_or(u64::MAX);
+        self.total_micros.fetch_add(micros, Ordering::Relaxed);
+        self.max_micros.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    /// Returns the values recorded so far
+    pub fn stats(&self) -> QueueTimeStats {
+        let buckets: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|b| b.load(Ordering::Relaxed))
+            .collect();
+        QueueTimeStats {
+            count: buckets.iter().sum(),
+            total: Duration::from_micros(self.total_micros.load(Ordering::Relaxed)),
+            max: Duration::from_micros(self.max_micros.load(Ordering::Relaxed)),
+            buckets,
+        }
+    }
+}
+
+/// Values of the [`QueueTimes`] histogram at some point
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueTimeStats {
+    /// Jobs taken by a thread
+    pub count: u64,
+    /// Time all of them waited
+    pub total: Duration,
+    /// Longest any of them waited
+    pub max: Duration,
+    /// Jobs that waited up to each of [`QUEUE_TIME_BUCKETS`] and not the previous one,
+    /// with a last entry for the ones that waited longer
+    pub buckets: Vec<u64>,
+}
+
+impl QueueTimeStats {
+    /// Returns the average time the jobs waited
+    pub fn mean(&self) -> Duration {
+        match self.count {
+            0 => Duration::ZERO,
+            count => self.total / count as u32,
+        }
+    }
+}
+
+/// State of a pool shared with its [`PoolHandle`]s
+struct Shared {
+    workers: Mutex<Vec<Option<thread::JoinHandle<()>>>>,
+    /// Taken when the pool is dropped, so the threads stop once the queue is empty
+    sender: Mutex<Option<mpsc::Sender<Message>>>,
+    receiver: Receiver,
+    size: Mutex<usize>,
+    queue_times: Arc<QueueTimes>,
+}
+
+impl Shared {
+    fn size(&self) -> usize {
+        *self.size.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn resize(&self, size: usize) {
+        let size = size.max(1);
+        let mut current = self.size.lock().unwrap_or_else(|e| e.into_inner());
+        let mut workers = self.workers.lock().unwrap_or_else(|e| e.into_inner());
+        let sender = self.sender.lock().unwrap_or_else(|e| e.into_inner());
+        // The pool is shutting down
+        let Some(sender) = sender.as_ref() else {
+            return;
+        };
+        // Forget the threads that already retired
+        workers.retain(|w| w.as_ref().is_some_and(|w| !w.is_finished()));
+
+        if size > *current {
+            for _ in *current..size {
+                workers.push(Some(ThreadPool::spawn_worker(&self.receiver)));
+            }
+        } else {
+            for _ in size..*current {
+                _ = sender.send(Message::Retire);
+            }
+        }
+        *current = size;
+    }
+}
+
 pub struct ThreadPool {
-    workers: Vec<Option<thread::JoinHandle<()>>>,
-    sender: Option<mpsc::Sender<Job>>,
+    shared: Arc<Shared>,
 }
 
 impl ThreadPool {
@@ -21,67 +147,147 @@ impl ThreadPool {
 
         let receiver = Arc::new(Mutex::new(receiver));
 
-        let mut workers = Vec::with_capacity(size);
-        for _ in 0..size {
-            let receiver: Arc<Mutex<mpsc::Receiver<Job>>> = Arc::clone(&receiver);
-
-            let worker = thread::spawn(move || loop {
-                let message = match receiver.lock() {
-                    Ok(receiver) => receiver.recv(),
-                    Err(_) => {
-                        // Mutex was poisoned, so we should exit the thread
-                        break;
-                    }
-                };
-
-                match message {
-                    Ok(job) => {
-                        job();
-                    }
-                    Err(_) => {
-                        // Sender was dropped, so we should exit the thread
-                        break;
-                    }
-                }
-            });
-
-            workers.push(Some(worker));
-        }
+        let workers = (0..size)
+            .map(|_| Some(ThreadPool::spawn_worker(&receiver)))
+            .collect();
 
         ThreadPool {
-            workers,
-            sender: Some(sender),
+            shared: Arc::new(Shared {
+                workers: Mutex::new(workers),
+                sender: Mutex::new(Some(sender)),
+                receiver,
+                size: Mutex::new(size),
+                queue_times: Arc::default(),
+            }),
         }
     }
 
+    fn spawn_worker(receiver: &Receiver) -> thread::JoinHandle<()> {
+        let receiver = Arc::clone(receiver);
+
+        thread::spawn(move || loop {
+            let message = match receiver.lock() {
+                Ok(receiver) => receiver.recv(),
+                Err(_) => {
+                    // Mutex was poisoned, so we should exit the thread
+                    break;
+                }
+            };
+
+            match message {
+                Ok(Message::Job(job)) => {
+                    job();
+                }
+                Ok(Message::Retire) => break,
+                Err(_) => {
+                    // Sender was dropped, so we should exit the thread
+                    break;
+                }
+            }
+        })
+    }
+
     pub fn execute<F>(&self, f: F)
     where
         F: FnOnce() + Send + 'static,
     {
-        self.sender
+        let queued = Instant::now();
+        let queue_times = Arc::clone(&self.shared.queue_times);
+        let job = move || {
+            queue_times.record(queued.elapsed());
+            f()
+        };
+        self.shared
+            .sender
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
             .as_ref()
             .unwrap()
-            .send(Box::new(f))
+            .send(Message::Job(Box::new(job)))
             .expect("Error sending job")
     }
+
+    /// Returns the histogram of how long the jobs waited for a thread
+    pub fn queue_times(&self) -> Arc<QueueTimes> {
+        Arc::clone(&self.shared.queue_times)
+    }
+
+    /// Returns the number of threads in the pool
+    pub fn size(&self) -> usize {
+        self.shared.size()
+    }
+
+    /// Changes the number of threads in the pool, with a minimum of 1, while it runs.
+    /// New threads start taking jobs right away, while the extra ones finish their
+    /// current job and retire once the jobs queued before the resize are taken,
+    /// so no queued job is dropped.
+    pub fn resize(&self, size: usize) {
+        self.shared.resize(size)
+    }
+
+    /// Returns a handle to resize the pool from other threads while it runs
+    pub fn handle(&self) -> PoolHandle {
+        PoolHandle {
+            shared: Arc::clone(&self.shared),
+        }
+    }
 }
 
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        drop(self.sender.take());
+        drop(
+            self.shared
+                .sender
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .take(),
+        );
 
-        for thread in &mut self.workers {
+        let workers = std::mem::take(
+            &mut *self
+                .shared
+                .workers
+                .lock()
+                .unwrap_or_else(|e| e.into_inner()),
+        );
+        for thread in workers.into_iter().flatten() {
             println!("Shutting down worker");
-            if let Some(thread) = thread.take() {
-                thread.join().expect("Error joining worker thread");
-            }
+            thread.join().expect("Error joining worker thread");
         }
     }
 }
 
+/// Resizes a [`ThreadPool`] from other threads. Once the pool is dropped it does nothing
+#[derive(Clone)]
+pub struct PoolHandle {
+    shared: Arc<Shared>,
+}
+
+impl fmt::Debug for PoolHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PoolHandle")
+            .field("size", &self.size())
+            .finish()
+    }
+}
+
+impl PoolHandle {
+    /// See [`ThreadPool::size`]
+    pub fn size(&self) -> usize {
+        self.shared.size()
+    }
+
+    /// See [`ThreadPool::resize`]
+    pub fn resize(&self, size: usize) {
+        self.shared.resize(size)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
 
     #[test]
     fn test_thread_pool() {
@@ -97,4 +303,68 @@ mod tests {
 
         thread::sleep(std::time::Duration::from_secs(5));
     }
+
+    #[test]
+    fn test_queue_times() {
+        let queue_times = QueueTimes::default();
+        for millis in [0, 1, 3, 70, 100, 2000, 9000] {
+            queue_times.record(Duration::from_millis(millis));
+        }
+        let stats = queue_times.stats();
+        assert_eq!(stats.count, 7);
+        assert_eq!(stats.buckets, [2, 1, 0, 0, 2, 0, 0, 1, 1]);
+        assert_eq!(stats.max, Duration::from_secs(9));
+        assert_eq!(stats.total, Duration::from_millis(11174));
+        assert_eq!(stats.mean(), Duration::from_millis(11174) / 7);
+        assert_eq!(QueueTimes::default().stats().mean(), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_thread_pool_queue_times() {
+        let pool = ThreadPool::new(1);
+        pool.execute(|| thread::sleep(Duration::from_millis(60)));
+        pool.execute(|| {});
+        let queue_times = pool.queue_times();
+        drop(pool);
+
+        let stats = queue_times.stats();
+        assert_eq!(stats.count, 2);
+        assert_eq!(stats.buckets.iter().sum::<u64>(), 2);
+        // The second job waits at least until the first one is done
+        assert!(stats.max >= Duration::from_millis(60));
+        assert!(stats.total >= stats.max);
+    }
+
+    #[test]
+    fn test_thread_pool_resize_keeps_queued_jobs() {
+        let done = Arc::new(AtomicUsize::new(0));
+        let pool = ThreadPool::new(4);
+        for _ in 0..12 {
+            let done = Arc::clone(&done);
+            pool.execute(move || {
+                thread::sleep(Duration::from_millis(20));
+                done.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+
+        pool.resize(1);
+        assert_eq!(pool.size(), 1);
+        pool.resize(3);
+        assert_eq!(pool.size(), 3);
+        drop(pool);
+        assert_eq!(done.load(Ordering::SeqCst), 12);
+    }
+
+    #[test]
+    fn test_thread_pool_handle() {
+        let pool = ThreadPool::new(2);
+        let handle = pool.handle();
+        thread::spawn(move || handle.resize(5)).join().unwrap();
+        assert_eq!(pool.size(), 5);
+
+        let handle = pool.handle();
+        drop(pool);
+        handle.resize(8);
+        assert_eq!(handle.size(), 5);
+    }
 }
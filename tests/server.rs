@@ -0,0 +1,491 @@
+//! End to end tests of the server over real sockets
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use HTTP_Server::config::{ListenerOptions, ParseErrors, ServerConfig};
+use HTTP_Server::context::Context;
+use HTTP_Server::http_status::HttpStatus;
+use HTTP_Server::router::Router;
+use HTTP_Server::server::Server;
+
+fn ping(ctx: &mut Context) {
+    ctx.string(HttpStatus::Ok, "pong")
+}
+
+fn echo(ctx: &mut Context) {
+    let body = ctx.body();
+    ctx.string(HttpStatus::Ok, &body)
+}
+
+fn slow(ctx: &mut Context) {
+    thread::sleep(Duration::from_millis(100));
+    ctx.string(HttpStatus::Ok, "slow")
+}
+
+fn user(ctx: &mut Context) {
+    let id = ctx.param("id").unwrap_or_default();
+    ctx.string(HttpStatus::Ok, &format!("user {id}"))
+}
+
+fn router() -> Router {
+    let mut router = Router::new();
+    router
+        .get("/ping", ping)
+        .get("/slow", slow)
+        .get("/users/{id}", user)
+        .post("/echo", echo);
+    router
+}
+
+/// Starts a server with the config on a free port, returning its address
+fn start(config: ServerConfig) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let mut server = Server::new(router(), None);
+        server.config = config;
+        server.serve(listener).unwrap();
+    });
+    addr
+}
+
+struct Response {
+    status: String,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Reads a single response with a `Content-Length`, leaving the connection open
+fn read_response<R: BufRead>(reader: &mut R) -> Option<Response> {
+    let mut status = String::new();
+    if reader.read_line(&mut status).ok()? == 0 {
+        return None;
+    }
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':')?;
+        headers.push((name.to_string(), value.trim().to_string()));
+    }
+    let mut response = Response {
+        status: status.trim_end().to_string(),
+        headers,
+        body: String::new(),
+    };
+    let length = response
+        .header("Content-Length")
+        .map_or(0, |l| l.parse().unwrap());
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).ok()?;
+    response.body = String::from_utf8(body).unwrap();
+    Some(response)
+}
+
+fn connect(addr: SocketAddr) -> (TcpStream, BufReader<TcpStream>) {
+    let stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let reader = BufReader::new(stream.try_clone().unwrap());
+    (stream, reader)
+}
+
+/// Sends the raw request on a new connection and reads its response
+fn send(addr: SocketAddr, request: &[u8]) -> Response {
+    let (mut stream, mut reader) = connect(addr);
+    stream.write_all(request).unwrap();
+    read_response(&mut reader).expect("no response")
+}
+
+#[test]
+fn serves_a_request() {
+    let addr = start(ServerConfig::default());
+    let response = send(addr, b"GET /users/7 HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert_eq!(response.status, "HTTP/1.1 200 OK");
+    assert_eq!(response.header("Content-Type"), Some("text/plain"));
+    assert_eq!(response.body, "user 7");
+
+    let response = send(addr, b"GET /missing HTTP/1.1\r\n\r\n");
+    assert_eq!(response.status, "HTTP/1.1 404 Not Found");
+}
+
+#[test]
+fn keeps_the_connection_alive() {
+    let addr = start(ServerConfig::default());
+    let (mut stream, mut reader) = connect(addr);
+    for i in 0..3 {
+        stream
+            .write_all(format!("GET /users/{i} HTTP/1.1\r\n\r\n").as_bytes())
+            .unwrap();
+        let response = read_response(&mut reader).unwrap();
+        assert_eq!(response.header("Connection"), Some("keep-alive"));
+        assert_eq!(response.body, format!("user {i}"));
+    }
+
+    // Pipelined requests are answered in order
+    stream
+        .write_all(b"GET /ping HTTP/1.1\r\n\r\nGET /users/9 HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    assert_eq!(read_response(&mut reader).unwrap().body, "pong");
+    let last = read_response(&mut reader).unwrap();
+    assert_eq!(last.body, "user 9");
+    assert_eq!(last.header("Connection"), Some("close"));
+    assert!(read_response(&mut reader).is_none());
+}
+
+#[test]
+fn closes_after_max_requests() {
+    let addr = start(ServerConfig {
+        max_requests_per_connection: 2,
+        ..ServerConfig::default()
+    });
+    let (mut stream, mut reader) = connect(addr);
+    stream
+        .write_all(
+            b"GET /ping HTTP/1.1\r\n\r\nGET /ping HTTP/1.1\r\n\r\nGET /ping HTTP/1.1\r\n\r\n",
+        )
+        .unwrap();
+    assert_eq!(
+        read_response(&mut reader).unwrap().header("Connection"),
+        Some("keep-alive")
+    );
+    assert_eq!(
+        read_response(&mut reader).unwrap().header("Connection"),
+        Some("close")
+    );
+    assert!(read_response(&mut reader).is_none());
+}
+
+#[test]
+fn echoes_a_large_body() {
+    let addr = start(ServerConfig::default());
+    let body = "0123456789".repeat(200_000);
+    let (mut stream, mut reader) = connect(addr);
+    let head = format!(
+        "POST /echo HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).unwrap();
+    // Sent in pieces, as a slow client would
+    for piece in body.as_bytes().chunks(64 * 1024) {
+        stream.write_all(piece).unwrap();
+    }
+    let response = read_response(&mut reader).unwrap();
+    assert_eq!(response.status, "HTTP/1.1 200 OK");
+    assert_eq!(response.body.len(), body.len());
+    assert!(response.body == body);
+}
+
+#[test]
+fn rejects_a_body_too_large() {
+    let addr = start(ServerConfig {
+        max_body_size: 1024,
+        ..ServerConfig::default()
+    });
+    let response = send(addr, b"POST /echo HTTP/1.1\r\nContent-Length: 1025\r\n\r\n");
+    assert_eq!(response.status, "HTTP/1.1 413 Payload Too Large");
+    assert_eq!(response.header("Connection"), Some("close"));
+}
+
+#[test]
+fn serves_concurrent_clients() {
+    let addr = start(ServerConfig::default());
+    let clients: Vec<_> = (0..16)
+        .map(|i| {
+            thread::spawn(move || {
+                let (mut stream, mut reader) = connect(addr);
+                for j in 0..5 {
+                    let path = if j == 0 {
+                        "/slow".to_string()
+                    } else {
+                        format!("/users/{i}-{j}")
+                    };
+                    stream
+                        .write_all(format!("GET {path} HTTP/1.1\r\n\r\n").as_bytes())
+                        .unwrap();
+                    let response = read_response(&mut reader).unwrap();
+                    assert_eq!(response.status, "HTTP/1.1 200 OK");
+                    if j > 0 {
+                        assert_eq!(response.body, format!("user {i}-{j}"));
+                    }
+                }
+            })
+        })
+        .collect();
+    for client in clients {
+        client.join().unwrap();
+    }
+}
+
+#[test]
+fn times_out_an_incomplete_request() {
+    let addr = start(ServerConfig {
+        header_read_timeout: Some(Duration::from_millis(200)),
+        ..ServerConfig::default()
+    });
+    let response = send(addr, b"GET /ping HTTP/1.1\r\nHost: loc");
+    assert_eq!(response.status, "HTTP/1.1 408 Request Timeout");
+
+    let addr = start(ServerConfig {
+        body_read_timeout: Some(Duration::from_millis(200)),
+        ..ServerConfig::default()
+    });
+    let response = send(
+        addr,
+        b"POST /echo HTTP/1.1\r\nContent-Length: 10\r\n\r\nhalf",
+    );
+    assert_eq!(response.status, "HTTP/1.1 408 Request Timeout");
+}
+
+#[test]
+fn closes_an_idle_connection() {
+    let addr = start(ServerConfig {
+        keep_alive_timeout: Duration::from_millis(100),
+        ..ServerConfig::default()
+    });
+    let (mut stream, mut reader) = connect(addr);
+    stream.write_all(b"GET /ping HTTP/1.1\r\n\r\n").unwrap();
+    assert_eq!(read_response(&mut reader).unwrap().body, "pong");
+    thread::sleep(Duration::from_millis(300));
+    let mut rest = Vec::new();
+    assert_eq!(reader.read_to_end(&mut rest).unwrap(), 0);
+}
+
+#[test]
+fn rejects_malformed_requests() {
+    let addr = start(ServerConfig::default());
+    let cases: [(&[u8], &str); 6] = [
+        (b"GET /ping\r\n\r\n", "400 Bad Request"),
+        (b"FETCH /ping HTTP/1.1\r\n\r\n", "400 Bad Request"),
+        (
+            b"GET /ping HTTP/2.0\r\n\r\n",
+            "505 HTTP Version Not Supported",
+        ),
+        (
+            b"GET /ping HTTP/1.1\r\nBad Header\r\n\r\n",
+            "400 Bad Request",
+        ),
+        (
+            b"GET /ping HTTP/1.1\r\nX-A: 1\r\n folded\r\n\r\n",
+            "400 Bad Request",
+        ),
+        (
+            b"POST /echo HTTP/1.1\r\nContent-Length: 1\r\nTransfer-Encoding: chunked\r\n\r\n",
+            "400 Bad Request",
+        ),
+    ];
+    for (request, status) in cases {
+        let response = send(addr, request);
+        assert_eq!(response.status, format!("HTTP/1.1 {status}"));
+        assert_eq!(response.header("Content-Type"), Some("application/json"));
+        assert!(response.body.contains("message"));
+    }
+
+    let huge = format!(
+        "GET /ping HTTP/1.1\r\nX-Big: {}\r\n\r\n",
+        "a".repeat(64 * 1024)
+    );
+    let response = send(addr, huge.as_bytes());
+    assert_eq!(
+        response.status,
+        "HTTP/1.1 431 Request Header Fields Too Large"
+    );
+}
+
+#[test]
+fn binds_an_ephemeral_port() {
+    let first = Server::new(router(), None).spawn("127.0.0.1:0").unwrap();
+    let second = Server::new(router(), None).spawn("127.0.0.1:0").unwrap();
+    assert_ne!(first.local_addr().port(), 0);
+    assert_ne!(first.local_addr(), second.local_addr());
+    for handle in [first, second] {
+        assert_eq!(
+            send(handle.local_addr(), b"GET /ping HTTP/1.1\r\n\r\n").body,
+            "pong"
+        );
+        handle.shutdown().unwrap();
+    }
+}
+
+#[test]
+fn runs_on_a_listener_bound_by_the_caller() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || Server::from_listener(router(), listener).run().unwrap());
+    assert_eq!(send(addr, b"GET /ping HTTP/1.1\r\n\r\n").body, "pong");
+}
+
+#[test]
+fn serves_several_listeners() {
+    let listeners = vec![
+        TcpListener::bind("127.0.0.1:0").unwrap(),
+        TcpListener::bind("127.0.0.1:0").unwrap(),
+    ];
+    let addrs: Vec<SocketAddr> = listeners.iter().map(|l| l.local_addr().unwrap()).collect();
+    thread::spawn(move || {
+        // One worker, so both listeners share it
+        let server = Server::builder(router()).threads(1).build();
+        server.serve_all(listeners).unwrap();
+    });
+    for addr in addrs {
+        let response = send(addr, b"GET /users/3 HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert_eq!(response.body, "user 3");
+    }
+}
+
+#[test]
+fn listeners_override_the_parse_errors() {
+    let public = TcpListener::bind("127.0.0.1:0").unwrap();
+    let admin = TcpListener::bind("127.0.0.1:0").unwrap();
+    let (public_addr, admin_addr) = (public.local_addr().unwrap(), admin.local_addr().unwrap());
+    let generic = ListenerOptions {
+        parse_errors: Some(ParseErrors::Generic),
+    };
+    thread::spawn(move || {
+        let server = Server::builder(router()).threads(1).build();
+        server
+            .serve_with(vec![(public, generic), (admin, ListenerOptions::default())])
+            .unwrap();
+    });
+
+    let malformed = b"GET / HTTP/1.1\r\nBad Header\r\n\r\n";
+    let response = send(public_addr, malformed);
+    assert_eq!(response.status, "HTTP/1.1 400 Bad Request");
+    assert_eq!(response.body, r#"{"message":"Bad Request."}"#);
+    let response = send(admin_addr, malformed);
+    assert_eq!(response.status, "HTTP/1.1 400 Bad Request");
+    assert!(response.body.contains("Bad Header"), "{}", response.body);
+}
+
+#[test]
+fn reports_the_queue_time() {
+    let server = Server::builder(router())
+        .threads(1)
+        .config(ServerConfig {
+            queue_time_header: true,
+            ..ServerConfig::default()
+        })
+        .build();
+    let handle = server.spawn("127.0.0.1:0").unwrap();
+    let addr = handle.local_addr();
+
+    // The only worker is busy with the slow request while the second one waits
+    let (mut slow, mut slow_reader) = connect(addr);
+    slow.write_all(b"GET /slow HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    thread::sleep(Duration::from_millis(20));
+    let (mut waiting, mut reader) = connect(addr);
+    waiting.write_all(b"GET /ping HTTP/1.1\r\n\r\n").unwrap();
+    let queue_time =
+        |response: &Response| -> f64 { response.header("X-Queue-Time").unwrap().parse().unwrap() };
+    assert!(queue_time(&read_response(&mut slow_reader).unwrap()) < 20.0);
+    assert!(queue_time(&read_response(&mut reader).unwrap()) >= 50.0);
+    waiting
+        .write_all(b"GET /ping HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    assert_eq!(queue_time(&read_response(&mut reader).unwrap()), 0.0);
+
+    // A sample for each connection, the request kept alive isn't one
+    let stats = handle.queue_times();
+    assert_eq!(stats.count, 2);
+    assert!(stats.max >= Duration::from_millis(50));
+    assert_eq!(stats.buckets.iter().sum::<u64>(), 2);
+    handle.shutdown().unwrap();
+}
+
+#[test]
+fn shuts_down_after_the_requests_in_flight() {
+    let handle = Server::new(router(), None).spawn("127.0.0.1:0").unwrap();
+    let addr = handle.local_addr();
+    assert_eq!(send(addr, b"GET /ping HTTP/1.1\r\n\r\n").body, "pong");
+
+    let (mut stream, mut reader) = connect(addr);
+    stream.write_all(b"GET /slow HTTP/1.1\r\n\r\n").unwrap();
+    thread::sleep(Duration::from_millis(20));
+    handle.shutdown().unwrap();
+
+    assert_eq!(read_response(&mut reader).unwrap().body, "slow");
+    assert!(read_response(&mut reader).is_none());
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+#[test]
+fn shutdown_gives_up_on_idle_connections() {
+    let mut server = Server::new(router(), None);
+    server.config.keep_alive_timeout = Duration::from_secs(5);
+    let handle = server.spawn("127.0.0.1:0").unwrap();
+
+    let (mut stream, mut reader) = connect(handle.local_addr());
+    stream.write_all(b"GET /ping HTTP/1.1\r\n\r\n").unwrap();
+    assert_eq!(read_response(&mut reader).unwrap().body, "pong");
+    // Until the worker waits for the next request
+    thread::sleep(Duration::from_millis(50));
+    let err = handle
+        .shutdown_timeout(Duration::from_millis(100))
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+}
+
+#[test]
+fn resizes_the_workers_of_a_running_server() {
+    // Each request waits for the other to arrive, which only happens if two workers
+    // serve them at the same time. The timeout only ends a test that is failing
+    static ARRIVED: Mutex<usize> = Mutex::new(0);
+    static ALL_HERE: Condvar = Condvar::new();
+    fn meet(ctx: &mut Context) {
+        let mut arrived = ARRIVED.lock().unwrap();
+        *arrived += 1;
+        ALL_HERE.notify_all();
+        let (arrived, _) = ALL_HERE
+            .wait_timeout_while(arrived, Duration::from_secs(30), |arrived| *arrived < 2)
+            .unwrap();
+        let met = *arrived >= 2;
+        drop(arrived);
+        ctx.string(HttpStatus::Ok, if met { "met" } else { "alone" })
+    }
+    let mut router = router();
+    router.get("/meet", meet);
+    let handle = Server::builder(router)
+        .threads(1)
+        .build()
+        .spawn("127.0.0.1:0")
+        .unwrap();
+    let addr = handle.local_addr();
+    assert_eq!(handle.threads(), 1);
+
+    handle.resize(2);
+    assert_eq!(handle.threads(), 2);
+    let requests: Vec<_> = (0..2)
+        .map(|_| {
+            thread::spawn(move || {
+                send(addr, b"GET /meet HTTP/1.1\r\nConnection: close\r\n\r\n").body
+            })
+        })
+        .collect();
+    for request in requests {
+        assert_eq!(request.join().unwrap(), "met");
+    }
+
+    handle.resize(0);
+    assert_eq!(handle.threads(), 1);
+    assert_eq!(send(addr, b"GET /ping HTTP/1.1\r\n\r\n").body, "pong");
+    handle.shutdown().unwrap();
+}